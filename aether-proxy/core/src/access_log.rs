@@ -0,0 +1,345 @@
+//! Access log: one structured record per tunnel stream.
+//!
+//! Separate from the tracing diagnostics, so it can be kept, shipped and
+//! rotated on its own.  `--access-log` selects the sink:
+//!
+//! - `stdout`: JSON lines on standard output
+//! - `file:<path>`: JSON lines appended to `<path>`
+//! - `aether`: batches posted to each server's
+//!   `/api/admin/proxy-nodes/access-log` endpoint, buffered across
+//!   control-plane outages.  Needs an Aether release with that endpoint;
+//!   a server that answers 404 gets no more records until the node
+//!   restarts
+//!
+//! Streams reach the node through Aether, so the client's address is known
+//! only to the control plane; records carry the client identity Aether
+//! tags the stream with (see [`crate::usage`]) and the server and stream
+//! they arrived on.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use tokio::sync::{mpsc, watch, Mutex};
+use tracing::{debug, warn};
+
+use crate::registration::client;
+use crate::state::ServerContext;
+
+/// Records queued between the stream handlers and the sink.
+const CHANNEL_CAPACITY: usize = 4096;
+/// How often the Aether sink uploads.
+const UPLOAD_INTERVAL: Duration = Duration::from_secs(10);
+/// Records kept per server while Aether is unreachable; the oldest are
+/// dropped beyond this.
+const MAX_BUFFERED: usize = 10_000;
+/// Largest batch per upload request.
+const MAX_BATCH: usize = 1_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Sink {
+    Off,
+    Stdout,
+    File(PathBuf),
+    Aether,
+}
+
+impl Sink {
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let raw = raw.trim();
+        if let Some(path) = raw.strip_prefix("file:") {
+            if path.is_empty() {
+                return Err("file: needs a path".to_string());
+            }
+            return Ok(Self::File(PathBuf::from(path)));
+        }
+        match raw.to_ascii_lowercase().as_str() {
+            "" | "off" | "none" => Ok(Self::Off),
+            "stdout" => Ok(Self::Stdout),
+            "aether" => Ok(Self::Aether),
+            other => Err(format!(
+                "unknown sink {:?} (use off, stdout, file:<path> or aether)",
+                other
+            )),
+        }
+    }
+}
+
+/// How a stream ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// The upstream response was relayed.
+    Completed,
+    /// The node answered with a stream error (blocked target, upstream
+    /// failure, timeout...).
+    Failed,
+    /// Refused before any work, e.g. load shedding.
+    Rejected,
+    /// The client went away.
+    Cancelled,
+}
+
+/// Which side ended an upgraded (`101 Switching Protocols`) stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CloseReason {
+    /// The client finished sending, then the origin finished too.
+    ClientClosed,
+    /// The origin closed its side.
+    TargetClosed,
+    /// The stream went away while the node was draining.
+    Shutdown,
+    /// Relaying failed; see the record's reason.
+    Error,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Record {
+    /// Unix time in milliseconds when the stream ended.
+    pub ts: u64,
+    pub server: String,
+    pub stream_id: u32,
+    pub request_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client: Option<String>,
+    pub method: String,
+    pub host: String,
+    pub port: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    pub outcome: Outcome,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    /// Set for upgraded streams.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub close_reason: Option<CloseReason>,
+    pub bytes_received: u64,
+    pub bytes_sent: u64,
+    pub duration_ms: u64,
+}
+
+/// Cheap handle for submitting records; a no-op when the log is off.
+#[derive(Clone, Default)]
+pub struct AccessLog {
+    tx: Option<mpsc::Sender<Record>>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl AccessLog {
+    /// Handle plus the receiver to pass to [`spawn`] (`None` when off).
+    pub fn new(sink: &Sink) -> (Self, Option<mpsc::Receiver<Record>>) {
+        if *sink == Sink::Off {
+            return (Self::default(), None);
+        }
+        let (tx, rx) = mpsc::channel(CHANNEL_CAPACITY);
+        (
+            Self {
+                tx: Some(tx),
+                dropped: Arc::default(),
+            },
+            Some(rx),
+        )
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.tx.is_some()
+    }
+
+    /// Queue a record; dropped (and counted) if the sink is falling behind.
+    pub fn record(&self, record: Record) {
+        if let Some(tx) = &self.tx {
+            if tx.try_send(record).is_err() {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Run the sink.  Records keep flowing while streams drain after a
+/// shutdown signal; the Aether sink uploads right away on shutdown and
+/// then keeps its usual cycle until the process exits.
+pub fn spawn(
+    sink: Sink,
+    mut rx: mpsc::Receiver<Record>,
+    servers: Arc<Mutex<Vec<Arc<ServerContext>>>>,
+    shutdown: watch::Receiver<bool>,
+) {
+    tokio::spawn(async move {
+        match sink {
+            Sink::Off => {}
+            Sink::Stdout => write_lines(&mut rx, tokio::io::stdout()).await,
+            Sink::File(path) => {
+                match tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&path)
+                    .await
+                {
+                    Ok(file) => write_lines(&mut rx, file).await,
+                    Err(e) => {
+                        warn!(path = %path.display(), error = %e, "cannot open access log, disabled");
+                    }
+                }
+            }
+            Sink::Aether => run_uploader(rx, servers, shutdown).await,
+        }
+    });
+}
+
+/// Write records as JSON lines, flushing whenever the queue runs empty.
+async fn write_lines<W: AsyncWrite + Unpin>(rx: &mut mpsc::Receiver<Record>, mut out: W) {
+    while let Some(record) = rx.recv().await {
+        let mut buf = json_line(&record);
+        while let Ok(record) = rx.try_recv() {
+            buf.extend(json_line(&record));
+        }
+        if let Err(e) = async {
+            out.write_all(&buf).await?;
+            out.flush().await
+        }
+        .await
+        {
+            debug!(error = %e, "access log write failed");
+        }
+    }
+}
+
+fn json_line(record: &Record) -> Vec<u8> {
+    let mut line = serde_json::to_vec(record).unwrap_or_default();
+    line.push(b'\n');
+    line
+}
+
+async fn run_uploader(
+    mut rx: mpsc::Receiver<Record>,
+    servers: Arc<Mutex<Vec<Arc<ServerContext>>>>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let mut pending: HashMap<String, VecDeque<Record>> = HashMap::new();
+    // Servers without the access-log endpoint
+    let mut unsupported: HashSet<String> = HashSet::new();
+    let mut ticker = tokio::time::interval(UPLOAD_INTERVAL);
+    ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    let mut stopping = false;
+    loop {
+        tokio::select! {
+            Some(record) = rx.recv() => {
+                if !unsupported.contains(&record.server) {
+                    buffer(&mut pending, record);
+                }
+                continue;
+            }
+            _ = ticker.tick() => {}
+            _ = shutdown.changed(), if !stopping => stopping = true,
+        }
+        while let Ok(record) = rx.try_recv() {
+            if !unsupported.contains(&record.server) {
+                buffer(&mut pending, record);
+            }
+        }
+        let servers = servers.lock().await.clone();
+        for server in &servers {
+            let Some(queue) = pending.get_mut(&server.server_label) else {
+                continue;
+            };
+            while !queue.is_empty() {
+                let batch: Vec<&Record> = queue.iter().take(MAX_BATCH).collect();
+                let node_id = server.node_id.read().unwrap().clone();
+                match server
+                    .aether_client
+                    .upload_access_log(&node_id, &batch)
+                    .await
+                {
+                    Ok(()) => {
+                        let sent = batch.len();
+                        queue.drain(..sent);
+                    }
+                    Err(e) if client::is_unsupported(&e) => {
+                        warn!(server = %server.server_label, error = %e, "access log uploads disabled");
+                        queue.clear();
+                        unsupported.insert(server.server_label.clone());
+                        break;
+                    }
+                    Err(e) => {
+                        debug!(server = %server.server_label, error = %e, "access log upload failed, keeping records");
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn buffer(pending: &mut HashMap<String, VecDeque<Record>>, record: Record) {
+    let queue = pending.entry(record.server.clone()).or_default();
+    if queue.len() == MAX_BUFFERED {
+        queue.pop_front();
+    }
+    queue.push_back(record);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sink() {
+        assert_eq!(Sink::parse("").unwrap(), Sink::Off);
+        assert_eq!(Sink::parse("stdout").unwrap(), Sink::Stdout);
+        assert_eq!(Sink::parse("Aether").unwrap(), Sink::Aether);
+        assert_eq!(
+            Sink::parse("file:/var/log/aether-proxy/access.log").unwrap(),
+            Sink::File(PathBuf::from("/var/log/aether-proxy/access.log"))
+        );
+        assert!(Sink::parse("file:").is_err());
+        assert!(Sink::parse("syslog").is_err());
+    }
+
+    #[test]
+    fn test_record_is_dropped_when_full() {
+        let (log, rx) = AccessLog::new(&Sink::Stdout);
+        let record = Record {
+            ts: 0,
+            server: "server".into(),
+            stream_id: 1,
+            request_id: "0123abcd".into(),
+            client: None,
+            method: "GET".into(),
+            host: "example.com".into(),
+            port: 443,
+            status: Some(200),
+            outcome: Outcome::Completed,
+            reason: None,
+            close_reason: None,
+            bytes_received: 0,
+            bytes_sent: 10,
+            duration_ms: 5,
+        };
+        for _ in 0..CHANNEL_CAPACITY + 3 {
+            log.record(record.clone());
+        }
+        assert_eq!(log.dropped(), 3);
+        drop(rx);
+
+        let line = String::from_utf8(json_line(&record)).unwrap();
+        assert!(line.ends_with("}\n"));
+        assert!(line.contains(r#""outcome":"completed""#));
+        assert!(!line.contains("reason"));
+
+        let upgraded = Record {
+            close_reason: Some(CloseReason::TargetClosed),
+            ..record
+        };
+        let line = String::from_utf8(json_line(&upgraded)).unwrap();
+        assert!(line.contains(r#""close_reason":"target_closed""#));
+    }
+}
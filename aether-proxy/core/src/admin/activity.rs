@@ -0,0 +1,156 @@
+//! In-memory throughput history and recent errors for the admin UI.
+//!
+//! Both buffers are fixed-size rings: the history keeps the last
+//! [`HISTORY_LEN`] samples (30 minutes at the default interval), the error
+//! log the last [`MAX_ERRORS`] stream failures.  Nothing is persisted.
+
+use std::collections::VecDeque;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use tokio::sync::watch;
+
+use crate::state::{MetricsTotals, ServerContext};
+
+pub const SAMPLE_INTERVAL: Duration = Duration::from_secs(5);
+pub const HISTORY_LEN: usize = 360;
+pub const MAX_ERRORS: usize = 50;
+
+/// Node-wide counts over one sample interval.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ThroughputSample {
+    /// Unix timestamp (seconds) at the end of the interval.
+    pub at: u64,
+    pub requests: u64,
+    /// Failed, rejected, shed, aborted and stalled streams.
+    pub failed: u64,
+    /// Active streams at sampling time.
+    pub active: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ErrorEntry {
+    pub at: u64,
+    pub server: String,
+    pub message: String,
+}
+
+#[derive(Default)]
+pub struct Activity {
+    history: Mutex<VecDeque<ThroughputSample>>,
+    errors: Mutex<VecDeque<ErrorEntry>>,
+}
+
+impl Activity {
+    /// Remember a stream failure, evicting the oldest entry when full.
+    pub fn record_error(&self, server: &str, message: &str) {
+        let mut errors = self.errors.lock().unwrap();
+        if errors.len() == MAX_ERRORS {
+            errors.pop_front();
+        }
+        errors.push_back(ErrorEntry {
+            at: unix_now(),
+            server: server.to_string(),
+            message: message.to_string(),
+        });
+    }
+
+    fn push_sample(&self, sample: ThroughputSample) {
+        let mut history = self.history.lock().unwrap();
+        if history.len() == HISTORY_LEN {
+            history.pop_front();
+        }
+        history.push_back(sample);
+    }
+
+    /// Samples, oldest first.
+    pub fn history(&self) -> Vec<ThroughputSample> {
+        self.history.lock().unwrap().iter().copied().collect()
+    }
+
+    /// Errors, newest first.
+    pub fn errors(&self) -> Vec<ErrorEntry> {
+        self.errors.lock().unwrap().iter().rev().cloned().collect()
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn failures(totals: &MetricsTotals) -> u64 {
+    totals.failed
+        + totals.dns_failures
+        + totals.stream_errors
+        + totals.shed_requests
+        + totals.stalled_streams
+}
+
+/// Sample node-wide throughput into `activity` every [`SAMPLE_INTERVAL`].
+pub fn spawn_sampler(
+    activity: Arc<Activity>,
+    servers: Arc<tokio::sync::Mutex<Vec<Arc<ServerContext>>>>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(SAMPLE_INTERVAL);
+        let mut last: Option<(u64, u64)> = None;
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                _ = shutdown.changed() => return,
+            }
+            let (mut requests, mut failed, mut active) = (0, 0, 0);
+            for server in servers.lock().await.iter() {
+                let totals = server.metrics.totals();
+                requests += totals.requests;
+                failed += failures(&totals);
+                active += server.active_connections.load(Ordering::Acquire);
+            }
+            // The first tick only establishes the baseline.
+            if let Some((prev_requests, prev_failed)) = last {
+                activity.push_sample(ThroughputSample {
+                    at: unix_now(),
+                    requests: requests.saturating_sub(prev_requests),
+                    failed: failed.saturating_sub(prev_failed),
+                    active,
+                });
+            }
+            last = Some((requests, failed));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rings_are_bounded() {
+        let activity = Activity::default();
+        for i in 0..(HISTORY_LEN as u64 + 3) {
+            activity.push_sample(ThroughputSample {
+                at: i,
+                requests: i,
+                failed: 0,
+                active: 0,
+            });
+        }
+        let history = activity.history();
+        assert_eq!(history.len(), HISTORY_LEN);
+        assert_eq!(history[0].at, 3);
+
+        for i in 0..(MAX_ERRORS + 1) {
+            activity.record_error("server-0", &format!("error {i}"));
+        }
+        let errors = activity.errors();
+        assert_eq!(errors.len(), MAX_ERRORS);
+        assert_eq!(errors[0].message, format!("error {MAX_ERRORS}"));
+        assert_eq!(errors.last().unwrap().message, "error 1");
+    }
+}
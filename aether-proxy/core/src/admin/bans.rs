@@ -0,0 +1,165 @@
+//! Temporary bans for sources that keep failing admin authentication.
+//!
+//! A source that presents a wrong or missing token `threshold` times within
+//! the ban period is refused for that period: its connections are closed
+//! before a request is read.  Loopback peers are never banned, since every
+//! SSH-tunnelled operator shares them.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Sources tracked at once; expired entries are pruned beyond this, and
+/// new failures are not tracked while the table is still full.
+const MAX_TRACKED: usize = 10_000;
+
+struct Failures {
+    count: u32,
+    first_at: Instant,
+    banned_until: Option<Instant>,
+}
+
+pub struct BanList {
+    threshold: u32,
+    period: Duration,
+    sources: Mutex<HashMap<IpAddr, Failures>>,
+}
+
+impl BanList {
+    /// `threshold == 0` disables banning.
+    pub fn new(threshold: u32, period: Duration) -> Self {
+        Self {
+            threshold,
+            period,
+            sources: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_banned(&self, ip: IpAddr) -> bool {
+        self.is_banned_at(ip, Instant::now())
+    }
+
+    /// Count a failed authentication.  Returns true if it got `ip` banned.
+    pub fn record_failure(&self, ip: IpAddr) -> bool {
+        self.record_failure_at(ip, Instant::now())
+    }
+
+    /// A successful authentication clears the source's failures.
+    pub fn record_success(&self, ip: IpAddr) {
+        if self.threshold > 0 {
+            self.sources.lock().unwrap().remove(&ip.to_canonical());
+        }
+    }
+
+    /// Currently banned sources.
+    pub fn banned_count(&self) -> usize {
+        let now = Instant::now();
+        self.sources
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|f| f.banned_until.is_some_and(|until| until > now))
+            .count()
+    }
+
+    fn is_banned_at(&self, ip: IpAddr, now: Instant) -> bool {
+        if self.threshold == 0 {
+            return false;
+        }
+        self.sources
+            .lock()
+            .unwrap()
+            .get(&ip.to_canonical())
+            .and_then(|f| f.banned_until)
+            .is_some_and(|until| until > now)
+    }
+
+    fn record_failure_at(&self, ip: IpAddr, now: Instant) -> bool {
+        let ip = ip.to_canonical();
+        if self.threshold == 0 || ip.is_loopback() {
+            return false;
+        }
+        let mut sources = self.sources.lock().unwrap();
+        if sources.len() >= MAX_TRACKED && !sources.contains_key(&ip) {
+            let period = self.period;
+            sources.retain(|_, f| match f.banned_until {
+                Some(until) => until > now,
+                None => now.duration_since(f.first_at) < period,
+            });
+            if sources.len() >= MAX_TRACKED {
+                return false;
+            }
+        }
+        let failures = sources.entry(ip).or_insert(Failures {
+            count: 0,
+            first_at: now,
+            banned_until: None,
+        });
+        let expired = match failures.banned_until {
+            Some(until) => until <= now,
+            None => now.duration_since(failures.first_at) >= self.period,
+        };
+        if expired {
+            *failures = Failures {
+                count: 0,
+                first_at: now,
+                banned_until: None,
+            };
+        }
+        failures.count += 1;
+        if failures.count >= self.threshold && failures.banned_until.is_none() {
+            failures.banned_until = Some(now + self.period);
+            return true;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ban_after_threshold_and_expiry() {
+        let bans = BanList::new(3, Duration::from_secs(60));
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        let start = Instant::now();
+
+        assert!(!bans.record_failure_at(ip, start));
+        assert!(!bans.record_failure_at(ip, start + Duration::from_secs(1)));
+        assert!(!bans.is_banned_at(ip, start + Duration::from_secs(2)));
+        assert!(bans.record_failure_at(ip, start + Duration::from_secs(2)));
+        assert!(bans.is_banned_at(ip, start + Duration::from_secs(3)));
+        assert!(!bans.is_banned_at("203.0.113.8".parse().unwrap(), start));
+
+        // Lifted after the period, with a fresh count
+        let later = start + Duration::from_secs(63);
+        assert!(!bans.is_banned_at(ip, later));
+        assert!(!bans.record_failure_at(ip, later));
+        assert!(!bans.is_banned_at(ip, later));
+    }
+
+    #[test]
+    fn test_failures_outside_period_do_not_add_up() {
+        let bans = BanList::new(2, Duration::from_secs(60));
+        let ip: IpAddr = "2001:db8::1".parse().unwrap();
+        let start = Instant::now();
+        assert!(!bans.record_failure_at(ip, start));
+        assert!(!bans.record_failure_at(ip, start + Duration::from_secs(61)));
+        assert!(bans.record_failure_at(ip, start + Duration::from_secs(62)));
+    }
+
+    #[test]
+    fn test_loopback_and_disabled_never_ban() {
+        let bans = BanList::new(1, Duration::from_secs(60));
+        let loopback: IpAddr = "127.0.0.1".parse().unwrap();
+        assert!(!bans.record_failure(loopback));
+        assert!(!bans.is_banned(loopback));
+
+        let off = BanList::new(0, Duration::from_secs(60));
+        let ip: IpAddr = "203.0.113.7".parse().unwrap();
+        assert!(!off.record_failure(ip));
+        assert!(!off.is_banned(ip));
+    }
+}
@@ -0,0 +1,484 @@
+//! Authenticated admin port with a built-in status page.
+//!
+//! Enabled with `--admin-listen`.  The listener stays on loopback and is
+//! reached through an SSH tunnel (`ssh -L 9465:127.0.0.1:9465 vps`); other
+//! addresses are refused unless `--admin-allow-remote` is set.
+//!
+//! - `GET /`: single-page status UI (static; holds no node data)
+//! - `GET /healthz`: liveness probe, 200 until the node has stopped
+//! - `GET /readyz`: readiness probe, 200 while serving with at least one
+//!   tunnel up
+//! - `GET /api/status`: connections, per-server counters, throughput
+//!   history and recent errors
+//! - `GET /api/config`: effective settings, credentials redacted
+//! - `GET /api/connections`: in-flight streams (server, target, bytes, age)
+//! - `DELETE /api/connections/{id}`: terminate one stream
+//! - `POST /api/drain`: unregister and shut down gracefully, like SIGTERM
+//! - `GET /api/log-level`: current tracing filter
+//! - `PUT /api/log-level?level=<filter>`: replace the tracing filter (e.g.
+//!   `debug` or `info,aether_proxy_core::tunnel=trace`) without a restart;
+//!   it holds until changed again or the control plane pushes another level
+//!
+//! API routes require `Authorization: Bearer <admin_token>`; the probes
+//! disclose only the lifecycle phase and are open so orchestrators can
+//! reach them without the token.  Sources that keep presenting a wrong
+//! token are banned for a while (see [`bans`]).
+
+pub mod activity;
+mod bans;
+
+use std::net::SocketAddr;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use percent_encoding::percent_decode_str;
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::sync::{watch, Mutex};
+use tracing::{debug, info, warn};
+
+use crate::config::Config;
+use crate::http_head::{self, RequestHead};
+use crate::lifecycle::Phase;
+use crate::runtime;
+use crate::state::{AppState, ServerContext};
+
+use bans::BanList;
+
+const INDEX_HTML: &str = include_str!("ui.html");
+
+/// Largest request head accepted.
+const MAX_REQUEST_HEAD: usize = 8 * 1024;
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Parsed request head; the admin API never reads request bodies.
+#[derive(Debug, PartialEq, Eq)]
+struct Request<'a> {
+    method: &'a str,
+    path: &'a str,
+    query: &'a str,
+    bearer: Option<&'a str>,
+}
+
+struct Response {
+    status: &'static str,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn json(status: &'static str, value: serde_json::Value) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body: value.to_string(),
+        }
+    }
+
+    fn error(status: &'static str, message: &str) -> Self {
+        Self::json(status, serde_json::json!({ "error": message }))
+    }
+}
+
+/// Bind the admin listener and start serving.
+pub async fn spawn(
+    state: Arc<AppState>,
+    servers: Arc<Mutex<Vec<Arc<ServerContext>>>>,
+    mut shutdown: watch::Receiver<bool>,
+) -> anyhow::Result<()> {
+    let Some(addr) = state.config.admin_listen else {
+        return Ok(());
+    };
+    let listener = crate::handoff::bind_listener("admin", addr)
+        .map_err(|e| anyhow::anyhow!("admin_listen {}: {}", addr, e))?;
+    if !addr.ip().is_loopback() {
+        warn!(%addr, "admin port is reachable from the network (--admin-allow-remote)");
+    }
+    info!(%addr, "admin UI listening");
+
+    let activity = Arc::clone(&state.activity);
+    activity::spawn_sampler(activity, Arc::clone(&servers), shutdown.clone());
+
+    let bans = Arc::new(BanList::new(
+        state.config.admin_ban_threshold,
+        Duration::from_secs(state.config.admin_ban_secs),
+    ));
+
+    tokio::spawn(async move {
+        loop {
+            let (stream, peer) = tokio::select! {
+                accepted = listener.accept() => match accepted {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        debug!(error = %e, "admin accept failed");
+                        continue;
+                    }
+                },
+                _ = shutdown.changed() => return,
+            };
+            if bans.is_banned(peer.ip()) {
+                debug!(%peer, "admin connection from banned source dropped");
+                continue;
+            }
+            let state = Arc::clone(&state);
+            let servers = Arc::clone(&servers);
+            let bans = Arc::clone(&bans);
+            tokio::spawn(async move {
+                if let Err(e) = serve(stream, peer, &state, &servers, &bans).await {
+                    debug!(%peer, error = %e, "admin request failed");
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+async fn serve(
+    mut stream: TcpStream,
+    peer: SocketAddr,
+    state: &AppState,
+    servers: &Mutex<Vec<Arc<ServerContext>>>,
+    bans: &BanList,
+) -> std::io::Result<()> {
+    let Some(head) = http_head::read(&mut stream, MAX_REQUEST_HEAD, READ_TIMEOUT).await? else {
+        return Ok(());
+    };
+
+    let head = String::from_utf8_lossy(&head);
+    let response = match parse_request(&head) {
+        Some(request) => route(&request, peer, state, servers, bans).await,
+        None => Response::error("400 Bad Request", "malformed request"),
+    };
+
+    let mut out = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nCache-Control: no-store\r\nX-Content-Type-Options: nosniff\r\nConnection: close\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    );
+    if response.content_type.starts_with("text/html") {
+        out.push_str(
+            "Content-Security-Policy: default-src 'none'; script-src 'unsafe-inline'; style-src 'unsafe-inline'; connect-src 'self'; frame-ancestors 'none'\r\n",
+        );
+    }
+    if response.status.starts_with("401") {
+        out.push_str("WWW-Authenticate: Bearer\r\n");
+    }
+    out.push_str("\r\n");
+    out.push_str(&response.body);
+    stream.write_all(out.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+async fn route(
+    request: &Request<'_>,
+    peer: SocketAddr,
+    state: &AppState,
+    servers: &Mutex<Vec<Arc<ServerContext>>>,
+    bans: &BanList,
+) -> Response {
+    let api = request.path.starts_with("/api/");
+    if api {
+        let expected = state.config.admin_token.as_deref().unwrap_or_default();
+        if !request.bearer.is_some_and(|t| token_matches(t, expected)) {
+            if bans.record_failure(peer.ip()) {
+                warn!(
+                    %peer,
+                    ban_secs = state.config.admin_ban_secs,
+                    "too many failed admin authentications, source banned"
+                );
+            }
+            return Response::error("401 Unauthorized", "missing or invalid admin token");
+        }
+        bans.record_success(peer.ip());
+    }
+
+    if let Some(id) = request.path.strip_prefix("/api/connections/") {
+        if request.method != "DELETE" {
+            return Response::error("405 Method Not Allowed", "method not allowed");
+        }
+        let Ok(id) = id.parse::<u64>() else {
+            return Response::error("404 Not Found", "no such connection");
+        };
+        let servers = servers.lock().await.clone();
+        if !servers.iter().any(|server| server.live_streams.kill(id)) {
+            return Response::error("404 Not Found", "no such connection");
+        }
+        warn!(%peer, id, "connection terminated via admin API");
+        return Response::json("202 Accepted", serde_json::json!({ "id": id }));
+    }
+
+    match (request.method, request.path) {
+        ("GET", "/") => Response {
+            status: "200 OK",
+            content_type: "text/html; charset=utf-8",
+            body: INDEX_HTML.to_string(),
+        },
+        ("GET", "/healthz") => {
+            let phase = state.lifecycle.phase();
+            let status = if phase < Phase::Stopped {
+                "200 OK"
+            } else {
+                "503 Service Unavailable"
+            };
+            Response::json(status, serde_json::json!({ "phase": phase.as_str() }))
+        }
+        ("GET", "/readyz") => {
+            let phase = state.lifecycle.phase();
+            let tunnels: u64 = servers
+                .lock()
+                .await
+                .iter()
+                .map(|s| s.connected_tunnels.load(Ordering::Acquire))
+                .sum();
+            let status = if phase == Phase::Serving && tunnels > 0 {
+                "200 OK"
+            } else {
+                "503 Service Unavailable"
+            };
+            Response::json(
+                status,
+                serde_json::json!({ "phase": phase.as_str(), "connected_tunnels": tunnels }),
+            )
+        }
+        ("GET", "/api/config") => Response::json("200 OK", config_json(&state.config)),
+        ("GET", "/api/connections") => {
+            let servers = servers.lock().await.clone();
+            Response::json("200 OK", connections_json(&servers))
+        }
+        ("GET", "/api/status") => {
+            let servers = servers.lock().await.clone();
+            let mut status = status_json(state, &servers);
+            status["admin_banned_sources"] = bans.banned_count().into();
+            Response::json("200 OK", status)
+        }
+        ("POST", "/api/drain") => {
+            warn!(%peer, "drain requested via admin API");
+            state.lifecycle.request_drain();
+            Response::json(
+                "202 Accepted",
+                serde_json::json!({ "phase": state.lifecycle.phase().as_str() }),
+            )
+        }
+        ("GET", "/api/log-level") => {
+            let servers = servers.lock().await.clone();
+            let level = servers
+                .first()
+                .map(|server| server.dynamic.load().log_level.clone())
+                .unwrap_or_else(|| state.config.log_level.clone());
+            Response::json("200 OK", serde_json::json!({ "log_level": level }))
+        }
+        ("PUT", "/api/log-level") => {
+            let Some(level) = query_param(request.query, "level").filter(|l| !l.trim().is_empty())
+            else {
+                return Response::error("400 Bad Request", "missing level parameter");
+            };
+            if let Err(e) = runtime::reload_log_level(&level) {
+                return Response::error("400 Bad Request", &e);
+            }
+            for server in servers.lock().await.iter() {
+                runtime::store_log_level(&server.dynamic, &level);
+            }
+            warn!(%peer, log_level = %level, "log level changed via admin API");
+            Response::json("200 OK", serde_json::json!({ "log_level": level }))
+        }
+        (
+            _,
+            "/" | "/healthz" | "/readyz" | "/api/status" | "/api/config" | "/api/connections"
+            | "/api/drain" | "/api/log-level",
+        ) => Response::error("405 Method Not Allowed", "method not allowed"),
+        _ => Response::error("404 Not Found", "not found"),
+    }
+}
+
+fn parse_request(head: &str) -> Option<Request<'_>> {
+    let head = RequestHead::parse(head)?;
+    let bearer = head.header("authorization").and_then(|value| {
+        value
+            .get(..7)
+            .filter(|scheme| scheme.eq_ignore_ascii_case("bearer "))
+            .map(|_| value[7..].trim())
+    });
+    Some(Request {
+        method: head.method,
+        path: head.path,
+        query: head.query,
+        bearer,
+    })
+}
+
+/// Percent-decoded value of query parameter `name`.
+fn query_param(query: &str, name: &str) -> Option<String> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| {
+            percent_decode_str(&value.replace('+', " "))
+                .decode_utf8_lossy()
+                .into_owned()
+        })
+}
+
+/// Constant-time token comparison.
+fn token_matches(given: &str, expected: &str) -> bool {
+    let (given, expected) = (given.as_bytes(), expected.as_bytes());
+    if expected.is_empty() || given.len() != expected.len() {
+        return false;
+    }
+    given
+        .iter()
+        .zip(expected)
+        .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+        == 0
+}
+
+/// Settings the node runs with, from every source (flags, environment,
+/// config file and defaults), credentials redacted.
+fn config_json(config: &Config) -> serde_json::Value {
+    let mut settings = serde_json::to_value(config).unwrap_or_default();
+    crate::redact::redact_json(&mut settings);
+    settings
+}
+
+fn connections_json(servers: &[Arc<ServerContext>]) -> serde_json::Value {
+    let connections: Vec<serde_json::Value> = servers
+        .iter()
+        .flat_map(|server| {
+            server.live_streams.list_json().into_iter().map(|mut conn| {
+                conn["server"] = server.server_label.clone().into();
+                conn
+            })
+        })
+        .collect();
+    serde_json::json!({ "connections": connections })
+}
+
+fn status_json(state: &AppState, servers: &[Arc<ServerContext>]) -> serde_json::Value {
+    let servers: Vec<serde_json::Value> = servers
+        .iter()
+        .map(|server| {
+            let totals = server.metrics.totals();
+            let avg_latency_ms =
+                totals.latency_ns.checked_div(totals.requests).unwrap_or(0) / 1_000_000;
+            serde_json::json!({
+                "label": server.server_label,
+                "aether_url": server.aether_url,
+                "aether_endpoint": server.aether_client.endpoint().1.base_url,
+                "node_id": *server.node_id.read().unwrap(),
+                "active_connections": server.active_connections.load(Ordering::Acquire),
+                "connected_tunnels": server.connected_tunnels.load(Ordering::Acquire),
+                "control_plane_degraded": server.control_plane.is_degraded(),
+                "control_plane_silent_secs": server.control_plane.silence().as_secs(),
+                "interference": server.interference.status_json(),
+                "requests": totals.requests,
+                "failed": totals.failed,
+                "dns_failures": totals.dns_failures,
+                "stream_errors": totals.stream_errors,
+                "upstream_resets": totals.upstream_resets,
+                "shed_requests": totals.shed_requests,
+                "stalled_streams": totals.stalled_streams,
+                "avg_latency_ms": avg_latency_ms,
+            })
+        })
+        .collect();
+
+    serde_json::json!({
+        "node_name": state.config.node_name,
+        "version": env!("CARGO_PKG_VERSION"),
+        "lifecycle": state.lifecycle.status_json(),
+        "load_shed_level": state.load_shedder.level() as u64,
+        "dns_cache": state.dns_cache.stats_json(),
+        "response_cache": state
+            .response_cache
+            .as_ref()
+            .map(|cache| cache.stats_json()),
+        "warm_pool": state.warm_pool.as_ref().map(|pool| pool.stats_json()),
+        "latency_probes": state.prober.as_ref().map(|prober| prober.stats_json()),
+        "upstream_proxies": state
+            .upstream_proxy
+            .as_ref()
+            .map(|routes| routes.status_json()),
+        "access_log_dropped": state.access_log.dropped(),
+        "clock_skew_ms": crate::clock::skew_ms(),
+        "servers": servers,
+        "sample_interval_secs": activity::SAMPLE_INTERVAL.as_secs(),
+        "history": state.activity.history(),
+        "errors": state.activity.errors(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_request() {
+        let req = parse_request(
+            "GET /api/status?t=1 HTTP/1.1\r\nHost: localhost\r\nauthorization: Bearer  s3cret \r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(
+            req,
+            Request {
+                method: "GET",
+                path: "/api/status",
+                query: "t=1",
+                bearer: Some("s3cret"),
+            }
+        );
+
+        let req =
+            parse_request("POST /api/drain HTTP/1.1\r\nAuthorization: Basic eA==\r\n\r\n").unwrap();
+        assert_eq!(req.bearer, None);
+        assert!(parse_request("").is_none());
+    }
+
+    #[test]
+    fn test_config_json() {
+        use clap::Parser;
+
+        let config = Config::try_parse_from([
+            "aether-proxy",
+            "--aether-url",
+            "https://aether.example",
+            "--management-token",
+            "ae_secret",
+            "--upstream-proxy",
+            "http://user:pw@proxy:8080",
+            "--max-hops",
+            "5",
+        ])
+        .unwrap();
+        let json = config_json(&config);
+        assert_eq!(json["aether_url"], "https://aether.example");
+        assert_eq!(json["management_token"], crate::redact::REDACTED);
+        assert_eq!(json["upstream_proxy"], "http://<redacted>@proxy:8080");
+        assert_eq!(json["max_hops"], 5);
+        assert_eq!(json["admin_listen"], serde_json::Value::Null);
+    }
+
+    #[test]
+    fn test_query_param() {
+        let query = "level=info%2Caether_proxy_core%3A%3Atunnel%3Dtrace&x=1";
+        assert_eq!(
+            query_param(query, "level").as_deref(),
+            Some("info,aether_proxy_core::tunnel=trace")
+        );
+        assert_eq!(
+            query_param("level=debug", "level").as_deref(),
+            Some("debug")
+        );
+        assert_eq!(query_param("levels=debug", "level"), None);
+        assert_eq!(query_param("", "level"), None);
+    }
+
+    #[test]
+    fn test_token_matches() {
+        assert!(token_matches("s3cret", "s3cret"));
+        assert!(!token_matches("s3cre", "s3cret"));
+        assert!(!token_matches("s3creT", "s3cret"));
+        assert!(!token_matches("", ""));
+    }
+}
@@ -0,0 +1,841 @@
+//! Application lifecycle: initialization, task orchestration, and shutdown.
+
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use arc_swap::{ArcSwap, ArcSwapOption};
+use tokio::signal;
+use tokio::sync::{watch, Mutex};
+use tracing::{error, info, warn};
+
+use crate::access_log::{self, AccessLog};
+use crate::circuit_breaker::CircuitBreaker;
+use crate::clock;
+use crate::config::{Config, ServerEntry};
+use crate::dns::{DnsResolver, EchLookup};
+use crate::error_report::{self, Event, Kind};
+use crate::lifecycle::{Lifecycle, Phase};
+use crate::load_shed::{self, LoadShedder};
+use crate::logging;
+use crate::metrics;
+use crate::net;
+use crate::probe::{self, Prober};
+use crate::registration::client::AetherClient;
+use crate::registration::{endpoint, ip_watch};
+use crate::response_cache::ResponseCache;
+use crate::runtime::{self, DynamicConfig};
+use crate::state::{AppState, ProxyMetrics, ServerContext};
+use crate::tunnel::headers::HopKey;
+use crate::tunnel::live_view::LiveStreams;
+use crate::upstream_client::{self, EgressBinding};
+use crate::warm_pool::{self, WarmPool};
+use crate::{
+    admin, destinations, handoff, hardware, systemd, target_filter, tunnel, upstream_proxy, usage,
+};
+
+/// Run the full application lifecycle after config has been parsed, until
+/// SIGINT/SIGTERM.
+pub async fn run(config: Config, servers: Vec<ServerEntry>) -> anyhow::Result<()> {
+    run_until(config, servers, wait_for_shutdown()).await
+}
+
+/// [`run`], shutting down (draining and unregistering) when `shutdown`
+/// completes instead of on a signal.  For embedding the relay.
+pub async fn run_until(
+    mut config: Config,
+    servers: Vec<ServerEntry>,
+    shutdown: impl Future<Output = ()>,
+) -> anyhow::Result<()> {
+    // No-op when the embedding process has installed a provider already
+    let _ = rustls::crypto::ring::default_provider().install_default();
+    config.validate()?;
+    init_tracing(&config)?;
+    let lifecycle = Arc::new(Lifecycle::new());
+
+    info!(
+        version = env!("CARGO_PKG_VERSION"),
+        node_name = %config.node_name,
+        server_count = servers.len(),
+        "aether-proxy starting (tunnel mode)"
+    );
+    if let Some(pid) = handoff::predecessor() {
+        info!(predecessor = pid, "taking over from previous process");
+    }
+    if config.clock_sync {
+        clock::enable_correction();
+    }
+
+    // Resolve public IPv4/IPv6 addresses (best-effort for region info)
+    let ip_sources = config.ip_sources()?;
+    let public_addrs =
+        net::detect_public_addrs(config.public_ip.as_deref(), config.public_ipv6, &ip_sources)
+            .await;
+
+    // Auto-detect region if not configured
+    if config.node_region.is_none() {
+        if let Some(region) = net::detect_region(&public_addrs.primary).await {
+            config.node_region = Some(region);
+        }
+    }
+    error_report::init(&config)?;
+
+    // Collect hardware info (once at startup, sent during registration)
+    let hw_info = hardware::collect();
+
+    // Auto-detect tunnel_max_streams from hardware if not explicitly set
+    if config.tunnel_max_streams.is_none() {
+        let auto = (hw_info.estimated_max_concurrency / 10).clamp(64, 1024) as u32;
+        config.tunnel_max_streams = Some(auto);
+        info!(
+            tunnel_max_streams = auto,
+            "auto-detected tunnel_max_streams from hardware"
+        );
+    }
+
+    info!(
+        max_concurrency = hw_info.estimated_max_concurrency,
+        "hardware info collected"
+    );
+
+    let dns_resolver = DnsResolver::build(&config.dns_settings()?)
+        .await
+        .map_err(|e| anyhow::anyhow!("dns: {}", e))?;
+    if matches!(dns_resolver, DnsResolver::Custom(_)) {
+        info!(
+            mode = %config.dns_mode,
+            servers = ?config.dns_servers,
+            url = ?config.dns_url,
+            "using custom DNS resolver for targets"
+        );
+    }
+    let host_overrides = config.host_overrides()?;
+    if !host_overrides.is_empty() {
+        info!(overrides = %host_overrides.describe(), "static host overrides enabled");
+    }
+    let ech = if config.upstream_ech {
+        info!("encrypted client hello enabled for HTTPS targets");
+        Some(EchLookup::new(&dns_resolver).map_err(|e| anyhow::anyhow!("ech: {}", e))?)
+    } else {
+        None
+    };
+    let mut dns_cache = target_filter::DnsCache::new(
+        Duration::from_secs(config.dns_cache_ttl_secs),
+        Duration::from_secs(config.dns_negative_ttl_secs),
+        config.dns_cache_capacity,
+        dns_resolver,
+    )
+    .with_host_overrides(host_overrides);
+    if let Some(ech) = ech {
+        dns_cache = dns_cache.with_ech(ech);
+    }
+    let dns_cache = Arc::new(dns_cache);
+
+    let address_policy = Arc::new(build_address_policy(&config, &servers, &public_addrs));
+    resolve_control_plane(&address_policy, &servers).await;
+
+    // Build Hyper client for tunnel upstream requests (shared).
+    // DNS still flows through validated addresses from DnsCache, while the
+    // custom connector exposes per-request connect/TLS timing when available.
+    let egress = config.egress_binding()?;
+    if egress != EgressBinding::default() {
+        info!(
+            ipv4 = ?egress.ipv4,
+            ipv6 = ?egress.ipv6,
+            interface = ?egress.interface,
+            "upstream egress binding enabled"
+        );
+    }
+    let upstream_proxy = config.upstream_proxy()?.map(Arc::new);
+    if let Some(routes) = &upstream_proxy {
+        info!(routes = %routes.describe(), "upstream proxy routing enabled");
+    }
+    let warm_pool = WarmPool::from_config(&config).map(Arc::new);
+    // Dials the hot target connections handed out by the primary client
+    let warm_connector = warm_pool.as_ref().map(|_| {
+        upstream_client::build_connector(
+            &config,
+            Arc::clone(&dns_cache),
+            Arc::clone(&address_policy),
+            &egress,
+            upstream_proxy.clone(),
+            false,
+        )
+    });
+    let prober = (config.probe_interval_secs > 0).then(|| {
+        let connector = upstream_client::build_connector(
+            &config,
+            Arc::clone(&dns_cache),
+            Arc::clone(&address_policy),
+            &egress,
+            upstream_proxy.clone(),
+            false,
+        );
+        (Arc::new(Prober::default()), connector)
+    });
+    let upstream_client = upstream_client::build_upstream_client(
+        &config,
+        Arc::clone(&dns_cache),
+        Arc::clone(&address_policy),
+        &egress,
+        upstream_proxy.clone(),
+        false,
+        warm_pool.clone(),
+    );
+    let upstream_upgrade_client = upstream_client::build_upstream_client(
+        &config,
+        Arc::clone(&dns_cache),
+        Arc::clone(&address_policy),
+        &egress,
+        upstream_proxy.clone(),
+        true,
+        None,
+    );
+    let upstream_alt_client = config.egress_alt_bind_ip.map(|ip| {
+        info!(bind_ip = %ip, "alternate egress enabled for reset retries");
+        upstream_client::build_upstream_client(
+            &config,
+            Arc::clone(&dns_cache),
+            Arc::clone(&address_policy),
+            &EgressBinding::from_ips(&[ip]).unwrap_or_default(),
+            upstream_proxy.clone(),
+            false,
+            None,
+        )
+    });
+
+    // Register with each Aether server and build per-server contexts.
+    let aether_tls_config = Arc::new(config.aether_tls()?);
+    lifecycle.advance(Phase::Registering);
+    // Wrapped in Arc<Mutex> so retry_failed_registrations can append later.
+    let server_contexts: Arc<Mutex<Vec<Arc<ServerContext>>>> = Arc::new(Mutex::new(Vec::new()));
+    let mut failed_entries: Vec<(String, ServerEntry)> = Vec::new();
+    for (i, entry) in servers.iter().enumerate() {
+        let label = if servers.len() == 1 {
+            "server".to_string()
+        } else {
+            format!("server-{}", i)
+        };
+        let node_name = entry
+            .node_name
+            .clone()
+            .unwrap_or_else(|| config.node_name.clone());
+        let client = Arc::new(AetherClient::new(
+            &config,
+            &aether_tls_config,
+            &entry.aether_url,
+            &entry.management_token,
+        ));
+        match client
+            .register(&config, &node_name, &public_addrs, Some(&hw_info))
+            .await
+        {
+            Ok(node_id) => {
+                info!(server = %label, node_id = %node_id, url = %entry.aether_url, node_name = %node_name, "registered");
+                // Initialize dynamic config with per-server node_name (not global),
+                // so that the heartbeat and reconnect use the correct name.
+                let mut dynamic = DynamicConfig::from_config(&config);
+                dynamic.node_name = node_name.clone();
+                server_contexts.lock().await.push(Arc::new(ServerContext {
+                    server_label: label,
+                    aether_url: entry.aether_url.clone(),
+                    management_token: entry.management_token.clone(),
+                    hop_key: HopKey::new(&entry.management_token),
+                    node_name,
+                    node_id: Arc::new(RwLock::new(node_id)),
+                    aether_client: client,
+                    dynamic: Arc::new(ArcSwap::from_pointee(dynamic)),
+                    active_connections: Arc::new(AtomicU64::new(0)),
+                    connected_tunnels: Arc::new(AtomicU64::new(0)),
+                    metrics: Arc::new(ProxyMetrics::new()),
+                    live_streams: Arc::new(LiveStreams::default()),
+                    usage: Arc::default(),
+                    quotas: Arc::default(),
+                    client_streams: Arc::default(),
+                    destinations: Arc::default(),
+                    control_plane: Arc::default(),
+                    interference: Arc::default(),
+                }));
+            }
+            Err(e) => {
+                warn!(
+                    server = %label,
+                    url = %entry.aether_url,
+                    error = %e,
+                    "registration failed, will retry in background"
+                );
+                error_report::report(
+                    Event::new(
+                        Kind::RegistrationFailed,
+                        format!("registration with {} failed: {}", entry.aether_url, e),
+                    )
+                    .server(&label, None),
+                );
+                failed_entries.push((label, entry.clone()));
+            }
+        }
+    }
+
+    {
+        let ctx_count = server_contexts.lock().await.len();
+        if ctx_count == 0 && failed_entries.is_empty() {
+            anyhow::bail!("no servers configured");
+        }
+        if ctx_count == 0 {
+            // The process is about to exit, so wait for delivery
+            error_report::report_now(Event::new(
+                Kind::RegistrationFailed,
+                "no server registered, exiting",
+            ))
+            .await;
+            anyhow::bail!(
+                "no servers registered successfully (all {} failed)",
+                failed_entries.len()
+            );
+        }
+    }
+
+    // Build shared application state
+    let load_shedder = Arc::new(LoadShedder::from_config(&config));
+    let circuit_breaker = CircuitBreaker::from_config(&config);
+    let response_cache = ResponseCache::from_config(&config);
+    let live_view_sealer = ArcSwapOption::from(config.live_view_sealer()?.map(Arc::new));
+    let anonymity = config.anonymity()?;
+    let relay_hosts = config.relay_hosts()?;
+    let access_log_sink = config.access_log_sink()?;
+    let (public_addrs_tx, public_addrs_rx) = watch::channel(public_addrs);
+    let (access_log, access_log_rx) = AccessLog::new(&access_log_sink);
+    let privacy = config.log_privacy()?;
+    let state = Arc::new(AppState {
+        config: Arc::new(config),
+        dns_cache,
+        address_policy,
+        upstream_client,
+        upstream_upgrade_client,
+        upstream_alt_client,
+        egress: egress.clone(),
+        upstream_proxy,
+        anonymity,
+        relay_hosts,
+        load_shedder,
+        circuit_breaker,
+        response_cache,
+        warm_pool: warm_pool.clone(),
+        prober: prober.as_ref().map(|(prober, _)| Arc::clone(prober)),
+        lifecycle: Arc::clone(&lifecycle),
+        live_view_sealer,
+        activity: Arc::default(),
+        access_log,
+        privacy,
+        aether_tls_config,
+        public_addrs: public_addrs_rx,
+        hw_info,
+    });
+
+    // Shutdown signal channel
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+
+    load_shed::spawn_sampler(Arc::clone(&state.load_shedder), shutdown_rx.clone());
+    spawn_control_plane_resolver(
+        Arc::clone(&state.address_policy),
+        servers.clone(),
+        shutdown_rx.clone(),
+    );
+    systemd::spawn_watchdog(Arc::clone(&lifecycle));
+    if state.config.usage_report_interval_secs > 0 {
+        usage::spawn_reporter(
+            Arc::clone(&server_contexts),
+            Duration::from_secs(state.config.usage_report_interval_secs),
+            shutdown_rx.clone(),
+        );
+    }
+    if state.config.destination_report_interval_secs > 0 {
+        destinations::spawn_reporter(
+            Arc::clone(&server_contexts),
+            Duration::from_secs(state.config.destination_report_interval_secs),
+            state.config.destination_report_top,
+            shutdown_rx.clone(),
+        );
+    }
+    if let Some(rx) = access_log_rx {
+        access_log::spawn(
+            access_log_sink,
+            rx,
+            Arc::clone(&server_contexts),
+            shutdown_rx.clone(),
+        );
+    }
+    if let Some(routes) = &state.upstream_proxy {
+        if state.config.upstream_proxy_health_interval_secs > 0 {
+            upstream_proxy::pool::spawn_health_checks(
+                Arc::clone(routes),
+                upstream_client::proxy_connector(&state.config, &egress),
+                Duration::from_secs(state.config.upstream_proxy_health_interval_secs),
+                Duration::from_secs(state.config.upstream_handshake_timeout_secs),
+                shutdown_rx.clone(),
+            );
+        }
+    }
+    if let (Some(pool), Some(connector)) = (warm_pool, warm_connector) {
+        warm_pool::spawn_refill(
+            pool,
+            connector,
+            Arc::clone(&server_contexts),
+            shutdown_rx.clone(),
+        );
+    }
+    if let Some((prober, connector)) = prober {
+        probe::spawn(
+            prober,
+            connector,
+            Arc::clone(&server_contexts),
+            Duration::from_secs(state.config.probe_interval_secs),
+            shutdown_rx.clone(),
+        );
+    }
+    if let Some(backend) = metrics::build(&state.config, shutdown_rx.clone()).await? {
+        metrics::spawn_reporter(
+            backend,
+            Arc::clone(&state),
+            Arc::clone(&server_contexts),
+            shutdown_rx.clone(),
+        );
+    }
+
+    admin::spawn(
+        Arc::clone(&state),
+        Arc::clone(&server_contexts),
+        shutdown_rx.clone(),
+    )
+    .await?;
+
+    let active_servers = server_contexts.lock().await.len();
+    info!(active_servers, "running in tunnel mode");
+
+    // Spawn tunnel connections per server (pool_size connections each)
+    let pool_size = state.config.tunnel_connections.max(1) as usize;
+    let mut tunnel_handles = Vec::new();
+    for server in server_contexts.lock().await.iter() {
+        for conn_idx in 0..pool_size {
+            let s = Arc::clone(&state);
+            let srv = Arc::clone(server);
+            let rx = shutdown_rx.clone();
+            tunnel_handles.push(tokio::spawn(async move {
+                tunnel::run(&s, &srv, conn_idx, rx).await;
+            }));
+        }
+    }
+
+    lifecycle.set_pending_registrations(failed_entries.len());
+    lifecycle.advance(Phase::Serving);
+    systemd::ready();
+    if handoff::predecessor().is_some() {
+        spawn_handoff_ready(Arc::clone(&server_contexts), shutdown_rx.clone());
+    }
+
+    // Keep registrations in step with the public address
+    if state.config.public_ip.is_none() && state.config.public_ip_check_interval_secs > 0 {
+        ip_watch::spawn(
+            Arc::clone(&state),
+            Arc::clone(&server_contexts),
+            public_addrs_tx,
+            ip_sources,
+            Duration::from_secs(state.config.public_ip_check_interval_secs),
+            shutdown_rx.clone(),
+        );
+    }
+
+    // Spawn background retry for failed server registrations
+    if !failed_entries.is_empty() {
+        let retry_state = Arc::clone(&state);
+        let retry_contexts = Arc::clone(&server_contexts);
+        let retry_shutdown = shutdown_rx.clone();
+        let retry_pool_size = pool_size;
+        tokio::spawn(async move {
+            retry_failed_registrations(
+                retry_state,
+                retry_contexts,
+                failed_entries,
+                retry_pool_size,
+                retry_shutdown,
+            )
+            .await;
+        });
+    }
+
+    // Wait for a shutdown signal, a drain request from the admin API, or a
+    // handoff to a successor process
+    let overlap = Duration::from_secs(state.config.handoff_overlap_secs);
+    let mut shutdown = std::pin::pin!(shutdown);
+    let handed_off = loop {
+        tokio::select! {
+            _ = &mut shutdown => break false,
+            _ = lifecycle.drain_requested() => break false,
+            _ = handoff::requested() => {
+                info!(overlap_secs = overlap.as_secs(), "handoff requested, starting successor");
+                match handoff::spawn_successor(overlap).await {
+                    Ok(pid) => {
+                        info!(successor = pid, "successor is serving, handing off");
+                        break true;
+                    }
+                    Err(e) => error!(error = %e, "handoff failed, continuing to serve"),
+                }
+            }
+        }
+    };
+    info!("shutdown signal received, cleaning up...");
+    lifecycle.advance(Phase::Draining);
+    if !handed_off {
+        systemd::stopping();
+    }
+    let _ = shutdown_tx.send(true);
+
+    // Graceful unregister from all servers (including retry-registered ones).
+    // After a handoff the successor is serving under the same node IDs.
+    let servers = if handed_off {
+        Vec::new()
+    } else {
+        server_contexts.lock().await.clone()
+    };
+    for server in &servers {
+        let node_id = server.node_id.read().unwrap().clone();
+        if let Err(e) = server.aether_client.unregister(&node_id).await {
+            error!(
+                server = %server.server_label,
+                error = %e,
+                "unregister failed during shutdown"
+            );
+        }
+    }
+
+    // Wait for all tunnel tasks
+    for h in tunnel_handles {
+        let _ = h.await;
+    }
+
+    lifecycle.advance(Phase::Stopped);
+    info!("aether-proxy stopped");
+    Ok(())
+}
+
+/// Retry interval for failed server registrations (5 minutes).
+const REGISTRATION_RETRY_INTERVAL: Duration = Duration::from_secs(300);
+/// Max registration retry attempts before giving up.
+const REGISTRATION_RETRY_MAX: u32 = 12;
+
+/// Background task that retries registration for servers that failed at startup.
+async fn retry_failed_registrations(
+    state: Arc<AppState>,
+    server_contexts: Arc<Mutex<Vec<Arc<ServerContext>>>>,
+    failed: Vec<(String, ServerEntry)>,
+    pool_size: usize,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    for (label, entry) in &failed {
+        let node_name = entry
+            .node_name
+            .clone()
+            .unwrap_or_else(|| state.config.node_name.clone());
+        let client = Arc::new(AetherClient::new(
+            &state.config,
+            &state.aether_tls_config,
+            &entry.aether_url,
+            &entry.management_token,
+        ));
+
+        let mut attempt = 0u32;
+        let node_id = loop {
+            attempt += 1;
+
+            tokio::select! {
+                _ = tokio::time::sleep(REGISTRATION_RETRY_INTERVAL) => {}
+                _ = shutdown.changed() => {
+                    info!(server = %label, "shutdown during registration retry");
+                    return;
+                }
+            }
+
+            let addrs = state.public_addrs.borrow().clone();
+            match client
+                .register(&state.config, &node_name, &addrs, Some(&state.hw_info))
+                .await
+            {
+                Ok(id) => {
+                    info!(server = %label, node_id = %id, attempt, "registration retry succeeded");
+                    state.lifecycle.registration_resolved();
+                    break id;
+                }
+                Err(e) => {
+                    warn!(
+                        server = %label,
+                        attempt,
+                        max = REGISTRATION_RETRY_MAX,
+                        error = %e,
+                        "registration retry failed"
+                    );
+                    if attempt >= REGISTRATION_RETRY_MAX {
+                        error!(server = %label, "giving up registration after {} attempts", attempt);
+                        error_report::report(
+                            Event::new(
+                                Kind::RegistrationFailed,
+                                format!(
+                                    "gave up registering with {} after {} attempts: {}",
+                                    entry.aether_url, attempt, e
+                                ),
+                            )
+                            .server(label, None),
+                        );
+                        state.lifecycle.registration_resolved();
+                        return;
+                    }
+                }
+            }
+        };
+
+        // Build server context and spawn tunnels
+        let mut dynamic = DynamicConfig::from_config(&state.config);
+        dynamic.node_name = node_name.clone();
+        let server = Arc::new(ServerContext {
+            server_label: label.clone(),
+            aether_url: entry.aether_url.clone(),
+            management_token: entry.management_token.clone(),
+            hop_key: HopKey::new(&entry.management_token),
+            node_name,
+            node_id: Arc::new(RwLock::new(node_id)),
+            aether_client: client,
+            dynamic: Arc::new(ArcSwap::from_pointee(dynamic)),
+            active_connections: Arc::new(AtomicU64::new(0)),
+            connected_tunnels: Arc::new(AtomicU64::new(0)),
+            metrics: Arc::new(ProxyMetrics::new()),
+            live_streams: Arc::new(LiveStreams::default()),
+            usage: Arc::default(),
+            quotas: Arc::default(),
+            client_streams: Arc::default(),
+            destinations: Arc::default(),
+            control_plane: Arc::default(),
+            interference: Arc::default(),
+        });
+
+        // Add to shared list so shutdown can unregister this server
+        server_contexts.lock().await.push(Arc::clone(&server));
+
+        for conn_idx in 0..pool_size {
+            let s = Arc::clone(&state);
+            let srv = Arc::clone(&server);
+            let rx = shutdown.clone();
+            tokio::spawn(async move {
+                tunnel::run(&s, &srv, conn_idx, rx).await;
+            });
+        }
+    }
+}
+
+/// How often the control-plane hosts are re-resolved for the address policy.
+const CONTROL_PLANE_RESOLVE_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Collect the addresses a tunnel target must never point at: this node's
+/// own public/interface addresses and the Aether control plane.
+///
+/// Control-plane hosts are blocked by name here; the addresses they resolve
+/// to are added by [`resolve_control_plane`].  IP-literal URLs are blocked
+/// by address.
+pub fn build_address_policy(
+    config: &Config,
+    servers: &[ServerEntry],
+    public_addrs: &net::PublicAddrs,
+) -> target_filter::AddressPolicy {
+    let mut policy = target_filter::AddressPolicy::new(config.allow_private_targets);
+    if config.allow_private_targets {
+        warn!("private/reserved target addresses are allowed; do not use in production");
+    }
+
+    for ip in public_addrs.ips() {
+        policy.block_ip(ip);
+    }
+    for ip in config
+        .egress_bind_ip
+        .iter()
+        .chain(&config.egress_alt_bind_ip)
+    {
+        policy.block_ip(*ip);
+    }
+    for ip in net::local_interface_addrs() {
+        policy.block_ip(ip);
+    }
+    for host in control_plane_hosts(servers) {
+        policy.block_host(&host);
+    }
+    policy
+}
+
+/// Host of every configured control-plane URL.
+fn control_plane_hosts(servers: &[ServerEntry]) -> Vec<String> {
+    let mut hosts: Vec<String> = servers
+        .iter()
+        .flat_map(|entry| endpoint::split_urls(&entry.aether_url))
+        .filter_map(|url| {
+            url::Url::parse(url)
+                .ok()
+                .and_then(|u| u.host_str().map(str::to_string))
+        })
+        .collect();
+    hosts.sort();
+    hosts.dedup();
+    hosts
+}
+
+/// Block the addresses the control-plane hosts currently resolve to, so a
+/// target can't reach Aether by a raw IP or another name for the same
+/// address.  A host that fails to resolve keeps its previous addresses.
+pub async fn resolve_control_plane(policy: &target_filter::AddressPolicy, servers: &[ServerEntry]) {
+    for host in control_plane_hosts(servers) {
+        let literal = host.trim_start_matches('[').trim_end_matches(']');
+        match tokio::net::lookup_host((literal, 0)).await {
+            Ok(addrs) => policy.set_control_plane_ips(&host, addrs.map(|addr| addr.ip())),
+            Err(e) => warn!(host, error = %e, "failed to resolve control-plane host"),
+        }
+    }
+}
+
+/// Re-resolve the control-plane hosts every
+/// [`CONTROL_PLANE_RESOLVE_INTERVAL`] until shutdown.
+fn spawn_control_plane_resolver(
+    policy: Arc<target_filter::AddressPolicy>,
+    servers: Vec<ServerEntry>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(CONTROL_PLANE_RESOLVE_INTERVAL);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        ticker.tick().await;
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                _ = shutdown.changed() => return,
+            }
+            resolve_control_plane(&policy, &servers).await;
+        }
+    });
+}
+
+/// After a handoff, tell the previous process to drain once a tunnel is up.
+fn spawn_handoff_ready(
+    servers: Arc<Mutex<Vec<Arc<ServerContext>>>>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_millis(200));
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                _ = shutdown.changed() => return,
+            }
+            let connected = servers
+                .lock()
+                .await
+                .iter()
+                .any(|s| s.connected_tunnels.load(Ordering::Acquire) > 0);
+            if connected {
+                info!("tunnel up, reporting readiness to the previous process");
+                handoff::notify_ready();
+                return;
+            }
+        }
+    });
+}
+
+fn init_tracing(config: &Config) -> anyhow::Result<()> {
+    use tracing_subscriber::prelude::*;
+    use tracing_subscriber::{reload, EnvFilter};
+
+    let filter = EnvFilter::try_new(&config.log_level).unwrap_or_else(|_| EnvFilter::new("info"));
+
+    let (filter_layer, reload_handle) = reload::Layer::new(filter);
+
+    runtime::set_log_reloader(Box::new(move |level: &str| {
+        if let Ok(new_filter) = EnvFilter::try_new(level) {
+            let _ = reload_handle.modify(|f| *f = new_filter);
+        }
+    }));
+
+    let mut layers = Vec::new();
+    for output in config.log_outputs()? {
+        let layer = match output {
+            logging::Output::Stdout => output_layer(config.log_json, std::io::stdout, true),
+            logging::Output::File(path) => {
+                let file = logging::RollingFile::open(&path, config.log_rotation())
+                    .map_err(|e| anyhow::anyhow!("log file {}: {}", path.display(), e))?;
+                output_layer(config.log_json, logging::SinkWriter::file(file), false)
+            }
+            #[cfg(unix)]
+            logging::Output::Syslog => output_layer(
+                config.log_json,
+                logging::SinkWriter::datagram(logging::Datagram::syslog()?),
+                false,
+            ),
+            #[cfg(unix)]
+            logging::Output::Journald => output_layer(
+                config.log_json,
+                logging::SinkWriter::datagram(logging::Datagram::journald()?),
+                false,
+            ),
+            #[cfg(not(unix))]
+            _ => unreachable!("rejected by Output::parse"),
+        };
+        layers.push(layer);
+    }
+
+    // An embedding process may have installed its own subscriber already.
+    let _ = tracing_subscriber::registry()
+        .with(filter_layer)
+        .with(layers)
+        .try_init();
+    Ok(())
+}
+
+/// A formatting layer writing to `writer`, colored if `ansi`.
+fn output_layer<S, W>(
+    json: bool,
+    writer: W,
+    ansi: bool,
+) -> Box<dyn tracing_subscriber::Layer<S> + Send + Sync>
+where
+    S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+    W: for<'w> tracing_subscriber::fmt::MakeWriter<'w> + Send + Sync + 'static,
+{
+    use tracing_subscriber::Layer;
+
+    let layer = tracing_subscriber::fmt::layer()
+        .with_writer(writer)
+        .with_ansi(ansi);
+    if json {
+        layer.json().boxed()
+    } else {
+        layer.boxed()
+    }
+}
+
+async fn wait_for_shutdown() {
+    let ctrl_c = async {
+        signal::ctrl_c()
+            .await
+            .expect("failed to install Ctrl+C handler");
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        signal::unix::signal(signal::unix::SignalKind::terminate())
+            .expect("failed to install SIGTERM handler")
+            .recv()
+            .await;
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
@@ -0,0 +1,250 @@
+//! Per-target circuit breaker.
+//!
+//! A target (`host:port`) that fails `--circuit-breaker-failures` times in
+//! a row (connect or TLS error, including their timeouts; a slow response
+//! is not a failure, 5xx responses only with `--circuit-breaker-count-5xx`)
+//! is opened for
+//! `--circuit-breaker-open-secs`: streams to it are answered with 502 and
+//! `Retry-After` right away instead of spending sockets and latency on a
+//! dead or blocked origin.  Once the period is over exactly one trial
+//! request goes through while the rest are still refused; a success closes
+//! the circuit, a failure opens it again.  A trial that ends without
+//! reaching the target is handed back with [`CircuitBreaker::release`];
+//! one that never reports back is given up on after another open period.
+//!
+//! Off unless the `circuit_breaker` feature flag is enabled.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+
+/// Targets tracked at most; beyond that, new targets are not tracked until
+/// closed circuits have been pruned.
+const MAX_TARGETS: usize = 4096;
+
+#[derive(Default)]
+struct TargetState {
+    failures: u32,
+    open_until: Option<Instant>,
+    /// Set while the half-open trial request is in flight.
+    trial_until: Option<Instant>,
+}
+
+/// How [`CircuitBreaker::check`] let a request through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Admission {
+    /// The circuit is closed.
+    Closed,
+    /// The request is the half-open trial; its outcome decides the circuit.
+    Trial,
+}
+
+pub struct CircuitBreaker {
+    /// Consecutive failures that open the circuit; 0 disables the breaker.
+    threshold: u32,
+    open_for: Duration,
+    /// Whether 5xx responses count as failures.
+    count_server_errors: bool,
+    targets: Mutex<HashMap<String, TargetState>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, open_for: Duration) -> Self {
+        Self {
+            threshold,
+            open_for,
+            count_server_errors: false,
+            targets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            count_server_errors: config.circuit_breaker_count_5xx,
+            ..Self::new(
+                config.circuit_breaker_failures,
+                Duration::from_secs(config.circuit_breaker_open_secs),
+            )
+        }
+    }
+
+    /// Whether a response with `status` counts as a failure of its target.
+    pub fn is_failure(&self, status: hyper::StatusCode) -> bool {
+        self.count_server_errors && status.is_server_error()
+    }
+
+    /// Whether a request to `target` may go ahead; `Err` carries the time
+    /// left until the circuit lets a trial request through.
+    pub fn check(&self, target: &str) -> Result<Admission, Duration> {
+        if self.threshold == 0 {
+            return Ok(Admission::Closed);
+        }
+        let mut targets = self.targets.lock().unwrap();
+        let Some(state) = targets.get_mut(target) else {
+            return Ok(Admission::Closed);
+        };
+        let Some(until) = state.open_until else {
+            return Ok(Admission::Closed);
+        };
+        let now = Instant::now();
+        if now < until {
+            return Err(until - now);
+        }
+        // Half-open: only one trial request at a time
+        if let Some(trial_until) = state.trial_until.filter(|t| now < *t) {
+            return Err(trial_until - now);
+        }
+        state.trial_until = Some(now + self.open_for);
+        Ok(Admission::Trial)
+    }
+
+    /// Hand back the trial [`Self::check`] gave out for `target` when the
+    /// request ended without trying the target (refused locally, answered
+    /// from the cache, cancelled), so the next request can take it.
+    pub fn release(&self, target: &str) {
+        if let Some(state) = self.targets.lock().unwrap().get_mut(target) {
+            state.trial_until = None;
+        }
+    }
+
+    /// Record the outcome of a request to `target`.  Returns true when this
+    /// failure opened the circuit.
+    pub fn record(&self, target: &str, ok: bool) -> bool {
+        if self.threshold == 0 {
+            return false;
+        }
+        let mut targets = self.targets.lock().unwrap();
+        if ok {
+            targets.remove(target);
+            return false;
+        }
+        if !targets.contains_key(target) && targets.len() >= MAX_TARGETS {
+            let now = Instant::now();
+            targets.retain(|_, state| state.open_until.is_some_and(|until| until > now));
+            if targets.len() >= MAX_TARGETS {
+                return false;
+            }
+        }
+        let state = targets.entry(target.to_string()).or_default();
+        state.failures += 1;
+        // A failed trial reopens the circuit
+        if state.trial_until.is_some()
+            || (state.failures >= self.threshold && state.open_until.is_none())
+        {
+            state.open_until = Some(Instant::now() + self.open_for);
+            state.trial_until = None;
+            return true;
+        }
+        false
+    }
+
+    /// Targets whose circuit is currently open.
+    pub fn open_targets(&self) -> usize {
+        let now = Instant::now();
+        self.targets
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|state| state.open_until.is_some_and(|until| until > now))
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opens_after_consecutive_failures() {
+        let breaker = CircuitBreaker::new(3, Duration::from_secs(30));
+        assert!(!breaker.record("a:443", false));
+        assert!(!breaker.record("a:443", false));
+        // A success in between starts over
+        breaker.record("a:443", true);
+        assert!(!breaker.record("a:443", false));
+        assert!(!breaker.record("a:443", false));
+        assert!(breaker.check("a:443").is_ok());
+        assert!(breaker.record("a:443", false));
+
+        let retry_after = breaker.check("a:443").unwrap_err();
+        assert!(retry_after <= Duration::from_secs(30));
+        assert!(breaker.check("b:443").is_ok());
+        assert_eq!(breaker.open_targets(), 1);
+    }
+
+    #[test]
+    fn test_half_open_trial() {
+        let open_for = Duration::from_millis(50);
+        let breaker = CircuitBreaker::new(2, open_for);
+        breaker.record("a:80", false);
+        assert!(breaker.record("a:80", false));
+        assert!(breaker.check("a:80").is_err());
+
+        // Open period over: exactly one trial goes through, its failure
+        // reopens the circuit
+        std::thread::sleep(open_for);
+        assert!(breaker.check("a:80").is_ok());
+        assert!(breaker.check("a:80").is_err());
+        assert!(breaker.record("a:80", false));
+        assert!(breaker.check("a:80").is_err());
+
+        // A successful trial closes it for everyone
+        std::thread::sleep(open_for);
+        assert!(breaker.check("a:80").is_ok());
+        breaker.record("a:80", true);
+        assert!(breaker.check("a:80").is_ok());
+        assert!(breaker.check("a:80").is_ok());
+        assert!(!breaker.record("a:80", false));
+    }
+
+    #[test]
+    fn test_abandoned_trial_is_replaced() {
+        let open_for = Duration::from_millis(50);
+        let breaker = CircuitBreaker::new(1, open_for);
+        assert!(breaker.record("a:80", false));
+        std::thread::sleep(open_for);
+        assert!(breaker.check("a:80").is_ok());
+        assert!(breaker.check("a:80").is_err());
+        // The trial never reported back
+        std::thread::sleep(open_for);
+        assert!(breaker.check("a:80").is_ok());
+    }
+
+    #[test]
+    fn test_released_trial_is_given_out_again() {
+        let open_for = Duration::from_millis(50);
+        let breaker = CircuitBreaker::new(1, open_for);
+        assert!(breaker.record("a:80", false));
+        std::thread::sleep(open_for);
+        assert_eq!(breaker.check("a:80"), Ok(Admission::Trial));
+        assert!(breaker.check("a:80").is_err());
+        // The trial was refused before reaching the target
+        breaker.release("a:80");
+        assert_eq!(breaker.check("a:80"), Ok(Admission::Trial));
+        assert!(breaker.record("a:80", false));
+        assert!(breaker.check("a:80").is_err());
+    }
+
+    #[test]
+    fn test_server_errors_opt_in() {
+        let breaker = CircuitBreaker::new(1, Duration::from_secs(30));
+        assert!(!breaker.is_failure(hyper::StatusCode::BAD_GATEWAY));
+        let counting = CircuitBreaker {
+            count_server_errors: true,
+            ..CircuitBreaker::new(1, Duration::from_secs(30))
+        };
+        assert!(counting.is_failure(hyper::StatusCode::BAD_GATEWAY));
+        assert!(!counting.is_failure(hyper::StatusCode::NOT_FOUND));
+    }
+
+    #[test]
+    fn test_disabled() {
+        let breaker = CircuitBreaker::new(0, Duration::from_secs(30));
+        for _ in 0..10 {
+            assert!(!breaker.record("a:443", false));
+        }
+        assert!(breaker.check("a:443").is_ok());
+    }
+}
@@ -0,0 +1,175 @@
+//! Clock skew against the control plane.
+//!
+//! Cheap VPSes drift.  Every response from Aether (API calls and the tunnel
+//! handshake) carries a `Date` header; the node compares it with its own
+//! clock, keeps the median of recent samples as the skew, reports it
+//! (admin status, `clock_skew_seconds` metric) and warns when it exceeds
+//! [`WARN_SKEW_MS`].  With `--clock-sync`, [`now_ms`] (timestamps of access
+//! records, usage periods and quota days) is corrected by the skew.
+//!
+//! `Date` has one-second resolution and includes the response latency, so
+//! the estimate is only good to about a second; it is meant to catch
+//! drift, not to replace NTP.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tracing::{info, warn};
+
+/// Skew beyond which the node warns.
+pub const WARN_SKEW_MS: i64 = 30_000;
+/// Samples the median is taken over.
+const SAMPLES: usize = 15;
+
+static SAMPLES_MS: Mutex<VecDeque<i64>> = Mutex::new(VecDeque::new());
+static SKEW_MS: AtomicI64 = AtomicI64::new(0);
+static OBSERVED: AtomicBool = AtomicBool::new(false);
+static WARNED: AtomicBool = AtomicBool::new(false);
+static CORRECT: AtomicBool = AtomicBool::new(false);
+
+/// Apply the learned skew to [`now_ms`].
+pub fn enable_correction() {
+    CORRECT.store(true, Ordering::Relaxed);
+}
+
+/// Unix time in milliseconds, corrected by the learned skew when
+/// `--clock-sync` is on.
+pub fn now_ms() -> u64 {
+    let local = local_ms();
+    if CORRECT.load(Ordering::Relaxed) {
+        local.saturating_add_signed(SKEW_MS.load(Ordering::Relaxed))
+    } else {
+        local
+    }
+}
+
+/// Control-plane time minus local time (median of recent samples), once
+/// any response has been seen.
+pub fn skew_ms() -> Option<i64> {
+    OBSERVED
+        .load(Ordering::Relaxed)
+        .then(|| SKEW_MS.load(Ordering::Relaxed))
+}
+
+/// Take a sample from an Aether response's `Date` header.
+pub fn observe_date(date: Option<&str>) {
+    let Some(server_secs) = date.and_then(parse_http_date) else {
+        return;
+    };
+    // The header truncates to the second: assume mid-second.
+    let sample = (server_secs * 1000 + 500) as i64 - local_ms() as i64;
+    let skew = {
+        let mut samples = SAMPLES_MS.lock().unwrap();
+        if samples.len() == SAMPLES {
+            samples.pop_front();
+        }
+        samples.push_back(sample);
+        median(samples.iter().copied())
+    };
+    SKEW_MS.store(skew, Ordering::Relaxed);
+    OBSERVED.store(true, Ordering::Relaxed);
+
+    let drifting = skew.abs() > WARN_SKEW_MS;
+    if drifting && !WARNED.swap(true, Ordering::Relaxed) {
+        warn!(
+            skew_ms = skew,
+            corrected = CORRECT.load(Ordering::Relaxed),
+            "clock differs from the Aether server; check NTP on this host"
+        );
+    } else if !drifting && WARNED.swap(false, Ordering::Relaxed) {
+        info!(skew_ms = skew, "clock back in sync with the Aether server");
+    }
+}
+
+fn local_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+fn median(values: impl Iterator<Item = i64>) -> i64 {
+    let mut values: Vec<i64> = values.collect();
+    values.sort_unstable();
+    values.get(values.len() / 2).copied().unwrap_or(0)
+}
+
+/// Parse an IMF-fixdate (`Sun, 06 Nov 1994 08:49:37 GMT`) into Unix
+/// seconds.  The obsolete RFC 850 and asctime forms are not accepted;
+/// Aether's HTTP stack never sends them.
+pub(crate) fn parse_http_date(raw: &str) -> Option<u64> {
+    let (_, rest) = raw.trim().split_once(", ")?;
+    let mut parts = rest.split(' ');
+    let day: u64 = parts.next()?.parse().ok()?;
+    let month = match parts.next()? {
+        "Jan" => 1,
+        "Feb" => 2,
+        "Mar" => 3,
+        "Apr" => 4,
+        "May" => 5,
+        "Jun" => 6,
+        "Jul" => 7,
+        "Aug" => 8,
+        "Sep" => 9,
+        "Oct" => 10,
+        "Nov" => 11,
+        "Dec" => 12,
+        _ => return None,
+    };
+    let year: u64 = parts.next()?.parse().ok()?;
+    let mut hms = parts.next()?.split(':');
+    let hour: u64 = hms.next()?.parse().ok()?;
+    let minute: u64 = hms.next()?.parse().ok()?;
+    let second: u64 = hms.next()?.parse().ok()?;
+    if parts.next()? != "GMT"
+        || year < 1970
+        || !(1..=31).contains(&day)
+        || hour > 23
+        || minute > 59
+        || second > 60
+    {
+        return None;
+    }
+    Some(days_from_civil(year, month, day) * 86_400 + hour * 3600 + minute * 60 + second)
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date (year >= 1970).
+fn days_from_civil(year: u64, month: u64, day: u64) -> u64 {
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y / 400;
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_http_date() {
+        assert_eq!(
+            parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT"),
+            Some(784_111_777)
+        );
+        assert_eq!(parse_http_date("Thu, 01 Jan 1970 00:00:00 GMT"), Some(0));
+        assert_eq!(
+            parse_http_date("Tue, 29 Feb 2028 23:59:59 GMT"),
+            Some(1_835_481_599)
+        );
+        assert_eq!(parse_http_date("Sunday, 06-Nov-94 08:49:37 GMT"), None);
+        assert_eq!(parse_http_date("Sun, 06 Nov 1994 08:49:37 PST"), None);
+        assert_eq!(parse_http_date(""), None);
+    }
+
+    #[test]
+    fn test_median() {
+        assert_eq!(median([5, -100, 7].into_iter()), 5);
+        assert_eq!(median([1, 2].into_iter()), 2);
+        assert_eq!(median(std::iter::empty()), 0);
+    }
+}
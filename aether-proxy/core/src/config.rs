@@ -0,0 +1,1866 @@
+use std::path::{Path, PathBuf};
+
+use clap::Parser;
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::access_log;
+use crate::dns::{DnsSettings, HostOverrides};
+use crate::error_report;
+use crate::features::FeatureFlags;
+use crate::header_rules::HeaderRules;
+use crate::logging;
+use crate::metrics::BackendKind;
+use crate::net::IpSources;
+use crate::privacy::Privacy;
+use crate::registration::tls;
+use crate::target_filter::{CidrRules, DomainPattern, DomainRules};
+use crate::tunnel::headers::Anonymity;
+use crate::tunnel::live_view::MetadataSealer;
+use crate::upstream_client::EgressBinding;
+use crate::upstream_proxy::{ProxyRoutes, UpstreamProxy};
+use crate::upstream_tls::UpstreamTls;
+use crate::warm_pool::{self, HotTarget};
+
+/// Fields that existed in 0.1.x but were removed in 0.2.0.
+const LEGACY_ONLY_KEYS: &[&str] = &[
+    "hmac_key",
+    "listen_port",
+    "timestamp_tolerance",
+    "connect_timeout_secs",
+    "tls_handshake_timeout_secs",
+    "enable_tls",
+    "tls_cert",
+    "tls_key",
+];
+
+/// Fields renamed from 0.1.x `delegate_*` to 0.2.0 `upstream_*`.
+const DELEGATE_TO_UPSTREAM: &[(&str, &str)] = &[
+    (
+        "delegate_connect_timeout_secs",
+        "upstream_connect_timeout_secs",
+    ),
+    (
+        "delegate_pool_max_idle_per_host",
+        "upstream_pool_max_idle_per_host",
+    ),
+    (
+        "delegate_pool_idle_timeout_secs",
+        "upstream_pool_idle_timeout_secs",
+    ),
+    ("delegate_tcp_keepalive_secs", "upstream_tcp_keepalive_secs"),
+    ("delegate_tcp_nodelay", "upstream_tcp_nodelay"),
+];
+
+/// Aether tunnel proxy.
+///
+/// Deployed on overseas VPS to relay API traffic for Aether instances
+/// behind the GFW. Connects to Aether via WebSocket tunnel, registers
+/// with Aether, and relays upstream requests.
+#[derive(Parser, Debug, Clone, Serialize)]
+#[command(version, about)]
+pub struct Config {
+    /// Aether server URL (e.g. https://aether.example.com); several
+    /// comma-separated URLs of the same control plane are failed over in order
+    #[arg(long, env = "AETHER_PROXY_AETHER_URL")]
+    pub aether_url: String,
+
+    /// Management Token for Aether admin API (ae_xxx)
+    #[arg(long, env = "AETHER_PROXY_MANAGEMENT_TOKEN")]
+    pub management_token: String,
+
+    /// Public IP address of this node (auto-detected if omitted)
+    #[arg(long, env = "AETHER_PROXY_PUBLIC_IP")]
+    pub public_ip: Option<String>,
+
+    /// Public IPv6 address for dual-stack registration (auto-detected if
+    /// omitted and --public-ip is not set)
+    #[arg(long, env = "AETHER_PROXY_PUBLIC_IPV6")]
+    pub public_ipv6: Option<std::net::Ipv6Addr>,
+
+    /// Re-detect the public address every N seconds and update the
+    /// registration when it changes (0 disables; unused with --public-ip)
+    #[arg(
+        long = "public-ip-check-interval",
+        env = "AETHER_PROXY_PUBLIC_IP_CHECK_INTERVAL",
+        default_value_t = 600
+    )]
+    pub public_ip_check_interval_secs: u64,
+
+    /// Custom HTTP(S) endpoints echoing the caller's IP as plain text,
+    /// replacing the built-in ones for public address detection
+    #[arg(
+        long = "ip-echo-url",
+        env = "AETHER_PROXY_IP_ECHO_URLS",
+        value_delimiter = ','
+    )]
+    pub ip_echo_urls: Vec<String>,
+
+    /// STUN servers (`host:port`) asked when no echo endpoint answers;
+    /// built-in servers when empty, `off` disables STUN
+    #[arg(
+        long = "stun-server",
+        env = "AETHER_PROXY_STUN_SERVERS",
+        value_delimiter = ','
+    )]
+    pub stun_servers: Vec<String>,
+
+    /// Human-readable node name
+    #[arg(long, env = "AETHER_PROXY_NODE_NAME", default_value = "proxy-01")]
+    pub node_name: String,
+
+    /// Region label (e.g. ap-northeast-1)
+    #[arg(long, env = "AETHER_PROXY_NODE_REGION")]
+    pub node_region: Option<String>,
+
+    /// Heartbeat interval in seconds
+    #[arg(long, env = "AETHER_PROXY_HEARTBEAT_INTERVAL", default_value_t = 30)]
+    pub heartbeat_interval: u64,
+
+    /// Control-plane X25519 public key (base64); when set, heartbeats carry
+    /// an encrypted snapshot of in-flight streams for the live view
+    #[arg(long, env = "AETHER_PROXY_LIVE_VIEW_PUBLIC_KEY")]
+    pub live_view_public_key: Option<String>,
+
+    /// Execute one-shot commands (drain, log level, stream termination,
+    /// live-view key rotation) sent by the control plane in heartbeat ACKs
+    #[arg(long, env = "AETHER_PROXY_REMOTE_COMMANDS", default_value_t = true)]
+    pub remote_commands: bool,
+
+    /// Access log sink: off, stdout, file:<path>, or aether (uploaded to
+    /// the control plane)
+    #[arg(long, env = "AETHER_PROXY_ACCESS_LOG", default_value = "off")]
+    pub access_log: String,
+
+    /// How targets and clients appear in the access log and destinations
+    /// reports: off, truncate (registrable domain, /24 or /48) or hash
+    #[arg(long, env = "AETHER_PROXY_LOG_PRIVACY", default_value = "off")]
+    pub log_privacy: String,
+
+    /// Secret the `hash` log privacy key is derived from, so hashes stay
+    /// stable across restarts (random per run when unset)
+    #[arg(long, env = "AETHER_PROXY_LOG_PRIVACY_SECRET")]
+    pub log_privacy_secret: Option<String>,
+
+    /// Keep the `x-aether-request-id` Aether sends with a stream instead of
+    /// generating a request ID of the node's own
+    #[arg(long, env = "AETHER_PROXY_TRUST_REQUEST_ID", default_value_t = false)]
+    pub trust_request_id: bool,
+
+    /// Seconds between per-client usage uploads to Aether (0 = disabled)
+    #[arg(long, env = "AETHER_PROXY_USAGE_REPORT_INTERVAL", default_value_t = 0)]
+    pub usage_report_interval_secs: u64,
+
+    /// Seconds between top destinations reports to Aether (0 = disabled)
+    #[arg(
+        long,
+        env = "AETHER_PROXY_DESTINATION_REPORT_INTERVAL",
+        default_value_t = 0
+    )]
+    pub destination_report_interval_secs: u64,
+
+    /// Hosts listed individually in each destinations report
+    #[arg(
+        long,
+        env = "AETHER_PROXY_DESTINATION_REPORT_TOP",
+        default_value_t = 50
+    )]
+    pub destination_report_top: usize,
+
+    /// Correct node timestamps (access log, usage, quota days) by the clock
+    /// skew observed against Aether's Date headers
+    #[arg(long, env = "AETHER_PROXY_CLOCK_SYNC", default_value_t = false)]
+    pub clock_sync: bool,
+
+    /// Allowed destination ports (default: 80,443,8080,8443)
+    #[arg(
+        long,
+        env = "AETHER_PROXY_ALLOWED_PORTS",
+        value_delimiter = ',',
+        default_values_t = vec![80, 443, 8080, 8443]
+    )]
+    pub allowed_ports: Vec<u16>,
+
+    /// Allowed destination domains (exact or `*.example.com`; empty allows all)
+    #[arg(long, env = "AETHER_PROXY_ALLOWED_DOMAINS", value_delimiter = ',')]
+    pub allowed_domains: Vec<String>,
+
+    /// Blocked destination domains (exact or `*.example.com`; takes precedence)
+    #[arg(long, env = "AETHER_PROXY_BLOCKED_DOMAINS", value_delimiter = ',')]
+    pub blocked_domains: Vec<String>,
+
+    /// Allowed destination IP ranges (CIDR, v4/v6; empty allows all)
+    #[arg(long, env = "AETHER_PROXY_ALLOWED_CIDRS", value_delimiter = ',')]
+    pub allowed_cidrs: Vec<String>,
+
+    /// Blocked destination IP ranges (CIDR, v4/v6; takes precedence)
+    #[arg(long, env = "AETHER_PROXY_BLOCKED_CIDRS", value_delimiter = ',')]
+    pub blocked_cidrs: Vec<String>,
+
+    /// Allow private/loopback/reserved target addresses (testing only)
+    #[arg(
+        long,
+        env = "AETHER_PROXY_ALLOW_PRIVATE_TARGETS",
+        default_value_t = false
+    )]
+    pub allow_private_targets: bool,
+
+    /// Reject requests that already passed through this many proxy nodes
+    #[arg(long, env = "AETHER_PROXY_MAX_HOPS", default_value_t = 3)]
+    pub max_hops: u8,
+
+    /// Target hosts that are other aether-proxy nodes or gateways in front
+    /// of them (exact or `*.example.com`); with elite anonymity only these
+    /// are sent the signed hop count
+    #[arg(long, env = "AETHER_PROXY_RELAY_HOSTS", value_delimiter = ',')]
+    pub relay_hosts: Vec<String>,
+
+    /// Aether API request timeout in seconds
+    #[arg(
+        long,
+        env = "AETHER_PROXY_AETHER_REQUEST_TIMEOUT",
+        default_value_t = 10
+    )]
+    pub aether_request_timeout_secs: u64,
+
+    /// Aether API connect timeout in seconds
+    #[arg(
+        long,
+        env = "AETHER_PROXY_AETHER_CONNECT_TIMEOUT",
+        default_value_t = 10
+    )]
+    pub aether_connect_timeout_secs: u64,
+
+    /// Aether API max idle connections per host
+    #[arg(
+        long,
+        env = "AETHER_PROXY_AETHER_POOL_MAX_IDLE_PER_HOST",
+        default_value_t = 8
+    )]
+    pub aether_pool_max_idle_per_host: usize,
+
+    /// Aether API idle timeout in seconds
+    #[arg(
+        long,
+        env = "AETHER_PROXY_AETHER_POOL_IDLE_TIMEOUT",
+        default_value_t = 90
+    )]
+    pub aether_pool_idle_timeout_secs: u64,
+
+    /// Aether API TCP keepalive in seconds (0 disables)
+    #[arg(long, env = "AETHER_PROXY_AETHER_TCP_KEEPALIVE", default_value_t = 60)]
+    pub aether_tcp_keepalive_secs: u64,
+
+    /// Aether API TCP_NODELAY
+    #[arg(long, env = "AETHER_PROXY_AETHER_TCP_NODELAY", default_value_t = true)]
+    pub aether_tcp_nodelay: bool,
+
+    /// Enable HTTP/2 when talking to Aether API
+    #[arg(long, env = "AETHER_PROXY_AETHER_HTTP2", default_value_t = true)]
+    pub aether_http2: bool,
+
+    /// PEM bundle of CAs trusted for the Aether server, replacing the
+    /// built-in web PKI roots (API and tunnel)
+    #[arg(long, env = "AETHER_PROXY_AETHER_CA_CERT")]
+    pub aether_ca_cert: Option<String>,
+
+    /// Pinned Aether server public keys (base64 SHA-256 of the SPKI,
+    /// optionally `sha256/`-prefixed); one must appear in the server's chain
+    #[arg(long, env = "AETHER_PROXY_AETHER_CERT_PIN", value_delimiter = ',')]
+    pub aether_cert_pin: Vec<String>,
+
+    /// Proxy for control-plane traffic (registration, uploads and the
+    /// tunnel): http://, socks5:// or socks5h://, with optional user:pass@
+    #[arg(long, env = "AETHER_PROXY_AETHER_PROXY_URL")]
+    pub aether_proxy_url: Option<String>,
+
+    /// PEM client certificate chain presented to Aether (mTLS)
+    #[arg(long, env = "AETHER_PROXY_AETHER_CLIENT_CERT")]
+    pub aether_client_cert: Option<String>,
+
+    /// PEM private key for `--aether-client-cert`
+    #[arg(long, env = "AETHER_PROXY_AETHER_CLIENT_KEY")]
+    pub aether_client_key: Option<String>,
+
+    /// Aether API retry attempts (including initial)
+    #[arg(
+        long,
+        env = "AETHER_PROXY_AETHER_RETRY_MAX_ATTEMPTS",
+        default_value_t = 3
+    )]
+    pub aether_retry_max_attempts: u32,
+
+    /// Aether API retry base delay in milliseconds
+    #[arg(
+        long,
+        env = "AETHER_PROXY_AETHER_RETRY_BASE_DELAY_MS",
+        default_value_t = 200
+    )]
+    pub aether_retry_base_delay_ms: u64,
+
+    /// Aether API retry max delay in milliseconds
+    #[arg(
+        long,
+        env = "AETHER_PROXY_AETHER_RETRY_MAX_DELAY_MS",
+        default_value_t = 2000
+    )]
+    pub aether_retry_max_delay_ms: u64,
+
+    /// When the Aether host resolves to several addresses, race connections
+    /// and reuse the fastest for this many seconds (0 disables racing)
+    #[arg(
+        long,
+        env = "AETHER_PROXY_AETHER_ENDPOINT_REELECT",
+        default_value_t = 300
+    )]
+    pub aether_endpoint_reelect_secs: u64,
+
+    /// Maximum concurrent TCP connections (defaults to hardware estimate)
+    #[arg(long, env = "AETHER_PROXY_MAX_CONCURRENT_CONNECTIONS")]
+    pub max_concurrent_connections: Option<u64>,
+
+    /// Nameservers for target resolution (`ip` or `ip:port`; empty = system resolver)
+    #[arg(
+        long = "dns-server",
+        env = "AETHER_PROXY_DNS_SERVERS",
+        value_delimiter = ','
+    )]
+    pub dns_servers: Vec<String>,
+
+    /// DNS transport for target resolution: plain, dot or doh
+    #[arg(long, env = "AETHER_PROXY_DNS_MODE", default_value = "plain")]
+    pub dns_mode: String,
+
+    /// DoT/DoH server URL (`tls://host[:port]` or `https://host/dns-query`)
+    #[arg(long, env = "AETHER_PROXY_DNS_URL")]
+    pub dns_url: Option<String>,
+
+    /// Pin target domains to fixed addresses, bypassing DNS
+    /// (`api.openai.com=203.0.113.7`, `*.example.com=192.0.2.1|2001:db8::1`)
+    #[arg(
+        long = "host-override",
+        env = "AETHER_PROXY_HOST_OVERRIDES",
+        value_delimiter = ','
+    )]
+    pub host_overrides: Vec<String>,
+
+    /// Upper bound on DNS cache TTL in seconds (shorter record TTLs win)
+    #[arg(long, env = "AETHER_PROXY_DNS_CACHE_TTL", default_value_t = 60)]
+    pub dns_cache_ttl_secs: u64,
+
+    /// DNS cache capacity (entries)
+    #[arg(long, env = "AETHER_PROXY_DNS_CACHE_CAPACITY", default_value_t = 1024)]
+    pub dns_cache_capacity: usize,
+
+    /// How long to remember non-existent hostnames (NXDOMAIN), in seconds;
+    /// 0 disables negative caching
+    #[arg(long, env = "AETHER_PROXY_DNS_NEGATIVE_TTL", default_value_t = 30)]
+    pub dns_negative_ttl_secs: u64,
+
+    /// Upstream HTTP client connect timeout in seconds
+    #[arg(
+        long,
+        env = "AETHER_PROXY_UPSTREAM_CONNECT_TIMEOUT",
+        default_value_t = 30
+    )]
+    pub upstream_connect_timeout_secs: u64,
+
+    /// Upstream handshake timeout in seconds: TLS with the target and the
+    /// CONNECT / SOCKS5 negotiation with an upstream proxy, each
+    #[arg(
+        long,
+        env = "AETHER_PROXY_UPSTREAM_HANDSHAKE_TIMEOUT",
+        default_value_t = 10
+    )]
+    pub upstream_handshake_timeout_secs: u64,
+
+    /// Abort a response whose body delivers no bytes, or close an upgraded
+    /// connection whose target takes no bytes, for this many seconds (0
+    /// disables stall detection)
+    #[arg(
+        long,
+        env = "AETHER_PROXY_UPSTREAM_STALL_TIMEOUT",
+        default_value_t = 120
+    )]
+    pub upstream_stall_timeout_secs: u64,
+
+    /// Largest request body relayed upstream, in bytes; larger requests are
+    /// answered with 413 (0 = unlimited)
+    #[arg(long, env = "AETHER_PROXY_MAX_REQUEST_BODY", default_value_t = 0)]
+    pub max_request_body: u64,
+
+    /// Largest response body relayed back, in bytes; larger responses fail
+    /// with 502, or are cut off once already streaming (0 = unlimited)
+    #[arg(long, env = "AETHER_PROXY_MAX_RESPONSE_BODY", default_value_t = 0)]
+    pub max_response_body: u64,
+
+    /// Memory for caching cacheable GET responses, in bytes (0 = no cache)
+    #[arg(long, env = "AETHER_PROXY_RESPONSE_CACHE_SIZE", default_value_t = 0)]
+    pub response_cache_size: u64,
+
+    /// Upstream HTTP client max idle connections per host
+    #[arg(
+        long,
+        env = "AETHER_PROXY_UPSTREAM_POOL_MAX_IDLE_PER_HOST",
+        default_value_t = 64
+    )]
+    pub upstream_pool_max_idle_per_host: usize,
+
+    /// Upstream HTTP client idle timeout in seconds
+    #[arg(
+        long,
+        env = "AETHER_PROXY_UPSTREAM_POOL_IDLE_TIMEOUT",
+        default_value_t = 300
+    )]
+    pub upstream_pool_idle_timeout_secs: u64,
+
+    /// Targets to keep pre-established connections to
+    /// (`api.openai.com:443`, `http://internal.example:8080`)
+    #[arg(
+        long = "hot-target",
+        env = "AETHER_PROXY_HOT_TARGETS",
+        value_delimiter = ','
+    )]
+    pub hot_targets: Vec<String>,
+
+    /// Idle connections kept ready per hot target (0 disables)
+    #[arg(long, env = "AETHER_PROXY_HOT_TARGET_CONNECTIONS", default_value_t = 2)]
+    pub hot_target_connections: usize,
+
+    /// Seconds after which an unused hot target connection is replaced,
+    /// below the time origins allow a connection to send its first request
+    #[arg(long, env = "AETHER_PROXY_HOT_TARGET_MAX_IDLE", default_value_t = 20)]
+    pub hot_target_max_idle_secs: u64,
+
+    /// Targets to measure connect latency to, in `--hot-target` syntax
+    #[arg(
+        long = "probe-target",
+        env = "AETHER_PROXY_PROBE_TARGETS",
+        value_delimiter = ','
+    )]
+    pub probe_targets: Vec<String>,
+
+    /// Seconds between latency probes of each probe target (0 disables)
+    #[arg(long, env = "AETHER_PROXY_PROBE_INTERVAL", default_value_t = 60)]
+    pub probe_interval_secs: u64,
+
+    /// Upstream TCP keepalive in seconds (0 disables)
+    #[arg(
+        long,
+        env = "AETHER_PROXY_UPSTREAM_TCP_KEEPALIVE",
+        default_value_t = 60
+    )]
+    pub upstream_tcp_keepalive_secs: u64,
+
+    /// Upstream TCP_NODELAY
+    #[arg(
+        long,
+        env = "AETHER_PROXY_UPSTREAM_TCP_NODELAY",
+        default_value_t = true
+    )]
+    pub upstream_tcp_nodelay: bool,
+
+    /// TCP keepalive probe interval in seconds, for upstream and tunnel
+    /// sockets (tunnel default 5, upstream default: OS)
+    #[arg(long, env = "AETHER_PROXY_TCP_KEEPALIVE_INTERVAL")]
+    pub tcp_keepalive_interval_secs: Option<u64>,
+
+    /// Unanswered TCP keepalive probes before a connection is dropped
+    /// (tunnel default 3, upstream default: OS)
+    #[arg(long, env = "AETHER_PROXY_TCP_KEEPALIVE_PROBES")]
+    pub tcp_keepalive_probes: Option<u32>,
+
+    /// SO_RCVBUF for upstream and tunnel sockets, in bytes (default: OS
+    /// autotuning)
+    #[arg(long, env = "AETHER_PROXY_TCP_RECV_BUFFER")]
+    pub tcp_recv_buffer: Option<usize>,
+
+    /// SO_SNDBUF for upstream and tunnel sockets, in bytes (default: OS
+    /// autotuning)
+    #[arg(long, env = "AETHER_PROXY_TCP_SEND_BUFFER")]
+    pub tcp_send_buffer: Option<usize>,
+
+    /// Delay before racing the other address family when connecting to a
+    /// dual-stack target, in milliseconds (RFC 8305); 0 tries addresses
+    /// one after another
+    #[arg(
+        long,
+        env = "AETHER_PROXY_UPSTREAM_HAPPY_EYEBALLS_DELAY",
+        default_value_t = 250
+    )]
+    pub upstream_happy_eyeballs_delay_ms: u64,
+
+    /// Resolved addresses of a target tried before giving up on it; the
+    /// connect timeout is split evenly between them
+    #[arg(
+        long,
+        env = "AETHER_PROXY_UPSTREAM_CONNECT_ATTEMPTS",
+        default_value_t = 4
+    )]
+    pub upstream_connect_attempts: usize,
+
+    /// Plain-HTTP target hosts known to speak HTTP/2 without TLS (h2c prior
+    /// knowledge); requests to them share one multiplexed connection per
+    /// host.  HTTPS targets negotiate HTTP/2 via ALPN automatically
+    #[arg(long, env = "AETHER_PROXY_UPSTREAM_H2C_HOSTS", value_delimiter = ',')]
+    pub upstream_h2c_hosts: Vec<String>,
+
+    /// PEM bundle of CAs trusted for HTTPS targets in addition to the
+    /// built-in web PKI roots (e.g. a corporate TLS-inspection CA)
+    #[arg(long, env = "AETHER_PROXY_UPSTREAM_CA_BUNDLE")]
+    pub upstream_ca_bundle: Option<String>,
+
+    /// Per-domain public key pins for HTTPS targets
+    /// (`api.example.com=sha256/<base64>|<base64>`, `*` for every target);
+    /// one certificate in the target's chain must match
+    #[arg(
+        long = "upstream-cert-pin",
+        env = "AETHER_PROXY_UPSTREAM_CERT_PINS",
+        value_delimiter = ','
+    )]
+    pub upstream_cert_pins: Vec<String>,
+
+    /// HTTPS target domains whose certificate chain and name are not
+    /// verified (`*` for every target); pins still apply
+    #[arg(
+        long,
+        env = "AETHER_PROXY_UPSTREAM_TLS_INSECURE",
+        value_delimiter = ','
+    )]
+    pub upstream_tls_insecure: Vec<String>,
+
+    /// Encrypt the ClientHello (ECH) to HTTPS targets whose DNS `HTTPS`
+    /// record advertises an ECH config, hiding the target name on the wire
+    #[arg(long, env = "AETHER_PROXY_UPSTREAM_ECH", default_value_t = false)]
+    pub upstream_ech: bool,
+
+    /// Forward upstream requests through this proxy
+    /// (`http://`, `socks5://` or `socks5h://`, with optional `user:pass@`);
+    /// a comma-separated list with optional `#weight=N` suffixes forms a
+    /// failover pool
+    #[arg(long, env = "AETHER_PROXY_UPSTREAM_PROXY")]
+    pub upstream_proxy: Option<String>,
+
+    /// Per-domain egress routes, first match wins
+    /// (`*.onion=socks5h://127.0.0.1:9050`, `api.example.com=direct`,
+    /// `*.tracker.example=reject`); separate pool members with `|`
+    #[arg(
+        long = "upstream-proxy-rule",
+        env = "AETHER_PROXY_UPSTREAM_PROXY_RULES",
+        value_delimiter = ','
+    )]
+    pub upstream_proxy_rules: Vec<String>,
+
+    /// Seconds between health checks of upstream proxies: a TCP connect,
+    /// plus the method negotiation for SOCKS5 (0 = only mark proxies down
+    /// when real connections fail)
+    #[arg(
+        long,
+        env = "AETHER_PROXY_UPSTREAM_PROXY_HEALTH_INTERVAL",
+        default_value_t = 10
+    )]
+    pub upstream_proxy_health_interval_secs: u64,
+
+    /// Header anonymity towards targets: transparent (client headers
+    /// untouched, node added to Via), anonymous (client addresses such as
+    /// X-Forwarded-For stripped) or elite (no Via, no proxy-revealing
+    /// headers, hop count only towards relay hosts)
+    #[arg(long, env = "AETHER_PROXY_ANONYMITY", default_value = "transparent")]
+    pub anonymity: String,
+
+    /// Request header rewrites per target domain, applied in order
+    /// (`api.example.com=set:authorization:Bearer ...`,
+    /// `*.example.com=add:x-team:infra`, `*=remove:x-tracking-id`)
+    #[arg(
+        long = "header-rule",
+        env = "AETHER_PROXY_HEADER_RULES",
+        value_delimiter = ','
+    )]
+    pub header_rules: Vec<String>,
+
+    /// Local source IPs for upstream connections (at most one IPv4 and one
+    /// IPv6; each applies to targets of its family)
+    #[arg(long, env = "AETHER_PROXY_EGRESS_BIND_IP", value_delimiter = ',')]
+    pub egress_bind_ip: Vec<std::net::IpAddr>,
+
+    /// Bind upstream connections to this network interface
+    /// (SO_BINDTODEVICE, Linux only)
+    #[arg(long, env = "AETHER_PROXY_EGRESS_INTERFACE")]
+    pub egress_interface: Option<String>,
+
+    /// Alternate local source IP used to retry once when an upstream
+    /// connection is reset during connect (disabled if omitted)
+    #[arg(long, env = "AETHER_PROXY_EGRESS_ALT_BIND_IP")]
+    pub egress_alt_bind_ip: Option<std::net::IpAddr>,
+
+    /// Log level (trace, debug, info, warn, error)
+    #[arg(long, env = "AETHER_PROXY_LOG_LEVEL", default_value = "info")]
+    pub log_level: String,
+
+    /// Output logs as JSON
+    #[arg(long, env = "AETHER_PROXY_LOG_JSON", default_value_t = false)]
+    pub log_json: bool,
+
+    /// Log outputs: stdout, file:<path>, syslog, journald
+    #[arg(
+        long,
+        env = "AETHER_PROXY_LOG_OUTPUT",
+        value_delimiter = ',',
+        default_value = "stdout"
+    )]
+    pub log_output: Vec<String>,
+
+    /// Rotate a log file before it exceeds this many MiB (0 = no limit)
+    #[arg(long, env = "AETHER_PROXY_LOG_FILE_MAX_SIZE_MB", default_value_t = 100)]
+    pub log_file_max_size_mb: u64,
+
+    /// Also rotate log files at the first event of each UTC day
+    #[arg(long, env = "AETHER_PROXY_LOG_FILE_DAILY", default_value_t = false)]
+    pub log_file_daily: bool,
+
+    /// Rotated log files kept
+    #[arg(long, env = "AETHER_PROXY_LOG_FILE_KEEP", default_value_t = 7)]
+    pub log_file_keep: usize,
+
+    /// Sentry DSN to report panics, heartbeat and registration failures to
+    #[arg(long, env = "AETHER_PROXY_SENTRY_DSN")]
+    pub sentry_dsn: Option<String>,
+
+    /// URL receiving the same failure reports as JSON POSTs
+    #[arg(long, env = "AETHER_PROXY_ERROR_WEBHOOK_URL")]
+    pub error_webhook_url: Option<String>,
+
+    /// Metrics export backend (none, prometheus, statsd, dogstatsd, emf)
+    #[arg(long, env = "AETHER_PROXY_METRICS_BACKEND", default_value = "none")]
+    pub metrics_backend: String,
+
+    /// Metrics sampling/push interval in seconds
+    #[arg(long, env = "AETHER_PROXY_METRICS_INTERVAL", default_value_t = 10)]
+    pub metrics_interval_secs: u64,
+
+    /// Prometheus scrape listen address
+    #[arg(
+        long,
+        env = "AETHER_PROXY_METRICS_LISTEN",
+        default_value = "127.0.0.1:9464"
+    )]
+    pub metrics_listen: std::net::SocketAddr,
+
+    /// StatsD / DogStatsD agent address (host:port)
+    #[arg(
+        long,
+        env = "AETHER_PROXY_METRICS_STATSD_ADDR",
+        default_value = "127.0.0.1:8125"
+    )]
+    pub metrics_statsd_addr: String,
+
+    /// CloudWatch namespace for EMF output
+    #[arg(
+        long,
+        env = "AETHER_PROXY_METRICS_EMF_NAMESPACE",
+        default_value = "AetherProxy"
+    )]
+    pub metrics_emf_namespace: String,
+
+    /// File to append EMF records to (default: stdout)
+    #[arg(long, env = "AETHER_PROXY_METRICS_EMF_PATH")]
+    pub metrics_emf_path: Option<String>,
+
+    /// Admin status UI listen address (disabled when unset); must be a
+    /// loopback address unless --admin-allow-remote is set
+    #[arg(long, env = "AETHER_PROXY_ADMIN_LISTEN")]
+    pub admin_listen: Option<std::net::SocketAddr>,
+
+    /// Allow --admin-listen on a non-loopback address
+    #[arg(long, env = "AETHER_PROXY_ADMIN_ALLOW_REMOTE", default_value_t = false)]
+    pub admin_allow_remote: bool,
+
+    /// Bearer token required by the admin API
+    #[arg(long, env = "AETHER_PROXY_ADMIN_TOKEN")]
+    pub admin_token: Option<String>,
+
+    /// Failed admin authentications from one source before it is banned
+    /// (0 = never ban; loopback is exempt)
+    #[arg(long, env = "AETHER_PROXY_ADMIN_BAN_THRESHOLD", default_value_t = 10)]
+    pub admin_ban_threshold: u32,
+
+    /// How long a banned source stays banned, and the window its failures
+    /// are counted in (seconds)
+    #[arg(long, env = "AETHER_PROXY_ADMIN_BAN_SECS", default_value_t = 900)]
+    pub admin_ban_secs: u64,
+
+    /// Tunnel reconnect base delay in milliseconds (used by exponential backoff)
+    #[arg(
+        long,
+        env = "AETHER_PROXY_TUNNEL_RECONNECT_BASE_MS",
+        default_value_t = 500
+    )]
+    pub tunnel_reconnect_base_ms: u64,
+
+    /// Tunnel reconnect max delay in milliseconds (cap for exponential backoff)
+    #[arg(
+        long,
+        env = "AETHER_PROXY_TUNNEL_RECONNECT_MAX_MS",
+        default_value_t = 30000
+    )]
+    pub tunnel_reconnect_max_ms: u64,
+
+    /// WebSocket tunnel ping interval in seconds
+    #[arg(long, env = "AETHER_PROXY_TUNNEL_PING_INTERVAL", default_value_t = 15)]
+    pub tunnel_ping_interval_secs: u64,
+
+    /// Maximum concurrent streams over tunnel (auto-detected from hardware if omitted)
+    #[arg(long, env = "AETHER_PROXY_TUNNEL_MAX_STREAMS")]
+    pub tunnel_max_streams: Option<u32>,
+
+    /// Maximum concurrent streams per client identity (the x-aether-client
+    /// tag Aether puts on each stream) across the tunnels to a server
+    /// (0 = unlimited).  Not a per-source-IP limit: the node never sees
+    /// client addresses.  Streams without a client are not limited
+    #[arg(long, env = "AETHER_PROXY_MAX_STREAMS_PER_CLIENT", default_value_t = 0)]
+    pub max_streams_per_client: usize,
+
+    /// Feature flag rules (`name=on|off|N%`), overridable by the control plane
+    #[arg(long = "feature", env = "AETHER_PROXY_FEATURES", value_delimiter = ',')]
+    pub features: Vec<String>,
+
+    /// CPU/memory usage percent at which bulk streams are shed (0 = disabled;
+    /// needs the `load_shedding` feature)
+    #[arg(long, env = "AETHER_PROXY_LOAD_SHED_THRESHOLD", default_value_t = 90)]
+    pub load_shed_threshold: u8,
+
+    /// CPU/memory usage percent at which interactive streams are shed too
+    #[arg(long, env = "AETHER_PROXY_LOAD_SHED_CRITICAL", default_value_t = 97)]
+    pub load_shed_critical: u8,
+
+    /// Declared request body size, in bytes, from which a stream counts as
+    /// bulk for load shedding (0 = size never does)
+    #[arg(
+        long,
+        env = "AETHER_PROXY_LOAD_SHED_BULK_BODY",
+        default_value_t = 1024 * 1024
+    )]
+    pub load_shed_bulk_body: u64,
+
+    /// Target hosts whose streams count as bulk for load shedding (exact or
+    /// `*.example.com`)
+    #[arg(long, env = "AETHER_PROXY_LOAD_SHED_BULK_HOSTS", value_delimiter = ',')]
+    pub load_shed_bulk_hosts: Vec<String>,
+
+    /// Tokio runtime: multi-thread or current-thread (one thread, for
+    /// single-vCPU hosts)
+    #[arg(
+        long,
+        env = "AETHER_PROXY_RUNTIME_FLAVOR",
+        default_value = "multi-thread"
+    )]
+    pub runtime_flavor: String,
+
+    /// Runtime worker threads (multi-thread flavor; default: one per CPU)
+    #[arg(long, env = "AETHER_PROXY_WORKER_THREADS")]
+    pub worker_threads: Option<usize>,
+
+    /// Upper bound on threads for blocking work such as DNS lookups and
+    /// file I/O (default 512)
+    #[arg(long, env = "AETHER_PROXY_MAX_BLOCKING_THREADS")]
+    pub max_blocking_threads: Option<usize>,
+
+    /// Consecutive connect/TLS errors (including their timeouts) after
+    /// which a target is refused for a while (0 = disabled; needs the `circuit_breaker`
+    /// feature)
+    #[arg(
+        long,
+        env = "AETHER_PROXY_CIRCUIT_BREAKER_FAILURES",
+        default_value_t = 5
+    )]
+    pub circuit_breaker_failures: u32,
+
+    /// How long an opened target circuit refuses requests, in seconds
+    #[arg(long, env = "AETHER_PROXY_CIRCUIT_BREAKER_OPEN", default_value_t = 30)]
+    pub circuit_breaker_open_secs: u64,
+
+    /// Also count 5xx responses as circuit breaker failures
+    #[arg(
+        long = "circuit-breaker-count-5xx",
+        env = "AETHER_PROXY_CIRCUIT_BREAKER_COUNT_5XX",
+        default_value_t = false
+    )]
+    pub circuit_breaker_count_5xx: bool,
+
+    /// WebSocket tunnel TCP connect timeout in seconds
+    #[arg(
+        long,
+        env = "AETHER_PROXY_TUNNEL_CONNECT_TIMEOUT",
+        default_value_t = 15
+    )]
+    pub tunnel_connect_timeout_secs: u64,
+
+    /// WebSocket tunnel TCP keepalive in seconds (0 disables)
+    #[arg(long, env = "AETHER_PROXY_TUNNEL_TCP_KEEPALIVE", default_value_t = 30)]
+    pub tunnel_tcp_keepalive_secs: u64,
+
+    /// WebSocket tunnel TCP_NODELAY
+    #[arg(long, env = "AETHER_PROXY_TUNNEL_TCP_NODELAY", default_value_t = true)]
+    pub tunnel_tcp_nodelay: bool,
+
+    /// Tunnel connection staleness timeout in seconds (triggers reconnect if no data received)
+    #[arg(long, env = "AETHER_PROXY_TUNNEL_STALE_TIMEOUT", default_value_t = 45)]
+    pub tunnel_stale_timeout_secs: u64,
+
+    /// Seconds to wait for a successor started by SIGUSR2 to register and
+    /// open a tunnel; one that is not ready by then is stopped
+    #[arg(long, env = "AETHER_PROXY_HANDOFF_OVERLAP", default_value_t = 15)]
+    pub handoff_overlap_secs: u64,
+
+    /// Number of parallel WebSocket tunnel connections per server (connection pool)
+    #[arg(long, env = "AETHER_PROXY_TUNNEL_CONNECTIONS", default_value_t = 3)]
+    pub tunnel_connections: u32,
+
+    /// Validate the config, server URLs and listener ports, print a report
+    /// and exit without contacting the control plane
+    #[arg(long, alias = "validate")]
+    pub dry_run: bool,
+}
+
+impl Config {
+    /// Validate configuration values are within sane ranges.
+    /// Called after parsing to catch misconfigurations early.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.heartbeat_interval == 0 {
+            anyhow::bail!("heartbeat_interval must be > 0");
+        }
+        if self.heartbeat_interval > 3600 {
+            anyhow::bail!("heartbeat_interval must be <= 3600");
+        }
+        if self.allowed_ports.is_empty() {
+            anyhow::bail!("allowed_ports must not be empty");
+        }
+        for &port in &self.allowed_ports {
+            if port == 0 {
+                anyhow::bail!("allowed_ports: port 0 is not valid");
+            }
+        }
+        self.domain_rules()?;
+        self.cidr_rules()?;
+        self.dns_settings()?;
+        self.host_overrides()?;
+        self.feature_flags()?;
+        self.live_view_sealer()?;
+        self.egress_binding()?;
+        self.upstream_h2c_hosts()?;
+        self.load_shed_bulk_hosts()?;
+        self.upstream_tls()?;
+        self.hot_targets()?;
+        self.probe_targets()?;
+        self.upstream_proxy()?;
+        self.anonymity()?;
+        self.relay_hosts()?;
+        self.header_rules()?;
+        self.access_log_sink()?;
+        self.log_privacy()?;
+        self.log_outputs()?;
+        self.sentry_dsn()?;
+        self.error_webhook_url()?;
+        self.aether_tls()?;
+        self.aether_proxy()?;
+        self.ip_sources()?;
+        BackendKind::parse(&self.metrics_backend)
+            .map_err(|e| anyhow::anyhow!("metrics_backend: {}", e))?;
+        if self.metrics_interval_secs == 0 {
+            anyhow::bail!("metrics_interval_secs must be > 0");
+        }
+        if self.admin_listen.is_some()
+            && self
+                .admin_token
+                .as_deref()
+                .is_none_or(|t| t.trim().is_empty())
+        {
+            anyhow::bail!("admin_token is required when admin_listen is set");
+        }
+        if let Some(addr) = self.admin_listen {
+            if !addr.ip().is_loopback() && !self.admin_allow_remote {
+                anyhow::bail!(
+                    "admin_listen: {} is not a loopback address (reach the admin port through an SSH tunnel, or set admin_allow_remote)",
+                    addr
+                );
+            }
+        }
+        if self.max_hops == 0 {
+            anyhow::bail!("max_hops must be >= 1");
+        }
+        if self.tunnel_connect_timeout_secs == 0 {
+            anyhow::bail!("tunnel_connect_timeout_secs must be > 0");
+        }
+        if self.tunnel_ping_interval_secs == 0 {
+            anyhow::bail!("tunnel_ping_interval_secs must be > 0");
+        }
+        if self.tunnel_stale_timeout_secs <= self.tunnel_ping_interval_secs {
+            anyhow::bail!(
+                "tunnel_stale_timeout_secs ({}) must be > tunnel_ping_interval_secs ({})",
+                self.tunnel_stale_timeout_secs,
+                self.tunnel_ping_interval_secs
+            );
+        }
+        if self.load_shed_threshold > 0
+            && (self.load_shed_critical > 100
+                || self.load_shed_critical <= self.load_shed_threshold)
+        {
+            anyhow::bail!(
+                "load_shed_critical ({}) must be > load_shed_threshold ({}) and <= 100",
+                self.load_shed_critical,
+                self.load_shed_threshold
+            );
+        }
+        if self.tunnel_connections == 0 {
+            anyhow::bail!("tunnel_connections must be > 0");
+        }
+        if self.aether_retry_max_attempts == 0 {
+            anyhow::bail!("aether_retry_max_attempts must be >= 1");
+        }
+        if self.upstream_connect_timeout_secs == 0 {
+            anyhow::bail!("upstream_connect_timeout_secs must be > 0");
+        }
+        if self.upstream_connect_attempts == 0 {
+            anyhow::bail!("upstream_connect_attempts must be >= 1");
+        }
+        if self.upstream_handshake_timeout_secs == 0 {
+            anyhow::bail!("upstream_handshake_timeout_secs must be > 0");
+        }
+        Ok(())
+    }
+
+    /// Parse the configured domain allow/deny lists.
+    pub fn domain_rules(&self) -> anyhow::Result<DomainRules> {
+        DomainRules::new(&self.allowed_domains, &self.blocked_domains)
+            .map_err(|e| anyhow::anyhow!("allowed_domains/blocked_domains: {}", e))
+    }
+
+    /// Parse `host_overrides`.
+    pub fn host_overrides(&self) -> anyhow::Result<HostOverrides> {
+        HostOverrides::parse(&self.host_overrides)
+            .map_err(|e| anyhow::anyhow!("host_overrides: {}", e))
+    }
+
+    /// Parse the target resolver settings (`dns_mode`, `dns_servers`, `dns_url`).
+    pub fn dns_settings(&self) -> anyhow::Result<DnsSettings> {
+        DnsSettings::parse(&self.dns_mode, &self.dns_servers, self.dns_url.as_deref())
+            .map_err(|e| anyhow::anyhow!("dns: {}", e))
+    }
+
+    /// Parse the local feature flag rules.
+    pub fn feature_flags(&self) -> anyhow::Result<FeatureFlags> {
+        FeatureFlags::from_local(&self.features).map_err(|e| anyhow::anyhow!("features: {}", e))
+    }
+
+    /// Parse `live_view_public_key`; `None` when the live view is disabled.
+    pub fn live_view_sealer(&self) -> anyhow::Result<Option<MetadataSealer>> {
+        self.live_view_public_key
+            .as_deref()
+            .map(MetadataSealer::from_base64)
+            .transpose()
+            .map_err(|e| anyhow::anyhow!("live_view_public_key: {}", e))
+    }
+
+    /// Parse `upstream_h2c_hosts`.
+    pub fn upstream_h2c_hosts(&self) -> anyhow::Result<Vec<DomainPattern>> {
+        self.upstream_h2c_hosts
+            .iter()
+            .map(|raw| DomainPattern::parse(raw))
+            .collect::<Result<_, _>>()
+            .map_err(|e| anyhow::anyhow!("upstream_h2c_hosts: {}", e))
+    }
+
+    /// Parse `relay_hosts`.
+    pub fn relay_hosts(&self) -> anyhow::Result<Vec<DomainPattern>> {
+        self.relay_hosts
+            .iter()
+            .map(|raw| DomainPattern::parse(raw))
+            .collect::<Result<_, _>>()
+            .map_err(|e| anyhow::anyhow!("relay_hosts: {}", e))
+    }
+
+    /// Parse `load_shed_bulk_hosts`.
+    pub fn load_shed_bulk_hosts(&self) -> anyhow::Result<Vec<DomainPattern>> {
+        self.load_shed_bulk_hosts
+            .iter()
+            .map(|raw| DomainPattern::parse(raw))
+            .collect::<Result<_, _>>()
+            .map_err(|e| anyhow::anyhow!("load_shed_bulk_hosts: {}", e))
+    }
+
+    /// Origin TLS options from `upstream_ca_bundle`, `upstream_cert_pins`
+    /// and `upstream_tls_insecure`.
+    pub fn upstream_tls(&self) -> anyhow::Result<UpstreamTls> {
+        UpstreamTls::new(
+            self.upstream_ca_bundle.as_deref(),
+            &self.upstream_tls_insecure,
+            &self.upstream_cert_pins,
+        )
+        .map_err(|e| anyhow::anyhow!("upstream TLS: {}", e))
+    }
+
+    /// Parse `hot_targets`.
+    pub fn hot_targets(&self) -> anyhow::Result<Vec<HotTarget>> {
+        warm_pool::parse_targets(&self.hot_targets)
+            .map_err(|e| anyhow::anyhow!("hot_targets: {}", e))
+    }
+
+    /// Parse `probe_targets`.
+    pub fn probe_targets(&self) -> anyhow::Result<Vec<HotTarget>> {
+        warm_pool::parse_targets(&self.probe_targets)
+            .map_err(|e| anyhow::anyhow!("probe_targets: {}", e))
+    }
+
+    /// Parse `anonymity`.
+    pub fn anonymity(&self) -> anyhow::Result<Anonymity> {
+        Anonymity::parse(&self.anonymity).map_err(|e| anyhow::anyhow!("anonymity: {}", e))
+    }
+
+    /// Parse `header_rules`.
+    pub fn header_rules(&self) -> anyhow::Result<HeaderRules> {
+        HeaderRules::new(&self.header_rules).map_err(|e| anyhow::anyhow!("header_rules: {}", e))
+    }
+
+    /// Parse `access_log`.
+    pub fn access_log_sink(&self) -> anyhow::Result<access_log::Sink> {
+        access_log::Sink::parse(&self.access_log).map_err(|e| anyhow::anyhow!("access_log: {}", e))
+    }
+
+    /// Parse `log_output` (at least one output).
+    pub fn log_outputs(&self) -> anyhow::Result<Vec<logging::Output>> {
+        let outputs: Vec<logging::Output> = self
+            .log_output
+            .iter()
+            .filter(|raw| !raw.trim().is_empty())
+            .map(|raw| logging::Output::parse(raw))
+            .collect::<Result<_, _>>()
+            .map_err(|e| anyhow::anyhow!("log_output: {}", e))?;
+        if outputs.is_empty() {
+            anyhow::bail!("log_output: at least one output is required");
+        }
+        Ok(outputs)
+    }
+
+    /// Rotation of `file:` log outputs.
+    pub fn log_rotation(&self) -> logging::Rotation {
+        logging::Rotation {
+            max_bytes: (self.log_file_max_size_mb > 0).then_some(self.log_file_max_size_mb << 20),
+            daily: self.log_file_daily,
+            keep: self.log_file_keep,
+        }
+    }
+
+    pub fn sentry_dsn(&self) -> anyhow::Result<Option<error_report::SentryDsn>> {
+        self.sentry_dsn
+            .as_deref()
+            .filter(|raw| !raw.trim().is_empty())
+            .map(error_report::SentryDsn::parse)
+            .transpose()
+            .map_err(|e| anyhow::anyhow!("sentry_dsn: {}", e))
+    }
+
+    pub fn error_webhook_url(&self) -> anyhow::Result<Option<reqwest::Url>> {
+        let Some(raw) = self
+            .error_webhook_url
+            .as_deref()
+            .filter(|raw| !raw.trim().is_empty())
+        else {
+            return Ok(None);
+        };
+        let url = reqwest::Url::parse(raw.trim())
+            .map_err(|e| anyhow::anyhow!("error_webhook_url: {}", e))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            anyhow::bail!("error_webhook_url: must be an http(s) URL");
+        }
+        Ok(Some(url))
+    }
+
+    /// Parse `log_privacy`; a `hash` mode is keyed by `log_privacy_secret`
+    /// or, without one, gets a fresh key.
+    pub fn log_privacy(&self) -> anyhow::Result<Privacy> {
+        Privacy::parse(&self.log_privacy, self.log_privacy_secret.as_deref())
+            .map_err(|e| anyhow::anyhow!("log_privacy: {}", e))
+    }
+
+    /// Parse `ip_echo_urls` and `stun_servers`.
+    pub fn ip_sources(&self) -> anyhow::Result<IpSources> {
+        IpSources::new(&self.ip_echo_urls, &self.stun_servers)
+            .map_err(|e| anyhow::anyhow!("public IP detection: {}", e))
+    }
+
+    /// TLS client config for the Aether API and tunnel, from
+    /// `aether_ca_cert`, `aether_client_cert`, `aether_client_key` and
+    /// `aether_cert_pin`.
+    pub fn aether_tls(&self) -> anyhow::Result<rustls::ClientConfig> {
+        tls::client_config(tls::TlsOptions {
+            ca_cert: self.aether_ca_cert.as_deref(),
+            client_cert: self.aether_client_cert.as_deref(),
+            client_key: self.aether_client_key.as_deref(),
+            pins: &self.aether_cert_pin,
+        })
+        .map_err(|e| anyhow::anyhow!("aether TLS: {}", e))
+    }
+
+    /// Parse `aether_proxy_url`; `None` when the control plane is reached
+    /// directly.
+    pub fn aether_proxy(&self) -> anyhow::Result<Option<UpstreamProxy>> {
+        let Some(raw) = self
+            .aether_proxy_url
+            .as_deref()
+            .filter(|raw| !raw.trim().is_empty())
+        else {
+            return Ok(None);
+        };
+        let proxy =
+            UpstreamProxy::parse(raw).map_err(|e| anyhow::anyhow!("aether_proxy_url: {}", e))?;
+        reqwest::Proxy::all(raw.trim()).map_err(|e| anyhow::anyhow!("aether_proxy_url: {}", e))?;
+        Ok(Some(proxy))
+    }
+
+    /// Parse `upstream_proxy` and `upstream_proxy_rules`; `None` when all
+    /// requests go direct.
+    pub fn upstream_proxy(&self) -> anyhow::Result<Option<ProxyRoutes>> {
+        ProxyRoutes::new(self.upstream_proxy.as_deref(), &self.upstream_proxy_rules)
+            .map_err(|e| anyhow::anyhow!("upstream_proxy: {}", e))
+    }
+
+    /// Source binding for upstream connections.
+    pub fn egress_binding(&self) -> anyhow::Result<EgressBinding> {
+        let mut binding = EgressBinding::from_ips(&self.egress_bind_ip)
+            .map_err(|e| anyhow::anyhow!("egress_bind_ip: {}", e))?;
+        if let Some(interface) = self.egress_interface.as_deref().map(str::trim) {
+            if interface.is_empty() {
+                anyhow::bail!("egress_interface must not be empty");
+            }
+            if !cfg!(target_os = "linux") {
+                anyhow::bail!("egress_interface is only supported on Linux");
+            }
+            binding.interface = Some(interface.to_string());
+        }
+        Ok(binding)
+    }
+
+    /// Parse the configured CIDR allow/deny lists.
+    pub fn cidr_rules(&self) -> anyhow::Result<CidrRules> {
+        CidrRules::new(&self.allowed_cidrs, &self.blocked_cidrs)
+            .map_err(|e| anyhow::anyhow!("allowed_cidrs/blocked_cidrs: {}", e))
+    }
+}
+
+/// Per-server connection config (used in multi-server TOML `[[servers]]`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerEntry {
+    pub aether_url: String,
+    pub management_token: String,
+    /// Per-server node name override. Falls back to the global `node_name`.
+    pub node_name: Option<String>,
+}
+
+// ---------------------------------------------------------------------------
+// TOML config file support
+// ---------------------------------------------------------------------------
+
+/// Serializable config for TOML file persistence.
+/// All fields are optional -- only populated values are written.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct ConfigFile {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aether_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub management_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub public_ip: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub public_ipv6: Option<std::net::Ipv6Addr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub public_ip_check_interval_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ip_echo_urls: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stun_servers: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub node_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub node_region: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub heartbeat_interval: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub live_view_public_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote_commands: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access_log: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_privacy: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_privacy_secret: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trust_request_id: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage_report_interval_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub destination_report_interval_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub destination_report_top: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clock_sync: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_ports: Option<Vec<u16>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_domains: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blocked_domains: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allowed_cidrs: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub blocked_cidrs: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub allow_private_targets: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_hops: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub relay_hosts: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aether_request_timeout_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aether_connect_timeout_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aether_pool_max_idle_per_host: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aether_pool_idle_timeout_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aether_tcp_keepalive_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aether_tcp_nodelay: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aether_http2: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aether_ca_cert: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aether_cert_pin: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aether_proxy_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aether_client_cert: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aether_client_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aether_retry_max_attempts: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aether_retry_base_delay_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aether_retry_max_delay_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub aether_endpoint_reelect_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_concurrent_connections: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns_servers: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns_mode: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host_overrides: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns_cache_ttl_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns_cache_capacity: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns_negative_ttl_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_connect_timeout_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_handshake_timeout_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_stall_timeout_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_request_body: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_response_body: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_cache_size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_pool_max_idle_per_host: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_pool_idle_timeout_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hot_targets: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hot_target_connections: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hot_target_max_idle_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub probe_targets: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub probe_interval_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_tcp_keepalive_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_tcp_nodelay: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_happy_eyeballs_delay_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tcp_keepalive_interval_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tcp_keepalive_probes: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tcp_recv_buffer: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tcp_send_buffer: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_connect_attempts: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_h2c_hosts: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_ca_bundle: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_cert_pins: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_tls_insecure: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_ech: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_proxy: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_proxy_rules: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upstream_proxy_health_interval_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub anonymity: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub header_rules: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub egress_bind_ip: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub egress_interface: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub egress_alt_bind_ip: Option<std::net::IpAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_level: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_json: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_output: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_file_max_size_mb: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_file_daily: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_file_keep: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sentry_dsn: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_webhook_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics_backend: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics_interval_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics_listen: Option<std::net::SocketAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics_statsd_addr: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics_emf_namespace: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metrics_emf_path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub admin_listen: Option<std::net::SocketAddr>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub admin_allow_remote: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub admin_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub admin_ban_threshold: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub admin_ban_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tunnel_reconnect_base_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tunnel_reconnect_max_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tunnel_ping_interval_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tunnel_max_streams: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_streams_per_client: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub features: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub load_shed_threshold: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub load_shed_critical: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub load_shed_bulk_body: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub load_shed_bulk_hosts: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub runtime_flavor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub worker_threads: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_blocking_threads: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub circuit_breaker_failures: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub circuit_breaker_open_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub circuit_breaker_count_5xx: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tunnel_connect_timeout_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tunnel_tcp_keepalive_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tunnel_tcp_nodelay: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tunnel_stale_timeout_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub handoff_overlap_secs: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tunnel_connections: Option<u32>,
+
+    /// Multi-server config: each entry connects to a separate Aether instance.
+    /// When present, top-level aether_url/management_token are ignored for
+    /// tunnel connections (but still injected as env for clap compatibility).
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub servers: Vec<ServerEntry>,
+}
+
+impl ConfigFile {
+    /// Load from a TOML file.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let content = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&content)?)
+    }
+
+    /// Save to a TOML file.
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        let content = toml::to_string_pretty(self)?;
+        std::fs::write(path, content)?;
+        Ok(())
+    }
+
+    /// Detect and migrate a 0.1.x config file to 0.2.0 format in-place.
+    ///
+    /// The original file is backed up as `<name>.v1.bak` before rewriting;
+    /// returns the backup's path, or `None` if the file was already current.
+    pub fn migrate_legacy(path: &Path) -> anyhow::Result<Option<PathBuf>> {
+        let content = match std::fs::read_to_string(path) {
+            Ok(c) => c,
+            Err(_) => return Ok(None),
+        };
+        let mut table: toml::map::Map<String, toml::Value> = toml::from_str(&content)?;
+
+        // Detect legacy format: presence of any 0.1.x-only key.
+        let is_legacy = LEGACY_ONLY_KEYS.iter().any(|k| table.contains_key(*k))
+            || DELEGATE_TO_UPSTREAM
+                .iter()
+                .any(|(old, _)| table.contains_key(*old));
+
+        if !is_legacy {
+            return Ok(None);
+        }
+
+        // 1. Rename delegate_* -> upstream_* (carry over user-customized values)
+        for &(old, new) in DELEGATE_TO_UPSTREAM {
+            if let Some(val) = table.remove(old) {
+                table.entry(new.to_string()).or_insert(val);
+            }
+        }
+
+        // 2. Build [[servers]] from top-level aether_url + management_token + node_name
+        if !table.contains_key("servers") {
+            let aether_url = table.get("aether_url").and_then(|v| v.as_str());
+            let management_token = table.get("management_token").and_then(|v| v.as_str());
+            if let (Some(url), Some(token)) = (aether_url, management_token) {
+                let mut entry = toml::map::Map::new();
+                entry.insert("aether_url".into(), toml::Value::String(url.to_string()));
+                entry.insert(
+                    "management_token".into(),
+                    toml::Value::String(token.to_string()),
+                );
+                if let Some(name) = table.get("node_name").and_then(|v| v.as_str()) {
+                    entry.insert("node_name".into(), toml::Value::String(name.to_string()));
+                }
+                table.insert(
+                    "servers".into(),
+                    toml::Value::Array(vec![toml::Value::Table(entry)]),
+                );
+            }
+        }
+
+        // 3. Remove top-level fields that are now in [[servers]] or obsolete
+        table.remove("aether_url");
+        table.remove("management_token");
+        table.remove("node_name");
+        for &key in LEGACY_ONLY_KEYS {
+            table.remove(key);
+        }
+
+        // 4. Backup original file (abort migration if backup fails)
+        let backup_path = path.with_extension("v1.bak");
+        std::fs::copy(path, &backup_path).map_err(|e| {
+            anyhow::anyhow!(
+                "failed to backup config before migration: {} -> {}: {}",
+                path.display(),
+                backup_path.display(),
+                e
+            )
+        })?;
+
+        // 5. Write migrated config
+        let new_content = toml::to_string_pretty(&table)?;
+        std::fs::write(path, &new_content)?;
+        info!(backup = %backup_path.display(), "config migrated from 0.1.x to 0.2.0 format");
+
+        Ok(Some(backup_path))
+    }
+
+    /// Resolve the effective server list.
+    ///
+    /// If `[[servers]]` is present, use it. Otherwise fall back to the
+    /// top-level `aether_url` + `management_token` as a single server.
+    pub fn effective_servers(&self) -> Vec<ServerEntry> {
+        if !self.servers.is_empty() {
+            return self.servers.clone();
+        }
+        match (&self.aether_url, &self.management_token) {
+            (Some(url), Some(token)) => vec![ServerEntry {
+                aether_url: url.clone(),
+                management_token: token.clone(),
+                node_name: None,
+            }],
+            _ => vec![],
+        }
+    }
+
+    /// Inject values as environment variables so clap picks them up.
+    ///
+    /// Only sets variables that are **not** already present in the
+    /// environment, preserving the precedence: CLI > env > config file.
+    pub fn inject_env(&self) {
+        self.inject_env_inner(false);
+    }
+
+    /// Inject values as environment variables, **overriding** any existing
+    /// values.  Used after setup to ensure the freshly-saved config takes
+    /// effect before re-parsing.
+    pub fn inject_env_override(&self) {
+        self.inject_env_inner(true);
+    }
+
+    fn inject_env_inner(&self, force: bool) {
+        macro_rules! set {
+            ($env:expr, $val:expr) => {
+                if let Some(ref v) = $val {
+                    if force || std::env::var($env).is_err() {
+                        std::env::set_var($env, v.to_string());
+                    }
+                }
+            };
+        }
+
+        // When top-level fields are absent, fall back to the first [[servers]]
+        // entry so that clap's required `aether_url` / `management_token` are
+        // satisfied even with the new config format.
+        let first_server = self.servers.first();
+        let aether_url = self
+            .aether_url
+            .as_deref()
+            .or(first_server.map(|s| s.aether_url.as_str()));
+        let management_token = self
+            .management_token
+            .as_deref()
+            .or(first_server.map(|s| s.management_token.as_str()));
+        let node_name = self
+            .node_name
+            .as_deref()
+            .or(first_server.and_then(|s| s.node_name.as_deref()));
+
+        set!("AETHER_PROXY_AETHER_URL", aether_url);
+        set!("AETHER_PROXY_MANAGEMENT_TOKEN", management_token);
+        set!("AETHER_PROXY_PUBLIC_IP", self.public_ip);
+        set!("AETHER_PROXY_PUBLIC_IPV6", self.public_ipv6);
+        set!(
+            "AETHER_PROXY_PUBLIC_IP_CHECK_INTERVAL",
+            self.public_ip_check_interval_secs
+        );
+        set!("AETHER_PROXY_NODE_NAME", node_name);
+        set!("AETHER_PROXY_NODE_REGION", self.node_region);
+        set!("AETHER_PROXY_HEARTBEAT_INTERVAL", self.heartbeat_interval);
+        set!(
+            "AETHER_PROXY_LIVE_VIEW_PUBLIC_KEY",
+            self.live_view_public_key
+        );
+        set!("AETHER_PROXY_REMOTE_COMMANDS", self.remote_commands);
+        set!("AETHER_PROXY_ACCESS_LOG", self.access_log);
+        set!("AETHER_PROXY_LOG_PRIVACY", self.log_privacy);
+        set!("AETHER_PROXY_LOG_PRIVACY_SECRET", self.log_privacy_secret);
+        set!("AETHER_PROXY_TRUST_REQUEST_ID", self.trust_request_id);
+        set!(
+            "AETHER_PROXY_USAGE_REPORT_INTERVAL",
+            self.usage_report_interval_secs
+        );
+        set!(
+            "AETHER_PROXY_DESTINATION_REPORT_INTERVAL",
+            self.destination_report_interval_secs
+        );
+        set!(
+            "AETHER_PROXY_DESTINATION_REPORT_TOP",
+            self.destination_report_top
+        );
+        set!("AETHER_PROXY_CLOCK_SYNC", self.clock_sync);
+        set!(
+            "AETHER_PROXY_AETHER_REQUEST_TIMEOUT",
+            self.aether_request_timeout_secs
+        );
+        set!(
+            "AETHER_PROXY_AETHER_CONNECT_TIMEOUT",
+            self.aether_connect_timeout_secs
+        );
+        set!(
+            "AETHER_PROXY_AETHER_POOL_MAX_IDLE_PER_HOST",
+            self.aether_pool_max_idle_per_host
+        );
+        set!(
+            "AETHER_PROXY_AETHER_POOL_IDLE_TIMEOUT",
+            self.aether_pool_idle_timeout_secs
+        );
+        set!(
+            "AETHER_PROXY_AETHER_TCP_KEEPALIVE",
+            self.aether_tcp_keepalive_secs
+        );
+        set!(
+            "AETHER_PROXY_ALLOW_PRIVATE_TARGETS",
+            self.allow_private_targets
+        );
+        set!("AETHER_PROXY_MAX_HOPS", self.max_hops);
+        set!("AETHER_PROXY_AETHER_TCP_NODELAY", self.aether_tcp_nodelay);
+        set!("AETHER_PROXY_AETHER_HTTP2", self.aether_http2);
+        set!("AETHER_PROXY_AETHER_CA_CERT", self.aether_ca_cert);
+        set!("AETHER_PROXY_AETHER_PROXY_URL", self.aether_proxy_url);
+        set!("AETHER_PROXY_AETHER_CLIENT_CERT", self.aether_client_cert);
+        set!("AETHER_PROXY_AETHER_CLIENT_KEY", self.aether_client_key);
+        set!(
+            "AETHER_PROXY_AETHER_RETRY_MAX_ATTEMPTS",
+            self.aether_retry_max_attempts
+        );
+        set!(
+            "AETHER_PROXY_AETHER_RETRY_BASE_DELAY_MS",
+            self.aether_retry_base_delay_ms
+        );
+        set!(
+            "AETHER_PROXY_AETHER_RETRY_MAX_DELAY_MS",
+            self.aether_retry_max_delay_ms
+        );
+        set!(
+            "AETHER_PROXY_AETHER_ENDPOINT_REELECT",
+            self.aether_endpoint_reelect_secs
+        );
+        set!(
+            "AETHER_PROXY_MAX_CONCURRENT_CONNECTIONS",
+            self.max_concurrent_connections
+        );
+        set!("AETHER_PROXY_DNS_MODE", self.dns_mode);
+        set!("AETHER_PROXY_DNS_URL", self.dns_url);
+        set!("AETHER_PROXY_DNS_CACHE_TTL", self.dns_cache_ttl_secs);
+        set!("AETHER_PROXY_DNS_CACHE_CAPACITY", self.dns_cache_capacity);
+        set!("AETHER_PROXY_DNS_NEGATIVE_TTL", self.dns_negative_ttl_secs);
+        set!(
+            "AETHER_PROXY_UPSTREAM_CONNECT_TIMEOUT",
+            self.upstream_connect_timeout_secs
+        );
+        set!(
+            "AETHER_PROXY_UPSTREAM_HANDSHAKE_TIMEOUT",
+            self.upstream_handshake_timeout_secs
+        );
+        set!(
+            "AETHER_PROXY_UPSTREAM_STALL_TIMEOUT",
+            self.upstream_stall_timeout_secs
+        );
+        set!("AETHER_PROXY_MAX_REQUEST_BODY", self.max_request_body);
+        set!("AETHER_PROXY_MAX_RESPONSE_BODY", self.max_response_body);
+        set!("AETHER_PROXY_RESPONSE_CACHE_SIZE", self.response_cache_size);
+        set!(
+            "AETHER_PROXY_UPSTREAM_POOL_MAX_IDLE_PER_HOST",
+            self.upstream_pool_max_idle_per_host
+        );
+        set!(
+            "AETHER_PROXY_UPSTREAM_POOL_IDLE_TIMEOUT",
+            self.upstream_pool_idle_timeout_secs
+        );
+        set!(
+            "AETHER_PROXY_HOT_TARGET_CONNECTIONS",
+            self.hot_target_connections
+        );
+        set!(
+            "AETHER_PROXY_HOT_TARGET_MAX_IDLE",
+            self.hot_target_max_idle_secs
+        );
+        set!("AETHER_PROXY_PROBE_INTERVAL", self.probe_interval_secs);
+        set!(
+            "AETHER_PROXY_UPSTREAM_TCP_KEEPALIVE",
+            self.upstream_tcp_keepalive_secs
+        );
+        set!(
+            "AETHER_PROXY_UPSTREAM_TCP_NODELAY",
+            self.upstream_tcp_nodelay
+        );
+        set!(
+            "AETHER_PROXY_UPSTREAM_HAPPY_EYEBALLS_DELAY",
+            self.upstream_happy_eyeballs_delay_ms
+        );
+        set!(
+            "AETHER_PROXY_TCP_KEEPALIVE_INTERVAL",
+            self.tcp_keepalive_interval_secs
+        );
+        set!(
+            "AETHER_PROXY_TCP_KEEPALIVE_PROBES",
+            self.tcp_keepalive_probes
+        );
+        set!("AETHER_PROXY_TCP_RECV_BUFFER", self.tcp_recv_buffer);
+        set!("AETHER_PROXY_TCP_SEND_BUFFER", self.tcp_send_buffer);
+        set!(
+            "AETHER_PROXY_UPSTREAM_CONNECT_ATTEMPTS",
+            self.upstream_connect_attempts
+        );
+        set!("AETHER_PROXY_UPSTREAM_CA_BUNDLE", self.upstream_ca_bundle);
+        set!("AETHER_PROXY_UPSTREAM_ECH", self.upstream_ech);
+        set!("AETHER_PROXY_UPSTREAM_PROXY", self.upstream_proxy);
+        set!(
+            "AETHER_PROXY_UPSTREAM_PROXY_HEALTH_INTERVAL",
+            self.upstream_proxy_health_interval_secs
+        );
+        set!("AETHER_PROXY_ANONYMITY", self.anonymity);
+        set!("AETHER_PROXY_EGRESS_INTERFACE", self.egress_interface);
+        set!("AETHER_PROXY_EGRESS_ALT_BIND_IP", self.egress_alt_bind_ip);
+        set!("AETHER_PROXY_LOG_LEVEL", self.log_level);
+        set!("AETHER_PROXY_LOG_JSON", self.log_json);
+        set!(
+            "AETHER_PROXY_LOG_FILE_MAX_SIZE_MB",
+            self.log_file_max_size_mb
+        );
+        set!("AETHER_PROXY_LOG_FILE_DAILY", self.log_file_daily);
+        set!("AETHER_PROXY_LOG_FILE_KEEP", self.log_file_keep);
+        set!("AETHER_PROXY_SENTRY_DSN", self.sentry_dsn);
+        set!("AETHER_PROXY_ERROR_WEBHOOK_URL", self.error_webhook_url);
+        set!("AETHER_PROXY_METRICS_BACKEND", self.metrics_backend);
+        set!("AETHER_PROXY_METRICS_INTERVAL", self.metrics_interval_secs);
+        set!("AETHER_PROXY_METRICS_LISTEN", self.metrics_listen);
+        set!("AETHER_PROXY_METRICS_STATSD_ADDR", self.metrics_statsd_addr);
+        set!(
+            "AETHER_PROXY_METRICS_EMF_NAMESPACE",
+            self.metrics_emf_namespace
+        );
+        set!("AETHER_PROXY_METRICS_EMF_PATH", self.metrics_emf_path);
+        set!("AETHER_PROXY_ADMIN_LISTEN", self.admin_listen);
+        set!("AETHER_PROXY_ADMIN_ALLOW_REMOTE", self.admin_allow_remote);
+        set!("AETHER_PROXY_ADMIN_TOKEN", self.admin_token);
+        set!("AETHER_PROXY_ADMIN_BAN_THRESHOLD", self.admin_ban_threshold);
+        set!("AETHER_PROXY_ADMIN_BAN_SECS", self.admin_ban_secs);
+        set!(
+            "AETHER_PROXY_TUNNEL_RECONNECT_BASE_MS",
+            self.tunnel_reconnect_base_ms
+        );
+        set!(
+            "AETHER_PROXY_TUNNEL_RECONNECT_MAX_MS",
+            self.tunnel_reconnect_max_ms
+        );
+        set!(
+            "AETHER_PROXY_TUNNEL_PING_INTERVAL",
+            self.tunnel_ping_interval_secs
+        );
+        set!("AETHER_PROXY_TUNNEL_MAX_STREAMS", self.tunnel_max_streams);
+        set!(
+            "AETHER_PROXY_MAX_STREAMS_PER_CLIENT",
+            self.max_streams_per_client
+        );
+        set!("AETHER_PROXY_LOAD_SHED_THRESHOLD", self.load_shed_threshold);
+        set!("AETHER_PROXY_LOAD_SHED_CRITICAL", self.load_shed_critical);
+        set!("AETHER_PROXY_LOAD_SHED_BULK_BODY", self.load_shed_bulk_body);
+        set!("AETHER_PROXY_RUNTIME_FLAVOR", self.runtime_flavor);
+        set!("AETHER_PROXY_WORKER_THREADS", self.worker_threads);
+        set!(
+            "AETHER_PROXY_MAX_BLOCKING_THREADS",
+            self.max_blocking_threads
+        );
+        set!(
+            "AETHER_PROXY_CIRCUIT_BREAKER_FAILURES",
+            self.circuit_breaker_failures
+        );
+        set!(
+            "AETHER_PROXY_CIRCUIT_BREAKER_OPEN",
+            self.circuit_breaker_open_secs
+        );
+        set!(
+            "AETHER_PROXY_CIRCUIT_BREAKER_COUNT_5XX",
+            self.circuit_breaker_count_5xx
+        );
+        set!(
+            "AETHER_PROXY_TUNNEL_CONNECT_TIMEOUT",
+            self.tunnel_connect_timeout_secs
+        );
+        set!(
+            "AETHER_PROXY_TUNNEL_TCP_KEEPALIVE",
+            self.tunnel_tcp_keepalive_secs
+        );
+        set!("AETHER_PROXY_TUNNEL_TCP_NODELAY", self.tunnel_tcp_nodelay);
+        set!(
+            "AETHER_PROXY_TUNNEL_STALE_TIMEOUT",
+            self.tunnel_stale_timeout_secs
+        );
+        set!("AETHER_PROXY_HANDOFF_OVERLAP", self.handoff_overlap_secs);
+        set!("AETHER_PROXY_TUNNEL_CONNECTIONS", self.tunnel_connections);
+
+        // allowed_ports needs special handling (comma-separated)
+        if let Some(ref ports) = self.allowed_ports {
+            if force || std::env::var("AETHER_PROXY_ALLOWED_PORTS").is_err() {
+                let s: String = ports
+                    .iter()
+                    .map(|p| p.to_string())
+                    .collect::<Vec<_>>()
+                    .join(",");
+                std::env::set_var("AETHER_PROXY_ALLOWED_PORTS", s);
+            }
+        }
+
+        // Domain and CIDR lists are comma-separated as well
+        for (env, list) in [
+            ("AETHER_PROXY_ALLOWED_DOMAINS", &self.allowed_domains),
+            ("AETHER_PROXY_BLOCKED_DOMAINS", &self.blocked_domains),
+            ("AETHER_PROXY_ALLOWED_CIDRS", &self.allowed_cidrs),
+            ("AETHER_PROXY_BLOCKED_CIDRS", &self.blocked_cidrs),
+            ("AETHER_PROXY_DNS_SERVERS", &self.dns_servers),
+            ("AETHER_PROXY_HOST_OVERRIDES", &self.host_overrides),
+            (
+                "AETHER_PROXY_UPSTREAM_PROXY_RULES",
+                &self.upstream_proxy_rules,
+            ),
+            ("AETHER_PROXY_RELAY_HOSTS", &self.relay_hosts),
+            ("AETHER_PROXY_HEADER_RULES", &self.header_rules),
+            ("AETHER_PROXY_HOT_TARGETS", &self.hot_targets),
+            (
+                "AETHER_PROXY_LOAD_SHED_BULK_HOSTS",
+                &self.load_shed_bulk_hosts,
+            ),
+            ("AETHER_PROXY_PROBE_TARGETS", &self.probe_targets),
+            ("AETHER_PROXY_LOG_OUTPUT", &self.log_output),
+            ("AETHER_PROXY_EGRESS_BIND_IP", &self.egress_bind_ip),
+            ("AETHER_PROXY_FEATURES", &self.features),
+            ("AETHER_PROXY_AETHER_CERT_PIN", &self.aether_cert_pin),
+            ("AETHER_PROXY_UPSTREAM_CERT_PINS", &self.upstream_cert_pins),
+            (
+                "AETHER_PROXY_UPSTREAM_TLS_INSECURE",
+                &self.upstream_tls_insecure,
+            ),
+            ("AETHER_PROXY_IP_ECHO_URLS", &self.ip_echo_urls),
+            ("AETHER_PROXY_STUN_SERVERS", &self.stun_servers),
+        ] {
+            if let Some(ref entries) = list {
+                if force || std::env::var(env).is_err() {
+                    std::env::set_var(env, entries.join(","));
+                }
+            }
+        }
+    }
+}
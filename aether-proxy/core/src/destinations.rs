@@ -0,0 +1,237 @@
+//! Top destinations report.
+//!
+//! With `--destination-report-interval-secs` set, each server connection counts
+//! requests and bytes per target host, and at every interval uploads the
+//! `--destination-report-top` busiest hosts (by requests, then bytes) to
+//! that server's `/api/admin/proxy-nodes/destinations` endpoint, with the
+//! remaining hosts summed up as `other`.  This shows admins what their exit
+//! nodes are used for.  Unlike usage rollups, a report that cannot be
+//! delivered is dropped: it is for visibility, not billing.
+//!
+//! The endpoint needs an Aether release that accepts these reports; a
+//! server that answers 404 gets no more reports until the node restarts.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::sync::watch;
+use tracing::{debug, warn};
+
+use crate::clock::now_ms;
+use crate::registration::client;
+use crate::state::ServerContext;
+
+/// Distinct hosts per period; further hosts are counted under
+/// [`OVERFLOW_HOST`].
+const MAX_HOSTS: usize = 10_000;
+const OVERFLOW_HOST: &str = "_other";
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct Usage {
+    pub requests: u64,
+    /// Requests that were rejected or failed.
+    pub failed: u64,
+    pub bytes_received: u64,
+    pub bytes_sent: u64,
+}
+
+impl Usage {
+    fn add(&mut self, other: &Usage) {
+        self.requests += other.requests;
+        self.failed += other.failed;
+        self.bytes_received += other.bytes_received;
+        self.bytes_sent += other.bytes_sent;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Destination {
+    pub host: String,
+    #[serde(flatten)]
+    pub usage: Usage,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    /// Unix milliseconds, start inclusive.
+    pub period_start: u64,
+    pub period_end: u64,
+    /// Busiest first.
+    pub top: Vec<Destination>,
+    /// All hosts not in `top`.
+    pub other: Usage,
+    /// Distinct hosts seen in the period.
+    pub hosts: usize,
+}
+
+struct Period {
+    started: u64,
+    hosts: HashMap<String, Usage>,
+}
+
+/// Requests per target host of one server connection since the last
+/// report.
+pub struct DestinationMeter {
+    current: Mutex<Period>,
+}
+
+impl Default for DestinationMeter {
+    fn default() -> Self {
+        Self {
+            current: Mutex::new(Period {
+                started: now_ms(),
+                hosts: HashMap::new(),
+            }),
+        }
+    }
+}
+
+impl DestinationMeter {
+    /// Account one finished (or refused) stream to `host`.
+    pub fn record(&self, host: &str, failed: bool, bytes_received: u64, bytes_sent: u64) {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        let mut current = self.current.lock().unwrap();
+        let key = if current.hosts.len() < MAX_HOSTS || current.hosts.contains_key(&host) {
+            host
+        } else {
+            OVERFLOW_HOST.to_string()
+        };
+        current.hosts.entry(key).or_default().add(&Usage {
+            requests: 1,
+            failed: u64::from(failed),
+            bytes_received,
+            bytes_sent,
+        });
+    }
+
+    /// Close the current period and report its `top` busiest hosts; `None`
+    /// if nothing was requested in it.
+    pub fn take(&self, top: usize) -> Option<Report> {
+        let now = now_ms();
+        let period = std::mem::replace(
+            &mut *self.current.lock().unwrap(),
+            Period {
+                started: now,
+                hosts: HashMap::new(),
+            },
+        );
+        if period.hosts.is_empty() {
+            return None;
+        }
+        let hosts = period.hosts.len();
+        let mut ranked: Vec<Destination> = period
+            .hosts
+            .into_iter()
+            .map(|(host, usage)| Destination { host, usage })
+            .collect();
+        ranked.sort_by(|a, b| {
+            let bytes = |d: &Destination| d.usage.bytes_received + d.usage.bytes_sent;
+            (b.usage.requests, bytes(b), &a.host).cmp(&(a.usage.requests, bytes(a), &b.host))
+        });
+        let mut other = Usage::default();
+        for rest in ranked.drain(top.min(ranked.len())..) {
+            other.add(&rest.usage);
+        }
+        Some(Report {
+            period_start: period.started,
+            period_end: now,
+            top: ranked,
+            other,
+            hosts,
+        })
+    }
+}
+
+/// Upload a report of each server every `interval` until shutdown, then
+/// once more.
+pub fn spawn_reporter(
+    servers: Arc<tokio::sync::Mutex<Vec<Arc<ServerContext>>>>,
+    interval: Duration,
+    top: usize,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    tokio::spawn(async move {
+        // Servers without the destinations endpoint
+        let mut unsupported: HashSet<String> = HashSet::new();
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        ticker.tick().await;
+        loop {
+            let stopping = tokio::select! {
+                _ = ticker.tick() => false,
+                _ = shutdown.changed() => true,
+            };
+            let servers = servers.lock().await.clone();
+            for server in &servers {
+                let Some(report) = server.destinations.take(top) else {
+                    continue;
+                };
+                if unsupported.contains(&server.server_label) {
+                    continue;
+                }
+                let node_id = server.node_id.read().unwrap().clone();
+                match server
+                    .aether_client
+                    .upload_destinations(&node_id, &report)
+                    .await
+                {
+                    Ok(()) => {}
+                    Err(e) if client::is_unsupported(&e) => {
+                        warn!(server = %server.server_label, error = %e, "destination reports disabled");
+                        unsupported.insert(server.server_label.clone());
+                    }
+                    Err(e) => debug!(
+                        server = %server.server_label,
+                        error = %e,
+                        "destination report upload failed, dropping it"
+                    ),
+                }
+            }
+            if stopping {
+                return;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_ranks_and_resets() {
+        let meter = DestinationMeter::default();
+        assert!(meter.take(2).is_none());
+        for _ in 0..3 {
+            meter.record("API.openai.com.", false, 10, 100);
+        }
+        meter.record("api.anthropic.com", true, 0, 0);
+        meter.record("api.anthropic.com", false, 5, 5000);
+        meter.record("example.com", false, 1, 1);
+        meter.record("example.org", false, 1, 1);
+
+        let report = meter.take(2).unwrap();
+        let hosts: Vec<&str> = report.top.iter().map(|d| d.host.as_str()).collect();
+        assert_eq!(hosts, ["api.openai.com", "api.anthropic.com"]);
+        assert_eq!(
+            report.top[1].usage,
+            Usage {
+                requests: 2,
+                failed: 1,
+                bytes_received: 5,
+                bytes_sent: 5000,
+            }
+        );
+        assert_eq!(report.other.requests, 2);
+        assert_eq!(report.other.bytes_sent, 2);
+        assert_eq!(report.hosts, 4);
+        assert!(report.period_end >= report.period_start);
+
+        let json = serde_json::to_value(&report.top[0]).unwrap();
+        assert_eq!(json["host"], "api.openai.com");
+        assert_eq!(json["requests"], 3);
+        assert!(meter.take(2).is_none());
+    }
+}
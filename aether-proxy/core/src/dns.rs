@@ -0,0 +1,546 @@
+//! Target hostname resolution.
+//!
+//! By default targets are resolved through the nameservers and hosts file
+//! of the system configuration (`/etc/resolv.conf`, `/etc/hosts`), queried
+//! via hickory-resolver so that record TTLs and NXDOMAIN answers reach the
+//! DNS cache; `getaddrinfo` is the fallback where that configuration
+//! can't be read.  With `--dns-server` the node queries the given
+//! nameservers instead, which sidesteps poisoned or slow VPS defaults.
+//! `--dns-mode dot|doh` with `--dns-url` switches those queries to
+//! DNS-over-TLS / DNS-over-HTTPS so lookups can't be observed or tampered
+//! with on the wire.  `--host-override` pins selected domains to fixed
+//! addresses without any lookup.
+//!
+//! With `--upstream-ech` the `HTTPS` records of targets are looked up as
+//! well, for the Encrypted Client Hello configs they advertise.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use hickory_resolver::config::{LookupIpStrategy, NameServerConfig, ResolverConfig};
+use hickory_resolver::name_server::TokioConnectionProvider;
+use hickory_resolver::proto::rr::rdata::svcb::SvcParamValue;
+use hickory_resolver::proto::rr::{RData, RecordType};
+use hickory_resolver::proto::xfer::Protocol;
+use hickory_resolver::proto::ProtoErrorKind;
+use hickory_resolver::{ResolveError, ResolverBuilder, TokioResolver};
+use tracing::warn;
+
+use crate::target_filter::DomainPattern;
+
+/// Transport used to reach the configured nameservers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DnsMode {
+    /// Classic DNS over UDP, falling back to TCP.
+    Plain,
+    /// DNS-over-TLS (RFC 7858).
+    Dot,
+    /// DNS-over-HTTPS (RFC 8484).
+    Doh,
+}
+
+impl DnsMode {
+    fn parse(raw: &str) -> Result<Self, String> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "plain" => Ok(Self::Plain),
+            "dot" => Ok(Self::Dot),
+            "doh" => Ok(Self::Doh),
+            other => Err(format!(
+                "invalid dns mode {:?} (use plain, dot or doh)",
+                other
+            )),
+        }
+    }
+
+    fn default_port(self) -> u16 {
+        match self {
+            Self::Plain => 53,
+            Self::Dot => 853,
+            Self::Doh => 443,
+        }
+    }
+}
+
+/// Parsed resolver settings, validated at startup before any network I/O.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsSettings {
+    mode: DnsMode,
+    /// Nameserver addresses.  For DoT/DoH these may be empty, in which case
+    /// the `--dns-url` host is resolved once at startup.
+    servers: Vec<SocketAddr>,
+    /// TLS server name and port taken from `--dns-url` (DoT/DoH only).
+    tls_name: Option<String>,
+    url_port: u16,
+    /// DoH request path (hickory defaults to `/dns-query`).
+    http_endpoint: Option<String>,
+}
+
+impl DnsSettings {
+    /// Parse `--dns-mode`, `--dns-server` and `--dns-url`.
+    pub fn parse<S: AsRef<str>>(
+        mode: &str,
+        servers: &[S],
+        url: Option<&str>,
+    ) -> Result<Self, String> {
+        let mode = DnsMode::parse(mode)?;
+        let mut settings = Self {
+            mode,
+            servers: Vec::new(),
+            tls_name: None,
+            url_port: mode.default_port(),
+            http_endpoint: None,
+        };
+
+        match (mode, url) {
+            (DnsMode::Plain, Some(_)) => {
+                return Err("dns_url requires dns_mode dot or doh".to_string());
+            }
+            (DnsMode::Plain, None) => {}
+            (_, None) => return Err("dns_mode dot/doh requires dns_url".to_string()),
+            (_, Some(raw)) => {
+                let url = url::Url::parse(raw).map_err(|e| format!("invalid dns_url: {}", e))?;
+                let expected = if mode == DnsMode::Doh { "https" } else { "tls" };
+                if url.scheme() != expected {
+                    return Err(format!("dns_url must use {}:// in this mode", expected));
+                }
+                let host = match url.host() {
+                    Some(url::Host::Domain(d)) => d.to_string(),
+                    Some(url::Host::Ipv4(ip)) => ip.to_string(),
+                    Some(url::Host::Ipv6(ip)) => ip.to_string(),
+                    None => return Err("dns_url is missing a host".to_string()),
+                };
+                settings.url_port = url.port().unwrap_or(mode.default_port());
+                if mode == DnsMode::Doh && url.path() != "/" {
+                    settings.http_endpoint = Some(url.path().to_string());
+                }
+                if let Ok(ip) = host.parse::<IpAddr>() {
+                    settings
+                        .servers
+                        .push(SocketAddr::new(ip, settings.url_port));
+                }
+                settings.tls_name = Some(host);
+            }
+        }
+
+        // Explicit servers override the address derived from the URL.
+        let explicit = parse_servers(servers, settings.url_port)?;
+        if !explicit.is_empty() {
+            settings.servers = explicit;
+        }
+        Ok(settings)
+    }
+}
+
+/// Static `domain=ip` mappings that bypass DNS, hosts-file style.  A
+/// pinned address still has to pass the address policy and CIDR rules.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HostOverrides {
+    /// First match wins.
+    entries: Vec<(DomainPattern, Vec<IpAddr>)>,
+}
+
+impl HostOverrides {
+    /// Parse `--host-override` entries: `domain=ip`, with `|` between
+    /// several addresses (`api.example.com=203.0.113.7|2001:db8::7`).
+    pub fn parse<S: AsRef<str>>(entries: &[S]) -> Result<Self, String> {
+        let mut parsed = Vec::new();
+        for entry in entries
+            .iter()
+            .map(|e| e.as_ref().trim())
+            .filter(|e| !e.is_empty())
+        {
+            let (pattern, addrs) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("{:?} must be `domain=ip`", entry))?;
+            let pattern = DomainPattern::parse(pattern)?;
+            let addrs = addrs
+                .split('|')
+                .map(|ip| {
+                    ip.trim()
+                        .trim_start_matches('[')
+                        .trim_end_matches(']')
+                        .parse::<IpAddr>()
+                        .map_err(|_| format!("{:?}: invalid IP address {:?}", entry, ip.trim()))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            parsed.push((pattern, addrs));
+        }
+        Ok(Self { entries: parsed })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Pinned addresses for `host`, if any.
+    pub fn get(&self, host: &str) -> Option<&[IpAddr]> {
+        self.entries
+            .iter()
+            .find(|(pattern, _)| pattern.matches_host(host))
+            .map(|(_, addrs)| addrs.as_slice())
+    }
+
+    /// Human-readable mapping for startup logs.
+    pub fn describe(&self) -> String {
+        self.entries
+            .iter()
+            .map(|(pattern, addrs)| {
+                let addrs: Vec<String> = addrs.iter().map(IpAddr::to_string).collect();
+                format!("{}={}", pattern, addrs.join("|"))
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Successful lookup result.
+pub struct Resolved {
+    pub addrs: Vec<SocketAddr>,
+    /// Remaining record TTL, when the backend reports one.
+    pub ttl: Option<Duration>,
+}
+
+/// Lookup failure.
+#[derive(Debug)]
+pub enum LookupError {
+    /// The name has no address records (NXDOMAIN or NODATA).  Safe to
+    /// cache negatively; `negative_ttl` comes from the zone's SOA if present.
+    NotFound { negative_ttl: Option<Duration> },
+    /// Timeout, network or server failure; not cached.
+    Failed(io::Error),
+}
+
+impl LookupError {
+    fn from_resolve(err: ResolveError) -> Self {
+        let Some(proto) = err.proto() else {
+            return Self::Failed(io::Error::other(err.to_string()));
+        };
+        match proto.kind() {
+            ProtoErrorKind::NoRecordsFound { negative_ttl, .. } => Self::NotFound {
+                negative_ttl: negative_ttl.map(|s| Duration::from_secs(u64::from(s))),
+            },
+            _ => Self::Failed(io::Error::other(err.to_string())),
+        }
+    }
+}
+
+/// Resolver backend used for upstream target lookups.
+pub enum DnsResolver {
+    /// The system configuration's nameservers and hosts file, queried
+    /// through hickory-resolver.
+    System(Box<TokioResolver>),
+    /// `getaddrinfo` via `tokio::net::lookup_host`, when the system
+    /// configuration can't be read.
+    Getaddrinfo,
+    /// Explicit nameservers queried through hickory-resolver.
+    Custom(Box<TokioResolver>),
+}
+
+impl DnsResolver {
+    /// Build the resolver.  Plain mode without servers selects the system
+    /// configuration; a DoT/DoH URL with a hostname and no `--dns-server`
+    /// is bootstrapped through the system resolver once.
+    pub async fn build(settings: &DnsSettings) -> Result<Self, String> {
+        if settings.mode == DnsMode::Plain && settings.servers.is_empty() {
+            return Ok(match TokioResolver::builder_tokio() {
+                Ok(builder) => Self::System(Box::new(Self::finish(builder))),
+                Err(e) => {
+                    warn!(error = %e, "cannot read the system DNS configuration, using getaddrinfo without record TTLs");
+                    Self::Getaddrinfo
+                }
+            });
+        }
+
+        let mut servers = settings.servers.clone();
+        if servers.is_empty() {
+            let host = settings.tls_name.as_deref().unwrap_or_default();
+            servers = tokio::net::lookup_host((host, settings.url_port))
+                .await
+                .map_err(|e| format!("cannot resolve DNS server {}: {}", host, e))?
+                .collect();
+        }
+
+        let mut config = ResolverConfig::new();
+        for addr in servers {
+            match settings.mode {
+                DnsMode::Plain => {
+                    config.add_name_server(NameServerConfig::new(addr, Protocol::Udp));
+                    config.add_name_server(NameServerConfig::new(addr, Protocol::Tcp));
+                }
+                DnsMode::Dot | DnsMode::Doh => {
+                    let protocol = if settings.mode == DnsMode::Dot {
+                        Protocol::Tls
+                    } else {
+                        Protocol::Https
+                    };
+                    let mut ns = NameServerConfig::new(addr, protocol);
+                    ns.tls_dns_name = settings.tls_name.clone();
+                    ns.http_endpoint = settings.http_endpoint.clone();
+                    config.add_name_server(ns);
+                }
+            }
+        }
+        let builder =
+            TokioResolver::builder_with_config(config, TokioConnectionProvider::default());
+        Ok(Self::Custom(Box::new(Self::finish(builder))))
+    }
+
+    fn finish(mut builder: ResolverBuilder<TokioConnectionProvider>) -> TokioResolver {
+        // Query A and AAAA together so dual-stack targets can be raced.
+        builder.options_mut().ip_strategy = LookupIpStrategy::Ipv4AndIpv6;
+        builder.build()
+    }
+
+    /// Resolve `host` to socket addresses on `port`.
+    ///
+    /// `getaddrinfo` reports neither TTLs nor a reliable NXDOMAIN signal,
+    /// so its results carry no TTL and its failures are never cached
+    /// negatively.
+    pub async fn lookup(&self, host: &str, port: u16) -> Result<Resolved, LookupError> {
+        match self {
+            Self::Getaddrinfo => {
+                let addrs = tokio::net::lookup_host((host, port))
+                    .await
+                    .map_err(LookupError::Failed)?
+                    .collect();
+                Ok(Resolved { addrs, ttl: None })
+            }
+            Self::System(resolver) | Self::Custom(resolver) => {
+                let lookup = resolver
+                    .lookup_ip(host)
+                    .await
+                    .map_err(LookupError::from_resolve)?;
+                let ttl = lookup
+                    .valid_until()
+                    .saturating_duration_since(Instant::now());
+                Ok(Resolved {
+                    addrs: lookup.iter().map(|ip| SocketAddr::new(ip, port)).collect(),
+                    ttl: Some(ttl),
+                })
+            }
+        }
+    }
+}
+
+/// ECH config lists from target `HTTPS` records (RFC 9460 `ech` parameter).
+///
+/// Queries go to the nameservers configured for targets, or to those of
+/// `/etc/resolv.conf` when targets use the system resolver.  Answers are
+/// cached for the record TTL (at most an hour); names without an ECH
+/// config and failed lookups are remembered for a while too, so that a
+/// nameserver ignoring `HTTPS` queries doesn't cost every connection.
+pub struct EchLookup {
+    resolver: TokioResolver,
+    /// Host -> (config list, expiry).
+    cache: Mutex<HashMap<String, CachedEch>>,
+}
+
+type CachedEch = (Option<Arc<[u8]>>, Instant);
+
+impl EchLookup {
+    const CAPACITY: usize = 4096;
+    const MAX_TTL: Duration = Duration::from_secs(3600);
+    const NEGATIVE_TTL: Duration = Duration::from_secs(300);
+    const FAILURE_TTL: Duration = Duration::from_secs(30);
+
+    /// Query through the same nameservers as `resolver`.
+    pub fn new(resolver: &DnsResolver) -> Result<Self, String> {
+        let resolver = match resolver {
+            DnsResolver::System(resolver) | DnsResolver::Custom(resolver) => (**resolver).clone(),
+            DnsResolver::Getaddrinfo => TokioResolver::builder_tokio()
+                .map_err(|e| format!("system DNS config: {}", e))?
+                .build(),
+        };
+        Ok(Self {
+            resolver,
+            cache: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// The ECH config list advertised for `host`, if any.
+    pub async fn config_list(&self, host: &str) -> Option<Arc<[u8]>> {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        let now = Instant::now();
+        if let Some((list, expires)) = self.cache.lock().unwrap().get(&host) {
+            if *expires > now {
+                return list.clone();
+            }
+        }
+
+        let (list, ttl) = match self.resolver.lookup(host.as_str(), RecordType::HTTPS).await {
+            Ok(lookup) => match ech_config_list(lookup.iter()) {
+                Some(list) => {
+                    let ttl = lookup.valid_until().saturating_duration_since(now);
+                    (Some(Arc::from(list)), ttl.min(Self::MAX_TTL))
+                }
+                None => (None, Self::NEGATIVE_TTL),
+            },
+            Err(err) => match LookupError::from_resolve(err) {
+                LookupError::NotFound { .. } => (None, Self::NEGATIVE_TTL),
+                LookupError::Failed(_) => (None, Self::FAILURE_TTL),
+            },
+        };
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= Self::CAPACITY {
+            cache.retain(|_, (_, expires)| *expires > now);
+            if cache.len() >= Self::CAPACITY {
+                cache.clear();
+            }
+        }
+        cache.insert(host, (list.clone(), now + ttl));
+        list
+    }
+
+    /// Drop the cached config of `host`, e.g. after the target rejected it.
+    pub fn forget(&self, host: &str) {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        self.cache.lock().unwrap().remove(&host);
+    }
+}
+
+/// The `ech` parameter of the most preferred service-mode `HTTPS` record.
+fn ech_config_list<'a>(records: impl Iterator<Item = &'a RData>) -> Option<Vec<u8>> {
+    records
+        .filter_map(|record| match record {
+            RData::HTTPS(https) if https.svc_priority() > 0 => Some(&https.0),
+            _ => None,
+        })
+        .filter_map(|svcb| {
+            svcb.svc_params().iter().find_map(|(_, value)| match value {
+                SvcParamValue::EchConfigList(list) => Some((svcb.svc_priority(), &list.0)),
+                _ => None,
+            })
+        })
+        .min_by_key(|(priority, _)| *priority)
+        .map(|(_, list)| list.clone())
+}
+
+/// Parse nameserver entries; a bare IP uses `default_port`.
+fn parse_servers<S: AsRef<str>>(
+    servers: &[S],
+    default_port: u16,
+) -> Result<Vec<SocketAddr>, String> {
+    servers
+        .iter()
+        .map(|s| s.as_ref().trim())
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            s.parse::<SocketAddr>()
+                .or_else(|_| {
+                    s.parse::<IpAddr>()
+                        .map(|ip| SocketAddr::new(ip, default_port))
+                })
+                .map_err(|_| format!("invalid DNS server: {:?}", s))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_servers() {
+        let addrs = parse_servers(
+            &["1.1.1.1", "8.8.8.8:5353", "[2606:4700::1111]:53", " "],
+            53,
+        )
+        .unwrap();
+        assert_eq!(
+            addrs,
+            vec![
+                "1.1.1.1:53".parse().unwrap(),
+                "8.8.8.8:5353".parse().unwrap(),
+                "[2606:4700::1111]:53".parse().unwrap(),
+            ]
+        );
+        assert!(parse_servers(&["dns.google"], 53).is_err());
+    }
+
+    #[test]
+    fn test_host_overrides() {
+        let overrides = HostOverrides::parse(&[
+            "api.openai.com=203.0.113.7",
+            "*.example.com=192.0.2.1|[2001:db8::1]",
+            " ",
+        ])
+        .unwrap();
+        assert_eq!(
+            overrides.get("API.openai.com."),
+            Some(&["203.0.113.7".parse().unwrap()][..])
+        );
+        assert_eq!(overrides.get("a.example.com").map(<[_]>::len), Some(2));
+        assert_eq!(overrides.get("example.com"), None);
+        assert_eq!(
+            overrides.describe(),
+            "api.openai.com=203.0.113.7, *.example.com=192.0.2.1|2001:db8::1"
+        );
+
+        assert!(HostOverrides::parse(&["api.openai.com"]).is_err());
+        assert!(HostOverrides::parse(&["api.openai.com=example.net"]).is_err());
+        assert!(HostOverrides::parse(&["=203.0.113.7"]).is_err());
+    }
+
+    #[test]
+    fn test_ech_config_list() {
+        use hickory_resolver::proto::rr::rdata::svcb::{EchConfigList, SvcParamKey, SVCB};
+        use hickory_resolver::proto::rr::rdata::{A, HTTPS};
+        use hickory_resolver::proto::rr::Name;
+
+        let https = |priority, ech: Option<&[u8]>| {
+            let params = ech
+                .map(|list| {
+                    (
+                        SvcParamKey::EchConfigList,
+                        SvcParamValue::EchConfigList(EchConfigList(list.to_vec())),
+                    )
+                })
+                .into_iter()
+                .collect();
+            RData::HTTPS(HTTPS(SVCB::new(priority, Name::root(), params)))
+        };
+        let records = [
+            RData::A(A::new(192, 0, 2, 1)),
+            // Alias mode carries no parameters that apply
+            https(0, Some(b"alias")),
+            https(2, Some(b"second")),
+            https(1, Some(b"first")),
+            https(0, None),
+        ];
+        assert_eq!(ech_config_list(records.iter()).unwrap(), b"first");
+        assert_eq!(ech_config_list(records[..2].iter()), None);
+        assert_eq!(ech_config_list([https(1, None)].iter()), None);
+    }
+
+    #[tokio::test]
+    async fn test_plain_without_servers_uses_system_resolver() {
+        let settings = DnsSettings::parse::<&str>("plain", &[], None).unwrap();
+        let resolver = DnsResolver::build(&settings).await.unwrap();
+        assert!(matches!(
+            resolver,
+            DnsResolver::System(_) | DnsResolver::Getaddrinfo
+        ));
+    }
+
+    #[test]
+    fn test_parse_doh_and_dot() {
+        let doh =
+            DnsSettings::parse::<&str>("doh", &[], Some("https://1.1.1.1/dns-query")).unwrap();
+        assert_eq!(doh.servers, vec!["1.1.1.1:443".parse().unwrap()]);
+        assert_eq!(doh.tls_name.as_deref(), Some("1.1.1.1"));
+        assert_eq!(doh.http_endpoint.as_deref(), Some("/dns-query"));
+
+        // Named host with explicit addresses: no bootstrap lookup needed
+        let dot = DnsSettings::parse("dot", &["9.9.9.9"], Some("tls://dns.quad9.net")).unwrap();
+        assert_eq!(dot.servers, vec!["9.9.9.9:853".parse().unwrap()]);
+        assert_eq!(dot.tls_name.as_deref(), Some("dns.quad9.net"));
+
+        assert!(DnsSettings::parse::<&str>("doh", &[], None).is_err());
+        assert!(DnsSettings::parse::<&str>("doh", &[], Some("tls://1.1.1.1")).is_err());
+        assert!(DnsSettings::parse::<&str>("plain", &[], Some("https://1.1.1.1")).is_err());
+        assert!(DnsSettings::parse::<&str>("dnscrypt", &[], None).is_err());
+    }
+}
@@ -0,0 +1,369 @@
+//! Reporting of node failures to Sentry and/or a webhook.
+//!
+//! With `--sentry-dsn` or `--error-webhook-url` set, these are reported
+//! together with the node's name, region, version and platform:
+//!
+//! - panics (the process keeps running if the panicking task was not
+//!   essential; one that takes the node down is reported best-effort)
+//! - [`HEARTBEAT_MISSES_REPORTED`] consecutive unacknowledged heartbeats
+//!   on a server connection
+//! - registration failures, at startup and when the node gives up retrying
+//!
+//! Sentry events go to the project's store endpoint; the webhook receives
+//! one JSON object per event (`kind`, `level`, `message`, `server`,
+//! `node_id`, `node_name`, `node_region`, `version`, `os`, `arch`, `ts`).
+//! The same kind of event for the same server is sent at most once per
+//! [`REPEAT_AFTER`], so a flapping node cannot flood the receiver.
+
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use reqwest::Url;
+use ring::rand::{SecureRandom, SystemRandom};
+use tracing::debug;
+
+use crate::config::Config;
+
+/// Consecutive unacknowledged heartbeats that are reported.
+pub const HEARTBEAT_MISSES_REPORTED: u32 = 3;
+/// Shortest interval between two reports of the same kind and server.
+pub const REPEAT_AFTER: Duration = Duration::from_secs(15 * 60);
+const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+static REPORTER: OnceLock<Reporter> = OnceLock::new();
+
+/// A Sentry project, from its DSN (`https://<key>@<host>/<project>`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SentryDsn {
+    store_url: Url,
+    key: String,
+}
+
+impl SentryDsn {
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let dsn = Url::parse(raw.trim()).map_err(|e| format!("invalid DSN: {}", e))?;
+        if dsn.username().is_empty() {
+            return Err("DSN has no public key".to_string());
+        }
+        let path = dsn.path().trim_end_matches('/');
+        let (prefix, project) = path.rsplit_once('/').unwrap_or(("", path));
+        if project.is_empty() {
+            return Err("DSN has no project id".to_string());
+        }
+        let mut store_url = dsn.clone();
+        let _ = store_url.set_username("");
+        let _ = store_url.set_password(None);
+        store_url.set_path(&format!("{}/api/{}/store/", prefix, project));
+        Ok(Self {
+            store_url,
+            key: dsn.username().to_string(),
+        })
+    }
+
+    fn auth_header(&self) -> String {
+        format!(
+            "Sentry sentry_version=7, sentry_key={}, sentry_client=aether-proxy/{}",
+            self.key,
+            env!("CARGO_PKG_VERSION")
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Kind {
+    Panic,
+    HeartbeatFailures,
+    RegistrationFailed,
+}
+
+impl Kind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Panic => "panic",
+            Self::HeartbeatFailures => "heartbeat_failures",
+            Self::RegistrationFailed => "registration_failed",
+        }
+    }
+
+    fn level(self) -> &'static str {
+        match self {
+            Self::Panic => "fatal",
+            Self::HeartbeatFailures => "warning",
+            Self::RegistrationFailed => "error",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub kind: Kind,
+    pub message: String,
+    /// Label of the server connection concerned.
+    pub server: Option<String>,
+    pub node_id: Option<String>,
+}
+
+impl Event {
+    pub fn new(kind: Kind, message: impl Into<String>) -> Self {
+        Self {
+            kind,
+            message: message.into(),
+            server: None,
+            node_id: None,
+        }
+    }
+
+    pub fn server(mut self, label: &str, node_id: Option<&str>) -> Self {
+        self.server = Some(label.to_string());
+        self.node_id = node_id.map(str::to_string);
+        self
+    }
+}
+
+/// What every event says about the node.
+#[derive(Debug, Clone)]
+struct NodeInfo {
+    name: String,
+    region: Option<String>,
+}
+
+struct Reporter {
+    sentry: Option<SentryDsn>,
+    webhook: Option<Url>,
+    node: NodeInfo,
+    http: reqwest::Client,
+    runtime: tokio::runtime::Handle,
+    last_sent: Mutex<HashMap<(Kind, Option<String>), Instant>>,
+}
+
+/// Set up reporting from `config` and hook panics; a no-op without a DSN or
+/// webhook, or when already set up.  Needs a Tokio runtime.
+pub fn init(config: &Config) -> anyhow::Result<()> {
+    let sentry = config.sentry_dsn()?;
+    let webhook = config.error_webhook_url()?;
+    if (sentry.is_none() && webhook.is_none()) || REPORTER.get().is_some() {
+        return Ok(());
+    }
+    let reporter = Reporter {
+        sentry,
+        webhook,
+        node: NodeInfo {
+            name: config.node_name.clone(),
+            region: config.node_region.clone(),
+        },
+        http: reqwest::Client::builder().timeout(SEND_TIMEOUT).build()?,
+        runtime: tokio::runtime::Handle::current(),
+        last_sent: Mutex::default(),
+    };
+    if REPORTER.set(reporter).is_ok() {
+        let previous = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            let location = info
+                .location()
+                .map(|l| format!(" at {}:{}", l.file(), l.line()))
+                .unwrap_or_default();
+            let payload = info
+                .payload()
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| info.payload().downcast_ref::<String>().cloned())
+                .unwrap_or_else(|| "panic".to_string());
+            report(Event::new(Kind::Panic, format!("{}{}", payload, location)));
+            previous(info);
+        }));
+    }
+    Ok(())
+}
+
+/// Send `event` in the background, unless reporting is off or the same
+/// event was sent within [`REPEAT_AFTER`].
+pub fn report(event: Event) {
+    let Some(reporter) = REPORTER.get() else {
+        return;
+    };
+    if reporter.throttled(&event, Instant::now()) {
+        return;
+    }
+    reporter.runtime.spawn(reporter.deliver(event));
+}
+
+/// Like [`report`], but waits for delivery; for failures the process is
+/// about to exit on.
+pub async fn report_now(event: Event) {
+    let Some(reporter) = REPORTER.get() else {
+        return;
+    };
+    if !reporter.throttled(&event, Instant::now()) {
+        reporter.deliver(event).await;
+    }
+}
+
+impl Reporter {
+    fn throttled(&self, event: &Event, now: Instant) -> bool {
+        let mut last_sent = self.last_sent.lock().unwrap();
+        let key = (event.kind, event.server.clone());
+        if last_sent
+            .get(&key)
+            .is_some_and(|at| now.duration_since(*at) < REPEAT_AFTER)
+        {
+            return true;
+        }
+        last_sent.insert(key, now);
+        false
+    }
+
+    async fn deliver(&self, event: Event) {
+        if let Some(dsn) = &self.sentry {
+            let result = self
+                .http
+                .post(dsn.store_url.clone())
+                .header("X-Sentry-Auth", dsn.auth_header())
+                .json(&sentry_event(&self.node, &event))
+                .send()
+                .await
+                .and_then(|resp| resp.error_for_status());
+            if let Err(e) = result {
+                debug!(error = %e, "sentry report failed");
+            }
+        }
+        if let Some(url) = &self.webhook {
+            let result = self
+                .http
+                .post(url.clone())
+                .json(&webhook_event(&self.node, &event))
+                .send()
+                .await
+                .and_then(|resp| resp.error_for_status());
+            if let Err(e) = result {
+                debug!(error = %e, "error webhook failed");
+            }
+        }
+    }
+}
+
+fn now() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+fn webhook_event(node: &NodeInfo, event: &Event) -> serde_json::Value {
+    serde_json::json!({
+        "kind": event.kind.as_str(),
+        "level": event.kind.level(),
+        "message": event.message,
+        "server": event.server,
+        "node_id": event.node_id,
+        "node_name": node.name,
+        "node_region": node.region,
+        "version": env!("CARGO_PKG_VERSION"),
+        "os": std::env::consts::OS,
+        "arch": std::env::consts::ARCH,
+        "ts": now().as_millis() as u64,
+    })
+}
+
+fn sentry_event(node: &NodeInfo, event: &Event) -> serde_json::Value {
+    let mut event_id = [0u8; 16];
+    let _ = SystemRandom::new().fill(&mut event_id);
+    let mut tags = serde_json::json!({
+        "kind": event.kind.as_str(),
+        "os": std::env::consts::OS,
+        "arch": std::env::consts::ARCH,
+    });
+    for (name, value) in [
+        ("server", &event.server),
+        ("node_id", &event.node_id),
+        ("node_region", &node.region),
+    ] {
+        if let Some(value) = value {
+            tags[name] = value.clone().into();
+        }
+    }
+    serde_json::json!({
+        "event_id": hex::encode(event_id),
+        "timestamp": now().as_secs_f64(),
+        "platform": "other",
+        "logger": "aether-proxy",
+        "level": event.kind.level(),
+        "server_name": node.name,
+        "release": format!("aether-proxy@{}", env!("CARGO_PKG_VERSION")),
+        "message": { "formatted": event.message },
+        "tags": tags,
+        "fingerprint": [event.kind.as_str(), event.server.as_deref().unwrap_or("")],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sentry_dsn() {
+        let dsn = SentryDsn::parse("https://abc123@o42.ingest.sentry.io/4501").unwrap();
+        assert_eq!(
+            dsn.store_url.as_str(),
+            "https://o42.ingest.sentry.io/api/4501/store/"
+        );
+        assert!(dsn.auth_header().contains("sentry_key=abc123,"));
+
+        let dsn = SentryDsn::parse("http://key@sentry.internal:9000/sentry/7/").unwrap();
+        assert_eq!(
+            dsn.store_url.as_str(),
+            "http://sentry.internal:9000/sentry/api/7/store/"
+        );
+
+        assert!(SentryDsn::parse("https://o42.ingest.sentry.io/4501").is_err());
+        assert!(SentryDsn::parse("https://abc@o42.ingest.sentry.io/").is_err());
+        assert!(SentryDsn::parse("not a dsn").is_err());
+    }
+
+    #[test]
+    fn test_events() {
+        let node = NodeInfo {
+            name: "jp-1".into(),
+            region: Some("ap-northeast".into()),
+        };
+        let event = Event::new(Kind::HeartbeatFailures, "3 heartbeats unacknowledged")
+            .server("server-1", Some("node-9"));
+
+        let hook = webhook_event(&node, &event);
+        assert_eq!(hook["kind"], "heartbeat_failures");
+        assert_eq!(hook["level"], "warning");
+        assert_eq!(hook["server"], "server-1");
+        assert_eq!(hook["node_id"], "node-9");
+        assert_eq!(hook["node_name"], "jp-1");
+
+        let sentry = sentry_event(&node, &event);
+        assert_eq!(sentry["event_id"].as_str().unwrap().len(), 32);
+        assert_eq!(sentry["server_name"], "jp-1");
+        assert_eq!(
+            sentry["message"]["formatted"],
+            "3 heartbeats unacknowledged"
+        );
+        assert_eq!(sentry["tags"]["node_region"], "ap-northeast");
+        assert_eq!(sentry["fingerprint"][1], "server-1");
+    }
+
+    #[tokio::test]
+    async fn test_repeats_are_throttled() {
+        let reporter = Reporter {
+            sentry: None,
+            webhook: None,
+            node: NodeInfo {
+                name: "n".into(),
+                region: None,
+            },
+            http: reqwest::Client::new(),
+            runtime: tokio::runtime::Handle::current(),
+            last_sent: Mutex::default(),
+        };
+        let start = Instant::now();
+        let on = |server: &str| Event::new(Kind::RegistrationFailed, "x").server(server, None);
+        assert!(!reporter.throttled(&on("server-0"), start));
+        assert!(reporter.throttled(&on("server-0"), start + Duration::from_secs(60)));
+        assert!(!reporter.throttled(&on("server-1"), start + Duration::from_secs(60)));
+        assert!(!reporter.throttled(&on("server-0"), start + REPEAT_AFTER));
+    }
+}
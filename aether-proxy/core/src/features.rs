@@ -0,0 +1,228 @@
+//! Feature flags for experimental subsystems.
+//!
+//! Each flag is `on`, `off`, or a rollout percentage (`25%`).  Percentages
+//! are bucketed by a stable hash of the flag name and node ID, so a given
+//! node stays in (or out of) the rollout across restarts and the fleet-wide
+//! share matches the percentage.
+//!
+//! Local rules come from `--feature name=value`; the control plane can
+//! override individual flags through `remote_config.features`.
+
+use std::collections::HashMap;
+
+use sha2::{Digest, Sha256};
+
+/// Known feature flags.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Feature {
+    /// Retry connect resets once via `--egress-alt-bind-ip`.
+    EgressRetry,
+    /// Reject streams by class under CPU/memory pressure.
+    LoadShedding,
+    /// Refuse targets that keep failing (see [`crate::circuit_breaker`]).
+    CircuitBreaker,
+}
+
+impl Feature {
+    pub const ALL: &'static [Feature] = &[
+        Feature::EgressRetry,
+        Feature::LoadShedding,
+        Feature::CircuitBreaker,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::EgressRetry => "egress_retry",
+            Self::LoadShedding => "load_shedding",
+            Self::CircuitBreaker => "circuit_breaker",
+        }
+    }
+
+    /// Value used when neither local config nor the control plane sets it.
+    fn default_rule(self) -> FlagRule {
+        match self {
+            Self::EgressRetry => FlagRule::On,
+            // Refusing streams under pressure is left to the operator
+            Self::LoadShedding => FlagRule::Off,
+            // Refusing a shared provider host affects every tenant
+            Self::CircuitBreaker => FlagRule::Off,
+        }
+    }
+}
+
+/// How a flag is evaluated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlagRule {
+    On,
+    Off,
+    /// Enabled on this share (0-100) of nodes.
+    Percent(u8),
+}
+
+impl FlagRule {
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let raw = raw.trim().to_ascii_lowercase();
+        match raw.as_str() {
+            "on" | "true" => Ok(Self::On),
+            "off" | "false" => Ok(Self::Off),
+            _ => raw
+                .strip_suffix('%')
+                .and_then(|p| p.trim().parse::<u8>().ok())
+                .filter(|p| *p <= 100)
+                .map(Self::Percent)
+                .ok_or_else(|| format!("invalid flag value {:?} (use on, off or N%)", raw)),
+        }
+    }
+
+    /// Parse a JSON value from the control plane (bool, 0-100 number, or string).
+    fn from_json(value: &serde_json::Value) -> Result<Self, String> {
+        match value {
+            serde_json::Value::Bool(true) => Ok(Self::On),
+            serde_json::Value::Bool(false) => Ok(Self::Off),
+            serde_json::Value::Number(n) => n
+                .as_u64()
+                .filter(|p| *p <= 100)
+                .map(|p| Self::Percent(p as u8))
+                .ok_or_else(|| format!("invalid flag percentage {}", n)),
+            serde_json::Value::String(s) => Self::parse(s),
+            other => Err(format!("invalid flag value {}", other)),
+        }
+    }
+
+    fn evaluate(self, feature: Feature, node_id: &str) -> bool {
+        match self {
+            Self::On => true,
+            Self::Off => false,
+            Self::Percent(p) => rollout_bucket(feature, node_id) < p,
+        }
+    }
+}
+
+/// Stable 0-99 bucket for a (feature, node) pair.
+fn rollout_bucket(feature: Feature, node_id: &str) -> u8 {
+    let digest = Sha256::new()
+        .chain_update(feature.as_str())
+        .chain_update(b":")
+        .chain_update(node_id)
+        .finalize();
+    let n = u64::from_be_bytes(digest[..8].try_into().expect("digest is 32 bytes"));
+    (n % 100) as u8
+}
+
+/// Effective flag set: local rules overlaid with remote overrides.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FeatureFlags {
+    local: HashMap<Feature, FlagRule>,
+    remote: HashMap<Feature, FlagRule>,
+}
+
+impl FeatureFlags {
+    /// Parse local `name=value` entries.
+    pub fn from_local<S: AsRef<str>>(entries: &[S]) -> Result<Self, String> {
+        let mut local = HashMap::new();
+        for entry in entries.iter().map(|e| e.as_ref().trim()) {
+            if entry.is_empty() {
+                continue;
+            }
+            let (name, value) = entry
+                .split_once('=')
+                .ok_or_else(|| format!("invalid feature {:?} (expected name=value)", entry))?;
+            let feature =
+                lookup(name.trim()).ok_or_else(|| format!("unknown feature {:?}", name.trim()))?;
+            local.insert(feature, FlagRule::parse(value)?);
+        }
+        Ok(Self {
+            local,
+            remote: HashMap::new(),
+        })
+    }
+
+    /// Replace the remote overrides.  Unknown flag names are ignored so the
+    /// control plane can roll out flags ahead of node upgrades; invalid
+    /// values are reported.
+    pub fn with_remote(
+        &self,
+        overrides: &HashMap<String, serde_json::Value>,
+    ) -> Result<Self, String> {
+        let mut remote = HashMap::new();
+        for (name, value) in overrides {
+            if let Some(feature) = lookup(name) {
+                let rule = FlagRule::from_json(value).map_err(|e| format!("{}: {}", name, e))?;
+                remote.insert(feature, rule);
+            }
+        }
+        Ok(Self {
+            local: self.local.clone(),
+            remote,
+        })
+    }
+
+    fn rule(&self, feature: Feature) -> FlagRule {
+        self.remote
+            .get(&feature)
+            .or_else(|| self.local.get(&feature))
+            .copied()
+            .unwrap_or_else(|| feature.default_rule())
+    }
+
+    /// Whether `feature` is enabled for the node identified by `node_id`.
+    pub fn enabled(&self, feature: Feature, node_id: &str) -> bool {
+        self.rule(feature).evaluate(feature, node_id)
+    }
+}
+
+fn lookup(name: &str) -> Option<Feature> {
+    Feature::ALL.iter().copied().find(|f| f.as_str() == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rules() {
+        assert_eq!(FlagRule::parse("on"), Ok(FlagRule::On));
+        assert_eq!(FlagRule::parse("OFF"), Ok(FlagRule::Off));
+        assert_eq!(FlagRule::parse("25%"), Ok(FlagRule::Percent(25)));
+        assert!(FlagRule::parse("101%").is_err());
+        assert!(FlagRule::parse("maybe").is_err());
+
+        assert!(FeatureFlags::from_local(&["egress_retry=off"]).is_ok());
+        assert!(FeatureFlags::from_local(&["nope=on"]).is_err());
+        assert!(FeatureFlags::from_local(&["egress_retry"]).is_err());
+    }
+
+    #[test]
+    fn test_remote_overrides_local() {
+        let flags = FeatureFlags::from_local(&["load_shedding=off"]).unwrap();
+        assert!(!flags.enabled(Feature::LoadShedding, "node"));
+        assert!(flags.enabled(Feature::EgressRetry, "node"));
+        assert!(!flags.enabled(Feature::CircuitBreaker, "node"));
+        assert!(!FeatureFlags::default().enabled(Feature::LoadShedding, "node"));
+
+        let overrides: HashMap<String, serde_json::Value> = serde_json::from_str(
+            r#"{"load_shedding": true, "egress_retry": "0%", "future_flag": "on"}"#,
+        )
+        .unwrap();
+        let flags = flags.with_remote(&overrides).unwrap();
+        assert!(flags.enabled(Feature::LoadShedding, "node"));
+        assert!(!flags.enabled(Feature::EgressRetry, "node"));
+
+        // Dropping the override falls back to the local rule
+        let flags = flags.with_remote(&HashMap::new()).unwrap();
+        assert!(!flags.enabled(Feature::LoadShedding, "node"));
+    }
+
+    #[test]
+    fn test_percentage_rollout_is_stable_and_proportional() {
+        let flags = FeatureFlags::from_local(&["egress_retry=30%"]).unwrap();
+        let enabled = (0..1000)
+            .filter(|i| flags.enabled(Feature::EgressRetry, &format!("node-{i}")))
+            .count();
+        assert!((250..350).contains(&enabled), "enabled on {enabled}/1000");
+        assert_eq!(
+            flags.enabled(Feature::EgressRetry, "node-7"),
+            flags.enabled(Feature::EgressRetry, "node-7")
+        );
+    }
+}
@@ -0,0 +1,258 @@
+//! Zero-downtime restart by handing the node over to a new process.
+//!
+//! Tunnels are outbound TLS WebSockets, so there is no listen socket whose
+//! file descriptor could carry them across an exec.  Instead, on `SIGUSR2`
+//! the running node starts a successor from the binary currently on disk
+//! (with the original arguments and environment, so a new config file is
+//! picked up), waits until the successor reports that its tunnels are up,
+//! then drains: in-flight streams finish on the old tunnels and new ones
+//! land on the successor's.  The node is not unregistered, since the
+//! successor shares its node ID.
+//!
+//! Readiness travels over a pipe whose write end the successor inherits
+//! (its number is in `AETHER_PROXY_HANDOFF_READY_FD`).  The successor
+//! writes one byte once a tunnel has connected.  If it exits first, the
+//! pipe closes without that byte.  If it stays silent past the overlap,
+//! it is killed.  Either way the handoff fails and this process keeps
+//! serving.
+//!
+//! The admin and metrics listeners are bound with `SO_REUSEPORT` so both
+//! processes can hold them during the overlap.
+
+use std::ffi::OsString;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::process::Command;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use tokio::net::TcpListener;
+
+/// Set in the successor's environment to the PID it takes over from.
+pub const HANDOFF_ENV: &str = "AETHER_PROXY_HANDOFF_FROM";
+
+/// Set in the successor's environment to the inherited readiness pipe.
+pub const READY_FD_ENV: &str = "AETHER_PROXY_HANDOFF_READY_FD";
+
+/// Arguments and environment the process was started with, before the
+/// config file was injected into the environment.
+struct Launch {
+    args: Vec<OsString>,
+    env: Vec<(OsString, OsString)>,
+}
+
+static LAUNCH: Mutex<Option<Launch>> = Mutex::new(None);
+
+/// Remember how this process was started.  Call before the config file is
+/// injected into the environment, so the successor reads it afresh.
+pub fn record_launch() {
+    *LAUNCH.lock().unwrap() = Some(Launch {
+        args: std::env::args_os().skip(1).collect(),
+        env: std::env::vars_os()
+            .filter(|(key, _)| key != HANDOFF_ENV && key != READY_FD_ENV)
+            .collect(),
+    });
+}
+
+/// The node was started by the setup wizard: the successor runs the proxy
+/// directly from the config file setup wrote.
+pub fn record_setup_config(config_path: &Path) {
+    if let Some(launch) = LAUNCH.lock().unwrap().as_mut() {
+        launch.args.clear();
+        launch.env.retain(|(key, _)| key != "AETHER_PROXY_CONFIG");
+        launch
+            .env
+            .push(("AETHER_PROXY_CONFIG".into(), config_path.into()));
+    }
+}
+
+/// Resolves each time the operator requests a handoff (`SIGUSR2`).
+pub async fn requested() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::user_defined2()) {
+            Ok(mut usr2) => {
+                usr2.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    }
+    #[cfg(not(unix))]
+    std::future::pending::<()>().await;
+}
+
+/// Start the successor and wait up to `overlap` for it to report that it
+/// is serving.  Fails, and the caller keeps serving, if it cannot be
+/// started, exits early or is not ready in time.
+pub async fn spawn_successor(overlap: Duration) -> anyhow::Result<u32> {
+    let (args, env) = match LAUNCH.lock().unwrap().as_ref() {
+        Some(launch) => (launch.args.clone(), launch.env.clone()),
+        None => anyhow::bail!("launch environment was not recorded"),
+    };
+    let exe = std::env::current_exe()?;
+    let mut command = Command::new(&exe);
+    command
+        .args(args)
+        .env_clear()
+        .envs(env)
+        .env(HANDOFF_ENV, std::process::id().to_string());
+    hand_over(command, overlap)
+        .await
+        .map_err(|e| anyhow::anyhow!("{}: {}", exe.display(), e))
+}
+
+#[cfg(unix)]
+async fn hand_over(mut command: Command, overlap: Duration) -> anyhow::Result<u32> {
+    use std::os::fd::{AsRawFd, OwnedFd};
+    use std::os::unix::process::CommandExt;
+    use tokio::io::AsyncReadExt;
+
+    let (sender, mut receiver) = tokio::net::unix::pipe::pipe()?;
+    let ready_fd: OwnedFd = sender.into_blocking_fd()?;
+    let raw = ready_fd.as_raw_fd();
+    command.env(READY_FD_ENV, raw.to_string());
+    // The pipe is close-on-exec so no other child inherits it; clear the
+    // flag for the successor only.
+    unsafe {
+        command.pre_exec(move || {
+            if libc::fcntl(raw, libc::F_SETFD, 0) == -1 {
+                return Err(std::io::Error::last_os_error());
+            }
+            Ok(())
+        });
+    }
+    let mut child = command
+        .spawn()
+        .map_err(|e| anyhow::anyhow!("failed to start: {}", e))?;
+    drop(ready_fd);
+
+    let mut byte = [0u8; 1];
+    match tokio::time::timeout(overlap, receiver.read(&mut byte)).await {
+        Ok(Ok(1)) => Ok(child.id()),
+        Ok(Ok(_)) => {
+            let status = child.wait()?;
+            anyhow::bail!("successor exited during handoff ({})", status)
+        }
+        Ok(Err(e)) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            Err(e.into())
+        }
+        Err(_) => {
+            let _ = child.kill();
+            let _ = child.wait();
+            anyhow::bail!(
+                "successor was not ready within {}s, stopped it",
+                overlap.as_secs()
+            )
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn hand_over(_command: Command, _overlap: Duration) -> anyhow::Result<u32> {
+    anyhow::bail!("handoff is only supported on Unix")
+}
+
+/// Tell the process this one took over from that the handoff can go
+/// ahead.  Call once a tunnel is up; later calls do nothing.
+pub fn notify_ready() {
+    static SENT: AtomicBool = AtomicBool::new(false);
+    if SENT.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    #[cfg(unix)]
+    {
+        use std::io::Write;
+        use std::os::fd::FromRawFd;
+
+        let Some(fd) = std::env::var(READY_FD_ENV)
+            .ok()
+            .and_then(|v| v.parse::<i32>().ok())
+        else {
+            return;
+        };
+        // Only this call uses the inherited descriptor, and it is closed
+        // when the file drops.
+        let mut pipe = unsafe { std::fs::File::from_raw_fd(fd) };
+        if let Err(e) = pipe.write_all(b"1") {
+            tracing::warn!(error = %e, "failed to report readiness to the previous process");
+        }
+    }
+}
+
+/// PID this process took over from, if it was started by a handoff.
+pub fn predecessor() -> Option<u32> {
+    std::env::var(HANDOFF_ENV).ok()?.parse().ok()
+}
+
+/// Bind a listener that a successor can also bind during a handoff, or
+/// use the socket systemd passed under `name`.
+pub fn bind_listener(name: &str, addr: SocketAddr) -> std::io::Result<TcpListener> {
+    if let Some(listener) = crate::systemd::take_listener(name) {
+        listener.set_nonblocking(true)?;
+        return TcpListener::from_std(listener);
+    }
+    let socket = socket2::Socket::new(
+        socket2::Domain::for_address(addr),
+        socket2::Type::STREAM,
+        Some(socket2::Protocol::TCP),
+    )?;
+    socket.set_reuse_address(true)?;
+    #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+    socket.set_reuse_port(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+    socket.listen(1024)?;
+    TcpListener::from_std(socket.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(unix)]
+    fn shell(script: &str) -> Command {
+        let mut command = Command::new("sh");
+        command.args(["-c", script]);
+        command
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_ready_successor_takes_over() {
+        let mut successor = shell(&format!("printf 1 > /dev/fd/${}; sleep 2", READY_FD_ENV));
+        successor.stdout(std::process::Stdio::null());
+        assert!(hand_over(successor, Duration::from_secs(5)).await.is_ok());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_failing_successor_is_rejected() {
+        let err = hand_over(shell("exit 3"), Duration::from_secs(5))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("exited"), "{}", err);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_silent_successor_times_out() {
+        let started = std::time::Instant::now();
+        let err = hand_over(shell("sleep 10"), Duration::from_millis(200))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("not ready"), "{}", err);
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_listener_can_be_bound_twice() {
+        let first = bind_listener("test", "127.0.0.1:0".parse().unwrap()).unwrap();
+        let addr = first.local_addr().unwrap();
+        assert!(bind_listener("test", addr).is_ok());
+    }
+}
@@ -0,0 +1,204 @@
+//! Request header rewriting per destination domain.
+//!
+//! Rules come from `--header-rule` or the control plane's
+//! `remote_config.header_rules` (which replaces the local list), one
+//! string each:
+//!
+//! - `api.example.com=set:authorization:Bearer sk-...` replaces the header
+//!   (or adds it when absent)
+//! - `*.example.com=add:x-team:infra` appends a value, keeping existing ones
+//! - `*=remove:x-tracking-id` drops the header for every target
+//!
+//! Rules run in order on the headers a tunnel stream is about to send
+//! upstream, after Aether's own headers and hop-by-hop headers are
+//! stripped.  Headers the node manages itself (hop-by-hop, `host`,
+//! `content-length`, `via`, proxy auth, the hop counter) cannot be
+//! rewritten.
+
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+
+use crate::target_filter::DomainPattern;
+
+/// Headers rules may not touch, besides the hop-by-hop ones.
+const PROTECTED: &[&str] = &[
+    "content-length",
+    "host",
+    "proxy-authorization",
+    "via",
+    "x-aether-proxy-hops",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Action {
+    Set(HeaderValue),
+    Add(HeaderValue),
+    Remove,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Rule {
+    /// `None` matches every host.
+    pattern: Option<DomainPattern>,
+    name: HeaderName,
+    action: Action,
+}
+
+impl Rule {
+    fn parse(raw: &str) -> Result<Self, String> {
+        // Values may be credentials: errors quote the rule up to the name
+        let shown = raw
+            .match_indices(':')
+            .nth(1)
+            .map_or(raw, |(i, _)| &raw[..i]);
+        let invalid = |why: &str| format!("rule {:?}: {}", shown, why);
+        let (pattern, spec) = raw
+            .split_once('=')
+            .ok_or_else(|| invalid("must be `domain=set|add|remove:header[:value]`"))?;
+        let pattern = match pattern.trim() {
+            "*" => None,
+            p => Some(DomainPattern::parse(p).map_err(|e| invalid(&e))?),
+        };
+        let mut parts = spec.splitn(3, ':');
+        let op = parts.next().unwrap_or_default().trim();
+        let name = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| invalid(&format!("invalid header name {:?}", name)))?;
+        if PROTECTED.contains(&name.as_str())
+            || crate::tunnel::headers::is_hop_by_hop(name.as_str(), &Default::default())
+        {
+            return Err(invalid(&format!("{} is managed by the node", name)));
+        }
+        let value = parts
+            .next()
+            .map(|v| HeaderValue::from_str(v.trim()))
+            .transpose()
+            .map_err(|_| invalid("invalid header value"))?;
+        let action = match (op, value) {
+            ("set", Some(value)) => Action::Set(value),
+            ("add", Some(value)) => Action::Add(value),
+            ("remove", None) => Action::Remove,
+            ("set" | "add", None) => return Err(invalid("missing header value")),
+            ("remove", Some(_)) => return Err(invalid("remove takes no value")),
+            (other, _) => {
+                return Err(invalid(&format!(
+                    "unknown action {:?} (use set, add or remove)",
+                    other
+                )))
+            }
+        };
+        Ok(Self {
+            pattern,
+            name,
+            action,
+        })
+    }
+}
+
+/// An ordered list of header rules.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HeaderRules {
+    rules: Vec<Rule>,
+}
+
+impl HeaderRules {
+    pub fn new<S: AsRef<str>>(rules: &[S]) -> Result<Self, String> {
+        let rules = rules
+            .iter()
+            .map(|r| r.as_ref().trim())
+            .filter(|r| !r.is_empty())
+            .map(Rule::parse)
+            .collect::<Result<_, _>>()?;
+        Ok(Self { rules })
+    }
+
+    pub fn len(&self) -> usize {
+        self.rules.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Rewrite `headers` of a request to `host`.
+    pub fn apply(&self, host: &str, headers: &mut HeaderMap) {
+        let matching = self.rules.iter().filter(|rule| {
+            rule.pattern
+                .as_ref()
+                .is_none_or(|pattern| pattern.matches_host(host))
+        });
+        for rule in matching {
+            match &rule.action {
+                Action::Set(value) => {
+                    headers.insert(rule.name.clone(), value.clone());
+                }
+                Action::Add(value) => {
+                    headers.append(rule.name.clone(), value.clone());
+                }
+                Action::Remove => {
+                    headers.remove(&rule.name);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(list: &[&str]) -> HeaderRules {
+        HeaderRules::new(list).unwrap()
+    }
+
+    fn values<'a>(headers: &'a HeaderMap, name: &str) -> Vec<&'a str> {
+        headers
+            .get_all(name)
+            .iter()
+            .map(|v| v.to_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_apply() {
+        let rules = rules(&[
+            "api.example.com=set:Authorization:Bearer sk-1:2",
+            "*.example.com=add:x-team:infra",
+            "*=remove:x-tracking-id",
+        ]);
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", HeaderValue::from_static("Bearer client"));
+        headers.insert("x-team", HeaderValue::from_static("web"));
+        headers.insert("x-tracking-id", HeaderValue::from_static("abc"));
+        let original = headers.clone();
+
+        rules.apply("API.example.com.", &mut headers);
+        assert_eq!(values(&headers, "authorization"), ["Bearer sk-1:2"]);
+        assert_eq!(values(&headers, "x-team"), ["web", "infra"]);
+        assert!(headers.get("x-tracking-id").is_none());
+
+        let mut headers = original;
+        rules.apply("example.org", &mut headers);
+        assert_eq!(values(&headers, "authorization"), ["Bearer client"]);
+        assert_eq!(values(&headers, "x-team"), ["web"]);
+        assert!(headers.get("x-tracking-id").is_none());
+    }
+
+    #[test]
+    fn test_invalid_rules() {
+        for bad in [
+            "api.example.com",
+            "api.example.com=set:authorization",
+            "api.example.com=remove:x-a:b",
+            "api.example.com=rename:x-a:x-b",
+            "api.example.com=set:bad header:v",
+            "api.example.com=set:x-a:bad\nvalue",
+            "api.*.com=remove:x-a",
+            "*=set:host:example.org",
+            "*=remove:connection",
+            "*=set:x-aether-proxy-hops:0",
+        ] {
+            assert!(HeaderRules::new(&[bad]).is_err(), "{bad:?}");
+        }
+        assert!(HeaderRules::new(&["", " "]).unwrap().is_empty());
+    }
+}
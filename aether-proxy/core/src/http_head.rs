@@ -0,0 +1,119 @@
+//! Minimal HTTP/1.1 request-head reader shared by the admin and Prometheus
+//! listeners.
+//!
+//! Both answer one request per connection and never read a body, so all
+//! they need is the head up to the blank line, bounded in size and time.
+
+use std::io;
+use std::time::Duration;
+
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Read a request head (through `\r\n\r\n`) from `stream`.  Returns `None`
+/// when the peer closes first or the head would exceed `max` bytes; a read
+/// stalling longer than `timeout` is a `TimedOut` error.
+pub async fn read<S>(stream: &mut S, max: usize, timeout: Duration) -> io::Result<Option<Vec<u8>>>
+where
+    S: AsyncRead + Unpin,
+{
+    let mut head = Vec::with_capacity(512);
+    let mut buf = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        let n = tokio::time::timeout(timeout, stream.read(&mut buf))
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+        if n == 0 || head.len() + n > max {
+            return Ok(None);
+        }
+        head.extend_from_slice(&buf[..n]);
+    }
+    Ok(Some(head))
+}
+
+/// Request line and headers of a parsed head.
+#[derive(Debug, PartialEq, Eq)]
+pub struct RequestHead<'a> {
+    pub method: &'a str,
+    pub path: &'a str,
+    /// Raw query string without the `?`; empty when absent.
+    pub query: &'a str,
+    headers: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> RequestHead<'a> {
+    /// Parse `head` as returned by [`read`]; `None` when the request line
+    /// is malformed.  Header lines without a colon are skipped.
+    pub fn parse(head: &'a str) -> Option<Self> {
+        let mut lines = head.split("\r\n");
+        let mut parts = lines.next()?.split(' ');
+        let method = parts.next().filter(|m| !m.is_empty())?;
+        let target = parts.next()?;
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let headers = lines
+            .take_while(|line| !line.is_empty())
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim(), value.trim()))
+            .collect();
+        Some(Self {
+            method,
+            path,
+            query,
+            headers,
+        })
+    }
+
+    /// Trimmed value of the first header called `name` (case-insensitive).
+    pub fn header(&self, name: &str) -> Option<&'a str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| *value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::AsyncWriteExt;
+
+    #[test]
+    fn test_parse() {
+        let head = RequestHead::parse(
+            "GET /api/status?t=1 HTTP/1.1\r\nHost: localhost\r\nX-Token:  abc \r\n\r\n",
+        )
+        .unwrap();
+        assert_eq!(head.method, "GET");
+        assert_eq!(head.path, "/api/status");
+        assert_eq!(head.query, "t=1");
+        assert_eq!(head.header("x-token"), Some("abc"));
+        assert_eq!(head.header("cookie"), None);
+        assert!(RequestHead::parse("").is_none());
+        assert!(RequestHead::parse("GET\r\n\r\n").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_read_bounds_the_head() {
+        let (mut client, mut server) = tokio::io::duplex(64 * 1024);
+        client
+            .write_all(b"GET / HTTP/1.1\r\nHost: x\r\n\r\n")
+            .await
+            .unwrap();
+        let head = read(&mut server, 1024, Duration::from_secs(1))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(head.starts_with(b"GET / "));
+
+        client.write_all(&[b'a'; 2048]).await.unwrap();
+        assert!(read(&mut server, 1024, Duration::from_secs(1))
+            .await
+            .unwrap()
+            .is_none());
+
+        let (_client, mut server) = tokio::io::duplex(1024);
+        let err = read(&mut server, 1024, Duration::from_millis(10))
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+}
@@ -0,0 +1,48 @@
+//! Relay core of aether-proxy: configuration, registration with Aether,
+//! the WebSocket tunnel and its stream handlers, upstream connections and
+//! target filtering, plus the admin, metrics and lifecycle plumbing around
+//! them.
+//!
+//! The `aether-proxy` binary is a CLI over [`app::run`]; other binaries can
+//! embed the relay the same way.
+
+pub mod access_log;
+pub mod admin;
+pub mod app;
+pub mod circuit_breaker;
+pub mod clock;
+pub mod config;
+pub mod destinations;
+pub mod dns;
+pub mod error_report;
+pub mod features;
+pub mod handoff;
+pub mod hardware;
+pub mod header_rules;
+pub mod http_head;
+pub mod lifecycle;
+pub mod load_shed;
+pub mod logging;
+pub mod metrics;
+pub mod net;
+pub mod privacy;
+pub mod probe;
+pub mod quota;
+pub mod redact;
+pub mod registration;
+pub mod remote_upgrade;
+pub mod request_id;
+pub mod response_cache;
+pub mod runtime;
+pub mod state;
+pub mod stun;
+pub mod systemd;
+pub mod target_filter;
+#[cfg(any(test, feature = "test-support"))]
+pub mod test_support;
+pub mod tunnel;
+pub mod upstream_client;
+pub mod upstream_proxy;
+pub mod upstream_tls;
+pub mod usage;
+pub mod warm_pool;
@@ -0,0 +1,166 @@
+//! Process lifecycle phases.
+//!
+//! The node moves strictly forward through
+//! `Initializing → Registering → Serving → Draining → Stopped`.  The current
+//! phase (plus any server registrations still being retried in the
+//! background) is reported in every heartbeat so the control plane can tell
+//! a partially registered node from a healthy one, and to operators through
+//! the admin API's status and the exported metrics.
+
+use std::sync::atomic::{AtomicU8, AtomicUsize, Ordering};
+use std::time::Instant;
+
+use tokio::sync::Notify;
+use tracing::{debug, info};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum Phase {
+    /// Parsing config, probing hardware, building clients.
+    Initializing = 0,
+    /// Registering with the configured Aether servers.
+    Registering = 1,
+    /// Tunnels are up and accepting streams.
+    Serving = 2,
+    /// Shutdown requested; unregistering and waiting for tunnels to close.
+    Draining = 3,
+    /// All tasks finished.
+    Stopped = 4,
+}
+
+impl Phase {
+    fn from_u8(v: u8) -> Self {
+        match v {
+            0 => Self::Initializing,
+            1 => Self::Registering,
+            2 => Self::Serving,
+            3 => Self::Draining,
+            _ => Self::Stopped,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Initializing => "initializing",
+            Self::Registering => "registering",
+            Self::Serving => "serving",
+            Self::Draining => "draining",
+            Self::Stopped => "stopped",
+        }
+    }
+}
+
+/// Shared lifecycle state.
+pub struct Lifecycle {
+    phase: AtomicU8,
+    started_at: Instant,
+    /// Servers whose registration failed at startup and is still being
+    /// retried in the background.
+    pending_registrations: AtomicUsize,
+    /// Signalled when an operator asks the node to drain.
+    drain: Notify,
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Lifecycle {
+    pub fn new() -> Self {
+        Self {
+            phase: AtomicU8::new(Phase::Initializing as u8),
+            started_at: Instant::now(),
+            pending_registrations: AtomicUsize::new(0),
+            drain: Notify::new(),
+        }
+    }
+
+    pub fn phase(&self) -> Phase {
+        Phase::from_u8(self.phase.load(Ordering::Acquire))
+    }
+
+    /// Move to `to`.  Backward or repeated transitions are ignored.
+    pub fn advance(&self, to: Phase) {
+        let prev = Phase::from_u8(self.phase.fetch_max(to as u8, Ordering::AcqRel));
+        if prev < to {
+            info!(
+                from = prev.as_str(),
+                to = to.as_str(),
+                uptime_ms = self.started_at.elapsed().as_millis() as u64,
+                "lifecycle phase changed"
+            );
+        } else {
+            debug!(
+                current = prev.as_str(),
+                requested = to.as_str(),
+                "ignoring non-forward lifecycle transition"
+            );
+        }
+    }
+
+    pub fn pending_registrations(&self) -> usize {
+        self.pending_registrations.load(Ordering::Acquire)
+    }
+
+    pub fn set_pending_registrations(&self, n: usize) {
+        self.pending_registrations.store(n, Ordering::Release);
+    }
+
+    /// Mark one background registration as resolved (succeeded or given up).
+    pub fn registration_resolved(&self) {
+        let _ = self
+            .pending_registrations
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1));
+    }
+
+    /// Ask the node to drain and shut down, as if it had received SIGTERM.
+    pub fn request_drain(&self) {
+        info!("drain requested");
+        self.drain.notify_one();
+    }
+
+    /// Resolves once [`request_drain`](Self::request_drain) has been called.
+    pub async fn drain_requested(&self) {
+        self.drain.notified().await;
+    }
+
+    /// Status object for the heartbeat payload.
+    pub fn status_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "phase": self.phase().as_str(),
+            "pending_registrations": self.pending_registrations(),
+            "uptime_secs": self.started_at.elapsed().as_secs(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transitions_are_forward_only() {
+        let lc = Lifecycle::new();
+        assert_eq!(lc.phase(), Phase::Initializing);
+        lc.advance(Phase::Registering);
+        lc.advance(Phase::Serving);
+        assert_eq!(lc.phase(), Phase::Serving);
+        lc.advance(Phase::Registering);
+        assert_eq!(lc.phase(), Phase::Serving);
+        lc.advance(Phase::Stopped);
+        lc.advance(Phase::Draining);
+        assert_eq!(lc.phase(), Phase::Stopped);
+    }
+
+    #[test]
+    fn test_pending_registrations_never_underflow() {
+        let lc = Lifecycle::new();
+        lc.set_pending_registrations(1);
+        lc.registration_resolved();
+        lc.registration_resolved();
+        assert_eq!(lc.pending_registrations(), 0);
+        assert_eq!(lc.status_json()["pending_registrations"], 0);
+    }
+}
@@ -0,0 +1,364 @@
+//! Deterministic load shedding by request class under CPU/memory pressure.
+//!
+//! A background sampler turns host CPU and memory usage into a shed level.
+//! New streams are classified on arrival and rejected if their class is
+//! shed at the current level: bulk transfers go first, interactive API
+//! relays only under critical pressure, control traffic never.
+//!
+//! Streams are classified locally (see [`LoadShedder::classify`]):
+//! upgraded connections, request bodies declared at least
+//! `--load-shed-bulk-body` bytes and targets on `--load-shed-bulk-hosts`
+//! are bulk, everything else interactive.  [`CLASS_HEADER`], should the
+//! control plane set it, overrides that.  Rejecting streams is opt-in
+//! through the `load_shedding` feature.
+//!
+//! Levels use hysteresis (enter at the threshold, leave only once pressure
+//! drops [`RECOVERY_MARGIN`] points below it) so the node doesn't flap
+//! between shedding and accepting on every sample.
+
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use sysinfo::System;
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+use crate::config::Config;
+use crate::target_filter::DomainPattern;
+use crate::tunnel::protocol::RequestMeta;
+use crate::tunnel::upgrade;
+
+/// Header the control plane may set to override a request's class.
+/// Stripped before the request is forwarded upstream.
+pub const CLASS_HEADER: &str = "x-aether-request-class";
+
+/// How far (in percentage points) pressure must fall below a threshold
+/// before the corresponding level is left.
+const RECOVERY_MARGIN: f32 = 10.0;
+
+/// Interval between pressure samples.
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Priority class of a tunnel stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RequestClass {
+    /// Probes and management traffic; never shed.
+    Control,
+    /// Regular API relays (default).
+    Interactive,
+    /// Large uploads/downloads and batch jobs; shed first.
+    Bulk,
+}
+
+impl RequestClass {
+    /// The class named by [`CLASS_HEADER`], if the request carries a known
+    /// one.
+    pub fn declared(headers: &std::collections::HashMap<String, String>) -> Option<Self> {
+        let hint = headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(CLASS_HEADER))?
+            .1
+            .trim()
+            .to_ascii_lowercase();
+        match hint.as_str() {
+            "control" => Some(Self::Control),
+            "interactive" => Some(Self::Interactive),
+            "bulk" => Some(Self::Bulk),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Control => "control",
+            Self::Interactive => "interactive",
+            Self::Bulk => "bulk",
+        }
+    }
+}
+
+/// Current shedding level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum ShedLevel {
+    Normal = 0,
+    /// Bulk streams are rejected.
+    Bulk = 1,
+    /// Bulk and interactive streams are rejected.
+    Interactive = 2,
+}
+
+impl ShedLevel {
+    fn from_u8(v: u8) -> Self {
+        match v {
+            1 => Self::Bulk,
+            2 => Self::Interactive,
+            _ => Self::Normal,
+        }
+    }
+
+    /// Whether a stream of `class` is rejected at this level.
+    pub fn sheds(self, class: RequestClass) -> bool {
+        match class {
+            RequestClass::Control => false,
+            RequestClass::Interactive => self >= Self::Interactive,
+            RequestClass::Bulk => self >= Self::Bulk,
+        }
+    }
+}
+
+/// Pressure thresholds in percent (0-100).
+#[derive(Debug, Clone, Copy)]
+pub struct Thresholds {
+    /// Pressure at which bulk streams start being shed.
+    pub bulk: f32,
+    /// Pressure at which interactive streams are shed as well.
+    pub interactive: f32,
+}
+
+/// Compute the next level from the current one and a pressure sample.
+fn next_level(current: ShedLevel, pressure: f32, t: Thresholds) -> ShedLevel {
+    let target = if pressure >= t.interactive {
+        ShedLevel::Interactive
+    } else if pressure >= t.bulk {
+        ShedLevel::Bulk
+    } else {
+        ShedLevel::Normal
+    };
+    if target >= current {
+        return target;
+    }
+    // Stepping down: only leave a level once well below its threshold.
+    let exit_at = match current {
+        ShedLevel::Interactive => t.interactive,
+        ShedLevel::Bulk => t.bulk,
+        ShedLevel::Normal => return ShedLevel::Normal,
+    } - RECOVERY_MARGIN;
+    if pressure < exit_at {
+        // Re-evaluate against the lower level's own exit condition.
+        let lower = ShedLevel::from_u8(current as u8 - 1);
+        next_level(lower, pressure, t).max(target)
+    } else {
+        current
+    }
+}
+
+/// Shared shedding state, read by the dispatcher for every new stream.
+pub struct LoadShedder {
+    level: AtomicU8,
+    thresholds: Option<Thresholds>,
+    /// Declared body size from which a stream is bulk.
+    bulk_body: Option<u64>,
+    bulk_hosts: Vec<DomainPattern>,
+}
+
+impl LoadShedder {
+    pub fn from_config(config: &Config) -> Self {
+        let thresholds = (config.load_shed_threshold > 0).then_some(Thresholds {
+            bulk: f32::from(config.load_shed_threshold),
+            interactive: f32::from(config.load_shed_critical),
+        });
+        Self {
+            level: AtomicU8::new(ShedLevel::Normal as u8),
+            thresholds,
+            bulk_body: (config.load_shed_bulk_body > 0).then_some(config.load_shed_bulk_body),
+            bulk_hosts: config.load_shed_bulk_hosts().unwrap_or_default(),
+        }
+    }
+
+    /// Class of a new stream: the one [`CLASS_HEADER`] declares, else bulk
+    /// for upgraded connections, bodies declared at least the bulk size and
+    /// bulk hosts, else interactive.
+    pub fn classify(&self, meta: &RequestMeta) -> RequestClass {
+        if let Some(class) = RequestClass::declared(&meta.headers) {
+            return class;
+        }
+        let declared_body = meta
+            .headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case("content-length"))
+            .and_then(|(_, v)| v.trim().parse::<u64>().ok());
+        let bulk_host = || {
+            url::Url::parse(&meta.url).ok().is_some_and(|url| {
+                url.host_str()
+                    .is_some_and(|host| self.bulk_hosts.iter().any(|p| p.matches_host(host)))
+            })
+        };
+        if upgrade::requested_protocol(meta).is_some()
+            || self
+                .bulk_body
+                .is_some_and(|bulk| declared_body.is_some_and(|len| len >= bulk))
+            || bulk_host()
+        {
+            RequestClass::Bulk
+        } else {
+            RequestClass::Interactive
+        }
+    }
+
+    pub fn level(&self) -> ShedLevel {
+        ShedLevel::from_u8(self.level.load(Ordering::Acquire))
+    }
+
+    /// Whether a new stream of `class` should be rejected right now.
+    pub fn should_shed(&self, class: RequestClass) -> bool {
+        self.level().sheds(class)
+    }
+
+    fn observe(&self, pressure: f32) {
+        let Some(t) = self.thresholds else {
+            return;
+        };
+        let current = self.level();
+        let next = next_level(current, pressure, t);
+        if next != current {
+            self.level.store(next as u8, Ordering::Release);
+            if next > current {
+                warn!(pressure, level = ?next, "load shedding level raised");
+            } else {
+                info!(pressure, level = ?next, "load shedding level lowered");
+            }
+        }
+    }
+}
+
+/// Spawn the pressure sampler.  No-op when shedding is disabled.
+pub fn spawn_sampler(shedder: Arc<LoadShedder>, mut shutdown: watch::Receiver<bool>) {
+    if shedder.thresholds.is_none() {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut sys = System::new();
+        let mut interval = tokio::time::interval(SAMPLE_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = shutdown.changed() => return,
+            }
+            sys.refresh_cpu_usage();
+            sys.refresh_memory();
+            let cpu = sys.global_cpu_usage();
+            let mem = if sys.total_memory() > 0 {
+                sys.used_memory() as f32 * 100.0 / sys.total_memory() as f32
+            } else {
+                0.0
+            };
+            shedder.observe(cpu.max(mem));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const T: Thresholds = Thresholds {
+        bulk: 85.0,
+        interactive: 95.0,
+    };
+
+    #[test]
+    fn test_levels_enter_at_threshold() {
+        assert_eq!(next_level(ShedLevel::Normal, 50.0, T), ShedLevel::Normal);
+        assert_eq!(next_level(ShedLevel::Normal, 85.0, T), ShedLevel::Bulk);
+        assert_eq!(
+            next_level(ShedLevel::Normal, 99.0, T),
+            ShedLevel::Interactive
+        );
+    }
+
+    #[test]
+    fn test_hysteresis_on_recovery() {
+        // Stay at Bulk until pressure drops below 75
+        assert_eq!(next_level(ShedLevel::Bulk, 80.0, T), ShedLevel::Bulk);
+        assert_eq!(next_level(ShedLevel::Bulk, 74.0, T), ShedLevel::Normal);
+
+        // Interactive -> Bulk below 85, but not yet Normal
+        assert_eq!(
+            next_level(ShedLevel::Interactive, 90.0, T),
+            ShedLevel::Interactive
+        );
+        assert_eq!(next_level(ShedLevel::Interactive, 80.0, T), ShedLevel::Bulk);
+        assert_eq!(
+            next_level(ShedLevel::Interactive, 10.0, T),
+            ShedLevel::Normal
+        );
+    }
+
+    #[test]
+    fn test_shed_order() {
+        assert!(!ShedLevel::Normal.sheds(RequestClass::Bulk));
+        assert!(ShedLevel::Bulk.sheds(RequestClass::Bulk));
+        assert!(!ShedLevel::Bulk.sheds(RequestClass::Interactive));
+        assert!(ShedLevel::Interactive.sheds(RequestClass::Interactive));
+        assert!(!ShedLevel::Interactive.sheds(RequestClass::Control));
+    }
+
+    fn shedder(level: ShedLevel) -> LoadShedder {
+        LoadShedder {
+            level: AtomicU8::new(level as u8),
+            thresholds: Some(T),
+            bulk_body: Some(1024 * 1024),
+            bulk_hosts: vec![DomainPattern::parse("*.files.example").unwrap()],
+        }
+    }
+
+    fn meta(url: &str, headers: &[(&str, &str)]) -> RequestMeta {
+        RequestMeta {
+            method: "POST".into(),
+            url: url.into(),
+            headers: headers
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            timeout: 60,
+        }
+    }
+
+    #[test]
+    fn test_large_upload_is_shed_first() {
+        let shedder = shedder(ShedLevel::Bulk);
+        let upload = meta(
+            "https://api.example.com/v1/files",
+            &[("Content-Length", "52428800")],
+        );
+        assert_eq!(shedder.classify(&upload), RequestClass::Bulk);
+        assert!(shedder.should_shed(shedder.classify(&upload)));
+
+        let small = meta(
+            "https://api.example.com/v1/chat",
+            &[("content-length", "512")],
+        );
+        assert_eq!(shedder.classify(&small), RequestClass::Interactive);
+        assert!(!shedder.should_shed(shedder.classify(&small)));
+    }
+
+    #[test]
+    fn test_classify() {
+        let shedder = shedder(ShedLevel::Normal);
+        let ws = meta(
+            "https://api.example.com/ws",
+            &[("Connection", "Upgrade"), ("Upgrade", "websocket")],
+        );
+        assert_eq!(shedder.classify(&ws), RequestClass::Bulk);
+        let host = meta("https://eu.files.example/blob", &[]);
+        assert_eq!(shedder.classify(&host), RequestClass::Bulk);
+        assert_eq!(
+            shedder.classify(&meta("https://api.example.com/", &[])),
+            RequestClass::Interactive
+        );
+
+        // The control plane's class wins over the local guess
+        let declared = meta(
+            "https://eu.files.example/blob",
+            &[("X-Aether-Request-Class", "Interactive")],
+        );
+        assert_eq!(shedder.classify(&declared), RequestClass::Interactive);
+        let declared = meta(
+            "https://api.example.com/",
+            &[("x-aether-request-class", "control")],
+        );
+        assert_eq!(shedder.classify(&declared), RequestClass::Control);
+    }
+}
@@ -0,0 +1,339 @@
+//! Outputs for the node's diagnostic log.
+//!
+//! `--log-output` takes one or more of:
+//!
+//! - `stdout` (default)
+//! - `file:<path>`: appended to `<path>`, which is rotated once it would
+//!   grow past `--log-file-max-size-mb` and, with `--log-file-daily`, at the
+//!   first event of each UTC day.  Rotated files are `<path>.1` (newest) to
+//!   `<path>.N`, keeping `--log-file-keep` of them
+//! - `syslog`: RFC 3164 datagrams to `/dev/log`, facility `daemon`
+//! - `journald`: the journal's native protocol, with `PRIORITY` from the
+//!   event level, so `journalctl -p warning` works
+//!
+//! Every output gets the same events (`--log-json` applies to all of them);
+//! only stdout is colored.  A log file that cannot be opened fails startup;
+//! later write errors are ignored rather than taking the node down.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use tracing::{Level, Metadata};
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::clock::now_ms;
+
+const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+/// `SYSLOG_IDENTIFIER` / syslog tag.
+const IDENTIFIER: &str = "aether-proxy";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Output {
+    Stdout,
+    File(PathBuf),
+    Syslog,
+    Journald,
+}
+
+impl Output {
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let raw = raw.trim();
+        if let Some(path) = raw.strip_prefix("file:") {
+            if path.is_empty() {
+                return Err("file: needs a path".to_string());
+            }
+            return Ok(Self::File(PathBuf::from(path)));
+        }
+        match raw.to_ascii_lowercase().as_str() {
+            "stdout" => Ok(Self::Stdout),
+            "syslog" if cfg!(unix) => Ok(Self::Syslog),
+            "journald" if cfg!(unix) => Ok(Self::Journald),
+            "syslog" | "journald" => Err(format!("{} is only available on Unix", raw)),
+            other => Err(format!(
+                "unknown output {:?} (use stdout, file:<path>, syslog or journald)",
+                other
+            )),
+        }
+    }
+}
+
+/// When a log file is rotated.
+#[derive(Debug, Clone, Copy)]
+pub struct Rotation {
+    /// Rotate before the file would exceed this; `None` for no limit.
+    pub max_bytes: Option<u64>,
+    pub daily: bool,
+    /// Rotated files kept.
+    pub keep: usize,
+}
+
+struct FileState {
+    file: Option<File>,
+    len: u64,
+    day: u64,
+}
+
+/// A log file rotated by size and/or day.
+pub struct RollingFile {
+    path: PathBuf,
+    rotation: Rotation,
+    state: Mutex<FileState>,
+}
+
+impl RollingFile {
+    /// Open (creating) `path` for appending.
+    pub fn open(path: &Path, rotation: Rotation) -> io::Result<Self> {
+        let file = open_append(path)?;
+        let len = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            rotation,
+            state: Mutex::new(FileState {
+                file: Some(file),
+                len,
+                day: now_ms() / DAY_MS,
+            }),
+        })
+    }
+
+    fn write_line(&self, buf: &[u8]) -> io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        let day = now_ms() / DAY_MS;
+        let full = self
+            .rotation
+            .max_bytes
+            .is_some_and(|max| state.len > 0 && state.len + buf.len() as u64 > max);
+        if full || (self.rotation.daily && day != state.day) || state.file.is_none() {
+            state.file = None;
+            if state.len > 0 {
+                self.rotate()?;
+            }
+            let file = open_append(&self.path)?;
+            state.len = file.metadata()?.len();
+            state.file = Some(file);
+            state.day = day;
+        }
+        if let Some(file) = state.file.as_mut() {
+            file.write_all(buf)?;
+            state.len += buf.len() as u64;
+        }
+        Ok(())
+    }
+
+    /// Shift `<path>.N-1` to `<path>.N` ... and `<path>` to `<path>.1`,
+    /// dropping what falls beyond `keep`.
+    fn rotate(&self) -> io::Result<()> {
+        let numbered = |n: usize| {
+            let mut name = self.path.clone().into_os_string();
+            name.push(format!(".{}", n));
+            PathBuf::from(name)
+        };
+        if self.rotation.keep == 0 {
+            return fs::remove_file(&self.path);
+        }
+        let _ = fs::remove_file(numbered(self.rotation.keep));
+        for n in (1..self.rotation.keep).rev() {
+            let _ = fs::rename(numbered(n), numbered(n + 1));
+        }
+        fs::rename(&self.path, numbered(1))
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/// Writer for one event: the formatted line is written as a whole.
+pub struct LineWriter<'a> {
+    sink: &'a dyn LineSink,
+    level: Level,
+}
+
+trait LineSink: Send + Sync {
+    fn write_line(&self, level: Level, line: &[u8]) -> io::Result<()>;
+}
+
+impl Write for LineWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // Errors are swallowed: logging must not take the node down.
+        let _ = self.sink.write_line(self.level, buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl LineSink for RollingFile {
+    fn write_line(&self, _level: Level, line: &[u8]) -> io::Result<()> {
+        RollingFile::write_line(self, line)
+    }
+}
+
+/// Datagrams to the local syslog daemon or journald.
+#[cfg(unix)]
+pub struct Datagram {
+    socket: std::os::unix::net::UnixDatagram,
+    path: &'static str,
+    journald: bool,
+}
+
+#[cfg(unix)]
+impl Datagram {
+    pub fn syslog() -> io::Result<Self> {
+        Self::new("/dev/log", false)
+    }
+
+    pub fn journald() -> io::Result<Self> {
+        Self::new("/run/systemd/journal/socket", true)
+    }
+
+    fn new(path: &'static str, journald: bool) -> io::Result<Self> {
+        Ok(Self {
+            socket: std::os::unix::net::UnixDatagram::unbound()?,
+            path,
+            journald,
+        })
+    }
+}
+
+#[cfg(unix)]
+impl LineSink for Datagram {
+    fn write_line(&self, level: Level, line: &[u8]) -> io::Result<()> {
+        let line = line.strip_suffix(b"\n").unwrap_or(line);
+        let message = if self.journald {
+            journald_message(level, line)
+        } else {
+            syslog_message(level, line)
+        };
+        self.socket.send_to(&message, self.path).map(drop)
+    }
+}
+
+/// Syslog severity of an event level.
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::ERROR => 3,
+        Level::WARN => 4,
+        Level::INFO => 6,
+        _ => 7,
+    }
+}
+
+/// `<PRI>tag[pid]: line`, facility `daemon` (3).
+fn syslog_message(level: Level, line: &[u8]) -> Vec<u8> {
+    let mut message = format!(
+        "<{}>{}[{}]: ",
+        3 * 8 + severity(level),
+        IDENTIFIER,
+        std::process::id()
+    )
+    .into_bytes();
+    message.extend_from_slice(line);
+    message
+}
+
+/// Journal export format; `MESSAGE` is length-prefixed since it may hold
+/// newlines.
+fn journald_message(level: Level, line: &[u8]) -> Vec<u8> {
+    let mut message = format!(
+        "PRIORITY={}\nSYSLOG_IDENTIFIER={}\nMESSAGE\n",
+        severity(level),
+        IDENTIFIER
+    )
+    .into_bytes();
+    message.extend_from_slice(&(line.len() as u64).to_le_bytes());
+    message.extend_from_slice(line);
+    message.push(b'\n');
+    message
+}
+
+/// A [`MakeWriter`] over a file or datagram sink.
+#[derive(Clone)]
+pub struct SinkWriter(Arc<dyn LineSink>);
+
+impl SinkWriter {
+    pub fn file(file: RollingFile) -> Self {
+        Self(Arc::new(file))
+    }
+
+    #[cfg(unix)]
+    pub fn datagram(datagram: Datagram) -> Self {
+        Self(Arc::new(datagram))
+    }
+}
+
+impl<'a> MakeWriter<'a> for SinkWriter {
+    type Writer = LineWriter<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        LineWriter {
+            sink: &*self.0,
+            level: Level::INFO,
+        }
+    }
+
+    fn make_writer_for(&'a self, meta: &Metadata<'_>) -> Self::Writer {
+        LineWriter {
+            sink: &*self.0,
+            level: *meta.level(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_output() {
+        assert_eq!(Output::parse("stdout").unwrap(), Output::Stdout);
+        assert_eq!(
+            Output::parse("file:/var/log/aether-proxy.log").unwrap(),
+            Output::File(PathBuf::from("/var/log/aether-proxy.log"))
+        );
+        assert_eq!(Output::parse("Journald").unwrap(), Output::Journald);
+        assert_eq!(Output::parse("syslog").unwrap(), Output::Syslog);
+        assert!(Output::parse("file:").is_err());
+        assert!(Output::parse("stderr").is_err());
+    }
+
+    #[test]
+    fn test_rotation_by_size() {
+        let dir = std::env::temp_dir().join(format!("aether-proxy-log-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("node.log");
+        let rotation = Rotation {
+            max_bytes: Some(10),
+            daily: false,
+            keep: 2,
+        };
+        let file = RollingFile::open(&path, rotation).unwrap();
+        for line in ["first\n", "second\n", "third\n", "fourth\n"] {
+            file.write_line(line.as_bytes()).unwrap();
+        }
+        let read = |name: &str| fs::read_to_string(dir.join(name)).unwrap();
+        assert_eq!(read("node.log"), "fourth\n");
+        assert_eq!(read("node.log.1"), "third\n");
+        assert_eq!(read("node.log.2"), "second\n");
+        assert!(!dir.join("node.log.3").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_datagram_formats() {
+        assert_eq!(
+            String::from_utf8(syslog_message(Level::WARN, b"stale")).unwrap(),
+            format!("<28>aether-proxy[{}]: stale", std::process::id())
+        );
+        let journal = journald_message(Level::ERROR, b"a\nb");
+        let head = b"PRIORITY=3\nSYSLOG_IDENTIFIER=aether-proxy\nMESSAGE\n";
+        assert!(journal.starts_with(head));
+        assert_eq!(&journal[head.len()..head.len() + 8], &3u64.to_le_bytes());
+        assert!(journal.ends_with(b"a\nb\n"));
+    }
+}
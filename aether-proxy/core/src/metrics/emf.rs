@@ -0,0 +1,147 @@
+//! CloudWatch Embedded Metric Format.
+//!
+//! One JSON record per dimension set (node-wide, then each server) is
+//! appended to stdout or `--metrics-emf-path`, where the CloudWatch agent
+//! picks it up.  Counters are emitted as per-interval increments so that
+//! CloudWatch `Sum` statistics are meaningful.
+
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tracing::debug;
+
+use super::{CounterDeltas, MetricKind, MetricsBackend, Sample};
+
+pub struct Emf {
+    namespace: String,
+    node: String,
+    /// `None` writes to stdout.
+    file: Option<Mutex<File>>,
+    deltas: CounterDeltas,
+}
+
+impl Emf {
+    pub fn new(namespace: &str, node: &str, path: Option<&str>) -> anyhow::Result<Self> {
+        let file = path
+            .map(|p| {
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(p)
+                    .map_err(|e| anyhow::anyhow!("metrics_emf_path {}: {}", p, e))
+            })
+            .transpose()?
+            .map(Mutex::new);
+        Ok(Self {
+            namespace: namespace.to_string(),
+            node: node.to_string(),
+            file,
+            deltas: CounterDeltas::default(),
+        })
+    }
+
+    /// Build one EMF record for samples sharing the same `server`.
+    fn record(&self, server: Option<&str>, samples: &[&Sample], timestamp_ms: u64) -> String {
+        let mut dimensions = vec!["Node"];
+        let mut root = serde_json::Map::new();
+        root.insert("Node".into(), self.node.clone().into());
+        if let Some(server) = server {
+            dimensions.push("Server");
+            root.insert("Server".into(), server.into());
+        }
+
+        let mut metrics = Vec::with_capacity(samples.len());
+        for sample in samples {
+            let (value, unit) = match sample.kind {
+                MetricKind::Counter => (self.deltas.delta(sample), unit_for(sample.name)),
+                MetricKind::Gauge => (sample.value, "None"),
+            };
+            metrics.push(serde_json::json!({ "Name": sample.name, "Unit": unit }));
+            root.insert(sample.name.into(), value.into());
+        }
+
+        root.insert(
+            "_aws".into(),
+            serde_json::json!({
+                "Timestamp": timestamp_ms,
+                "CloudWatchMetrics": [{
+                    "Namespace": self.namespace,
+                    "Dimensions": [dimensions],
+                    "Metrics": metrics,
+                }],
+            }),
+        );
+        serde_json::Value::Object(root).to_string()
+    }
+}
+
+fn unit_for(name: &str) -> &'static str {
+    if name.contains("_seconds") {
+        "Seconds"
+    } else {
+        "Count"
+    }
+}
+
+impl MetricsBackend for Emf {
+    fn publish(&self, samples: &[Sample]) {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+
+        let mut servers: Vec<Option<&str>> = Vec::new();
+        for sample in samples {
+            let server = sample.server.as_deref();
+            if !servers.contains(&server) {
+                servers.push(server);
+            }
+        }
+
+        let mut out = String::new();
+        for server in servers {
+            let group: Vec<&Sample> = samples
+                .iter()
+                .filter(|s| s.server.as_deref() == server)
+                .collect();
+            out.push_str(&self.record(server, &group, timestamp_ms));
+            out.push('\n');
+        }
+
+        let result = match &self.file {
+            Some(file) => file.lock().unwrap().write_all(out.as_bytes()),
+            None => std::io::stdout().lock().write_all(out.as_bytes()),
+        };
+        if let Err(e) = result {
+            debug!(error = %e, "failed to write EMF record");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_shape() {
+        let emf = Emf::new("AetherProxy", "hk-01", None).unwrap();
+        let sample = Sample {
+            name: "requests_total",
+            help: "",
+            kind: MetricKind::Counter,
+            value: 12.0,
+            server: Some("server-0".into()),
+        };
+        let record: serde_json::Value =
+            serde_json::from_str(&emf.record(Some("server-0"), &[&sample], 1_000)).unwrap();
+        assert_eq!(record["Node"], "hk-01");
+        assert_eq!(record["Server"], "server-0");
+        assert_eq!(record["requests_total"], 12.0);
+        let directive = &record["_aws"]["CloudWatchMetrics"][0];
+        assert_eq!(directive["Namespace"], "AetherProxy");
+        assert_eq!(directive["Dimensions"][0][1], "Server");
+        assert_eq!(directive["Metrics"][0]["Unit"], "Count");
+    }
+}
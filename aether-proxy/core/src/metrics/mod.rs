@@ -0,0 +1,341 @@
+//! Metrics export to operator monitoring stacks.
+//!
+//! A reporter task samples node metrics every `--metrics-interval-secs` and
+//! hands them to the backend selected with `--metrics-backend`:
+//!
+//! - `prometheus`: serves the latest sample on `GET /metrics` (pull)
+//! - `statsd` / `dogstatsd`: pushes UDP datagrams to a local agent
+//! - `emf`: writes CloudWatch Embedded Metric Format records
+//!
+//! The heartbeat to Aether is independent of this and always runs.
+
+pub mod emf;
+pub mod prometheus;
+pub mod statsd;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::{watch, Mutex};
+use tracing::info;
+
+use crate::config::Config;
+use crate::state::{AppState, ServerContext};
+
+const PREFIX: &str = "aether_proxy";
+
+/// Selected backend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    None,
+    Prometheus,
+    Statsd,
+    Dogstatsd,
+    Emf,
+}
+
+impl BackendKind {
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "" | "none" => Ok(Self::None),
+            "prometheus" => Ok(Self::Prometheus),
+            "statsd" => Ok(Self::Statsd),
+            "dogstatsd" | "datadog" => Ok(Self::Dogstatsd),
+            "emf" | "cloudwatch" => Ok(Self::Emf),
+            other => Err(format!(
+                "unknown backend {:?} (use none, prometheus, statsd, dogstatsd or emf)",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MetricKind {
+    /// Monotonic total since startup.
+    Counter,
+    Gauge,
+}
+
+/// One exported value.
+#[derive(Debug, Clone)]
+pub struct Sample {
+    /// Name without the `aether_proxy` prefix.
+    pub name: &'static str,
+    pub help: &'static str,
+    pub kind: MetricKind,
+    pub value: f64,
+    /// Server label for per-server metrics; `None` for node-wide ones.
+    pub server: Option<String>,
+}
+
+/// A metrics sink.  `publish` is called from the reporter task and must not
+/// block for long; push backends use non-blocking I/O.
+pub trait MetricsBackend: Send + Sync {
+    fn publish(&self, samples: &[Sample]);
+}
+
+/// Converts counter totals into per-interval increments for push backends,
+/// whose receivers aggregate deltas.
+#[derive(Default)]
+struct CounterDeltas {
+    last: std::sync::Mutex<HashMap<(&'static str, Option<String>), f64>>,
+}
+
+impl CounterDeltas {
+    fn delta(&self, sample: &Sample) -> f64 {
+        let mut last = self.last.lock().unwrap();
+        let prev = last.insert((sample.name, sample.server.clone()), sample.value);
+        (sample.value - prev.unwrap_or(0.0)).max(0.0)
+    }
+}
+
+/// Build the configured backend; `None` when export is disabled.
+pub async fn build(
+    config: &Config,
+    shutdown: watch::Receiver<bool>,
+) -> anyhow::Result<Option<Arc<dyn MetricsBackend>>> {
+    let backend: Arc<dyn MetricsBackend> = match BackendKind::parse(&config.metrics_backend)
+        .map_err(|e| anyhow::anyhow!("metrics_backend: {}", e))?
+    {
+        BackendKind::None => return Ok(None),
+        BackendKind::Prometheus => {
+            Arc::new(prometheus::Prometheus::bind(config.metrics_listen, shutdown).await?)
+        }
+        BackendKind::Statsd => Arc::new(statsd::Statsd::connect(
+            &config.metrics_statsd_addr,
+            &config.node_name,
+            false,
+        )?),
+        BackendKind::Dogstatsd => Arc::new(statsd::Statsd::connect(
+            &config.metrics_statsd_addr,
+            &config.node_name,
+            true,
+        )?),
+        BackendKind::Emf => Arc::new(emf::Emf::new(
+            &config.metrics_emf_namespace,
+            &config.node_name,
+            config.metrics_emf_path.as_deref(),
+        )?),
+    };
+    info!(backend = %config.metrics_backend, "metrics export enabled");
+    Ok(Some(backend))
+}
+
+/// Sample node-wide and per-server metrics.
+pub fn collect(state: &AppState, servers: &[Arc<ServerContext>]) -> Vec<Sample> {
+    use std::sync::atomic::Ordering;
+
+    let mut samples = Vec::new();
+    let (hits, misses, negative_hits) = state.dns_cache.stats();
+    let node = |name, help, kind, value: u64| Sample {
+        name,
+        help,
+        kind,
+        value: value as f64,
+        server: None,
+    };
+    samples.push(node(
+        "dns_cache_hits_total",
+        "DNS cache hits",
+        MetricKind::Counter,
+        hits,
+    ));
+    samples.push(node(
+        "dns_cache_misses_total",
+        "DNS cache misses",
+        MetricKind::Counter,
+        misses,
+    ));
+    samples.push(node(
+        "dns_cache_negative_hits_total",
+        "Lookups answered from the NXDOMAIN cache",
+        MetricKind::Counter,
+        negative_hits,
+    ));
+    samples.push(node(
+        "lifecycle_phase",
+        "Lifecycle phase (0 initializing, 1 registering, 2 serving, 3 draining, 4 stopped)",
+        MetricKind::Gauge,
+        state.lifecycle.phase() as u64,
+    ));
+    samples.push(node(
+        "pending_registrations",
+        "Server registrations still being retried in the background",
+        MetricKind::Gauge,
+        state.lifecycle.pending_registrations() as u64,
+    ));
+    samples.push(node(
+        "load_shed_level",
+        "Current load shedding level (0 normal, 1 bulk, 2 interactive)",
+        MetricKind::Gauge,
+        state.load_shedder.level() as u64,
+    ));
+    samples.push(node(
+        "circuit_open_targets",
+        "Targets currently refused by the circuit breaker",
+        MetricKind::Gauge,
+        state.circuit_breaker.open_targets() as u64,
+    ));
+    if let Some(skew_ms) = crate::clock::skew_ms() {
+        samples.push(Sample {
+            name: "clock_skew_seconds",
+            help: "Aether server clock minus this node's clock",
+            kind: MetricKind::Gauge,
+            value: skew_ms as f64 / 1000.0,
+            server: None,
+        });
+    }
+
+    for server in servers {
+        let totals = server.metrics.totals();
+        let label = Some(server.server_label.clone());
+        let per_server = |name, help, kind, value: f64| Sample {
+            name,
+            help,
+            kind,
+            value,
+            server: label.clone(),
+        };
+        samples.push(per_server(
+            "active_connections",
+            "Streams currently being relayed",
+            MetricKind::Gauge,
+            server.active_connections.load(Ordering::Acquire) as f64,
+        ));
+        samples.push(per_server(
+            "control_plane_degraded",
+            "Whether Aether has not been heard from for several heartbeats",
+            MetricKind::Gauge,
+            u8::from(server.control_plane.is_degraded()) as f64,
+        ));
+        samples.push(per_server(
+            "control_plane_silence_seconds",
+            "Time since Aether was last heard from",
+            MetricKind::Gauge,
+            server.control_plane.silence().as_secs_f64(),
+        ));
+        samples.push(per_server(
+            "requests_total",
+            "Upstream requests that received response headers",
+            MetricKind::Counter,
+            totals.requests as f64,
+        ));
+        samples.push(per_server(
+            "connect_latency_seconds_total",
+            "Cumulative connection-establishment latency",
+            MetricKind::Counter,
+            totals.latency_ns as f64 / 1e9,
+        ));
+        samples.push(per_server(
+            "failed_requests_total",
+            "Upstream requests that failed",
+            MetricKind::Counter,
+            totals.failed as f64,
+        ));
+        samples.push(per_server(
+            "dns_failures_total",
+            "Targets rejected or unresolvable",
+            MetricKind::Counter,
+            totals.dns_failures as f64,
+        ));
+        samples.push(per_server(
+            "stream_errors_total",
+            "Streams aborted while relaying",
+            MetricKind::Counter,
+            totals.stream_errors as f64,
+        ));
+        samples.push(per_server(
+            "upstream_resets_total",
+            "Upstream connections reset during connect",
+            MetricKind::Counter,
+            totals.upstream_resets as f64,
+        ));
+        samples.push(per_server(
+            "shed_requests_total",
+            "Streams rejected by load shedding",
+            MetricKind::Counter,
+            totals.shed_requests as f64,
+        ));
+        samples.push(per_server(
+            "stalled_streams_total",
+            "Responses aborted after the upstream stopped sending",
+            MetricKind::Counter,
+            totals.stalled_streams as f64,
+        ));
+        samples.push(per_server(
+            "upgraded_streams_total",
+            "Upgraded (101) streams relayed to the end",
+            MetricKind::Counter,
+            server.metrics.upgraded_streams.load(Ordering::Acquire) as f64,
+        ));
+        samples.push(per_server(
+            "upgraded_received_bytes_total",
+            "Bytes relayed from clients over upgraded streams",
+            MetricKind::Counter,
+            server
+                .metrics
+                .upgraded_bytes_received
+                .load(Ordering::Acquire) as f64,
+        ));
+        samples.push(per_server(
+            "upgraded_sent_bytes_total",
+            "Bytes relayed to clients over upgraded streams",
+            MetricKind::Counter,
+            server.metrics.upgraded_bytes_sent.load(Ordering::Acquire) as f64,
+        ));
+    }
+    samples
+}
+
+/// Spawn the periodic reporter.
+pub fn spawn_reporter(
+    backend: Arc<dyn MetricsBackend>,
+    state: Arc<AppState>,
+    servers: Arc<Mutex<Vec<Arc<ServerContext>>>>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    let interval = Duration::from_secs(state.config.metrics_interval_secs);
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                _ = shutdown.changed() => return,
+            }
+            let servers = servers.lock().await.clone();
+            backend.publish(&collect(&state, &servers));
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_backend() {
+        assert_eq!(BackendKind::parse("none"), Ok(BackendKind::None));
+        assert_eq!(BackendKind::parse("Datadog"), Ok(BackendKind::Dogstatsd));
+        assert_eq!(BackendKind::parse("emf"), Ok(BackendKind::Emf));
+        assert!(BackendKind::parse("graphite").is_err());
+    }
+
+    #[test]
+    fn test_counter_deltas() {
+        let deltas = CounterDeltas::default();
+        let mut sample = Sample {
+            name: "requests_total",
+            help: "",
+            kind: MetricKind::Counter,
+            value: 5.0,
+            server: Some("server-0".into()),
+        };
+        assert_eq!(deltas.delta(&sample), 5.0);
+        sample.value = 8.0;
+        assert_eq!(deltas.delta(&sample), 3.0);
+        sample.server = Some("server-1".into());
+        assert_eq!(deltas.delta(&sample), 8.0);
+    }
+}
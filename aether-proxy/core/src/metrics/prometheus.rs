@@ -0,0 +1,152 @@
+//! Prometheus text exposition served on a plain HTTP listener.
+
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use tokio::io::AsyncWriteExt;
+use tokio::net::TcpStream;
+use tokio::sync::watch;
+use tracing::{debug, info};
+
+use crate::http_head::{self, RequestHead};
+
+use super::{MetricKind, MetricsBackend, Sample, PREFIX};
+
+/// Largest request head accepted from a scraper.
+const MAX_REQUEST_HEAD: usize = 8 * 1024;
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub struct Prometheus {
+    body: Arc<RwLock<String>>,
+}
+
+impl Prometheus {
+    /// Bind the scrape listener and start serving.
+    pub async fn bind(
+        addr: SocketAddr,
+        mut shutdown: watch::Receiver<bool>,
+    ) -> anyhow::Result<Self> {
+        let listener = crate::handoff::bind_listener("metrics", addr)
+            .map_err(|e| anyhow::anyhow!("metrics_listen {}: {}", addr, e))?;
+        info!(%addr, "prometheus metrics listening");
+        let body = Arc::new(RwLock::new(String::new()));
+        let served = Arc::clone(&body);
+        tokio::spawn(async move {
+            loop {
+                let (stream, peer) = tokio::select! {
+                    accepted = listener.accept() => match accepted {
+                        Ok(conn) => conn,
+                        Err(e) => {
+                            debug!(error = %e, "metrics accept failed");
+                            continue;
+                        }
+                    },
+                    _ = shutdown.changed() => return,
+                };
+                let body = served.read().unwrap().clone();
+                tokio::spawn(async move {
+                    if let Err(e) = serve(stream, &body).await {
+                        debug!(%peer, error = %e, "metrics scrape failed");
+                    }
+                });
+            }
+        });
+        Ok(Self { body })
+    }
+}
+
+impl MetricsBackend for Prometheus {
+    fn publish(&self, samples: &[Sample]) {
+        *self.body.write().unwrap() = render(samples);
+    }
+}
+
+async fn serve(mut stream: TcpStream, body: &str) -> std::io::Result<()> {
+    let Some(head) = http_head::read(&mut stream, MAX_REQUEST_HEAD, READ_TIMEOUT).await? else {
+        return Ok(());
+    };
+
+    let head = String::from_utf8_lossy(&head);
+    let scrape =
+        RequestHead::parse(&head).is_some_and(|r| r.method == "GET" && r.path == "/metrics");
+    let response = if scrape {
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            body.len(),
+            body
+        )
+    } else {
+        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string()
+    };
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Render samples in the text exposition format, one HELP/TYPE block per
+/// metric name.
+fn render(samples: &[Sample]) -> String {
+    let mut out = String::new();
+    let mut seen: Vec<&str> = Vec::new();
+    for sample in samples {
+        if seen.contains(&sample.name) {
+            continue;
+        }
+        seen.push(sample.name);
+        let kind = match sample.kind {
+            MetricKind::Counter => "counter",
+            MetricKind::Gauge => "gauge",
+        };
+        let _ = writeln!(out, "# HELP {PREFIX}_{} {}", sample.name, sample.help);
+        let _ = writeln!(out, "# TYPE {PREFIX}_{} {kind}", sample.name);
+        for s in samples.iter().filter(|s| s.name == sample.name) {
+            match &s.server {
+                Some(server) => {
+                    let _ = writeln!(
+                        out,
+                        "{PREFIX}_{}{{server=\"{}\"}} {}",
+                        s.name,
+                        escape_label(server),
+                        s.value
+                    );
+                }
+                None => {
+                    let _ = writeln!(out, "{PREFIX}_{} {}", s.name, s.value);
+                }
+            }
+        }
+    }
+    out
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_groups_by_name() {
+        let sample = |server: &str, value| Sample {
+            name: "requests_total",
+            help: "Requests",
+            kind: MetricKind::Counter,
+            value,
+            server: Some(server.to_string()),
+        };
+        let text = render(&[sample("server-0", 3.0), sample("a\"b", 1.0)]);
+        assert_eq!(
+            text,
+            "# HELP aether_proxy_requests_total Requests\n\
+             # TYPE aether_proxy_requests_total counter\n\
+             aether_proxy_requests_total{server=\"server-0\"} 3\n\
+             aether_proxy_requests_total{server=\"a\\\"b\"} 1\n"
+        );
+    }
+}
@@ -0,0 +1,150 @@
+//! StatsD and DogStatsD push over UDP.
+//!
+//! Counters are sent as per-interval increments (`|c`), gauges as absolute
+//! values (`|g`).  Plain StatsD has no tags, so the server label is folded
+//! into the metric name; DogStatsD carries `server` and `node` as tags.
+
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+
+use tracing::debug;
+
+use super::{CounterDeltas, MetricKind, MetricsBackend, Sample, PREFIX};
+
+/// Keep datagrams under a typical path MTU.
+const MAX_DATAGRAM: usize = 1400;
+
+pub struct Statsd {
+    socket: UdpSocket,
+    node: String,
+    tags: bool,
+    deltas: CounterDeltas,
+}
+
+impl Statsd {
+    pub fn connect(addr: &str, node: &str, tags: bool) -> anyhow::Result<Self> {
+        let target: SocketAddr = addr
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addrs| addrs.next())
+            .ok_or_else(|| anyhow::anyhow!("metrics_statsd_addr: cannot resolve {:?}", addr))?;
+        let bind: SocketAddr = if target.is_ipv6() {
+            "[::]:0".parse().unwrap()
+        } else {
+            "0.0.0.0:0".parse().unwrap()
+        };
+        let socket = UdpSocket::bind(bind)?;
+        socket.connect(target)?;
+        socket.set_nonblocking(true)?;
+        Ok(Self {
+            socket,
+            node: sanitize(node),
+            tags,
+            deltas: CounterDeltas::default(),
+        })
+    }
+
+    fn line(&self, sample: &Sample) -> String {
+        let (value, kind) = match sample.kind {
+            MetricKind::Counter => (self.deltas.delta(sample), "c"),
+            MetricKind::Gauge => (sample.value, "g"),
+        };
+        if self.tags {
+            let mut line = format!(
+                "{PREFIX}.{}:{}|{}|#node:{}",
+                sample.name, value, kind, self.node
+            );
+            if let Some(server) = &sample.server {
+                line.push_str(",server:");
+                line.push_str(&sanitize(server));
+            }
+            line
+        } else {
+            match &sample.server {
+                Some(server) => format!(
+                    "{PREFIX}.{}.{}.{}:{}|{}",
+                    self.node,
+                    sanitize(server),
+                    sample.name,
+                    value,
+                    kind
+                ),
+                None => format!("{PREFIX}.{}.{}:{}|{}", self.node, sample.name, value, kind),
+            }
+        }
+    }
+
+    fn send(&self, payload: &str) {
+        if let Err(e) = self.socket.send(payload.as_bytes()) {
+            debug!(error = %e, "statsd send failed");
+        }
+    }
+}
+
+impl MetricsBackend for Statsd {
+    fn publish(&self, samples: &[Sample]) {
+        let mut batch = String::new();
+        for sample in samples {
+            let line = self.line(sample);
+            if !batch.is_empty() && batch.len() + 1 + line.len() > MAX_DATAGRAM {
+                self.send(&batch);
+                batch.clear();
+            }
+            if !batch.is_empty() {
+                batch.push('\n');
+            }
+            batch.push_str(&line);
+        }
+        if !batch.is_empty() {
+            self.send(&batch);
+        }
+    }
+}
+
+/// Replace characters that are reserved in the StatsD line protocol.
+fn sanitize(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| match c {
+            ':' | '|' | '@' | '#' | ',' | '.' | ' ' | '\n' => '_',
+            c => c,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(kind: MetricKind, value: f64) -> Sample {
+        Sample {
+            name: "requests_total",
+            help: "",
+            kind,
+            value,
+            server: Some("server-0".into()),
+        }
+    }
+
+    #[test]
+    fn test_line_formats() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = receiver.local_addr().unwrap().to_string();
+
+        let plain = Statsd::connect(&addr, "hk.01", false).unwrap();
+        assert_eq!(
+            plain.line(&sample(MetricKind::Counter, 5.0)),
+            "aether_proxy.hk_01.server-0.requests_total:5|c"
+        );
+        // Counters are sent as increments
+        assert_eq!(
+            plain.line(&sample(MetricKind::Counter, 7.0)),
+            "aether_proxy.hk_01.server-0.requests_total:2|c"
+        );
+
+        let dog = Statsd::connect(&addr, "hk-01", true).unwrap();
+        assert_eq!(
+            dog.line(&sample(MetricKind::Gauge, 4.0)),
+            "aether_proxy.requests_total:4|g|#node:hk-01,server:server-0"
+        );
+    }
+}
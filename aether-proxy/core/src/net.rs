@@ -0,0 +1,400 @@
+//! Network utility functions (public IP detection, region detection, local
+//! interface addresses).
+//!
+//! These are standalone helpers not tied to any specific client or service.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+
+use reqwest::Client;
+use tracing::{debug, info, warn};
+
+/// IPv4-only endpoints returning the caller's address as plain text.
+const IPV4_ENDPOINTS: &[&str] = &[
+    "https://api.ipify.org",
+    "https://ipv4.icanhazip.com",
+    "https://ifconfig.me/ip",
+];
+
+/// IPv6-only endpoints returning the caller's address as plain text.
+const IPV6_ENDPOINTS: &[&str] = &["https://api6.ipify.org", "https://ipv6.icanhazip.com"];
+
+/// STUN servers asked when no echo endpoint answers.
+const STUN_SERVERS: &[&str] = &["stun.cloudflare.com:3478", "stun.l.google.com:19302"];
+
+/// Where the public address is detected from: HTTPS echo endpoints first,
+/// then STUN binding requests.
+#[derive(Debug, Clone, Default)]
+pub struct IpSources {
+    /// Custom echo endpoints (queried for both families); empty uses the
+    /// built-in per-family lists.
+    echo_urls: Vec<String>,
+    /// `host:port` of STUN servers; empty disables STUN.
+    stun_servers: Vec<String>,
+}
+
+impl IpSources {
+    /// `--ip-echo-url` and `--stun-server` values: no STUN server means the
+    /// built-in ones, `off` means none.
+    pub fn new(echo_urls: &[String], stun_servers: &[String]) -> Result<Self, String> {
+        for raw in echo_urls {
+            let url = url::Url::parse(raw).map_err(|e| format!("{:?}: {}", raw, e))?;
+            if !matches!(url.scheme(), "http" | "https") {
+                return Err(format!("{:?}: echo endpoints must be http(s) URLs", raw));
+            }
+        }
+        let stun_servers = match stun_servers {
+            [] => STUN_SERVERS.iter().map(|s| s.to_string()).collect(),
+            [off] if off.eq_ignore_ascii_case("off") => Vec::new(),
+            servers => {
+                for server in servers {
+                    let valid = server.rsplit_once(':').is_some_and(|(host, port)| {
+                        !host.is_empty() && port.parse::<u16>().is_ok()
+                    });
+                    if !valid {
+                        return Err(format!("{:?}: STUN servers must be host:port", server));
+                    }
+                }
+                servers.to_vec()
+            }
+        };
+        Ok(Self {
+            echo_urls: echo_urls.to_vec(),
+            stun_servers,
+        })
+    }
+}
+
+/// Public addresses this node is reachable at.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublicAddrs {
+    /// Address registered as the node's `ip` (IPv4 when available).
+    pub primary: String,
+    /// Public IPv6 address, if the node has one.
+    pub ipv6: Option<Ipv6Addr>,
+}
+
+impl PublicAddrs {
+    /// Every known public address, for self-target blocking.
+    pub fn ips(&self) -> Vec<IpAddr> {
+        let mut ips: Vec<IpAddr> = self.primary.parse().into_iter().collect();
+        if let Some(v6) = self.ipv6 {
+            if !ips.contains(&IpAddr::V6(v6)) {
+                ips.push(IpAddr::V6(v6));
+            }
+        }
+        ips
+    }
+
+    /// These addresses updated with a fresh detection.  A family that was
+    /// not detected this time is assumed unchanged, so a flaky echo
+    /// endpoint does not look like an address change.
+    pub fn with_detected(&self, v4: Option<Ipv4Addr>, v6: Option<Ipv6Addr>) -> Self {
+        let primary_is_v4 = self
+            .primary
+            .parse::<Ipv4Addr>()
+            .is_ok_and(|ip| !ip.is_unspecified());
+        let primary = match (v4, v6) {
+            (Some(v4), _) => v4.to_string(),
+            (None, Some(v6)) if !primary_is_v4 => v6.to_string(),
+            _ => self.primary.clone(),
+        };
+        Self {
+            primary,
+            ipv6: v6.or(self.ipv6),
+        }
+    }
+}
+
+/// Determine the node's public addresses.
+///
+/// `--public-ip` disables detection of the primary address;
+/// `--public-ipv6` pins the IPv6 one.  Without `--public-ip` both families
+/// are detected in parallel, each over a socket bound to that family so a
+/// dual-stack host reports both.
+pub async fn detect_public_addrs(
+    public_ip: Option<&str>,
+    public_ipv6: Option<Ipv6Addr>,
+    sources: &IpSources,
+) -> PublicAddrs {
+    if let Some(primary) = public_ip {
+        let ipv6 = public_ipv6.or_else(|| match primary.parse() {
+            Ok(IpAddr::V6(v6)) => Some(v6),
+            _ => None,
+        });
+        return PublicAddrs {
+            primary: primary.to_string(),
+            ipv6,
+        };
+    }
+
+    let (v4, ipv6) = detect_families(public_ipv6, sources).await;
+    let primary = v4
+        .map(IpAddr::V4)
+        .or(ipv6.map(IpAddr::V6))
+        .map(|ip| ip.to_string())
+        .unwrap_or_else(|| {
+            warn!("failed to detect public IP from any source; use --public-ip");
+            "0.0.0.0".to_string()
+        });
+    PublicAddrs { primary, ipv6 }
+}
+
+/// Detect the public IPv4 and IPv6 addresses in parallel (IPv6 pinned to
+/// `public_ipv6` when given); `None` for a family no endpoint reported.
+pub async fn detect_families(
+    public_ipv6: Option<Ipv6Addr>,
+    sources: &IpSources,
+) -> (Option<Ipv4Addr>, Option<Ipv6Addr>) {
+    let (v4, v6) = tokio::join!(
+        detect_family(IpAddr::V4(Ipv4Addr::UNSPECIFIED), IPV4_ENDPOINTS, sources),
+        async {
+            match public_ipv6 {
+                Some(v6) => Some(IpAddr::V6(v6)),
+                None => {
+                    detect_family(IpAddr::V6(Ipv6Addr::UNSPECIFIED), IPV6_ENDPOINTS, sources).await
+                }
+            }
+        }
+    );
+    let v4 = match v4 {
+        Some(IpAddr::V4(v4)) => Some(v4),
+        _ => None,
+    };
+    let v6 = match v6 {
+        Some(IpAddr::V6(v6)) => Some(v6),
+        _ => None,
+    };
+    (v4, v6)
+}
+
+/// Query the echo endpoints (`defaults` unless custom ones are configured),
+/// then the STUN servers, over sockets bound to `bind` (which fixes the
+/// address family) until one returns an address of that family.
+async fn detect_family(bind: IpAddr, defaults: &[&str], sources: &IpSources) -> Option<IpAddr> {
+    let endpoints: Vec<&str> = if sources.echo_urls.is_empty() {
+        defaults.to_vec()
+    } else {
+        sources.echo_urls.iter().map(String::as_str).collect()
+    };
+    if let Some(ip) = detect_by_echo(bind, &endpoints).await {
+        return Some(ip);
+    }
+    for server in &sources.stun_servers {
+        match crate::stun::public_addr(server, bind, Duration::from_secs(3)).await {
+            Ok(ip) if ip.is_ipv4() == bind.is_ipv4() => {
+                info!(ip = %ip, source = %server, "detected public IP via STUN");
+                return Some(ip);
+            }
+            Ok(ip) => debug!(server = %server, ip = %ip, "STUN returned the wrong family"),
+            Err(e) => debug!(server = %server, error = %e, "STUN detection failed"),
+        }
+    }
+    None
+}
+
+async fn detect_by_echo(bind: IpAddr, endpoints: &[&str]) -> Option<IpAddr> {
+    let client = Client::builder()
+        .timeout(Duration::from_secs(5))
+        .local_address(bind)
+        .build()
+        .ok()?;
+
+    for endpoint in endpoints {
+        match client.get(*endpoint).send().await {
+            Ok(resp) if resp.status().is_success() => {
+                let text = resp.text().await.unwrap_or_default();
+                match text.trim().parse::<IpAddr>() {
+                    Ok(ip) if ip.is_ipv4() == bind.is_ipv4() => {
+                        info!(ip = %ip, source = %endpoint, "detected public IP");
+                        return Some(ip);
+                    }
+                    _ => {
+                        debug!(endpoint = %endpoint, body = %text.trim(), "unexpected IP detection response");
+                    }
+                }
+            }
+            Ok(resp) => {
+                debug!(endpoint = %endpoint, status = %resp.status(), "IP detection failed");
+            }
+            Err(e) => {
+                debug!(endpoint = %endpoint, error = %e, "IP detection failed");
+            }
+        }
+    }
+    None
+}
+
+/// Auto-detect geographic region from a public IP address.
+///
+/// Uses multiple providers with HTTPS preferred.  Falls back to ip-api.com
+/// over plain HTTP (their free tier doesn't support HTTPS).
+/// This is best-effort and non-sensitive -- region detection should never
+/// block startup.
+pub async fn detect_region(ip: &str) -> Option<String> {
+    // Try HTTPS provider first
+    let https_url = format!("https://ipinfo.io/{}/country", ip);
+
+    let client = Client::builder()
+        .timeout(std::time::Duration::from_secs(5))
+        .build()
+        .ok()?;
+
+    // Try ipinfo.io (HTTPS, returns plain text country code)
+    if let Ok(resp) = client.get(&https_url).send().await {
+        if resp.status().is_success() {
+            if let Ok(text) = resp.text().await {
+                let code = text.trim();
+                if !code.is_empty() && code.len() <= 3 {
+                    info!(region = %code, ip = %ip, source = "ipinfo.io", "detected region");
+                    return Some(code.to_string());
+                }
+            }
+        }
+    }
+
+    // Fallback: ip-api.com (HTTP only on free tier, non-sensitive data)
+    let http_url = format!("http://ip-api.com/json/{}?fields=countryCode", ip);
+    match client.get(&http_url).send().await {
+        Ok(resp) if resp.status().is_success() => {
+            let body: serde_json::Value = resp.json().await.ok()?;
+            let code = body.get("countryCode")?.as_str()?;
+            if code.is_empty() {
+                return None;
+            }
+            info!(region = %code, ip = %ip, source = "ip-api.com", "detected region");
+            Some(code.to_string())
+        }
+        _ => {
+            debug!(ip = %ip, "region detection failed");
+            None
+        }
+    }
+}
+
+/// Addresses assigned to the local network interfaces.
+///
+/// Used to stop tunnel targets from pointing back at this host via one of
+/// its own public addresses. Best-effort: returns an empty list on failure.
+#[cfg(unix)]
+pub fn local_interface_addrs() -> Vec<IpAddr> {
+    let mut addrs = Vec::new();
+    let mut ifap: *mut libc::ifaddrs = std::ptr::null_mut();
+    // SAFETY: getifaddrs fills `ifap` with a linked list we free below.
+    if unsafe { libc::getifaddrs(&mut ifap) } != 0 {
+        return addrs;
+    }
+    let mut cur = ifap;
+    while !cur.is_null() {
+        // SAFETY: `cur` is a non-null node of the list returned above, and
+        // `ifa_addr` points at a sockaddr matching its `sa_family`.
+        unsafe {
+            let ifa = &*cur;
+            if !ifa.ifa_addr.is_null() {
+                match i32::from((*ifa.ifa_addr).sa_family) {
+                    libc::AF_INET => {
+                        let sin = &*(ifa.ifa_addr as *const libc::sockaddr_in);
+                        addrs.push(IpAddr::V4(Ipv4Addr::from(u32::from_be(
+                            sin.sin_addr.s_addr,
+                        ))));
+                    }
+                    libc::AF_INET6 => {
+                        let sin6 = &*(ifa.ifa_addr as *const libc::sockaddr_in6);
+                        addrs.push(IpAddr::V6(Ipv6Addr::from(sin6.sin6_addr.s6_addr)));
+                    }
+                    _ => {}
+                }
+            }
+            cur = ifa.ifa_next;
+        }
+    }
+    // SAFETY: `ifap` came from a successful getifaddrs call.
+    unsafe { libc::freeifaddrs(ifap) };
+    addrs
+}
+
+#[cfg(not(unix))]
+pub fn local_interface_addrs() -> Vec<IpAddr> {
+    Vec::new()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_explicit_public_addrs_skip_detection() {
+        let v4 = detect_public_addrs(Some("203.0.113.7"), None, &IpSources::default()).await;
+        assert_eq!(v4.primary, "203.0.113.7");
+        assert_eq!(v4.ipv6, None);
+
+        let v6: Ipv6Addr = "2001:db8::7".parse().unwrap();
+        let dual = detect_public_addrs(Some("203.0.113.7"), Some(v6), &IpSources::default()).await;
+        assert_eq!(
+            dual.ips(),
+            vec!["203.0.113.7".parse::<IpAddr>().unwrap(), v6.into()]
+        );
+
+        // An IPv6 --public-ip doubles as the IPv6 endpoint
+        let v6_only = detect_public_addrs(Some("2001:db8::7"), None, &IpSources::default()).await;
+        assert_eq!(v6_only.ipv6, Some(v6));
+        assert_eq!(v6_only.ips().len(), 1);
+    }
+
+    #[test]
+    fn test_with_detected_keeps_missing_families() {
+        let current = PublicAddrs {
+            primary: "203.0.113.7".to_string(),
+            ipv6: Some("2001:db8::7".parse().unwrap()),
+        };
+        let moved: Ipv4Addr = "198.51.100.9".parse().unwrap();
+        let v6: Ipv6Addr = "2001:db8::8".parse().unwrap();
+
+        assert_eq!(current.with_detected(None, None), current);
+        // A missing IPv4 answer doesn't promote the IPv6 address
+        assert_eq!(current.with_detected(None, Some(v6)).primary, "203.0.113.7");
+        let updated = current.with_detected(Some(moved), None);
+        assert_eq!(updated.primary, "198.51.100.9");
+        assert_eq!(updated.ipv6, current.ipv6);
+
+        // Startup detection failed entirely: anything found is an update
+        let unknown = PublicAddrs {
+            primary: "0.0.0.0".to_string(),
+            ipv6: None,
+        };
+        assert_eq!(
+            unknown.with_detected(Some(moved), None).primary,
+            "198.51.100.9"
+        );
+        let v6_only = PublicAddrs {
+            primary: "2001:db8::7".to_string(),
+            ipv6: Some("2001:db8::7".parse().unwrap()),
+        };
+        assert_eq!(v6_only.with_detected(None, Some(v6)).primary, "2001:db8::8");
+    }
+
+    #[test]
+    fn test_ip_sources() {
+        let defaults = IpSources::new(&[], &[]).unwrap();
+        assert!(defaults.echo_urls.is_empty());
+        assert_eq!(defaults.stun_servers.len(), STUN_SERVERS.len());
+        assert!(IpSources::new(&[], &["OFF".to_string()])
+            .unwrap()
+            .stun_servers
+            .is_empty());
+
+        let custom = IpSources::new(
+            &["https://ip.example.com/".to_string()],
+            &[
+                "stun.example.com:3478".to_string(),
+                "[2001:db8::1]:3478".to_string(),
+            ],
+        )
+        .unwrap();
+        assert_eq!(custom.echo_urls, vec!["https://ip.example.com/"]);
+        assert_eq!(custom.stun_servers.len(), 2);
+
+        assert!(IpSources::new(&["ftp://ip.example.com".to_string()], &[]).is_err());
+        assert!(IpSources::new(&[], &["stun.example.com".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,205 @@
+//! Data minimization for access logs and control-plane reports.
+//!
+//! `--log-privacy` controls how targets and clients appear in the access
+//! log (every sink, including uploads to Aether) and in destinations
+//! reports:
+//!
+//! - `off` (default): as requested
+//! - `truncate`: hostnames are cut down to the registrable domain
+//!   (`api.eu.example.co.uk` becomes `example.co.uk`), IP targets to their
+//!   /24 (IPv4) or /48 (IPv6) network
+//! - `hash`: hostnames, IP targets and client identities are replaced by a
+//!   keyed hash (16 hex digits).  The key is derived from
+//!   `--log-privacy-secret`, so hashes stay comparable across restarts and
+//!   across nodes sharing the secret.  Without one a key is generated at
+//!   startup, and hashes can be correlated within one run of the node only.
+//!   The secret is never derived from the management token: Aether knows
+//!   that, and could then hash candidate hostnames to undo the mapping
+//!
+//! The registrable domain is approximated without a public suffix list:
+//! the last two labels, or three under a two-letter country code with a
+//! common second level (`co`, `com`, `org`...).  Client source IPs are only
+//! ever seen by Aether, so the node has none to truncate.  Per-client usage
+//! rollups keep client identities, since they are needed for billing.
+
+use std::net::IpAddr;
+
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// Domain separation for deriving the hash key from the configured secret.
+const HASH_KEY_LABEL: &[u8] = b"aether-proxy log-privacy hash v1";
+
+/// Second levels under which country code TLDs register domains.
+const COUNTRY_SECOND_LEVELS: &[&str] = &[
+    "ac", "co", "com", "edu", "gov", "ltd", "ne", "net", "or", "org", "plc", "sch",
+];
+
+#[derive(Debug, Clone, Default)]
+pub enum Privacy {
+    #[default]
+    Off,
+    Truncate,
+    Hash(hmac::Key),
+}
+
+impl Privacy {
+    /// Parse a mode; `secret` keys the `hash` mode, which otherwise gets a
+    /// fresh random key.
+    pub fn parse(raw: &str, secret: Option<&str>) -> Result<Self, String> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "" | "off" => Ok(Self::Off),
+            "truncate" => Ok(Self::Truncate),
+            "hash" => {
+                let mut key = [0u8; 32];
+                match secret.filter(|s| !s.is_empty()) {
+                    Some(secret) => {
+                        let derive = hmac::Key::new(hmac::HMAC_SHA256, HASH_KEY_LABEL);
+                        key.copy_from_slice(hmac::sign(&derive, secret.as_bytes()).as_ref());
+                    }
+                    None => SystemRandom::new()
+                        .fill(&mut key)
+                        .map_err(|_| "no randomness for the hash key".to_string())?,
+                }
+                Ok(Self::Hash(hmac::Key::new(hmac::HMAC_SHA256, &key)))
+            }
+            other => Err(format!(
+                "unknown mode {:?} (use off, truncate or hash)",
+                other
+            )),
+        }
+    }
+
+    /// A target host as it may be logged.
+    pub fn host(&self, host: &str) -> String {
+        let name = host.trim_end_matches('.').to_ascii_lowercase();
+        match self {
+            Self::Off => host.to_string(),
+            Self::Truncate => {
+                let bare = name.trim_start_matches('[').trim_end_matches(']');
+                match bare.parse::<IpAddr>() {
+                    Ok(ip) => truncate_ip(ip),
+                    Err(_) => registrable_domain(&name).to_string(),
+                }
+            }
+            Self::Hash(key) => hash(key, &name),
+        }
+    }
+
+    /// A client identity as it may be logged.
+    pub fn client(&self, client: Option<String>) -> Option<String> {
+        match self {
+            Self::Hash(key) => client.map(|client| hash(key, &client)),
+            _ => client,
+        }
+    }
+
+    /// `text` with `host` replaced as by [`Self::host`], for free-form
+    /// messages that may name the target.
+    pub fn scrub(&self, text: String, host: &str) -> String {
+        match self {
+            Self::Off => text,
+            _ if host.is_empty() => text,
+            _ => text.replace(host, &self.host(host)),
+        }
+    }
+}
+
+fn hash(key: &hmac::Key, value: &str) -> String {
+    hex::encode(&hmac::sign(key, value.as_bytes()).as_ref()[..8])
+}
+
+fn truncate_ip(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(v4) => {
+            let [a, b, c, _] = v4.octets();
+            format!("{}.{}.{}.0/24", a, b, c)
+        }
+        IpAddr::V6(v6) => {
+            let mut segments = v6.segments();
+            segments[3..].fill(0);
+            format!("{}/48", std::net::Ipv6Addr::from(segments))
+        }
+    }
+}
+
+/// Approximate eTLD+1 of a lowercase hostname.
+fn registrable_domain(host: &str) -> &str {
+    let labels: Vec<&str> = host.rsplit('.').take(3).collect();
+    let keep = match labels.as_slice() {
+        [tld, second, _] if tld.len() == 2 && COUNTRY_SECOND_LEVELS.contains(second) => 3,
+        _ => 2,
+    };
+    match host.rmatch_indices('.').nth(keep - 1) {
+        Some((dot, _)) => &host[dot + 1..],
+        None => host,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_truncate() {
+        let privacy = Privacy::parse("Truncate", None).unwrap();
+        for (host, expected) in [
+            ("api.openai.com", "openai.com"),
+            ("API.eu.Example.co.uk.", "example.co.uk"),
+            ("example.co.uk", "example.co.uk"),
+            ("www.example.de", "example.de"),
+            ("co.uk", "co.uk"),
+            ("localhost", "localhost"),
+            ("203.0.113.77", "203.0.113.0/24"),
+            ("[2001:db8:1:2:3::4]", "2001:db8:1::/48"),
+        ] {
+            assert_eq!(privacy.host(host), expected, "{host}");
+        }
+        assert_eq!(
+            privacy.client(Some("tenant".into())).as_deref(),
+            Some("tenant")
+        );
+        assert_eq!(
+            privacy.scrub(
+                "connect to api.openai.com:443 failed".into(),
+                "api.openai.com"
+            ),
+            "connect to openai.com:443 failed"
+        );
+    }
+
+    #[test]
+    fn test_hash() {
+        let privacy = Privacy::parse("hash", None).unwrap();
+        let hashed = privacy.host("api.openai.com");
+        assert_eq!(hashed.len(), 16);
+        assert_eq!(privacy.host("API.openai.com."), hashed);
+        assert_ne!(privacy.host("api.anthropic.com"), hashed);
+        assert_ne!(privacy.client(Some("tenant".into())).unwrap(), "tenant");
+        // Without a secret each run has its own key
+        assert_ne!(
+            Privacy::parse("hash", None).unwrap().host("api.openai.com"),
+            hashed
+        );
+        // With one, hashes survive restarts
+        let keyed = |secret| {
+            Privacy::parse("hash", Some(secret))
+                .unwrap()
+                .host("api.openai.com")
+        };
+        assert_eq!(keyed("s3cret"), keyed("s3cret"));
+        assert_ne!(keyed("s3cret"), keyed("other"));
+        assert_ne!(
+            Privacy::parse("hash", Some(""))
+                .unwrap()
+                .host("api.openai.com"),
+            Privacy::parse("hash", Some(""))
+                .unwrap()
+                .host("api.openai.com")
+        );
+
+        let off = Privacy::parse("", None).unwrap();
+        assert_eq!(off.host("api.openai.com"), "api.openai.com");
+        assert!(Privacy::parse("mask", None).is_err());
+    }
+}
@@ -0,0 +1,187 @@
+//! Connect latency probes to benchmark targets.
+//!
+//! Every `--probe-interval-secs` the node opens a fresh connection to each
+//! `--probe-target` (or the control plane's `remote_config.probe_targets`,
+//! which replaces the local list), timing the TCP connect (including name
+//! resolution) and the TLS handshake, then closes it.  Targets use the
+//! `--hot-target` syntax.  The last [`WINDOW`] probes per target are
+//! summarised as percentiles in heartbeats and the admin status, so that
+//! Aether can rank nodes by how well they actually reach the APIs in use.
+//!
+//! Probes take the same route as real traffic: resolver, address checks,
+//! egress binding and upstream proxy rules.
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio::sync::watch;
+use tower_service::Service;
+
+use crate::state::ServerContext;
+use crate::upstream_client::InstrumentedConnector;
+use crate::warm_pool::HotTarget;
+
+/// Probes kept per target.
+pub const WINDOW: usize = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Sample {
+    connect_ms: u64,
+    tls_ms: u64,
+    total_ms: u64,
+}
+
+/// Recent probe results per target.
+#[derive(Default)]
+pub struct Prober {
+    results: Mutex<HashMap<String, VecDeque<Result<Sample, String>>>>,
+}
+
+impl Prober {
+    fn record(&self, target: &str, result: Result<Sample, String>) {
+        let mut results = self.results.lock().unwrap();
+        let window = results.entry(target.to_string()).or_default();
+        if window.len() == WINDOW {
+            window.pop_front();
+        }
+        window.push_back(result);
+    }
+
+    /// Forget targets no longer probed.
+    fn retain(&self, targets: &[HotTarget]) {
+        let keep: Vec<String> = targets.iter().map(ToString::to_string).collect();
+        self.results
+            .lock()
+            .unwrap()
+            .retain(|target, _| keep.contains(target));
+    }
+
+    pub fn stats_json(&self) -> serde_json::Value {
+        let results = self.results.lock().unwrap();
+        let targets = results
+            .iter()
+            .map(|(target, window)| {
+                let ok: Vec<Sample> = window.iter().filter_map(|r| r.clone().ok()).collect();
+                let last_error = window.iter().rev().find_map(|r| r.clone().err());
+                let field = |get: fn(&Sample) -> u64| {
+                    let mut values: Vec<u64> = ok.iter().map(get).collect();
+                    values.sort_unstable();
+                    serde_json::json!({
+                        "p50": percentile(&values, 50),
+                        "p90": percentile(&values, 90),
+                        "p99": percentile(&values, 99),
+                    })
+                };
+                let stats = serde_json::json!({
+                    "probes": window.len(),
+                    "failures": window.len() - ok.len(),
+                    "connect_ms": field(|s| s.connect_ms),
+                    "tls_ms": field(|s| s.tls_ms),
+                    "total_ms": field(|s| s.total_ms),
+                    "last_error": last_error,
+                });
+                (target.clone(), stats)
+            })
+            .collect::<serde_json::Map<_, _>>();
+        serde_json::Value::Object(targets)
+    }
+}
+
+/// Nearest-rank percentile of sorted `values`.
+fn percentile(values: &[u64], p: usize) -> Option<u64> {
+    let rank = (values.len() * p).div_ceil(100).max(1);
+    values.get(rank - 1).copied()
+}
+
+/// Probe the targets of all servers every `interval`, dialing with
+/// `connector`.
+pub fn spawn(
+    prober: Arc<Prober>,
+    connector: InstrumentedConnector,
+    servers: Arc<tokio::sync::Mutex<Vec<Arc<ServerContext>>>>,
+    interval: Duration,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                _ = shutdown.changed() => return,
+            }
+            let mut targets: Vec<HotTarget> = Vec::new();
+            for server in servers.lock().await.iter() {
+                for target in server.dynamic.load().probe_targets.iter() {
+                    if !targets.contains(target) {
+                        targets.push(target.clone());
+                    }
+                }
+            }
+            prober.retain(&targets);
+            let probes = targets.into_iter().map(|target| {
+                let mut connector = connector.clone();
+                let prober = Arc::clone(&prober);
+                async move {
+                    let start = Instant::now();
+                    let result = match connector.call(target.uri()).await {
+                        Ok(conn) => {
+                            let timing = conn.timing();
+                            Ok(Sample {
+                                connect_ms: timing.connect_ms,
+                                tls_ms: timing.tls_ms,
+                                total_ms: start.elapsed().as_millis() as u64,
+                            })
+                        }
+                        Err(e) => Err(e.to_string()),
+                    };
+                    prober.record(&target.to_string(), result);
+                }
+            });
+            futures_util::future::join_all(probes).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile() {
+        let values: Vec<u64> = (1..=10).collect();
+        assert_eq!(percentile(&values, 50), Some(5));
+        assert_eq!(percentile(&values, 90), Some(9));
+        assert_eq!(percentile(&values, 99), Some(10));
+        assert_eq!(percentile(&[7], 50), Some(7));
+        assert_eq!(percentile(&[], 50), None);
+    }
+
+    #[test]
+    fn test_stats() {
+        let prober = Prober::default();
+        let target = "https://api.openai.com:443";
+        for ms in 1..=WINDOW as u64 + 5 {
+            let sample = Sample {
+                connect_ms: ms,
+                tls_ms: 2 * ms,
+                total_ms: 3 * ms,
+            };
+            prober.record(target, Ok(sample));
+        }
+        prober.record(target, Err("connection refused".to_string()));
+
+        let stats = prober.stats_json();
+        let stats = &stats[target];
+        assert_eq!(stats["probes"], WINDOW);
+        assert_eq!(stats["failures"], 1);
+        assert_eq!(stats["last_error"], "connection refused");
+        // The oldest six probes rolled out of the window
+        assert_eq!(stats["connect_ms"]["p50"], 21);
+        assert_eq!(stats["tls_ms"]["p99"], 70);
+
+        prober.retain(&[]);
+        assert_eq!(prober.stats_json(), serde_json::json!({}));
+    }
+}
@@ -0,0 +1,255 @@
+//! Per-client daily quotas.
+//!
+//! Limits come from the control plane (`remote_config.client_quotas`):
+//!
+//! ```json
+//! {"default": {"daily_requests": 100000},
+//!  "clients": {"tenant-1": {"daily_requests": 5000, "daily_bytes": 1073741824}}}
+//! ```
+//!
+//! A client's own entry replaces the default.  Streams are tagged with
+//! their client by Aether (see [`crate::usage`]); untagged streams are
+//! never limited.  Requests are counted when admitted and bytes when a
+//! stream ends, so one large transfer may overshoot the byte quota; the
+//! next request is then refused.  Days are UTC days and each server
+//! connection keeps its own counters.
+//!
+//! Independently, `--max-streams-per-client` caps the streams one client
+//! has in flight across all tunnels to a server, so that a single
+//! misbehaving client cannot take up the whole stream budget.  It is keyed
+//! by the same client tag, not by source IP: requests reach the node only
+//! through the tunnel, so client addresses are never seen here.  Limiting
+//! by address is up to Aether, which accepts the connections.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use serde::Deserialize;
+
+use crate::clock::now_ms;
+
+const DAY_MS: u64 = 24 * 60 * 60 * 1000;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct Limits {
+    #[serde(default)]
+    pub daily_requests: Option<u64>,
+    #[serde(default)]
+    pub daily_bytes: Option<u64>,
+}
+
+impl Limits {
+    fn is_unlimited(&self) -> bool {
+        self.daily_requests.is_none() && self.daily_bytes.is_none()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct QuotaRules {
+    #[serde(default)]
+    pub default: Limits,
+    #[serde(default)]
+    pub clients: HashMap<String, Limits>,
+}
+
+impl QuotaRules {
+    fn limits_for(&self, client: &str) -> &Limits {
+        self.clients.get(client).unwrap_or(&self.default)
+    }
+}
+
+/// Which quota a request ran into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exceeded {
+    Requests(u64),
+    Bytes(u64),
+}
+
+impl Exceeded {
+    /// Value of the `x-aether-quota-exceeded` response header.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Requests(_) => "daily-requests",
+            Self::Bytes(_) => "daily-bytes",
+        }
+    }
+}
+
+impl fmt::Display for Exceeded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Requests(limit) => write!(f, "daily request quota of {} exceeded", limit),
+            Self::Bytes(limit) => write!(f, "daily transfer quota of {} bytes exceeded", limit),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Used {
+    requests: u64,
+    bytes: u64,
+}
+
+#[derive(Default)]
+struct Day {
+    index: u64,
+    clients: HashMap<String, Used>,
+}
+
+/// Today's usage of limited clients on one server connection.
+#[derive(Default)]
+pub struct QuotaTracker {
+    today: Mutex<Day>,
+}
+
+impl QuotaTracker {
+    /// Count a new request from `client`, or refuse it if a quota is used
+    /// up.
+    pub fn admit(&self, rules: &QuotaRules, client: &str) -> Result<(), Exceeded> {
+        self.admit_at(rules, client, now_ms())
+    }
+
+    fn admit_at(&self, rules: &QuotaRules, client: &str, now_ms: u64) -> Result<(), Exceeded> {
+        let limits = rules.limits_for(client);
+        if limits.is_unlimited() {
+            return Ok(());
+        }
+        let mut today = self.today.lock().unwrap();
+        let index = now_ms / DAY_MS;
+        if today.index != index {
+            *today = Day {
+                index,
+                clients: HashMap::new(),
+            };
+        }
+        let used = today.clients.entry(client.to_string()).or_default();
+        if let Some(limit) = limits.daily_requests.filter(|&l| used.requests >= l) {
+            return Err(Exceeded::Requests(limit));
+        }
+        if let Some(limit) = limits.daily_bytes.filter(|&l| used.bytes >= l) {
+            return Err(Exceeded::Bytes(limit));
+        }
+        used.requests += 1;
+        Ok(())
+    }
+
+    /// Add a finished stream's bytes to `client`'s usage today.
+    pub fn add_bytes(&self, client: &str, bytes: u64) {
+        if let Some(used) = self.today.lock().unwrap().clients.get_mut(client) {
+            used.bytes += bytes;
+        }
+    }
+}
+
+/// Streams in flight per client on one server connection.
+#[derive(Default)]
+pub struct ClientStreams {
+    open: Mutex<HashMap<String, usize>>,
+}
+
+impl ClientStreams {
+    /// Count a new stream of `client`, or `None` if it already has `limit`
+    /// open.  The stream counts until the slot is dropped.
+    pub fn open(self: &Arc<Self>, client: &str, limit: usize) -> Option<StreamSlot> {
+        let mut open = self.open.lock().unwrap();
+        let count = open.entry(client.to_string()).or_default();
+        if *count >= limit {
+            return None;
+        }
+        *count += 1;
+        Some(StreamSlot {
+            streams: Arc::clone(self),
+            client: client.to_string(),
+        })
+    }
+
+    fn close(&self, client: &str) {
+        let mut open = self.open.lock().unwrap();
+        if let Some(count) = open.get_mut(client) {
+            *count -= 1;
+            if *count == 0 {
+                open.remove(client);
+            }
+        }
+    }
+}
+
+/// One stream counted against its client's limit.
+pub struct StreamSlot {
+    streams: Arc<ClientStreams>,
+    client: String,
+}
+
+impl Drop for StreamSlot {
+    fn drop(&mut self) {
+        self.streams.close(&self.client);
+    }
+}
+
+/// Seconds until the quotas reset (next UTC midnight), for `Retry-After`.
+pub fn secs_until_reset() -> u64 {
+    (DAY_MS - now_ms() % DAY_MS).div_ceil(1000)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quota_enforced_and_reset_daily() {
+        let rules: QuotaRules = serde_json::from_value(serde_json::json!({
+            "default": {"daily_requests": 2},
+            "clients": {"big": {"daily_bytes": 100}},
+        }))
+        .unwrap();
+        let tracker = QuotaTracker::default();
+        let day = 20_000 * DAY_MS;
+
+        assert!(tracker.admit_at(&rules, "a", day).is_ok());
+        assert!(tracker.admit_at(&rules, "a", day + 1).is_ok());
+        assert_eq!(
+            tracker.admit_at(&rules, "a", day + 2),
+            Err(Exceeded::Requests(2))
+        );
+        // Other clients have their own counters
+        assert!(tracker.admit_at(&rules, "b", day + 3).is_ok());
+
+        // A client's own entry replaces the default request limit
+        for i in 0..5 {
+            assert!(tracker.admit_at(&rules, "big", day + i).is_ok());
+        }
+        tracker.add_bytes("big", 150);
+        assert_eq!(
+            tracker.admit_at(&rules, "big", day + 10),
+            Err(Exceeded::Bytes(100))
+        );
+
+        // Next UTC day
+        assert!(tracker.admit_at(&rules, "a", day + DAY_MS).is_ok());
+        assert!(tracker.admit_at(&rules, "big", day + DAY_MS).is_ok());
+    }
+
+    #[test]
+    fn test_unlimited_clients_are_not_tracked() {
+        let tracker = QuotaTracker::default();
+        for _ in 0..10 {
+            assert!(tracker.admit(&QuotaRules::default(), "a").is_ok());
+        }
+        assert!(tracker.today.lock().unwrap().clients.is_empty());
+    }
+
+    #[test]
+    fn test_client_stream_limit() {
+        let streams = Arc::new(ClientStreams::default());
+        let first = streams.open("a", 2).unwrap();
+        let second = streams.open("a", 2).unwrap();
+        assert!(streams.open("a", 2).is_none());
+        assert!(streams.open("b", 2).is_some());
+
+        drop(first);
+        let third = streams.open("a", 2).unwrap();
+        drop((second, third));
+        assert!(streams.open.lock().unwrap().is_empty());
+    }
+}
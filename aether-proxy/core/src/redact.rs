@@ -0,0 +1,124 @@
+//! Credential redaction for diagnostics output.
+//!
+//! Values under keys that look like credentials (token/secret/password/
+//! private key/webhook) are replaced outright; elsewhere only `user:pass@` in URL
+//! authorities is.  Used by the admin config view and the support bundle.
+
+pub const REDACTED: &str = "<redacted>";
+/// Key fragments whose values are always redacted; `header_rule` values
+/// may carry credentials such as `Authorization`, webhook URLs usually
+/// embed one.
+const SECRET_KEYS: &[&str] = &[
+    "token",
+    "secret",
+    "password",
+    "private_key",
+    "header_rule",
+    "webhook",
+];
+
+fn is_secret_key(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    SECRET_KEYS.iter().any(|marker| key.contains(marker))
+}
+
+pub fn redact_value(key: &str, value: &str) -> String {
+    if is_secret_key(key) && !value.is_empty() {
+        REDACTED.to_string()
+    } else {
+        redact_userinfo(value)
+    }
+}
+
+/// Replace `user:pass@` in every `scheme://` authority within `text`.
+pub fn redact_userinfo(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(idx) = rest.find("://") {
+        let (head, tail) = rest.split_at(idx + 3);
+        out.push_str(head);
+        let end = tail
+            .find(|c: char| c == '/' || c == ',' || c == '"' || c.is_whitespace())
+            .unwrap_or(tail.len());
+        match tail[..end].rfind('@') {
+            Some(at) => {
+                out.push_str(REDACTED);
+                out.push_str(&tail[at..end]);
+            }
+            None => out.push_str(&tail[..end]),
+        }
+        rest = &tail[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+pub fn redact_table(table: &mut toml::Table) {
+    for (key, value) in table.iter_mut() {
+        redact_toml(key, value);
+    }
+}
+
+fn redact_toml(key: &str, value: &mut toml::Value) {
+    match value {
+        toml::Value::String(s) => *s = redact_value(key, s),
+        toml::Value::Array(items) => items.iter_mut().for_each(|v| redact_toml(key, v)),
+        toml::Value::Table(table) => redact_table(table),
+        _ => {}
+    }
+}
+
+pub fn redact_json(value: &mut serde_json::Value) {
+    match value {
+        serde_json::Value::String(s) => *s = redact_userinfo(s),
+        serde_json::Value::Array(items) => items.iter_mut().for_each(redact_json),
+        serde_json::Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if is_secret_key(key) {
+                    *value = serde_json::Value::String(REDACTED.to_string());
+                } else {
+                    redact_json(value);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redacts_credentials() {
+        assert_eq!(
+            redact_userinfo("via http://u:p@proxy:3128/x and https://ok.example"),
+            "via http://<redacted>@proxy:3128/x and https://ok.example"
+        );
+        assert_eq!(
+            redact_userinfo("*.onion=socks5h://a:b@127.0.0.1:9050,x=direct"),
+            "*.onion=socks5h://<redacted>@127.0.0.1:9050,x=direct"
+        );
+        assert_eq!(
+            redact_value("AETHER_PROXY_ADMIN_TOKEN", "hunter2"),
+            REDACTED
+        );
+        assert_eq!(redact_value("live_view_public_key", "abc"), "abc");
+
+        let mut table: toml::Table = r#"
+            node_name = "n1"
+            upstream_proxy = "http://user:pw@proxy:8080"
+            [[servers]]
+            aether_url = "https://aether.example"
+            management_token = "ae_secret"
+        "#
+        .parse()
+        .unwrap();
+        redact_table(&mut table);
+        let rendered = toml::to_string(&table).unwrap();
+        assert!(!rendered.contains("ae_secret"));
+        assert!(!rendered.contains("user:pw"));
+        assert!(rendered.contains("n1"));
+        assert!(rendered.contains("https://aether.example"));
+    }
+}
@@ -0,0 +1,613 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use reqwest::{Client, StatusCode};
+use serde::{Deserialize, Serialize};
+use tokio::time::sleep;
+use tracing::{debug, error, info, warn};
+
+use crate::config::Config;
+use crate::hardware::HardwareInfo;
+use crate::net::PublicAddrs;
+use crate::target_filter::{CidrRules, ClientRules, DomainRules};
+use crate::upstream_proxy::UpstreamProxy;
+
+use super::endpoint::{self, Endpoint, EndpointResolver, EndpointSelector};
+
+/// An upload endpoint the Aether server does not have (HTTP 404): the
+/// server predates it, so retrying cannot help.
+#[derive(Debug, thiserror::Error)]
+#[error("this Aether server has no {0} upload endpoint (HTTP 404), upgrade it to use this report")]
+pub struct UploadUnsupported(pub String);
+
+/// Whether `err` means the server will never accept the upload.
+pub fn is_unsupported(err: &anyhow::Error) -> bool {
+    err.downcast_ref::<UploadUnsupported>().is_some()
+}
+
+#[derive(Debug, Serialize)]
+struct RegisterRequest {
+    name: String,
+    ip: String,
+    /// Public IPv6 address of a dual-stack node.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ipv6: Option<String>,
+    port: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    region: Option<String>,
+    heartbeat_interval: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    hardware_info: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    estimated_max_concurrency: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    proxy_metadata: Option<serde_json::Value>,
+    capabilities: Capabilities,
+    tunnel_mode: bool,
+}
+
+/// What the node can relay, so the scheduler only routes to nodes that
+/// support a request.  Listener protocols (CONNECT, SOCKS5) don't apply:
+/// everything arrives through the tunnel.
+#[derive(Debug, Serialize)]
+struct Capabilities {
+    /// Upstream protocols: `http` (HTTP/1.1), `h2` (HTTP/2 over TLS via
+    /// ALPN), `h2c` (prior-knowledge HTTP/2 to `--upstream-h2c-hosts`) and
+    /// `ws` (`Upgrade` requests such as WebSocket).
+    protocols: Vec<&'static str>,
+    /// HTTPS targets are supported.
+    upstream_tls: bool,
+    /// Concurrent streams across all tunnel connections.
+    max_streams: u64,
+    /// Upstream requests can be routed through `--upstream-proxy`.
+    upstream_proxy: bool,
+    version: &'static str,
+}
+
+impl Capabilities {
+    fn from_config(config: &Config) -> Self {
+        let mut protocols = vec!["http", "h2", "ws"];
+        if !config.upstream_h2c_hosts.is_empty() {
+            protocols.push("h2c");
+        }
+        Self {
+            protocols,
+            upstream_tls: true,
+            max_streams: u64::from(config.tunnel_max_streams.unwrap_or(128))
+                * u64::from(config.tunnel_connections.max(1)),
+            upstream_proxy: config.upstream_proxy.is_some()
+                || !config.upstream_proxy_rules.is_empty(),
+            version: env!("CARGO_PKG_VERSION"),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RegisterResponse {
+    pub node_id: String,
+}
+
+/// Remote configuration pushed by the Aether management backend.
+#[derive(Debug, Clone, Deserialize)]
+pub struct RemoteConfig {
+    pub node_name: Option<String>,
+    pub allowed_ports: Option<Vec<u16>>,
+    #[serde(default)]
+    pub allowed_domains: Option<Vec<String>>,
+    #[serde(default)]
+    pub blocked_domains: Option<Vec<String>>,
+    #[serde(default)]
+    pub allowed_cidrs: Option<Vec<String>>,
+    #[serde(default)]
+    pub blocked_cidrs: Option<Vec<String>>,
+    /// Feature flag overrides (`name -> bool | 0-100 | "on"/"off"/"N%"`).
+    #[serde(default)]
+    pub features: Option<std::collections::HashMap<String, serde_json::Value>>,
+    pub log_level: Option<String>,
+    pub heartbeat_interval: Option<u64>,
+    /// Request header rewrites, replacing `--header-rule`.
+    #[serde(default)]
+    pub header_rules: Option<Vec<String>>,
+    /// Targets to keep connections ready to, replacing `--hot-target`.
+    #[serde(default)]
+    pub hot_targets: Option<Vec<String>>,
+    /// Targets to measure connect latency to, replacing `--probe-target`.
+    #[serde(default)]
+    pub probe_targets: Option<Vec<String>>,
+    /// Per-client daily quotas enforced by the node.
+    #[serde(default)]
+    pub client_quotas: Option<crate::quota::QuotaRules>,
+    /// Per-client target restrictions, keyed by client identity.
+    #[serde(default)]
+    pub client_policies: Option<std::collections::HashMap<String, ClientPolicy>>,
+}
+
+/// Target restrictions for one client, on top of the node-wide rules.
+/// Omitted lists impose nothing.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ClientPolicy {
+    #[serde(default)]
+    pub allowed_ports: Option<Vec<u16>>,
+    #[serde(default)]
+    pub allowed_domains: Vec<String>,
+    #[serde(default)]
+    pub blocked_domains: Vec<String>,
+    #[serde(default)]
+    pub allowed_cidrs: Vec<String>,
+    #[serde(default)]
+    pub blocked_cidrs: Vec<String>,
+}
+
+impl ClientPolicy {
+    pub fn to_rules(&self) -> Result<ClientRules, String> {
+        Ok(ClientRules {
+            allowed_ports: self
+                .allowed_ports
+                .as_ref()
+                .map(|ports| ports.iter().copied().collect()),
+            domain_rules: DomainRules::new(&self.allowed_domains, &self.blocked_domains)?,
+            cidr_rules: CidrRules::new(&self.allowed_cidrs, &self.blocked_cidrs)?,
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct UnregisterRequest {
+    node_id: String,
+}
+
+/// Aether API client for proxy node lifecycle management.
+pub struct AetherClient {
+    http: Client,
+    /// URLs of the control plane, in configured order.  Their elected
+    /// addresses are shared with the tunnel.
+    endpoints: Vec<Endpoint>,
+    /// `--aether-proxy-url`, also used by the tunnel.
+    proxy: Option<Arc<UpstreamProxy>>,
+    /// Index of the URL currently in use; requests start there and fail
+    /// over to the next ones.
+    active: AtomicUsize,
+    token: String,
+    retry_max_attempts: u32,
+    retry_base_delay: Duration,
+    retry_max_delay: Duration,
+}
+
+impl AetherClient {
+    pub fn new(
+        config: &Config,
+        tls: &rustls::ClientConfig,
+        aether_url: &str,
+        management_token: &str,
+    ) -> Self {
+        let connect_timeout = Duration::from_secs(config.aether_connect_timeout_secs);
+        let mut urls = endpoint::split_urls(aether_url);
+        if urls.is_empty() {
+            urls.push(aether_url);
+        }
+        let endpoints: Vec<Endpoint> = urls
+            .into_iter()
+            .map(|url| Endpoint {
+                base_url: url.trim_end_matches('/').to_string(),
+                selector: Arc::new(EndpointSelector::new(
+                    url,
+                    Duration::from_secs(config.aether_endpoint_reelect_secs),
+                    connect_timeout,
+                )),
+            })
+            .collect();
+        let selectors = endpoints.iter().map(|e| Arc::clone(&e.selector)).collect();
+        // reqwest leaves ALPN to a preconfigured TLS config.
+        let mut tls = tls.clone();
+        tls.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        let mut builder = Client::builder()
+            .use_preconfigured_tls(tls)
+            .dns_resolver(Arc::new(EndpointResolver(selectors)))
+            .timeout(Duration::from_secs(config.aether_request_timeout_secs))
+            .connect_timeout(connect_timeout)
+            .pool_max_idle_per_host(config.aether_pool_max_idle_per_host)
+            .pool_idle_timeout(Duration::from_secs(config.aether_pool_idle_timeout_secs))
+            .tcp_nodelay(config.aether_tcp_nodelay);
+
+        if config.aether_tcp_keepalive_secs > 0 {
+            builder =
+                builder.tcp_keepalive(Some(Duration::from_secs(config.aether_tcp_keepalive_secs)));
+        } else {
+            builder = builder.tcp_keepalive(None);
+        }
+
+        if config.aether_http2 {
+            builder = builder.http2_adaptive_window(true);
+        }
+
+        let proxy = config
+            .aether_proxy()
+            .expect("aether proxy is checked by Config::validate")
+            .map(Arc::new);
+        if let Some(raw) = proxy.as_ref().and(config.aether_proxy_url.as_deref()) {
+            builder = builder.proxy(
+                reqwest::Proxy::all(raw.trim())
+                    .expect("aether proxy is checked by Config::validate"),
+            );
+        }
+
+        let http = builder.build().expect("failed to create HTTP client");
+
+        let retry_base_delay = Duration::from_millis(config.aether_retry_base_delay_ms);
+        let retry_max_delay =
+            Duration::from_millis(config.aether_retry_max_delay_ms).max(retry_base_delay);
+
+        Self {
+            http,
+            endpoints,
+            proxy,
+            active: AtomicUsize::new(0),
+            token: management_token.to_string(),
+            retry_max_attempts: config.aether_retry_max_attempts.max(1),
+            retry_base_delay,
+            retry_max_delay,
+        }
+    }
+
+    /// Proxy the tunnel must be opened through, if any.
+    pub fn proxy(&self) -> Option<&UpstreamProxy> {
+        self.proxy.as_deref()
+    }
+
+    /// The control-plane URL in use and its index, for tunnel connects.
+    pub fn endpoint(&self) -> (usize, &Endpoint) {
+        let index = self.active.load(Ordering::Relaxed);
+        (index, &self.endpoints[index])
+    }
+
+    /// Move on from the URL at `index` after it failed.  A no-op if another
+    /// caller already failed over, or if there is only one URL.
+    pub fn fail_over(&self, index: usize) {
+        let next = (index + 1) % self.endpoints.len();
+        if next != index
+            && self
+                .active
+                .compare_exchange(index, next, Ordering::Relaxed, Ordering::Relaxed)
+                .is_ok()
+        {
+            warn!(
+                from = %self.endpoints[index].base_url,
+                to = %self.endpoints[next].base_url,
+                "control-plane endpoint failing over"
+            );
+        }
+    }
+
+    /// Stick to the URL at `index` after it answered.
+    fn settle_on(&self, index: usize) {
+        let previous = self.active.swap(index, Ordering::Relaxed);
+        if previous != index {
+            info!(url = %self.endpoints[index].base_url, "using control-plane endpoint");
+        }
+    }
+
+    /// Register this node with Aether (idempotent upsert by ip:port).
+    ///
+    /// Returns the stable node_id assigned by Aether.
+    pub async fn register(
+        &self,
+        config: &Config,
+        node_name: &str,
+        public_addrs: &PublicAddrs,
+        hw: Option<&HardwareInfo>,
+    ) -> anyhow::Result<String> {
+        let body = RegisterRequest {
+            name: node_name.to_string(),
+            ip: public_addrs.primary.clone(),
+            ipv6: public_addrs.ipv6.map(|ip| ip.to_string()),
+            port: 0,
+            region: config.node_region.clone(),
+            heartbeat_interval: config.heartbeat_interval,
+            hardware_info: hw.and_then(|h| serde_json::to_value(h).ok()),
+            estimated_max_concurrency: hw.map(|h| h.estimated_max_concurrency),
+            proxy_metadata: Some(serde_json::json!({
+                "version": env!("CARGO_PKG_VERSION"),
+            })),
+            capabilities: Capabilities::from_config(config),
+            tunnel_mode: true,
+        };
+
+        info!(
+            name = %body.name,
+            ip = %body.ip,
+            ipv6 = ?body.ipv6,
+            "registering with Aether"
+        );
+
+        let resp = self
+            .send_with_retry(
+                |base_url| {
+                    self.http
+                        .post(format!("{base_url}/api/admin/proxy-nodes/register"))
+                        .header("Authorization", format!("Bearer {}", self.token))
+                        .json(&body)
+                },
+                "register",
+            )
+            .await?;
+
+        let status = resp.status();
+        if !status.is_success() {
+            let text = resp.text().await.unwrap_or_default();
+            anyhow::bail!("register failed (HTTP {}): {}", status, text);
+        }
+
+        let data: RegisterResponse = resp.json().await?;
+        info!(node_id = %data.node_id, "registered successfully");
+        Ok(data.node_id)
+    }
+
+    /// Unregister this node from Aether (graceful shutdown).
+    pub async fn unregister(&self, node_id: &str) -> anyhow::Result<()> {
+        let body = UnregisterRequest {
+            node_id: node_id.to_string(),
+        };
+
+        info!(node_id = %node_id, "unregistering from Aether");
+
+        let resp = self
+            .send_with_retry(
+                |base_url| {
+                    self.http
+                        .post(format!("{base_url}/api/admin/proxy-nodes/unregister"))
+                        .header("Authorization", format!("Bearer {}", self.token))
+                        .json(&body)
+                },
+                "unregister",
+            )
+            .await;
+
+        match resp {
+            Ok(r) if r.status().is_success() => {
+                info!(node_id = %node_id, "unregistered successfully");
+                Ok(())
+            }
+            Ok(r) => {
+                let text = r.text().await.unwrap_or_default();
+                error!(body = %text, "unregister failed");
+                anyhow::bail!("unregister failed: {}", text);
+            }
+            Err(e) => {
+                // Best-effort during shutdown
+                error!(error = %e, "unregister request failed");
+                anyhow::bail!("unregister request failed: {}", e);
+            }
+        }
+    }
+
+    /// Check every control-plane URL once: reachable, and the management
+    /// token accepted (a read-only node listing).  No retries, no failover.
+    pub async fn verify_access(&self) -> Vec<(String, anyhow::Result<()>)> {
+        let mut results = Vec::with_capacity(self.endpoints.len());
+        for endpoint in &self.endpoints {
+            let url = format!("{}/api/admin/proxy-nodes?limit=1", endpoint.base_url);
+            let result = async {
+                let resp = self
+                    .http
+                    .get(&url)
+                    .header("Authorization", format!("Bearer {}", self.token))
+                    .send()
+                    .await?;
+                observe_date(&resp);
+                match resp.status() {
+                    status if status.is_success() => Ok(()),
+                    StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => {
+                        anyhow::bail!("management token rejected (HTTP {})", resp.status())
+                    }
+                    status => anyhow::bail!("unexpected response (HTTP {})", status),
+                }
+            }
+            .await;
+            results.push((endpoint.base_url.clone(), result));
+        }
+        results
+    }
+
+    /// Upload a batch of access-log records.  A single attempt: the caller
+    /// keeps the batch and retries on its next upload cycle.
+    pub async fn upload_access_log<T: Serialize>(
+        &self,
+        node_id: &str,
+        records: &[T],
+    ) -> anyhow::Result<()> {
+        self.upload(
+            "access-log",
+            &serde_json::json!({ "node_id": node_id, "records": records }),
+        )
+        .await?;
+        debug!(node_id = %node_id, count = records.len(), "access log uploaded");
+        Ok(())
+    }
+
+    /// Upload per-client usage rollups; a single attempt, like
+    /// [`Self::upload_access_log`].
+    pub async fn upload_usage<T: Serialize>(
+        &self,
+        node_id: &str,
+        rollups: &[T],
+    ) -> anyhow::Result<()> {
+        self.upload(
+            "usage",
+            &serde_json::json!({ "node_id": node_id, "rollups": rollups }),
+        )
+        .await?;
+        debug!(node_id = %node_id, count = rollups.len(), "usage uploaded");
+        Ok(())
+    }
+
+    /// Upload a top destinations report; a single attempt, like
+    /// [`Self::upload_access_log`].
+    pub async fn upload_destinations<T: Serialize>(
+        &self,
+        node_id: &str,
+        report: &T,
+    ) -> anyhow::Result<()> {
+        self.upload(
+            "destinations",
+            &serde_json::json!({ "node_id": node_id, "report": report }),
+        )
+        .await?;
+        debug!(node_id = %node_id, "destination report uploaded");
+        Ok(())
+    }
+
+    /// POST `body` to `/api/admin/proxy-nodes/<endpoint>` once, on the
+    /// active URL; a failure moves later uploads to the next one.  A 404
+    /// is reported as [`UploadUnsupported`].
+    async fn upload(&self, endpoint: &str, body: &serde_json::Value) -> anyhow::Result<()> {
+        let (index, active) = self.endpoint();
+        let url = format!("{}/api/admin/proxy-nodes/{}", active.base_url, endpoint);
+        let resp = self
+            .http
+            .post(&url)
+            .header("Authorization", format!("Bearer {}", self.token))
+            .json(body)
+            .send()
+            .await;
+        let resp = match resp {
+            Ok(resp) if !resp.status().is_server_error() => resp,
+            Ok(resp) => {
+                self.fail_over(index);
+                resp
+            }
+            Err(e) => {
+                self.fail_over(index);
+                return Err(e.into());
+            }
+        };
+        observe_date(&resp);
+        let status = resp.status();
+        if status == StatusCode::NOT_FOUND {
+            return Err(UploadUnsupported(endpoint.to_string()).into());
+        }
+        if !status.is_success() {
+            let text = resp.text().await.unwrap_or_default();
+            anyhow::bail!("{} upload failed (HTTP {}): {}", endpoint, status, text);
+        }
+        Ok(())
+    }
+
+    /// Send with retries, starting at the active URL and failing over to
+    /// the others in order when one is unreachable or keeps answering with
+    /// server errors.  `make_req` gets the base URL to build against.
+    async fn send_with_retry<F>(
+        &self,
+        mut make_req: F,
+        label: &str,
+    ) -> Result<reqwest::Response, reqwest::Error>
+    where
+        F: FnMut(&str) -> reqwest::RequestBuilder,
+    {
+        let first = self.active.load(Ordering::Relaxed);
+        let count = self.endpoints.len();
+        let mut last = None;
+        for offset in 0..count {
+            let index = (first + offset) % count;
+            let base_url = &self.endpoints[index].base_url;
+            let result = self.send_to(|| make_req(base_url), label).await;
+            match &result {
+                Ok(resp) if !resp.status().is_server_error() => {
+                    self.settle_on(index);
+                    return result;
+                }
+                Ok(resp) if offset + 1 < count => {
+                    warn!(url = %base_url, status = %resp.status(), label, "Aether endpoint failing, trying next");
+                }
+                Err(e) if offset + 1 < count => {
+                    warn!(url = %base_url, error = %e, label, "Aether endpoint unreachable, trying next");
+                }
+                _ => {}
+            }
+            last = Some(result);
+        }
+        last.expect("AetherClient has at least one endpoint")
+    }
+
+    /// Send to one URL, retrying transient failures with backoff.
+    async fn send_to<F>(
+        &self,
+        mut make_req: F,
+        label: &str,
+    ) -> Result<reqwest::Response, reqwest::Error>
+    where
+        F: FnMut() -> reqwest::RequestBuilder,
+    {
+        let mut attempt: u32 = 0;
+        let mut delay = self.retry_base_delay;
+
+        loop {
+            attempt = attempt.saturating_add(1);
+            let resp = make_req().send().await;
+            match resp {
+                Ok(resp) => {
+                    observe_date(&resp);
+                    if should_retry_status(resp.status()) && attempt < self.retry_max_attempts {
+                        let sleep_for = jitter_delay(delay);
+                        debug!(
+                            attempt,
+                            status = %resp.status(),
+                            sleep_ms = sleep_for.as_millis(),
+                            label,
+                            "Aether request retrying"
+                        );
+                        sleep(sleep_for).await;
+                        let next_delay = delay.checked_mul(2).unwrap_or(self.retry_max_delay);
+                        delay = std::cmp::min(next_delay, self.retry_max_delay);
+                        continue;
+                    }
+                    return Ok(resp);
+                }
+                Err(e) => {
+                    if attempt < self.retry_max_attempts {
+                        let sleep_for = jitter_delay(delay);
+                        debug!(
+                            attempt,
+                            error = %e,
+                            sleep_ms = sleep_for.as_millis(),
+                            label,
+                            "Aether request retrying"
+                        );
+                        sleep(sleep_for).await;
+                        let next_delay = delay.checked_mul(2).unwrap_or(self.retry_max_delay);
+                        delay = std::cmp::min(next_delay, self.retry_max_delay);
+                        continue;
+                    }
+                    return Err(e);
+                }
+            }
+        }
+    }
+}
+
+/// Feed the response's `Date` header to the clock skew estimate.
+fn observe_date(resp: &reqwest::Response) {
+    crate::clock::observe_date(
+        resp.headers()
+            .get(reqwest::header::DATE)
+            .and_then(|v| v.to_str().ok()),
+    );
+}
+
+fn should_retry_status(status: StatusCode) -> bool {
+    status.is_server_error()
+        || status == StatusCode::TOO_MANY_REQUESTS
+        || status == StatusCode::REQUEST_TIMEOUT
+}
+
+fn jitter_delay(base: Duration) -> Duration {
+    if base.is_zero() {
+        return base;
+    }
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos() as u64)
+        .unwrap_or(0);
+    let jitter_ms = nanos % 100;
+    base + Duration::from_millis(jitter_ms)
+}
@@ -0,0 +1,289 @@
+//! Control-plane endpoint election.
+//!
+//! An Aether host behind anycast or a CDN often resolves to several
+//! addresses with very different latency from a given region.  When that
+//! happens, TCP connections to all of them are raced and the first to
+//! complete wins.  The winner is cached for `--aether-endpoint-reelect-secs`
+//! and preferred by both the registration client and tunnel connects; once
+//! it expires (or stops accepting connections) the race is run again.
+//!
+//! A control plane may also be reachable under several URLs (regions or
+//! fallback domains), given as a comma-separated `--aether-url`.  Each URL
+//! gets its own selector; the registration client decides which URL is
+//! active and fails over between them.
+
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use tokio::net::TcpStream;
+use tokio::task::JoinSet;
+use tracing::{debug, info};
+
+/// Split a comma-separated `--aether-url` into its URLs.
+pub fn split_urls(raw: &str) -> Vec<&str> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|url| !url.is_empty())
+        .collect()
+}
+
+/// One URL of the control plane and its address election.
+pub struct Endpoint {
+    /// Base URL without a trailing slash.
+    pub base_url: String,
+    pub selector: Arc<EndpointSelector>,
+}
+
+struct Winner {
+    addr: SocketAddr,
+    elected_at: Instant,
+}
+
+/// Elects the fastest address of one Aether server.
+pub struct EndpointSelector {
+    host: String,
+    port: u16,
+    /// `None` disables racing.
+    reelect_after: Option<Duration>,
+    connect_timeout: Duration,
+    winner: Mutex<Option<Winner>>,
+}
+
+impl EndpointSelector {
+    pub fn new(base_url: &str, reelect_after: Duration, connect_timeout: Duration) -> Self {
+        // A bare host is treated as https, as the tunnel URL builder does.
+        let (host, port) = url::Url::parse(base_url)
+            .ok()
+            .filter(|u| u.has_host())
+            .or_else(|| url::Url::parse(&format!("https://{base_url}")).ok())
+            .and_then(|u| Some((u.host_str()?.to_string(), u.port_or_known_default()?)))
+            .unwrap_or_default();
+        Self {
+            host: host.trim_matches(['[', ']']).to_string(),
+            port,
+            reelect_after: (!reelect_after.is_zero()).then_some(reelect_after),
+            connect_timeout,
+            winner: Mutex::new(None),
+        }
+    }
+
+    /// Host and port of the server, for tunneling through a proxy.
+    pub fn authority(&self) -> (&str, u16) {
+        (&self.host, self.port)
+    }
+
+    fn fresh_winner(&self) -> Option<SocketAddr> {
+        let ttl = self.reelect_after?;
+        let winner = self.winner.lock().unwrap();
+        winner
+            .as_ref()
+            .filter(|w| w.elected_at.elapsed() < ttl)
+            .map(|w| w.addr)
+    }
+
+    fn set_winner(&self, addr: SocketAddr) {
+        *self.winner.lock().unwrap() = Some(Winner {
+            addr,
+            elected_at: Instant::now(),
+        });
+    }
+
+    fn forget_winner(&self) {
+        *self.winner.lock().unwrap() = None;
+    }
+
+    async fn resolve(&self) -> io::Result<Vec<SocketAddr>> {
+        let addrs: Vec<SocketAddr> = tokio::net::lookup_host((self.host.as_str(), self.port))
+            .await?
+            .collect();
+        if addrs.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} resolved to no addresses", self.host),
+            ));
+        }
+        Ok(addrs)
+    }
+
+    /// Open a TCP connection to the server, preferring the elected address.
+    pub async fn connect(&self) -> io::Result<TcpStream> {
+        if self.reelect_after.is_none() {
+            return TcpStream::connect((self.host.as_str(), self.port)).await;
+        }
+        if let Some(addr) = self.fresh_winner() {
+            match TcpStream::connect(addr).await {
+                Ok(stream) => return Ok(stream),
+                Err(e) => {
+                    debug!(%addr, error = %e, "elected endpoint failed, re-electing");
+                    self.forget_winner();
+                }
+            }
+        }
+        let addrs = self.resolve().await?;
+        let (addr, stream) = race(&addrs, self.connect_timeout).await?;
+        if addrs.len() > 1 {
+            self.set_winner(addr);
+        }
+        Ok(stream)
+    }
+
+    /// All addresses of the server, elected winner first.
+    async fn ordered_addrs(&self) -> io::Result<Vec<SocketAddr>> {
+        let addrs = self.resolve().await?;
+        if self.reelect_after.is_none() || addrs.len() < 2 {
+            return Ok(addrs);
+        }
+        let winner = match self.fresh_winner() {
+            Some(addr) => Some(addr),
+            None => match race(&addrs, self.connect_timeout).await {
+                Ok((addr, _probe)) => {
+                    self.set_winner(addr);
+                    Some(addr)
+                }
+                Err(_) => None,
+            },
+        };
+        Ok(prefer(addrs, winner))
+    }
+}
+
+/// Move `winner` to the front, keeping the rest in resolver order.
+fn prefer(mut addrs: Vec<SocketAddr>, winner: Option<SocketAddr>) -> Vec<SocketAddr> {
+    if let Some(pos) = winner.and_then(|w| addrs.iter().position(|a| *a == w)) {
+        let addr = addrs.remove(pos);
+        addrs.insert(0, addr);
+    }
+    addrs
+}
+
+/// Connect to every address at once; the first successful connection wins
+/// and the rest are dropped.
+async fn race(addrs: &[SocketAddr], timeout: Duration) -> io::Result<(SocketAddr, TcpStream)> {
+    if let [addr] = addrs {
+        let stream = tokio::time::timeout(timeout, TcpStream::connect(addr))
+            .await
+            .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+        return Ok((*addr, stream));
+    }
+
+    let started = Instant::now();
+    let mut attempts = JoinSet::new();
+    for &addr in addrs {
+        attempts.spawn(async move {
+            let result = tokio::time::timeout(timeout, TcpStream::connect(addr))
+                .await
+                .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))
+                .and_then(|r| r);
+            (addr, result)
+        });
+    }
+
+    let mut last_err = io::Error::from(io::ErrorKind::TimedOut);
+    while let Some(joined) = attempts.join_next().await {
+        let Ok((addr, result)) = joined else {
+            continue;
+        };
+        match result {
+            Ok(stream) => {
+                info!(
+                    %addr,
+                    candidates = addrs.len(),
+                    elapsed_ms = started.elapsed().as_millis() as u64,
+                    "elected control-plane endpoint"
+                );
+                return Ok((addr, stream));
+            }
+            Err(e) => {
+                debug!(%addr, error = %e, "control-plane endpoint candidate failed");
+                last_err = e;
+            }
+        }
+    }
+    Err(last_err)
+}
+
+/// reqwest resolver that orders the Aether hosts' addresses by election
+/// result; other hosts resolve normally.
+pub struct EndpointResolver(pub Vec<Arc<EndpointSelector>>);
+
+impl Resolve for EndpointResolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let selector = self
+            .0
+            .iter()
+            .find(|s| name.as_str().eq_ignore_ascii_case(&s.host))
+            .cloned();
+        Box::pin(async move {
+            let addrs = match selector {
+                Some(selector) => selector.ordered_addrs().await?,
+                None => tokio::net::lookup_host((name.as_str(), 0)).await?.collect(),
+            };
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prefer_moves_winner_first() {
+        let a: SocketAddr = "192.0.2.1:443".parse().unwrap();
+        let b: SocketAddr = "192.0.2.2:443".parse().unwrap();
+        let c: SocketAddr = "[2001:db8::1]:443".parse().unwrap();
+        assert_eq!(prefer(vec![a, b, c], Some(c)), vec![c, a, b]);
+        assert_eq!(prefer(vec![a, b], None), vec![a, b]);
+        // A winner that no longer resolves is ignored
+        assert_eq!(prefer(vec![a, b], Some(c)), vec![a, b]);
+    }
+
+    #[tokio::test]
+    async fn test_race_skips_unreachable_candidates() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let live = listener.local_addr().unwrap();
+        // Bind then drop to get a port that refuses connections
+        let dead = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+
+        let (addr, _stream) = race(&[dead, live], Duration::from_secs(2)).await.unwrap();
+        assert_eq!(addr, live);
+        assert!(race(&[dead], Duration::from_secs(2)).await.is_err());
+    }
+
+    #[test]
+    fn test_split_urls() {
+        assert_eq!(
+            split_urls(" https://a.example.com/, ,https://b.example.com:8443 "),
+            vec!["https://a.example.com/", "https://b.example.com:8443"]
+        );
+        assert_eq!(split_urls("aether.example.com"), vec!["aether.example.com"]);
+        assert!(split_urls(" , ").is_empty());
+    }
+
+    #[test]
+    fn test_selector_parses_url() {
+        let sel = EndpointSelector::new(
+            "https://[2001:db8::1]/",
+            Duration::from_secs(300),
+            Duration::from_secs(5),
+        );
+        assert_eq!((sel.host.as_str(), sel.port), ("2001:db8::1", 443));
+        let sel = EndpointSelector::new(
+            "http://aether.example.com:8084",
+            Duration::ZERO,
+            Duration::from_secs(5),
+        );
+        assert_eq!((sel.host.as_str(), sel.port), ("aether.example.com", 8084));
+        assert!(sel.reelect_after.is_none());
+        let sel =
+            EndpointSelector::new("aether.example.com", Duration::ZERO, Duration::from_secs(5));
+        assert_eq!((sel.host.as_str(), sel.port), ("aether.example.com", 443));
+    }
+}
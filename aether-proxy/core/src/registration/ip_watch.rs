@@ -0,0 +1,83 @@
+//! Public address change detection.
+//!
+//! VPS providers re-IP instances and dynamic setups change address after a
+//! reboot.  Every `--public-ip-check-interval` seconds the public addresses
+//! are detected again (same sources as at startup); when they change,
+//! the new ones are blocked as self-targets and every server registration
+//! is updated.
+
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::{watch, Mutex};
+use tracing::warn;
+
+use crate::net::{self, IpSources, PublicAddrs};
+use crate::state::{AppState, ServerContext};
+
+/// Re-check the public addresses every `interval` until shutdown.
+/// `addrs` feeds [`AppState::public_addrs`], which every registration
+/// reads.
+pub fn spawn(
+    state: Arc<AppState>,
+    servers: Arc<Mutex<Vec<Arc<ServerContext>>>>,
+    addrs: watch::Sender<PublicAddrs>,
+    sources: IpSources,
+    interval: Duration,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    tokio::spawn(async move {
+        // Servers whose registration still carries an old address.
+        let mut stale: HashSet<String> = HashSet::new();
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        ticker.tick().await;
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                _ = shutdown.changed() => return,
+            }
+
+            let current = addrs.borrow().clone();
+            let (v4, v6) = net::detect_families(state.config.public_ipv6, &sources).await;
+            let detected = current.with_detected(v4, v6);
+            if detected != current {
+                warn!(
+                    old = %current.primary,
+                    old_ipv6 = ?current.ipv6,
+                    new = %detected.primary,
+                    new_ipv6 = ?detected.ipv6,
+                    "public address changed, updating registration"
+                );
+                for ip in detected.ips() {
+                    state.address_policy.block_ip(ip);
+                }
+                addrs.send_replace(detected.clone());
+                for server in servers.lock().await.iter() {
+                    stale.insert(server.server_label.clone());
+                }
+            }
+            if stale.is_empty() {
+                continue;
+            }
+
+            let servers = servers.lock().await.clone();
+            for server in &servers {
+                if !stale.contains(&server.server_label) {
+                    continue;
+                }
+                match super::reregister(&state, server).await {
+                    Ok(()) => {
+                        stale.remove(&server.server_label);
+                    }
+                    Err(e) => warn!(
+                        server = %server.server_label,
+                        error = %e,
+                        "re-registration failed, will retry"
+                    ),
+                }
+            }
+        }
+    });
+}
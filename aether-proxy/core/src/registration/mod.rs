@@ -0,0 +1,35 @@
+pub mod client;
+pub mod endpoint;
+pub mod ip_watch;
+pub mod tls;
+
+use tracing::{info, warn};
+
+use crate::state::{AppState, ServerContext};
+
+/// Register `server` again with the node's current addresses, e.g. after
+/// an address change or a control-plane outage.  Should Aether hand out a
+/// different node ID, the old node is unregistered so it doesn't linger as
+/// offline.
+pub async fn reregister(state: &AppState, server: &ServerContext) -> anyhow::Result<()> {
+    let node_name = server.dynamic.load().node_name.clone();
+    let addrs = state.public_addrs.borrow().clone();
+    let node_id = server
+        .aether_client
+        .register(&state.config, &node_name, &addrs, Some(&state.hw_info))
+        .await?;
+    server.control_plane.on_contact();
+    let previous = std::mem::replace(&mut *server.node_id.write().unwrap(), node_id.clone());
+    if previous != node_id {
+        info!(
+            server = %server.server_label,
+            old_node_id = %previous,
+            node_id = %node_id,
+            "node ID changed on re-registration"
+        );
+        if let Err(e) = server.aether_client.unregister(&previous).await {
+            warn!(server = %server.server_label, error = %e, "failed to unregister old node");
+        }
+    }
+    Ok(())
+}
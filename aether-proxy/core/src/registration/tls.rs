@@ -0,0 +1,230 @@
+//! TLS towards the Aether control plane.
+//!
+//! One client config is shared by the API client and the tunnel.  By default
+//! the server is verified against the bundled web PKI roots; `--aether-ca-cert`
+//! replaces those with a private CA bundle, and `--aether-client-cert` /
+//! `--aether-client-key` present a client certificate so that the control
+//! plane can require mTLS from its nodes.
+//!
+//! `--aether-cert-pin` additionally requires one of the presented
+//! certificates (leaf or intermediate) to carry a pinned public key, given as
+//! the base64 SHA-256 of its SubjectPublicKeyInfo (`openssl x509 -pubkey
+//! -noout | openssl pkey -pubin -outform der | openssl dgst -sha256 -binary
+//! | base64`).  Pins are checked after normal chain validation, never
+//! instead of it.
+
+use std::path::Path;
+use std::sync::Arc;
+
+use base64::Engine;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::WebPkiServerVerifier;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme};
+use sha2::{Digest, Sha256};
+
+/// Control-plane TLS options; all optional.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TlsOptions<'a> {
+    /// PEM CA bundle replacing the web PKI roots.
+    pub ca_cert: Option<&'a str>,
+    pub client_cert: Option<&'a str>,
+    pub client_key: Option<&'a str>,
+    /// SPKI SHA-256 pins, base64 with an optional `sha256/` prefix.
+    pub pins: &'a [String],
+}
+
+/// Build the client config for `options`.  No ALPN is offered; the API
+/// client adds its own.
+pub fn client_config(options: TlsOptions<'_>) -> Result<ClientConfig, String> {
+    let roots = match options.ca_cert {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(path)? {
+                roots
+                    .add(cert)
+                    .map_err(|e| format!("{}: invalid CA certificate: {}", path, e))?;
+            }
+            roots
+        }
+        None => RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+    };
+    let builder = if options.pins.is_empty() {
+        ClientConfig::builder().with_root_certificates(roots)
+    } else {
+        let pins = options
+            .pins
+            .iter()
+            .map(|pin| parse_pin(pin))
+            .collect::<Result<_, _>>()?;
+        let inner = WebPkiServerVerifier::builder(Arc::new(roots))
+            .build()
+            .map_err(|e| format!("certificate verifier: {}", e))?;
+        ClientConfig::builder()
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(PinnedVerifier { inner, pins }))
+    };
+
+    match (options.client_cert, options.client_key) {
+        (None, None) => Ok(builder.with_no_client_auth()),
+        (Some(cert_path), Some(key_path)) => {
+            let chain = read_certs(cert_path)?;
+            let key = PrivateKeyDer::from_pem_file(Path::new(key_path))
+                .map_err(|e| format!("{}: no usable private key: {}", key_path, e))?;
+            builder
+                .with_client_auth_cert(chain, key)
+                .map_err(|e| format!("client certificate: {}", e))
+        }
+        _ => Err("client certificate and key must be set together".to_string()),
+    }
+}
+
+/// Decode a `sha256/<base64>` or bare base64 SPKI pin.
+pub(crate) fn parse_pin(raw: &str) -> Result<[u8; 32], String> {
+    let raw = raw.trim();
+    let encoded = raw.strip_prefix("sha256/").unwrap_or(raw);
+    base64::engine::general_purpose::STANDARD
+        .decode(encoded)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| format!("invalid certificate pin {:?}: expected base64 SHA-256", raw))
+}
+
+pub(crate) fn spki_sha256(cert: &CertificateDer<'_>) -> Option<[u8; 32]> {
+    let cert = webpki::EndEntityCert::try_from(cert).ok()?;
+    Some(Sha256::digest(cert.subject_public_key_info().as_ref()).into())
+}
+
+/// Normal web PKI verification, plus a public key pin somewhere in the
+/// presented chain.
+#[derive(Debug)]
+struct PinnedVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    pins: Vec<[u8; 32]>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.inner.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+        let pinned = std::iter::once(end_entity)
+            .chain(intermediates)
+            .filter_map(spki_sha256)
+            .any(|hash| self.pins.contains(&hash));
+        if !pinned {
+            return Err(rustls::Error::General(
+                "server certificate matches no --aether-cert-pin".to_string(),
+            ));
+        }
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+pub(crate) fn read_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, String> {
+    let certs = CertificateDer::pem_file_iter(Path::new(path))
+        .and_then(|iter| iter.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("{}: {}", path, e))?;
+    if certs.is_empty() {
+        return Err(format!("{}: no certificates found", path));
+    }
+    Ok(certs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_config_options() {
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        assert!(client_config(TlsOptions::default()).is_ok());
+
+        let half = TlsOptions {
+            client_cert: Some("/nonexistent/cert.pem"),
+            ..Default::default()
+        };
+        assert!(client_config(half).unwrap_err().contains("together"));
+
+        let missing = TlsOptions {
+            ca_cert: Some("/nonexistent/ca.pem"),
+            ..Default::default()
+        };
+        assert!(client_config(missing).is_err());
+
+        let dir = std::env::temp_dir().join(format!("aether-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let empty = dir.join("empty.pem");
+        std::fs::write(&empty, "not a certificate\n").unwrap();
+        let empty = TlsOptions {
+            ca_cert: empty.to_str(),
+            ..Default::default()
+        };
+        assert!(client_config(empty)
+            .unwrap_err()
+            .contains("no certificates found"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let pins = ["sha256/47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=".to_string()];
+        let pinned = TlsOptions {
+            pins: &pins,
+            ..Default::default()
+        };
+        assert!(client_config(pinned).is_ok());
+        let pins = ["not-a-pin".to_string()];
+        let bad = TlsOptions {
+            pins: &pins,
+            ..Default::default()
+        };
+        assert!(client_config(bad).unwrap_err().contains("certificate pin"));
+    }
+
+    #[test]
+    fn test_parse_pin() {
+        let hash = Sha256::digest(b"");
+        assert_eq!(
+            parse_pin("47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=").unwrap(),
+            hash.as_slice()
+        );
+        assert_eq!(
+            parse_pin(" sha256/47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU= ").unwrap(),
+            hash.as_slice()
+        );
+        // Wrong length (SHA-1)
+        assert!(parse_pin("2jmj7l5rSw0yVb/vlWAYkK/YBwk=").is_err());
+    }
+}
@@ -0,0 +1,29 @@
+//! Upgrade instructions pushed by Aether in heartbeat ACKs.
+//!
+//! Replacing the binary and restarting the service is up to the process
+//! embedding the relay, so the library only hands the target version to
+//! the handler registered with [`set_handler`].  Without a handler the
+//! instruction is logged once and otherwise ignored.
+
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::OnceLock;
+
+/// Upgrade in progress, resolving once the new version is in place.
+pub type UpgradeFuture = Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send>>;
+
+type Handler = Box<dyn Fn(String) -> UpgradeFuture + Send + Sync>;
+
+static HANDLER: OnceLock<Handler> = OnceLock::new();
+
+/// Apply remote upgrade instructions with `handler`, called with the target
+/// version (without the `proxy-v` tag prefix).  Only the first handler
+/// registered in the process is kept.
+pub fn set_handler(handler: impl Fn(String) -> UpgradeFuture + Send + Sync + 'static) {
+    let _ = HANDLER.set(Box::new(handler));
+}
+
+/// Start upgrading to `version`, if a handler is registered.
+pub(crate) fn start(version: String) -> Option<UpgradeFuture> {
+    HANDLER.get().map(|handler| handler(version))
+}
@@ -0,0 +1,83 @@
+//! Per-stream request IDs.
+//!
+//! Every tunnel stream gets an ID that is attached to its tracing span and
+//! access-log record and returned in the `x-aether-request-id` response
+//! header, so a request seen in Aether can be found in the node's logs.
+//! With `--trust-request-id` the ID Aether sends in the same request header
+//! is kept instead of generating one.  The header never goes upstream.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use ring::rand::{SecureRandom, SystemRandom};
+
+/// Request and response header carrying the ID.
+pub const HEADER: &str = "x-aether-request-id";
+/// Longest incoming ID accepted.
+const MAX_LEN: usize = 128;
+
+/// ID of a stream: the incoming [`HEADER`] when `trust_incoming` and it is
+/// well-formed, otherwise a fresh one.
+pub fn of(headers: &HashMap<String, String>, trust_incoming: bool) -> String {
+    trust_incoming
+        .then(|| {
+            headers
+                .iter()
+                .find(|(k, _)| k.eq_ignore_ascii_case(HEADER))
+                .map(|(_, v)| v.trim())
+                .filter(|v| is_valid(v))
+        })
+        .flatten()
+        .map(str::to_string)
+        .unwrap_or_else(generate)
+}
+
+/// A new random ID: 32 hex digits.
+pub fn generate() -> String {
+    let mut bytes = [0u8; 16];
+    if SystemRandom::new().fill(&mut bytes).is_err() {
+        // Still unique within this process
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        bytes[..8].copy_from_slice(&crate::clock::now_ms().to_be_bytes());
+        bytes[8..].copy_from_slice(&COUNTER.fetch_add(1, Ordering::Relaxed).to_be_bytes());
+    }
+    hex::encode(bytes)
+}
+
+/// IDs end up in log lines and headers: keep them short and plain.
+fn is_valid(id: &str) -> bool {
+    !id.is_empty()
+        && id.len() <= MAX_LEN
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || matches!(b, b'-' | b'_' | b'.' | b':'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(id: &str) -> HashMap<String, String> {
+        HashMap::from([("X-Aether-Request-Id".to_string(), id.to_string())])
+    }
+
+    #[test]
+    fn test_generate() {
+        let a = generate();
+        assert_eq!(a.len(), 32);
+        assert!(a.bytes().all(|b| b.is_ascii_hexdigit()));
+        assert_ne!(a, generate());
+    }
+
+    #[test]
+    fn test_incoming_id() {
+        assert_eq!(of(&headers(" req-1:a.b_c "), true), "req-1:a.b_c");
+        // Ignored unless trusted
+        assert_ne!(of(&headers("req-1"), false), "req-1");
+        // Malformed IDs are replaced
+        for bad in ["", "a b", "a\nb", "x".repeat(MAX_LEN + 1).as_str()] {
+            assert_eq!(of(&headers(bad), true).len(), 32, "{bad:?}");
+        }
+        assert_eq!(of(&HashMap::new(), true).len(), 32);
+    }
+}
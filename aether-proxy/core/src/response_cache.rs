@@ -0,0 +1,650 @@
+//! In-memory cache of upstream GET responses.
+//!
+//! Enabled with `--response-cache-size` (bytes, least recently used
+//! entries evicted first).  It follows RFC 9111 for a shared cache, kept
+//! deliberately conservative:
+//!
+//! - Only `GET` requests without a body, `Range` or conditional headers of
+//!   the client's own are looked up; `Cache-Control: no-store` bypasses the
+//!   cache and `no-cache` / `max-age=0` force revalidation.
+//! - A response is stored when it carries explicit freshness (`s-maxage`,
+//!   `max-age` or `Expires`) or a validator, and no `no-store`, `private`,
+//!   `Set-Cookie` or `Vary: *`.  Nothing is served stale.
+//! - Stale entries with an `ETag` / `Last-Modified` are revalidated with a
+//!   conditional request; a `304` refreshes the entry and its body is
+//!   served without fetching it again.
+//! - Requests carrying credentials (`Authorization`, `Cookie`, or an API
+//!   key or token header such as `x-api-key`) are cached per credential:
+//!   the key includes a digest of those headers, so one client's response
+//!   is never served to another.  Keys are computed from the headers sent
+//!   upstream, after header rules.
+//!
+//! Responses say how they were answered in `x-aether-cache` (`hit`,
+//! `revalidated` or `miss`).
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use sha2::{Digest, Sha256};
+
+use crate::config::Config;
+
+/// Response header telling how the cache answered.
+pub const STATUS_HEADER: &str = "x-aether-cache";
+/// Largest single entry; never more than an eighth of the cache.
+const MAX_ENTRY_BYTES: usize = 4 * 1024 * 1024;
+/// Statuses cacheable by default (RFC 9110 section 15.1), when fresh.
+const CACHEABLE_STATUS: &[u16] = &[200, 203, 204, 300, 301, 404, 410];
+/// Request headers that make the client, not the cache, handle validation.
+const CLIENT_CONDITIONALS: &[&str] = &[
+    "if-match",
+    "if-none-match",
+    "if-modified-since",
+    "if-unmodified-since",
+    "if-range",
+    "range",
+];
+/// Request headers carrying credentials, besides names ending in one of
+/// [`CREDENTIAL_SUFFIXES`].
+const CREDENTIAL_HEADERS: &[&str] = &["authorization", "proxy-authorization", "cookie"];
+/// Suffixes of API key and token headers (`x-api-key`, `x-goog-api-key`,
+/// `x-auth-token`, ...).
+const CREDENTIAL_SUFFIXES: &[&str] = &["-key", "-token", "-secret"];
+/// Response headers never stored: per-response or per-hop.
+const UNSTORED_HEADERS: &[&str] = &[
+    "age",
+    "x-proxy-timing",
+    "x-aether-request-id",
+    STATUS_HEADER,
+];
+
+/// A stored response.
+#[derive(Debug)]
+pub struct Entry {
+    pub status: u16,
+    headers: Vec<(String, String)>,
+    pub body: Bytes,
+    /// Request header values the response varies on.
+    vary: Vec<(String, Option<String>)>,
+    stored_at: Instant,
+    /// `Age` the response already had when it arrived.
+    initial_age: Duration,
+    fresh_for: Duration,
+}
+
+impl Entry {
+    fn age(&self) -> Duration {
+        self.initial_age + self.stored_at.elapsed()
+    }
+
+    fn is_fresh(&self) -> bool {
+        self.age() < self.fresh_for
+    }
+
+    fn size(&self) -> usize {
+        self.body.len()
+            + self
+                .headers
+                .iter()
+                .map(|(k, v)| k.len() + v.len())
+                .sum::<usize>()
+    }
+
+    /// Conditional request headers revalidating this entry; empty when it
+    /// has no validator.
+    pub fn validators(&self) -> Vec<(String, String)> {
+        let mut conditions = Vec::new();
+        if let Some(etag) = header(&self.headers, "etag") {
+            conditions.push(("if-none-match".to_string(), etag.to_string()));
+        }
+        if let Some(modified) = header(&self.headers, "last-modified") {
+            conditions.push(("if-modified-since".to_string(), modified.to_string()));
+        }
+        conditions
+    }
+
+    /// Headers to answer with, `Age` and [`STATUS_HEADER`] included.
+    pub fn response_headers(&self, status: &str) -> Vec<(String, String)> {
+        let mut headers = self.headers.clone();
+        headers.push(("age".to_string(), self.age().as_secs().to_string()));
+        headers.push((STATUS_HEADER.to_string(), status.to_string()));
+        headers
+    }
+
+    fn matches(&self, request: &HashMap<String, String>) -> bool {
+        self.vary
+            .iter()
+            .all(|(name, value)| request_header(request, name) == value.as_deref())
+    }
+}
+
+/// Result of a cache lookup.
+pub enum Lookup {
+    Fresh(Arc<Entry>),
+    /// Usable only after a successful revalidation.
+    Stale(Arc<Entry>),
+    Miss,
+}
+
+struct Slot {
+    entry: Arc<Entry>,
+    tick: u64,
+}
+
+#[derive(Default)]
+struct Inner {
+    entries: HashMap<String, Slot>,
+    /// Last use -> key, oldest first.
+    lru: BTreeMap<u64, String>,
+    tick: u64,
+    size: usize,
+}
+
+impl Inner {
+    fn touch(&mut self, key: &str) {
+        self.tick += 1;
+        let tick = self.tick;
+        if let Some(slot) = self.entries.get_mut(key) {
+            self.lru.remove(&slot.tick);
+            slot.tick = tick;
+            self.lru.insert(tick, key.to_string());
+        }
+    }
+
+    fn remove(&mut self, key: &str) {
+        if let Some(slot) = self.entries.remove(key) {
+            self.lru.remove(&slot.tick);
+            self.size -= slot.entry.size() + key.len();
+        }
+    }
+}
+
+pub struct ResponseCache {
+    capacity: usize,
+    inner: Mutex<Inner>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    revalidated: AtomicU64,
+}
+
+impl ResponseCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::default(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            revalidated: AtomicU64::new(0),
+        }
+    }
+
+    /// `None` when `--response-cache-size` is 0.
+    pub fn from_config(config: &Config) -> Option<Self> {
+        (config.response_cache_size > 0).then(|| Self::new(config.response_cache_size as usize))
+    }
+
+    /// Largest body worth capturing for the cache.
+    pub fn max_entry(&self) -> usize {
+        (self.capacity / 8).min(MAX_ENTRY_BYTES)
+    }
+
+    /// Cache key of a request, from the headers sent upstream; `None` when
+    /// the cache must not be used for it at all.
+    pub fn key(method: &str, url: &str, request: &HashMap<String, String>) -> Option<String> {
+        if !method.eq_ignore_ascii_case("GET")
+            || CLIENT_CONDITIONALS
+                .iter()
+                .any(|name| request_header(request, name).is_some())
+            || request_header(request, "upgrade").is_some()
+            || request_header(request, "content-length").is_some_and(|len| len.trim() != "0")
+            || directives(request_header(request, "cache-control")).contains_key("no-store")
+        {
+            return None;
+        }
+        let mut credentials: Vec<String> = request
+            .iter()
+            .filter(|(name, _)| is_credential(name))
+            .map(|(name, value)| format!("{}: {}", name.to_ascii_lowercase(), value))
+            .collect();
+        if credentials.is_empty() {
+            return Some(url.to_string());
+        }
+        credentials.sort();
+        let digest = Sha256::digest(credentials.join("\n").as_bytes());
+        Some(format!("{} #{}", url, hex::encode(&digest[..16])))
+    }
+
+    pub fn lookup(&self, key: &str, request: &HashMap<String, String>) -> Lookup {
+        let entry = {
+            let mut inner = self.inner.lock().unwrap();
+            let entry = inner
+                .entries
+                .get(key)
+                .map(|slot| Arc::clone(&slot.entry))
+                .filter(|entry| entry.matches(request));
+            if entry.is_some() {
+                inner.touch(key);
+            }
+            entry
+        };
+        let Some(entry) = entry else {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return Lookup::Miss;
+        };
+        let cache_control = directives(request_header(request, "cache-control"));
+        let revalidate = cache_control.contains_key("no-cache")
+            || cache_control.get("max-age").is_some_and(|v| v == "0")
+            || request_header(request, "pragma").is_some_and(|v| v.contains("no-cache"));
+        if entry.is_fresh() && !revalidate {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            Lookup::Fresh(entry)
+        } else if entry.validators().is_empty() {
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            Lookup::Miss
+        } else {
+            Lookup::Stale(entry)
+        }
+    }
+
+    /// Whether a response may be stored; checked before its body is read.
+    pub fn storable(status: u16, headers: &[(String, String)]) -> bool {
+        freshness(status, headers).is_some()
+    }
+
+    /// Store a complete response to the request `key` was made for.
+    pub fn store(
+        &self,
+        key: &str,
+        request: &HashMap<String, String>,
+        status: u16,
+        headers: &[(String, String)],
+        body: Bytes,
+    ) {
+        let Some(fresh_for) = freshness(status, headers) else {
+            return;
+        };
+        if body.len() > self.max_entry() {
+            return;
+        }
+        let vary = header(headers, "vary")
+            .into_iter()
+            .flat_map(|v| v.split(','))
+            .map(|name| name.trim().to_ascii_lowercase())
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                let value = request_header(request, &name).map(str::to_string);
+                (name, value)
+            })
+            .collect();
+        let entry = Entry {
+            status,
+            headers: headers
+                .iter()
+                .filter(|(k, _)| !UNSTORED_HEADERS.contains(&k.to_ascii_lowercase().as_str()))
+                .cloned()
+                .collect(),
+            body,
+            vary,
+            stored_at: Instant::now(),
+            initial_age: Duration::from_secs(
+                header(headers, "age")
+                    .and_then(|v| v.trim().parse().ok())
+                    .unwrap_or(0),
+            ),
+            fresh_for,
+        };
+        self.insert(key, entry);
+    }
+
+    /// Apply a `304 Not Modified` to a stale entry: its headers are updated
+    /// and its freshness restarts.  Returns the entry to answer with.
+    pub fn revalidated(
+        &self,
+        key: &str,
+        stale: &Entry,
+        not_modified: &[(String, String)],
+    ) -> Arc<Entry> {
+        self.revalidated.fetch_add(1, Ordering::Relaxed);
+        let mut headers: Vec<(String, String)> = stale
+            .headers
+            .iter()
+            .filter(|(k, _)| header(not_modified, k).is_none())
+            .cloned()
+            .collect();
+        headers.extend(
+            not_modified
+                .iter()
+                .filter(|(k, _)| !UNSTORED_HEADERS.contains(&k.to_ascii_lowercase().as_str()))
+                .filter(|(k, _)| !k.eq_ignore_ascii_case("content-length"))
+                .cloned(),
+        );
+        let entry = Entry {
+            status: stale.status,
+            fresh_for: freshness(stale.status, &headers).unwrap_or_default(),
+            headers,
+            body: stale.body.clone(),
+            vary: stale.vary.clone(),
+            stored_at: Instant::now(),
+            initial_age: Duration::ZERO,
+        };
+        self.insert(key, entry)
+    }
+
+    fn insert(&self, key: &str, entry: Entry) -> Arc<Entry> {
+        let entry = Arc::new(entry);
+        let size = entry.size() + key.len();
+        if size > self.capacity {
+            return entry;
+        }
+        let mut inner = self.inner.lock().unwrap();
+        inner.remove(key);
+        while inner.size + size > self.capacity {
+            let Some((_, oldest)) = inner.lru.pop_first() else {
+                break;
+            };
+            inner.remove(&oldest);
+        }
+        inner.tick += 1;
+        let tick = inner.tick;
+        inner.entries.insert(
+            key.to_string(),
+            Slot {
+                entry: Arc::clone(&entry),
+                tick,
+            },
+        );
+        inner.lru.insert(tick, key.to_string());
+        inner.size += size;
+        entry
+    }
+
+    /// Counters and size for the admin API and heartbeats.
+    pub fn stats_json(&self) -> serde_json::Value {
+        let inner = self.inner.lock().unwrap();
+        serde_json::json!({
+            "entries": inner.entries.len(),
+            "bytes": inner.size,
+            "capacity": self.capacity,
+            "hits": self.hits.load(Ordering::Relaxed),
+            "misses": self.misses.load(Ordering::Relaxed),
+            "revalidated": self.revalidated.load(Ordering::Relaxed),
+        })
+    }
+}
+
+/// How long a response stays fresh, `None` when it must not be stored.
+fn freshness(status: u16, headers: &[(String, String)]) -> Option<Duration> {
+    if !CACHEABLE_STATUS.contains(&status)
+        || header(headers, "set-cookie").is_some()
+        || header(headers, "vary").is_some_and(|v| v.split(',').any(|n| n.trim() == "*"))
+    {
+        return None;
+    }
+    let cache_control = directives(header(headers, "cache-control"));
+    if cache_control.contains_key("no-store") || cache_control.contains_key("private") {
+        return None;
+    }
+    let seconds = |name: &str| cache_control.get(name).and_then(|v| v.parse::<u64>().ok());
+    let explicit = if cache_control.contains_key("no-cache") {
+        Some(0)
+    } else if let Some(secs) = seconds("s-maxage").or_else(|| seconds("max-age")) {
+        Some(secs)
+    } else {
+        header(headers, "expires").map(|expires| {
+            let date = header(headers, "date")
+                .and_then(crate::clock::parse_http_date)
+                .unwrap_or_else(|| crate::clock::now_ms() / 1000);
+            // An unparsable Expires means already expired
+            crate::clock::parse_http_date(expires).map_or(0, |expires| expires.saturating_sub(date))
+        })
+    };
+    let has_validator =
+        header(headers, "etag").is_some() || header(headers, "last-modified").is_some();
+    match explicit {
+        Some(secs) if secs > 0 || has_validator => Some(Duration::from_secs(secs)),
+        None if has_validator => Some(Duration::ZERO),
+        _ => None,
+    }
+}
+
+/// Request headers as the cache sees them; values that are not text are
+/// left out.
+pub fn request_headers(headers: &hyper::HeaderMap) -> HashMap<String, String> {
+    headers
+        .iter()
+        .filter_map(|(k, v)| Some((k.to_string(), v.to_str().ok()?.to_string())))
+        .collect()
+}
+
+/// Whether a request header identifies the client to the target.
+fn is_credential(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    CREDENTIAL_HEADERS.contains(&name.as_str())
+        || CREDENTIAL_SUFFIXES
+            .iter()
+            .any(|suffix| name.ends_with(suffix))
+}
+
+/// `Cache-Control` directives, lower-cased, with unquoted values.
+fn directives(raw: Option<&str>) -> HashMap<String, String> {
+    raw.into_iter()
+        .flat_map(|v| v.split(','))
+        .filter_map(|directive| {
+            let directive = directive.trim();
+            if directive.is_empty() {
+                return None;
+            }
+            let (name, value) = directive.split_once('=').unwrap_or((directive, ""));
+            Some((
+                name.trim().to_ascii_lowercase(),
+                value.trim().trim_matches('"').to_string(),
+            ))
+        })
+        .collect()
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+}
+
+fn request_header<'a>(headers: &'a HashMap<String, String>, name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(list: &[(&str, &str)]) -> Vec<(String, String)> {
+        list.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn request(list: &[(&str, &str)]) -> HashMap<String, String> {
+        headers(list).into_iter().collect()
+    }
+
+    const URL: &str = "https://api.example.com/v1/models";
+
+    #[test]
+    fn test_key() {
+        let plain = ResponseCache::key("GET", URL, &request(&[])).unwrap();
+        assert_eq!(plain, URL);
+        let alice = ResponseCache::key("GET", URL, &request(&[("Authorization", "a")])).unwrap();
+        let bob = ResponseCache::key("GET", URL, &request(&[("Authorization", "b")])).unwrap();
+        assert_ne!(alice, bob);
+        assert!(alice.starts_with(URL));
+        for name in ["x-api-key", "api-key", "x-goog-api-key", "Cookie"] {
+            let key = ResponseCache::key("GET", URL, &request(&[(name, "a")])).unwrap();
+            assert_ne!(key, plain, "{name}");
+        }
+        assert_eq!(
+            ResponseCache::key(
+                "GET",
+                URL,
+                &request(&[("Authorization", "a"), ("x-api-key", "b")])
+            ),
+            ResponseCache::key(
+                "GET",
+                URL,
+                &request(&[("x-api-key", "b"), ("authorization", "a")])
+            )
+        );
+
+        for bypass in [
+            request(&[("Cache-Control", "no-store")]),
+            request(&[("If-None-Match", "\"x\"")]),
+            request(&[("Range", "bytes=0-1")]),
+            request(&[("Content-Length", "3")]),
+        ] {
+            assert!(ResponseCache::key("GET", URL, &bypass).is_none());
+        }
+        assert!(ResponseCache::key("POST", URL, &request(&[])).is_none());
+    }
+
+    #[test]
+    fn test_freshness() {
+        let fresh = |list: &[(&str, &str)]| freshness(200, &headers(list));
+        assert_eq!(
+            fresh(&[("Cache-Control", "public, max-age=60, s-maxage=30")]),
+            Some(Duration::from_secs(30))
+        );
+        assert_eq!(
+            fresh(&[
+                ("Date", "Sun, 06 Nov 1994 08:49:37 GMT"),
+                ("Expires", "Sun, 06 Nov 1994 08:50:37 GMT"),
+            ]),
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(
+            fresh(&[("Cache-Control", "no-cache"), ("ETag", "\"v1\"")]),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(fresh(&[]), None);
+        assert_eq!(fresh(&[("Cache-Control", "no-cache")]), None);
+        assert_eq!(fresh(&[("Cache-Control", "private, max-age=60")]), None);
+        assert_eq!(
+            fresh(&[("Cache-Control", "max-age=60"), ("Vary", "*")]),
+            None
+        );
+        assert_eq!(
+            fresh(&[("Cache-Control", "max-age=60"), ("Set-Cookie", "a=b")]),
+            None
+        );
+        assert_eq!(
+            freshness(500, &headers(&[("Cache-Control", "max-age=60")])),
+            None
+        );
+    }
+
+    #[test]
+    fn test_lookup_and_revalidate() {
+        let cache = ResponseCache::new(1024 * 1024);
+        let req = request(&[("Accept-Encoding", "gzip")]);
+        let key = ResponseCache::key("GET", URL, &req).unwrap();
+        assert!(matches!(cache.lookup(&key, &req), Lookup::Miss));
+
+        cache.store(
+            &key,
+            &req,
+            200,
+            &headers(&[
+                ("Cache-Control", "max-age=60"),
+                ("ETag", "\"v1\""),
+                ("Vary", "Accept-Encoding"),
+                ("x-proxy-timing", "{}"),
+            ]),
+            Bytes::from_static(b"models"),
+        );
+        let Lookup::Fresh(entry) = cache.lookup(&key, &req) else {
+            panic!("expected a fresh hit");
+        };
+        assert_eq!(&entry.body[..], b"models");
+        let served = entry.response_headers("hit");
+        assert_eq!(header(&served, "age"), Some("0"));
+        assert_eq!(header(&served, STATUS_HEADER), Some("hit"));
+        assert!(header(&served, "x-proxy-timing").is_none());
+
+        // A different Accept-Encoding is a different variant
+        assert!(matches!(
+            cache.lookup(&key, &request(&[("Accept-Encoding", "br")])),
+            Lookup::Miss
+        ));
+
+        // The client asks for revalidation
+        let no_cache = request(&[("Accept-Encoding", "gzip"), ("Cache-Control", "no-cache")]);
+        let Lookup::Stale(stale) = cache.lookup(&key, &no_cache) else {
+            panic!("expected revalidation");
+        };
+        assert_eq!(stale.validators(), headers(&[("if-none-match", "\"v1\"")]));
+        let refreshed = cache.revalidated(
+            &key,
+            &stale,
+            &headers(&[("Cache-Control", "max-age=120"), ("ETag", "\"v1\"")]),
+        );
+        assert_eq!(&refreshed.body[..], b"models");
+        assert_eq!(
+            header(&refreshed.headers, "cache-control"),
+            Some("max-age=120")
+        );
+
+        let stats = cache.stats_json();
+        assert_eq!(stats["hits"], 1);
+        assert_eq!(stats["misses"], 2);
+        assert_eq!(stats["revalidated"], 1);
+        assert_eq!(stats["entries"], 1);
+    }
+
+    #[test]
+    fn test_api_keys_are_not_shared() {
+        let cache = ResponseCache::new(1024 * 1024);
+        let alice = request(&[("x-api-key", "alice")]);
+        let key = ResponseCache::key("GET", URL, &alice).unwrap();
+        cache.store(
+            &key,
+            &alice,
+            200,
+            &headers(&[("Cache-Control", "max-age=60")]),
+            Bytes::from_static(b"alice's models"),
+        );
+        assert!(matches!(cache.lookup(&key, &alice), Lookup::Fresh(_)));
+
+        let bob = request(&[("x-api-key", "bob")]);
+        let key = ResponseCache::key("GET", URL, &bob).unwrap();
+        assert!(matches!(cache.lookup(&key, &bob), Lookup::Miss));
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        // Room for eight entries of 900 bytes and their headers
+        let cache = ResponseCache::new(8000);
+        let fresh = headers(&[("cache-control", "max-age=60")]);
+        let body = Bytes::from(vec![b'x'; 900]);
+        let none = HashMap::new();
+        let keys = ["a", "b", "c", "d", "e", "f", "g", "h"];
+        for key in keys {
+            cache.store(key, &none, 200, &fresh, body.clone());
+        }
+        // "a" is used again, so "b" is the oldest when "i" needs room
+        assert!(matches!(cache.lookup("a", &none), Lookup::Fresh(_)));
+        cache.store("i", &none, 200, &fresh, body.clone());
+        assert!(matches!(cache.lookup("b", &none), Lookup::Miss));
+        for key in ["a", "c", "h", "i"] {
+            assert!(matches!(cache.lookup(key, &none), Lookup::Fresh(_)));
+        }
+
+        // Bodies over an eighth of the capacity are not kept
+        cache.store("j", &none, 200, &fresh, Bytes::from(vec![0; 1001]));
+        assert!(matches!(cache.lookup("j", &none), Lookup::Miss));
+    }
+}
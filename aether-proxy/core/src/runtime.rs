@@ -0,0 +1,386 @@
+//! Runtime-mutable configuration that can be updated remotely via heartbeat.
+//!
+//! Fields in [`DynamicConfig`] are initially populated from the static
+//! [`Config`](crate::config::Config) and may be overridden by the Aether
+//! management backend through the heartbeat response.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, OnceLock};
+
+use arc_swap::ArcSwap;
+use tracing::{info, warn};
+
+use crate::config::Config;
+use crate::features::FeatureFlags;
+use crate::header_rules::HeaderRules;
+use crate::quota::QuotaRules;
+use crate::target_filter::{CidrRules, ClientRules, DomainRules};
+use crate::warm_pool::{self, HotTarget};
+
+/// Configuration that can be changed at runtime without restart.
+#[derive(Debug, Clone)]
+pub struct DynamicConfig {
+    pub node_name: String,
+    pub allowed_ports: Arc<HashSet<u16>>,
+    pub domain_rules: Arc<DomainRules>,
+    pub cidr_rules: Arc<CidrRules>,
+    pub features: Arc<FeatureFlags>,
+    /// Request header rewrites per target domain.
+    pub header_rules: Arc<HeaderRules>,
+    /// Targets to keep connections ready to.
+    pub hot_targets: Arc<Vec<HotTarget>>,
+    /// Targets whose connect latency is probed.
+    pub probe_targets: Arc<Vec<HotTarget>>,
+    pub log_level: String,
+    pub heartbeat_interval: u64,
+    /// Per-client daily quotas; only ever set by the control plane.
+    pub client_quotas: Arc<QuotaRules>,
+    /// Per-client target restrictions; only ever set by the control plane.
+    pub client_rules: Arc<HashMap<String, ClientRules>>,
+    /// Monotonically increasing version from the backend.
+    /// `0` means no remote config has ever been applied.
+    pub config_version: u64,
+}
+
+impl DynamicConfig {
+    /// Initialize from static config (startup defaults).
+    pub fn from_config(config: &Config) -> Self {
+        Self {
+            node_name: config.node_name.clone(),
+            allowed_ports: Arc::new(config.allowed_ports.iter().copied().collect()),
+            domain_rules: Arc::new(
+                config
+                    .domain_rules()
+                    .expect("domain rules are checked by Config::validate"),
+            ),
+            cidr_rules: Arc::new(
+                config
+                    .cidr_rules()
+                    .expect("CIDR rules are checked by Config::validate"),
+            ),
+            features: Arc::new(
+                config
+                    .feature_flags()
+                    .expect("feature flags are checked by Config::validate"),
+            ),
+            header_rules: Arc::new(
+                config
+                    .header_rules()
+                    .expect("header rules are checked by Config::validate"),
+            ),
+            hot_targets: Arc::new(
+                config
+                    .hot_targets()
+                    .expect("hot targets are checked by Config::validate"),
+            ),
+            probe_targets: Arc::new(
+                config
+                    .probe_targets()
+                    .expect("probe targets are checked by Config::validate"),
+            ),
+            log_level: config.log_level.clone(),
+            heartbeat_interval: config.heartbeat_interval,
+            client_quotas: Arc::default(),
+            client_rules: Arc::default(),
+            config_version: 0,
+        }
+    }
+}
+
+/// Shared dynamic config handle (lock-free reads via ArcSwap).
+pub type SharedDynamicConfig = Arc<ArcSwap<DynamicConfig>>;
+
+// -- Log-level hot-reload -----
+
+/// Global log-level reloader function, set during tracing init.
+type LogReloader = Box<dyn Fn(&str) + Send + Sync>;
+
+static LOG_RELOADER: OnceLock<LogReloader> = OnceLock::new();
+
+/// Register the log-level reload function (called once from `init_tracing`).
+pub fn set_log_reloader(f: LogReloader) {
+    let _ = LOG_RELOADER.set(f);
+}
+
+/// Check that `level` parses as `EnvFilter` directives.
+fn validate_log_level(level: &str) -> Result<(), String> {
+    tracing_subscriber::EnvFilter::try_new(level)
+        .map(drop)
+        .map_err(|e| format!("invalid log level {:?}: {}", level, e))
+}
+
+/// Swap the tracing filter for `level` (`EnvFilter` directives) if valid.
+pub fn reload_log_level(level: &str) -> Result<(), String> {
+    validate_log_level(level)?;
+    if let Some(reloader) = LOG_RELOADER.get() {
+        reloader(level);
+    }
+    Ok(())
+}
+
+/// Record a level already applied by [`reload_log_level`], so heartbeats
+/// report it.
+pub fn store_log_level(dynamic: &SharedDynamicConfig, level: &str) {
+    dynamic.rcu(|current| {
+        let mut new_cfg = (**current).clone();
+        new_cfg.log_level = level.to_string();
+        new_cfg
+    });
+}
+
+/// Change the log level on command, outside the versioned remote config.
+pub fn set_log_level(dynamic: &SharedDynamicConfig, level: &str) -> Result<(), String> {
+    reload_log_level(level)?;
+    store_log_level(dynamic, level);
+    info!(log_level = %level, "log level changed by remote command");
+    Ok(())
+}
+
+/// Apply a remote config update to the dynamic config.
+///
+/// Uses copy-on-write: clones the current snapshot, applies changes and
+/// swaps in the new Arc, starting over if the snapshot was replaced in the
+/// meantime (e.g. by [`set_log_level`]). Reads are always lock-free.
+///
+/// Returns `true` if the config was actually changed.
+pub fn apply_remote_config(
+    dynamic: &SharedDynamicConfig,
+    remote: &crate::registration::client::RemoteConfig,
+    version: u64,
+) -> bool {
+    let mut merged = None;
+    let mut level_changed = false;
+    dynamic.rcu(|current| {
+        merged = merge_remote_config(current, remote, version);
+        match &merged {
+            Some((new_cfg, _)) => {
+                level_changed = new_cfg.log_level != current.log_level;
+                Arc::clone(new_cfg)
+            }
+            None => Arc::clone(current),
+        }
+    });
+    let Some((new_cfg, changed)) = merged else {
+        return false;
+    };
+    info!(
+        version,
+        changes = %changed.join(", "),
+        "remote config applied"
+    );
+    if level_changed {
+        // Hot-reload tracing filter
+        if let Some(reloader) = LOG_RELOADER.get() {
+            reloader(&new_cfg.log_level);
+        }
+    }
+    true
+}
+
+/// `current` with the changes in `remote`, and a description of each; `None`
+/// when nothing changes.
+fn merge_remote_config(
+    current: &DynamicConfig,
+    remote: &crate::registration::client::RemoteConfig,
+    version: u64,
+) -> Option<(Arc<DynamicConfig>, Vec<String>)> {
+    if version <= current.config_version {
+        return None;
+    }
+
+    let mut new_cfg = current.clone();
+    let mut changed = Vec::new();
+
+    if let Some(ref name) = remote.node_name {
+        if *name != new_cfg.node_name {
+            changed.push(format!("node_name -> {}", name));
+            new_cfg.node_name = name.clone();
+        }
+    }
+
+    if let Some(ref ports) = remote.allowed_ports {
+        let new_set: HashSet<u16> = ports.iter().copied().collect();
+        if new_set != *new_cfg.allowed_ports {
+            changed.push(format!("allowed_ports -> {:?}", ports));
+            new_cfg.allowed_ports = Arc::new(new_set);
+        }
+    }
+
+    if remote.allowed_domains.is_some() || remote.blocked_domains.is_some() {
+        // Each list is optional: an omitted list keeps the current value.
+        let (current_allow, current_deny) = new_cfg.domain_rules.to_strings();
+        let allow = remote.allowed_domains.clone().unwrap_or(current_allow);
+        let deny = remote.blocked_domains.clone().unwrap_or(current_deny);
+        match DomainRules::new(&allow, &deny) {
+            Ok(rules) => {
+                if rules != *new_cfg.domain_rules {
+                    changed.push(format!(
+                        "domain_rules -> allow {:?}, block {:?}",
+                        allow, deny
+                    ));
+                    new_cfg.domain_rules = Arc::new(rules);
+                }
+            }
+            Err(e) => warn!(error = %e, "ignoring invalid remote domain rules"),
+        }
+    }
+
+    if remote.allowed_cidrs.is_some() || remote.blocked_cidrs.is_some() {
+        let (current_allow, current_deny) = new_cfg.cidr_rules.to_strings();
+        let allow = remote.allowed_cidrs.clone().unwrap_or(current_allow);
+        let deny = remote.blocked_cidrs.clone().unwrap_or(current_deny);
+        match CidrRules::new(&allow, &deny) {
+            Ok(rules) => {
+                if rules != *new_cfg.cidr_rules {
+                    changed.push(format!("cidr_rules -> allow {:?}, block {:?}", allow, deny));
+                    new_cfg.cidr_rules = Arc::new(rules);
+                }
+            }
+            Err(e) => warn!(error = %e, "ignoring invalid remote CIDR rules"),
+        }
+    }
+
+    if let Some(ref overrides) = remote.features {
+        match new_cfg.features.with_remote(overrides) {
+            Ok(flags) => {
+                if flags != *new_cfg.features {
+                    changed.push(format!("features -> {:?}", overrides));
+                    new_cfg.features = Arc::new(flags);
+                }
+            }
+            Err(e) => warn!(error = %e, "ignoring invalid remote feature flags"),
+        }
+    }
+
+    if let Some(ref rules) = remote.header_rules {
+        match HeaderRules::new(rules) {
+            Ok(parsed) => {
+                if parsed != *new_cfg.header_rules {
+                    // Values may be credentials; log the count only
+                    changed.push(format!("header_rules -> {} rule(s)", parsed.len()));
+                    new_cfg.header_rules = Arc::new(parsed);
+                }
+            }
+            Err(e) => warn!(error = %e, "ignoring invalid remote header rules"),
+        }
+    }
+
+    if let Some(ref targets) = remote.hot_targets {
+        match warm_pool::parse_targets(targets) {
+            Ok(parsed) => {
+                if parsed != *new_cfg.hot_targets {
+                    changed.push(format!("hot_targets -> {:?}", targets));
+                    new_cfg.hot_targets = Arc::new(parsed);
+                }
+            }
+            Err(e) => warn!(error = %e, "ignoring invalid remote hot targets"),
+        }
+    }
+
+    if let Some(ref targets) = remote.probe_targets {
+        match warm_pool::parse_targets(targets) {
+            Ok(parsed) => {
+                if parsed != *new_cfg.probe_targets {
+                    changed.push(format!("probe_targets -> {:?}", targets));
+                    new_cfg.probe_targets = Arc::new(parsed);
+                }
+            }
+            Err(e) => warn!(error = %e, "ignoring invalid remote probe targets"),
+        }
+    }
+
+    if let Some(interval) = remote.heartbeat_interval {
+        if interval != new_cfg.heartbeat_interval {
+            changed.push(format!("heartbeat_interval -> {}s", interval));
+            new_cfg.heartbeat_interval = interval;
+        }
+    }
+
+    if let Some(ref quotas) = remote.client_quotas {
+        if *quotas != *new_cfg.client_quotas {
+            changed.push(format!(
+                "client_quotas -> default {:?}, {} client override(s)",
+                quotas.default,
+                quotas.clients.len()
+            ));
+            new_cfg.client_quotas = Arc::new(quotas.clone());
+        }
+    }
+
+    if let Some(ref policies) = remote.client_policies {
+        let parsed: Result<HashMap<String, ClientRules>, String> = policies
+            .iter()
+            .map(|(client, policy)| {
+                policy
+                    .to_rules()
+                    .map(|rules| (client.clone(), rules))
+                    .map_err(|e| format!("{}: {}", client, e))
+            })
+            .collect();
+        match parsed {
+            Ok(rules) => {
+                if rules != *new_cfg.client_rules {
+                    changed.push(format!("client_policies -> {} client(s)", rules.len()));
+                    new_cfg.client_rules = Arc::new(rules);
+                }
+            }
+            Err(e) => warn!(error = %e, "ignoring invalid remote client policies"),
+        }
+    }
+
+    if let Some(ref level) = remote.log_level {
+        if *level != new_cfg.log_level {
+            match validate_log_level(level) {
+                Ok(()) => {
+                    changed.push(format!("log_level -> {}", level));
+                    new_cfg.log_level = level.clone();
+                }
+                Err(e) => warn!(error = %e, "ignoring invalid remote log level"),
+            }
+        }
+    }
+
+    if changed.is_empty() {
+        return None;
+    }
+    new_cfg.config_version = version;
+    Some((Arc::new(new_cfg), changed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_remote_log_level_is_validated() {
+        use clap::Parser;
+
+        let config = Config::try_parse_from([
+            "aether-proxy",
+            "--aether-url",
+            "https://aether.example",
+            "--management-token",
+            "ae_secret",
+        ])
+        .unwrap();
+        let dynamic: SharedDynamicConfig =
+            Arc::new(ArcSwap::from_pointee(DynamicConfig::from_config(&config)));
+        let remote = |level: &str| {
+            serde_json::from_value::<crate::registration::client::RemoteConfig>(
+                serde_json::json!({ "log_level": level }),
+            )
+            .unwrap()
+        };
+
+        assert!(!apply_remote_config(&dynamic, &remote("tunnel=loud"), 1));
+        assert_eq!(dynamic.load().log_level, config.log_level);
+
+        assert!(apply_remote_config(&dynamic, &remote("debug"), 2));
+        assert_eq!(dynamic.load().log_level, "debug");
+        assert_eq!(dynamic.load().config_version, 2);
+
+        store_log_level(&dynamic, "trace");
+        assert_eq!(dynamic.load().log_level, "trace");
+        assert_eq!(dynamic.load().config_version, 2);
+    }
+}
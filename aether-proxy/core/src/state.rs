@@ -0,0 +1,252 @@
+//! Shared application state passed to all subsystems.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use arc_swap::ArcSwapOption;
+use tokio::sync::watch;
+
+use crate::access_log::AccessLog;
+use crate::admin::activity::Activity;
+use crate::circuit_breaker::CircuitBreaker;
+use crate::config::Config;
+use crate::destinations::DestinationMeter;
+use crate::features::Feature;
+use crate::hardware::HardwareInfo;
+use crate::lifecycle::Lifecycle;
+use crate::load_shed::LoadShedder;
+use crate::net::PublicAddrs;
+use crate::privacy::Privacy;
+use crate::probe::Prober;
+use crate::quota::{ClientStreams, QuotaTracker};
+use crate::registration::client::AetherClient;
+use crate::response_cache::ResponseCache;
+use crate::runtime::SharedDynamicConfig;
+use crate::target_filter::{AddressPolicy, DnsCache, DomainPattern};
+use crate::tunnel::headers::{Anonymity, HopKey};
+use crate::tunnel::health::ControlPlaneHealth;
+use crate::tunnel::interference::InterferenceMonitor;
+use crate::tunnel::live_view::{LiveStreams, MetadataSealer};
+use crate::upstream_client::{EgressBinding, UpstreamClient};
+use crate::upstream_proxy::ProxyRoutes;
+use crate::usage::UsageMeter;
+use crate::warm_pool::WarmPool;
+
+/// Central application state shared across all servers/tunnels.
+pub struct AppState {
+    pub config: Arc<Config>,
+    /// DNS cache for upstream target resolution (shared).
+    pub dns_cache: Arc<DnsCache>,
+    /// Which resolved target addresses may be connected to.
+    pub address_policy: Arc<AddressPolicy>,
+    /// Hyper client for tunnel upstream requests with validated DNS and connection timing.
+    pub upstream_client: UpstreamClient,
+    /// HTTP/1.1-only client for `Upgrade` requests (e.g. WebSocket).
+    pub upstream_upgrade_client: UpstreamClient,
+    /// Same client bound to the alternate egress address, used for a single
+    /// retry when the primary path is reset during connect.
+    pub upstream_alt_client: Option<UpstreamClient>,
+    /// Source binding for upstream connections and the proxies they go
+    /// through.
+    pub egress: EgressBinding,
+    /// Proxy routing for upstream requests, if any target is proxied.
+    pub upstream_proxy: Option<Arc<ProxyRoutes>>,
+    /// Which client and proxy headers reach the target.
+    pub anonymity: Anonymity,
+    /// Targets that are other relay nodes, told the hop count even when the
+    /// node hides itself.
+    pub relay_hosts: Vec<DomainPattern>,
+    /// Rejects new streams by class under CPU/memory pressure.
+    pub load_shedder: Arc<LoadShedder>,
+    /// Refuses targets that keep failing.
+    pub circuit_breaker: CircuitBreaker,
+    /// Cacheable GET responses, when `--response-cache-size` is set.
+    pub response_cache: Option<ResponseCache>,
+    /// Ready connections to hot targets, unless `--hot-target-connections`
+    /// is 0.
+    pub warm_pool: Option<Arc<WarmPool>>,
+    /// Connect latency to probe targets, unless `--probe-interval-secs` is 0.
+    pub prober: Option<Arc<Prober>>,
+    /// Current lifecycle phase, reported in heartbeats.
+    pub lifecycle: Arc<Lifecycle>,
+    /// Encrypts live-view snapshots; `None` when the live view is disabled.
+    /// Swappable by the control plane's `rotate_live_view_key` command.
+    pub live_view_sealer: ArcSwapOption<MetadataSealer>,
+    /// Throughput history and recent errors shown on the admin UI.
+    pub activity: Arc<Activity>,
+    /// One record per stream, when `--access-log` is set.
+    pub access_log: AccessLog,
+    /// Masks targets and clients in the access log and reports.
+    pub privacy: Privacy,
+    /// Shared TLS config for the Aether control plane (tunnel WebSocket and
+    /// API clients), honouring the custom CA and client certificate options.
+    pub aether_tls_config: Arc<rustls::ClientConfig>,
+    /// Public addresses registrations carry; updated on address changes.
+    pub public_addrs: watch::Receiver<PublicAddrs>,
+    /// Hardware info sent with every registration.
+    pub hw_info: HardwareInfo,
+}
+
+/// Per-server state: one instance per Aether server connection.
+pub struct ServerContext {
+    /// Human-readable label for logging (e.g. "server-0").
+    pub server_label: String,
+    /// Aether server URL for this connection.
+    pub aether_url: String,
+    /// Management token for this server.
+    pub management_token: String,
+    /// Signs the hop count sent upstream when the node hides itself.
+    pub hop_key: HopKey,
+    /// Resolved node name at registration time (per-server override or global fallback).
+    /// After startup, the active node_name is read from `dynamic` (may be updated remotely).
+    #[allow(dead_code)]
+    pub node_name: String,
+    /// Node ID assigned by this Aether server.
+    pub node_id: Arc<RwLock<String>>,
+    /// API client for this server.
+    pub aether_client: Arc<AetherClient>,
+    /// Dynamic config from this server's heartbeat ACKs.
+    pub dynamic: SharedDynamicConfig,
+    /// Per-server active connection count.
+    pub active_connections: Arc<AtomicU64>,
+    /// Tunnel connections to this server currently established.
+    pub connected_tunnels: Arc<AtomicU64>,
+    /// Per-server request/latency metrics.
+    pub metrics: Arc<ProxyMetrics>,
+    /// In-flight streams, tracked only when the live view is enabled.
+    pub live_streams: Arc<LiveStreams>,
+    /// Per-client usage since the last upload.
+    pub usage: Arc<UsageMeter>,
+    /// Today's usage of clients with quotas.
+    pub quotas: Arc<QuotaTracker>,
+    /// Streams in flight per client.
+    pub client_streams: Arc<ClientStreams>,
+    /// Requests per target host since the last destinations report.
+    pub destinations: Arc<DestinationMeter>,
+    /// Whether the control plane is still being heard from.
+    pub control_plane: Arc<ControlPlaneHealth>,
+    /// Signs of blocking on the tunnel path.
+    pub interference: Arc<InterferenceMonitor>,
+}
+
+impl ServerContext {
+    /// Evaluate a feature flag for this server's node.
+    pub fn feature_enabled(&self, feature: Feature) -> bool {
+        let node_id = self.node_id.read().unwrap();
+        self.dynamic.load().features.enabled(feature, &node_id)
+    }
+}
+
+/// Aggregate metrics for reporting to Aether.
+///
+/// Counters are cumulative since startup.  Heartbeats report the delta
+/// against `heartbeat_acked`, the totals the control plane last confirmed,
+/// and metrics backends export the totals directly.
+pub struct ProxyMetrics {
+    pub total_requests: AtomicU64,
+    /// Cumulative connection-establishment latency in nanoseconds
+    /// (DNS + TCP/TLS + TTFB, excludes response body streaming).
+    pub total_latency_ns: AtomicU64,
+    pub failed_requests: AtomicU64,
+    pub dns_failures: AtomicU64,
+    pub stream_errors: AtomicU64,
+    /// Upstream connections reset during connect (possible active blocking).
+    pub upstream_resets: AtomicU64,
+    /// Streams rejected by load shedding.
+    pub shed_requests: AtomicU64,
+    /// Responses aborted because the upstream stopped sending mid-body.
+    pub stalled_streams: AtomicU64,
+    /// Upgraded (`101 Switching Protocols`) streams relayed, and their
+    /// bytes from the client and to the client; exported as metrics only.
+    pub upgraded_streams: AtomicU64,
+    pub upgraded_bytes_received: AtomicU64,
+    pub upgraded_bytes_sent: AtomicU64,
+    /// Totals included in the last acknowledged heartbeat.
+    pub heartbeat_acked: Mutex<MetricsTotals>,
+}
+
+/// Point-in-time copy of the [`ProxyMetrics`] counters.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MetricsTotals {
+    pub requests: u64,
+    pub latency_ns: u64,
+    pub failed: u64,
+    pub dns_failures: u64,
+    pub stream_errors: u64,
+    pub upstream_resets: u64,
+    pub shed_requests: u64,
+    pub stalled_streams: u64,
+}
+
+impl MetricsTotals {
+    /// Counter increase since `earlier`.
+    pub fn since(self, earlier: Self) -> Self {
+        Self {
+            requests: self.requests.saturating_sub(earlier.requests),
+            latency_ns: self.latency_ns.saturating_sub(earlier.latency_ns),
+            failed: self.failed.saturating_sub(earlier.failed),
+            dns_failures: self.dns_failures.saturating_sub(earlier.dns_failures),
+            stream_errors: self.stream_errors.saturating_sub(earlier.stream_errors),
+            upstream_resets: self.upstream_resets.saturating_sub(earlier.upstream_resets),
+            shed_requests: self.shed_requests.saturating_sub(earlier.shed_requests),
+            stalled_streams: self.stalled_streams.saturating_sub(earlier.stalled_streams),
+        }
+    }
+}
+
+impl Default for ProxyMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProxyMetrics {
+    pub fn new() -> Self {
+        Self {
+            total_requests: AtomicU64::new(0),
+            total_latency_ns: AtomicU64::new(0),
+            failed_requests: AtomicU64::new(0),
+            dns_failures: AtomicU64::new(0),
+            stream_errors: AtomicU64::new(0),
+            upstream_resets: AtomicU64::new(0),
+            shed_requests: AtomicU64::new(0),
+            stalled_streams: AtomicU64::new(0),
+            upgraded_streams: AtomicU64::new(0),
+            upgraded_bytes_received: AtomicU64::new(0),
+            upgraded_bytes_sent: AtomicU64::new(0),
+            heartbeat_acked: Mutex::new(MetricsTotals::default()),
+        }
+    }
+
+    /// Current cumulative totals.
+    pub fn totals(&self) -> MetricsTotals {
+        MetricsTotals {
+            requests: self.total_requests.load(Ordering::Acquire),
+            latency_ns: self.total_latency_ns.load(Ordering::Acquire),
+            failed: self.failed_requests.load(Ordering::Acquire),
+            dns_failures: self.dns_failures.load(Ordering::Acquire),
+            stream_errors: self.stream_errors.load(Ordering::Acquire),
+            upstream_resets: self.upstream_resets.load(Ordering::Acquire),
+            shed_requests: self.shed_requests.load(Ordering::Acquire),
+            stalled_streams: self.stalled_streams.load(Ordering::Acquire),
+        }
+    }
+
+    /// Record a finished upgraded stream.
+    pub fn record_upgraded(&self, bytes_received: u64, bytes_sent: u64) {
+        self.upgraded_streams.fetch_add(1, Ordering::Release);
+        self.upgraded_bytes_received
+            .fetch_add(bytes_received, Ordering::Release);
+        self.upgraded_bytes_sent
+            .fetch_add(bytes_sent, Ordering::Release);
+    }
+
+    /// Record a completed request with its connection-establishment latency
+    /// (DNS + TCP/TLS + TTFB, excludes response body streaming).
+    pub fn record_request(&self, connect_elapsed: Duration) {
+        let nanos = u64::try_from(connect_elapsed.as_nanos()).unwrap_or(u64::MAX);
+        self.total_requests.fetch_add(1, Ordering::Release);
+        self.total_latency_ns.fetch_add(nanos, Ordering::Release);
+    }
+}
@@ -0,0 +1,180 @@
+//! Minimal STUN client (RFC 5389 binding request) for public address
+//! detection.
+//!
+//! A single binding request is sent over UDP and the reflexive address is
+//! read from the response's XOR-MAPPED-ADDRESS (or, from pre-5389 servers,
+//! MAPPED-ADDRESS) attribute.  No authentication, no retransmission beyond
+//! the caller trying the next server.
+
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+use ring::rand::{SecureRandom, SystemRandom};
+use tokio::net::UdpSocket;
+
+const BINDING_REQUEST: u16 = 0x0001;
+const BINDING_SUCCESS: u16 = 0x0101;
+const MAGIC_COOKIE: u32 = 0x2112_A442;
+const ATTR_MAPPED_ADDRESS: u16 = 0x0001;
+const ATTR_XOR_MAPPED_ADDRESS: u16 = 0x0020;
+const HEADER_LEN: usize = 20;
+
+/// Ask `server` (`host:port`) for this host's public address, over a socket
+/// bound to `bind` (which fixes the address family).
+pub async fn public_addr(server: &str, bind: IpAddr, timeout: Duration) -> io::Result<IpAddr> {
+    let target = tokio::net::lookup_host(server)
+        .await?
+        .find(|addr| addr.is_ipv4() == bind.is_ipv4())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{server} has no address of the requested family"),
+            )
+        })?;
+
+    let mut transaction = [0u8; 12];
+    SystemRandom::new()
+        .fill(&mut transaction)
+        .map_err(|_| io::Error::other("no randomness for STUN transaction ID"))?;
+
+    let socket = UdpSocket::bind(SocketAddr::new(bind, 0)).await?;
+    socket.connect(target).await?;
+    socket.send(&binding_request(&transaction)).await?;
+
+    let mut buf = [0u8; 512];
+    let len = tokio::time::timeout(timeout, socket.recv(&mut buf))
+        .await
+        .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))??;
+    parse_response(&buf[..len], &transaction).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{server} sent no usable binding response"),
+        )
+    })
+}
+
+fn binding_request(transaction: &[u8; 12]) -> [u8; HEADER_LEN] {
+    let mut msg = [0u8; HEADER_LEN];
+    msg[0..2].copy_from_slice(&BINDING_REQUEST.to_be_bytes());
+    // Message length 0: no attributes
+    msg[4..8].copy_from_slice(&MAGIC_COOKIE.to_be_bytes());
+    msg[8..20].copy_from_slice(transaction);
+    msg
+}
+
+/// Extract the mapped address from a binding success response.
+fn parse_response(msg: &[u8], transaction: &[u8; 12]) -> Option<IpAddr> {
+    if msg.len() < HEADER_LEN
+        || u16::from_be_bytes([msg[0], msg[1]]) != BINDING_SUCCESS
+        || msg[4..8] != MAGIC_COOKIE.to_be_bytes()
+        || msg[8..20] != transaction[..]
+    {
+        return None;
+    }
+    let body_len = u16::from_be_bytes([msg[2], msg[3]]) as usize;
+    let body = msg.get(HEADER_LEN..HEADER_LEN + body_len)?;
+
+    let mut mapped = None;
+    let mut offset = 0;
+    while offset + 4 <= body.len() {
+        let kind = u16::from_be_bytes([body[offset], body[offset + 1]]);
+        let len = u16::from_be_bytes([body[offset + 2], body[offset + 3]]) as usize;
+        let value = body.get(offset + 4..offset + 4 + len)?;
+        match kind {
+            ATTR_XOR_MAPPED_ADDRESS => return decode_address(value, Some(&msg[4..20])),
+            ATTR_MAPPED_ADDRESS => mapped = decode_address(value, None),
+            _ => {}
+        }
+        // Attributes are padded to a multiple of four bytes
+        offset += 4 + len.div_ceil(4) * 4;
+    }
+    mapped
+}
+
+/// Decode a (XOR-)MAPPED-ADDRESS value; `xor_key` is the magic cookie
+/// followed by the transaction ID for the XOR variant.
+fn decode_address(value: &[u8], xor_key: Option<&[u8]>) -> Option<IpAddr> {
+    let family = *value.get(1)?;
+    let len = match family {
+        0x01 => 4,
+        0x02 => 16,
+        _ => return None,
+    };
+    let mut addr = value.get(4..4 + len)?.to_vec();
+    if let Some(key) = xor_key {
+        for (byte, k) in addr.iter_mut().zip(key) {
+            *byte ^= k;
+        }
+    }
+    Some(match family {
+        0x01 => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(addr).ok()?)),
+        _ => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(addr).ok()?)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(transaction: &[u8; 12], attrs: &[(u16, Vec<u8>)]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (kind, value) in attrs {
+            body.extend_from_slice(&kind.to_be_bytes());
+            body.extend_from_slice(&(value.len() as u16).to_be_bytes());
+            body.extend_from_slice(value);
+            body.resize(body.len().div_ceil(4) * 4, 0);
+        }
+        let mut msg = Vec::new();
+        msg.extend_from_slice(&BINDING_SUCCESS.to_be_bytes());
+        msg.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        msg.extend_from_slice(&MAGIC_COOKIE.to_be_bytes());
+        msg.extend_from_slice(transaction);
+        msg.extend_from_slice(&body);
+        msg
+    }
+
+    #[test]
+    fn test_parse_xor_mapped_address() {
+        let transaction = [7u8; 12];
+        // 203.0.113.7 XOR 0x2112A442, port XOR 0x2112
+        let ip = u32::from(Ipv4Addr::new(203, 0, 113, 7)) ^ MAGIC_COOKIE;
+        let mut value = vec![0, 0x01, 0x00, 0x00];
+        value.extend_from_slice(&ip.to_be_bytes());
+        // A software attribute with padding comes first
+        let msg = response(
+            &transaction,
+            &[(0x8022, b"test".to_vec()), (ATTR_XOR_MAPPED_ADDRESS, value)],
+        );
+        assert_eq!(
+            parse_response(&msg, &transaction),
+            Some(IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7)))
+        );
+        // Transaction mismatch
+        assert_eq!(parse_response(&msg, &[8u8; 12]), None);
+    }
+
+    #[test]
+    fn test_parse_mapped_address_v6() {
+        let transaction = [1u8; 12];
+        let ip: Ipv6Addr = "2001:db8::7".parse().unwrap();
+        let mut value = vec![0, 0x02, 0x00, 0x50];
+        value.extend_from_slice(&ip.octets());
+        let msg = response(&transaction, &[(ATTR_MAPPED_ADDRESS, value)]);
+        assert_eq!(parse_response(&msg, &transaction), Some(IpAddr::V6(ip)));
+
+        // Truncated attribute
+        let truncated = &msg[..msg.len() - 4];
+        let mut truncated = truncated.to_vec();
+        truncated[2..4].copy_from_slice(&((msg.len() - 4 - HEADER_LEN) as u16).to_be_bytes());
+        assert_eq!(parse_response(&truncated, &transaction), None);
+    }
+
+    #[test]
+    fn test_binding_request_layout() {
+        let msg = binding_request(&[9u8; 12]);
+        assert_eq!(&msg[0..4], &[0x00, 0x01, 0x00, 0x00]);
+        assert_eq!(&msg[4..8], &[0x21, 0x12, 0xA4, 0x42]);
+        assert_eq!(&msg[8..], &[9u8; 12]);
+    }
+}
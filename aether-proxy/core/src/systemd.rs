@@ -0,0 +1,155 @@
+//! systemd integration: readiness/watchdog notifications and socket
+//! activation.
+//!
+//! Notifications go to `$NOTIFY_SOCKET` (set by systemd for `Type=notify`
+//! units) and are silently skipped elsewhere:
+//!
+//! - `READY=1` once registration is done and tunnels are starting, together
+//!   with `MAINPID=`, so a successor started by a handoff becomes the
+//!   service's main process (needs `NotifyAccess=all`)
+//! - `STOPPING=1` when the node starts draining
+//! - `WATCHDOG=1` every half `WatchdogSec` until the node has stopped
+//!
+//! Sockets passed with socket activation (`LISTEN_FDS`) are used for the
+//! admin and metrics listeners when their `FileDescriptorName=` is `admin`
+//! or `metrics`.  A handoff successor inherits both the sockets and the
+//! watchdog, which systemd addressed to its predecessor's PID.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use tracing::debug;
+
+use crate::lifecycle::{Lifecycle, Phase};
+
+/// First file descriptor passed by socket activation (`SD_LISTEN_FDS_START`).
+#[cfg(unix)]
+const LISTEN_FDS_START: i32 = 3;
+
+/// Send a notification to the service manager, if there is one.
+pub fn notify(message: &str) {
+    #[cfg(unix)]
+    {
+        let Some(path) = std::env::var_os("NOTIFY_SOCKET") else {
+            return;
+        };
+        if let Err(e) = send(&path, message) {
+            debug!(error = %e, "sd_notify failed");
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = message;
+}
+
+#[cfg(unix)]
+fn send(path: &std::ffi::OsStr, message: &str) -> std::io::Result<()> {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::net::UnixDatagram;
+
+    let socket = UnixDatagram::unbound()?;
+    let bytes = path.as_bytes();
+    match bytes.strip_prefix(b"@") {
+        #[cfg(target_os = "linux")]
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(message.as_bytes(), &addr)?;
+        }
+        _ => {
+            socket.send_to(message.as_bytes(), path)?;
+        }
+    }
+    Ok(())
+}
+
+/// Report readiness, claiming the main-process role for this PID.
+pub fn ready() {
+    notify(&format!("MAINPID={}\nREADY=1", std::process::id()));
+}
+
+pub fn stopping() {
+    notify("STOPPING=1");
+}
+
+/// Whether `var` names this process (or the one it took over from).
+fn addressed_to_us(var: &str) -> bool {
+    let Some(pid) = std::env::var(var).ok().and_then(|v| v.parse::<u32>().ok()) else {
+        return false;
+    };
+    pid == std::process::id() || Some(pid) == crate::handoff::predecessor()
+}
+
+/// Watchdog interval requested by systemd for this process.
+fn watchdog_interval() -> Option<Duration> {
+    if std::env::var_os("WATCHDOG_PID").is_some() && !addressed_to_us("WATCHDOG_PID") {
+        return None;
+    }
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    (usec > 0).then(|| Duration::from_micros(usec))
+}
+
+/// Ping the watchdog at half its interval while the node is alive.  The
+/// pings come from the runtime itself, so a wedged runtime gets restarted.
+/// Pinging continues through the drain and ends once the node has stopped.
+pub fn spawn_watchdog(lifecycle: Arc<Lifecycle>) {
+    let Some(interval) = watchdog_interval() else {
+        return;
+    };
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval / 2);
+        loop {
+            ticker.tick().await;
+            if lifecycle.phase() == Phase::Stopped {
+                return;
+            }
+            notify("WATCHDOG=1");
+        }
+    });
+}
+
+/// Take the socket-activated listener named `name`, if systemd passed one.
+pub fn take_listener(name: &str) -> Option<std::net::TcpListener> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::io::FromRawFd;
+
+        if !addressed_to_us("LISTEN_PID") {
+            return None;
+        }
+        let count: i32 = std::env::var("LISTEN_FDS").ok()?.parse().ok()?;
+        let names = std::env::var("LISTEN_FDNAMES").unwrap_or_default();
+        let index = names.split(':').position(|n| n == name)? as i32;
+        if index >= count {
+            return None;
+        }
+        // SAFETY: systemd passes `count` open sockets starting at fd 3; the
+        // admin and metrics listeners each take their own name once, at
+        // startup, so no descriptor gets two owners.
+        let listener = unsafe { std::net::TcpListener::from_raw_fd(LISTEN_FDS_START + index) };
+        Some(listener)
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = name;
+        None
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notify_sends_datagram() {
+        let dir = std::env::temp_dir().join(format!("aether-sd-notify-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("notify.sock");
+        let receiver = std::os::unix::net::UnixDatagram::bind(&path).unwrap();
+
+        send(path.as_os_str(), "READY=1").unwrap();
+        let mut buf = [0u8; 64];
+        let n = receiver.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"READY=1");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,1416 @@
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use ipnet::IpNet;
+use tokio::sync::RwLock;
+
+use crate::dns::{DnsResolver, EchLookup, HostOverrides, LookupError};
+
+/// Check if an IP address belongs to a private/reserved network.
+pub fn is_private_ip(ip: &IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => is_private_ipv4(v4),
+        IpAddr::V6(v6) => is_private_ipv6(v6),
+    }
+}
+
+fn is_private_ipv4(ip: &Ipv4Addr) -> bool {
+    let octets = ip.octets();
+    // 10.0.0.0/8
+    if octets[0] == 10 {
+        return true;
+    }
+    // 172.16.0.0/12
+    if octets[0] == 172 && (16..=31).contains(&octets[1]) {
+        return true;
+    }
+    // 192.168.0.0/16
+    if octets[0] == 192 && octets[1] == 168 {
+        return true;
+    }
+    // 127.0.0.0/8
+    if octets[0] == 127 {
+        return true;
+    }
+    // 169.254.0.0/16 (link-local)
+    if octets[0] == 169 && octets[1] == 254 {
+        return true;
+    }
+    // 0.0.0.0/8
+    if octets[0] == 0 {
+        return true;
+    }
+    // 100.64.0.0/10 (CGNAT / shared address space)
+    if octets[0] == 100 && (64..=127).contains(&octets[1]) {
+        return true;
+    }
+    // 192.0.0.0/24 (IETF protocol assignments)
+    if octets[0] == 192 && octets[1] == 0 && octets[2] == 0 {
+        return true;
+    }
+    // 198.18.0.0/15 (benchmark testing)
+    if octets[0] == 198 && (18..=19).contains(&octets[1]) {
+        return true;
+    }
+    // 240.0.0.0/4 (reserved for future use)
+    if octets[0] >= 240 {
+        return true;
+    }
+    false
+}
+
+fn is_private_ipv6(ip: &Ipv6Addr) -> bool {
+    // ::1 loopback
+    if ip.is_loopback() {
+        return true;
+    }
+    // :: unspecified
+    if ip.is_unspecified() {
+        return true;
+    }
+    let segments = ip.segments();
+    // fc00::/7 (ULA) - first byte is 0xfc or 0xfd
+    if segments[0] & 0xfe00 == 0xfc00 {
+        return true;
+    }
+    // fe80::/10 (link-local)
+    if segments[0] & 0xffc0 == 0xfe80 {
+        return true;
+    }
+    // IPv4-mapped IPv6 (::ffff:x.x.x.x) - check the embedded IPv4
+    if let Some(v4) = ip.to_ipv4_mapped() {
+        return is_private_ipv4(&v4);
+    }
+    false
+}
+
+#[derive(Debug)]
+pub enum FilterError {
+    PrivateIp(IpAddr),
+    PortNotAllowed(u16),
+    DomainNotAllowed(String),
+    DomainBlocked(String),
+    IpNotAllowed(IpAddr),
+    IpBlocked(IpAddr),
+    SelfTarget(String),
+    DnsResolutionFailed(String),
+    NoPublicAddrs(String),
+}
+
+impl std::fmt::Display for FilterError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::PrivateIp(ip) => write!(f, "target IP {} is in private/reserved range", ip),
+            Self::PortNotAllowed(port) => write!(f, "port {} not in allowed list", port),
+            Self::DomainNotAllowed(host) => write!(f, "host {} not in allowed domains", host),
+            Self::DomainBlocked(host) => write!(f, "host {} matches a blocked domain", host),
+            Self::IpNotAllowed(ip) => write!(f, "target IP {} is not in the allowed ranges", ip),
+            Self::IpBlocked(ip) => write!(f, "target IP {} is in a blocked range", ip),
+            Self::SelfTarget(target) => {
+                write!(f, "target {} is this node or its control plane", target)
+            }
+            Self::DnsResolutionFailed(host) => write!(f, "DNS resolution failed for {}", host),
+            Self::NoPublicAddrs(host) => {
+                write!(
+                    f,
+                    "all resolved addresses for {} are private/reserved or belong to this node",
+                    host
+                )
+            }
+        }
+    }
+}
+
+/// Which resolved addresses the node may connect to.
+///
+/// Built at startup. Private/reserved ranges are rejected unless
+/// `allow_private` is set (testing only). The node's own public addresses
+/// and the control plane (by name and by every address it resolves to,
+/// private ones included) are always rejected so a target can't loop back
+/// into the node or reach Aether from the relay side; addresses the node
+/// acquires later (a changed public IP) are added as they appear.
+#[derive(Debug, Default)]
+pub struct AddressPolicy {
+    pub allow_private: bool,
+    blocked_ips: std::sync::RwLock<HashSet<IpAddr>>,
+    blocked_hosts: HashSet<String>,
+    /// Current addresses of each control-plane host, replaced on every
+    /// re-resolution so addresses Aether has moved away from are released.
+    control_plane_ips: std::sync::RwLock<HashMap<String, HashSet<IpAddr>>>,
+}
+
+impl AddressPolicy {
+    pub fn new(allow_private: bool) -> Self {
+        Self {
+            allow_private,
+            ..Self::default()
+        }
+    }
+
+    /// Never allow connections to `ip`, one of the node's own addresses.
+    /// Private addresses are skipped since the range check already covers
+    /// them (and `allow_private` should be able to reopen them for local
+    /// testing).
+    pub fn block_ip(&self, ip: IpAddr) {
+        let ip = ip.to_canonical();
+        if !ip.is_unspecified() && !is_private_ip(&ip) {
+            self.blocked_ips.write().unwrap().insert(ip);
+        }
+    }
+
+    /// Never allow connections to control-plane `host` by name, or by
+    /// address if it is an IP literal, even a private one.
+    pub fn block_host(&mut self, host: &str) {
+        let host = normalize_host(host.trim_start_matches('[').trim_end_matches(']'));
+        match host.parse::<IpAddr>() {
+            Ok(ip) if !ip.is_unspecified() => {
+                self.blocked_ips.write().unwrap().insert(ip.to_canonical());
+            }
+            Ok(_) => {}
+            Err(_) if !host.is_empty() => {
+                self.blocked_hosts.insert(host);
+            }
+            Err(_) => {}
+        }
+    }
+
+    /// Replace the addresses control-plane `host` resolves to.  Unlike the
+    /// node's own addresses, private ones are kept: with `allow_private` a
+    /// control plane on the LAN would otherwise be reachable.
+    pub fn set_control_plane_ips(&self, host: &str, ips: impl IntoIterator<Item = IpAddr>) {
+        let ips: HashSet<IpAddr> = ips
+            .into_iter()
+            .map(|ip| ip.to_canonical())
+            .filter(|ip| !ip.is_unspecified())
+            .collect();
+        self.control_plane_ips
+            .write()
+            .unwrap()
+            .insert(normalize_host(host), ips);
+    }
+
+    fn check_host(&self, host: &str) -> Result<(), FilterError> {
+        if self.blocked_hosts.is_empty() {
+            return Ok(());
+        }
+        let host = normalize_host(host);
+        if self.blocked_hosts.contains(&host) {
+            return Err(FilterError::SelfTarget(host));
+        }
+        Ok(())
+    }
+
+    /// Check a single (resolved or literal) target address.
+    pub fn check_ip(&self, ip: &IpAddr) -> Result<(), FilterError> {
+        let ip = ip.to_canonical();
+        if self.blocked_ips.read().unwrap().contains(&ip)
+            || self
+                .control_plane_ips
+                .read()
+                .unwrap()
+                .values()
+                .any(|ips| ips.contains(&ip))
+        {
+            return Err(FilterError::SelfTarget(ip.to_string()));
+        }
+        if !self.allow_private && is_private_ip(&ip) {
+            return Err(FilterError::PrivateIp(ip));
+        }
+        Ok(())
+    }
+}
+
+/// A single destination domain pattern.
+///
+/// `api.openai.com` matches that host exactly; `*.openai.com` matches any
+/// subdomain of `openai.com` (but not the apex itself).  Matching is
+/// case-insensitive and ignores a trailing dot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DomainPattern {
+    Exact(String),
+    /// Stored with the leading dot (e.g. `.openai.com`).
+    Suffix(String),
+}
+
+impl DomainPattern {
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let normalized = normalize_host(raw.trim());
+        if normalized.is_empty() {
+            return Err("empty domain pattern".to_string());
+        }
+        if let Some(rest) = normalized.strip_prefix("*.") {
+            if rest.is_empty() || rest.contains('*') {
+                return Err(format!("invalid wildcard domain pattern: {}", raw));
+            }
+            return Ok(Self::Suffix(format!(".{}", rest)));
+        }
+        if normalized.contains('*') {
+            return Err(format!(
+                "wildcards are only supported as a leading `*.`: {}",
+                raw
+            ));
+        }
+        Ok(Self::Exact(normalized))
+    }
+
+    /// Match an arbitrary (not yet normalized) host name.
+    pub fn matches_host(&self, host: &str) -> bool {
+        self.matches(&normalize_host(host))
+    }
+
+    /// `host` must already be normalized (see [`normalize_host`]).
+    fn matches(&self, host: &str) -> bool {
+        match self {
+            Self::Exact(name) => host == name,
+            Self::Suffix(suffix) => host.len() > suffix.len() && host.ends_with(suffix.as_str()),
+        }
+    }
+}
+
+impl std::fmt::Display for DomainPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Exact(name) => f.write_str(name),
+            Self::Suffix(suffix) => write!(f, "*{}", suffix),
+        }
+    }
+}
+
+/// Destination domain allow/deny rules.
+///
+/// Deny entries always win.  When the allow list is non-empty, only hosts
+/// matching one of its entries may be relayed; IP-literal targets can never
+/// match a domain entry and are therefore rejected in that case.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DomainRules {
+    allow: Vec<DomainPattern>,
+    deny: Vec<DomainPattern>,
+}
+
+impl DomainRules {
+    pub fn new<S: AsRef<str>>(allow: &[S], deny: &[S]) -> Result<Self, String> {
+        let parse_all = |list: &[S]| -> Result<Vec<DomainPattern>, String> {
+            list.iter()
+                .map(|s| s.as_ref())
+                .filter(|s| !s.trim().is_empty())
+                .map(DomainPattern::parse)
+                .collect()
+        };
+        Ok(Self {
+            allow: parse_all(allow)?,
+            deny: parse_all(deny)?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    /// Render the rules back to their `(allow, deny)` string form.
+    pub fn to_strings(&self) -> (Vec<String>, Vec<String>) {
+        let render = |list: &[DomainPattern]| list.iter().map(|p| p.to_string()).collect();
+        (render(&self.allow), render(&self.deny))
+    }
+
+    /// Check a target host against the rules.
+    pub fn check(&self, host: &str) -> Result<(), FilterError> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        let host = normalize_host(host);
+        if self.deny.iter().any(|p| p.matches(&host)) {
+            return Err(FilterError::DomainBlocked(host));
+        }
+        if !self.allow.is_empty() && !self.allow.iter().any(|p| p.matches(&host)) {
+            return Err(FilterError::DomainNotAllowed(host));
+        }
+        Ok(())
+    }
+}
+
+/// Destination IP range allow/deny rules, evaluated against every resolved
+/// address of a target.
+///
+/// A target passes only if *all* of its addresses pass: the connector may
+/// pick any cached address, so one address in a blocked range (or outside
+/// the allowed ranges) rejects the whole target.  Deny entries win.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CidrRules {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+}
+
+impl CidrRules {
+    /// Parse CIDR strings; a bare address is treated as a single-host range.
+    pub fn new<S: AsRef<str>>(allow: &[S], deny: &[S]) -> Result<Self, String> {
+        let parse_all = |list: &[S]| -> Result<Vec<IpNet>, String> {
+            list.iter()
+                .map(|s| s.as_ref().trim())
+                .filter(|s| !s.is_empty())
+                .map(|s| {
+                    s.parse::<IpNet>()
+                        .or_else(|_| s.parse::<IpAddr>().map(IpNet::from))
+                        .map(|net| net.trunc())
+                        .map_err(|_| format!("invalid CIDR range: {:?}", s))
+                })
+                .collect()
+        };
+        Ok(Self {
+            allow: parse_all(allow)?,
+            deny: parse_all(deny)?,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    /// Render the rules back to their `(allow, deny)` string form.
+    pub fn to_strings(&self) -> (Vec<String>, Vec<String>) {
+        let render = |list: &[IpNet]| list.iter().map(|n| n.to_string()).collect();
+        (render(&self.allow), render(&self.deny))
+    }
+
+    /// Check a single target address against the rules.
+    pub fn check(&self, ip: &IpAddr) -> Result<(), FilterError> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        let ip = ip.to_canonical();
+        if self.deny.iter().any(|n| n.contains(&ip)) {
+            return Err(FilterError::IpBlocked(ip));
+        }
+        if !self.allow.is_empty() && !self.allow.iter().any(|n| n.contains(&ip)) {
+            return Err(FilterError::IpNotAllowed(ip));
+        }
+        Ok(())
+    }
+
+    fn check_all(&self, addrs: &[SocketAddr]) -> Result<(), FilterError> {
+        addrs.iter().try_for_each(|addr| self.check(&addr.ip()))
+    }
+}
+
+/// Target restrictions for one client, checked in addition to the
+/// node-wide rules: a client's policy can only narrow what the node allows.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClientRules {
+    /// `None` leaves ports to the node-wide list.
+    pub allowed_ports: Option<HashSet<u16>>,
+    pub domain_rules: DomainRules,
+    pub cidr_rules: CidrRules,
+}
+
+impl ClientRules {
+    /// Check a target that passed [`validate_target`], with the addresses
+    /// it resolved to.
+    pub fn check(&self, host: &str, port: u16, addrs: &[SocketAddr]) -> Result<(), FilterError> {
+        if self
+            .allowed_ports
+            .as_ref()
+            .is_some_and(|ports| !ports.contains(&port))
+        {
+            return Err(FilterError::PortNotAllowed(port));
+        }
+        self.domain_rules.check(host)?;
+        self.cidr_rules.check_all(addrs)
+    }
+}
+
+fn normalize_host(host: &str) -> String {
+    host.trim_end_matches('.').to_ascii_lowercase()
+}
+
+struct DnsCacheEntry {
+    addrs: Arc<Vec<SocketAddr>>,
+    expires_at: Instant,
+    inserted_at: Instant,
+}
+
+/// Lightweight DNS cache with TTL + capacity bounds.
+/// Stores all public resolved addresses per host (used by the connector's
+/// resolver to ensure it connects to the same validated addresses).
+/// Misses are resolved through the configured [`DnsResolver`].
+///
+/// Entries live for the record TTL reported by the resolver, capped at
+/// `ttl`.  Names that don't exist (NXDOMAIN/NODATA) are remembered for
+/// `negative_ttl` so repeated requests for a dead host don't each cost a
+/// round trip.
+pub struct DnsCache {
+    ttl: Duration,
+    negative_ttl: Duration,
+    capacity: usize,
+    entries: RwLock<HashMap<String, DnsCacheEntry>>,
+    /// Host -> expiry of a cached "does not exist" answer.
+    negative: RwLock<HashMap<String, Instant>>,
+    resolver: DnsResolver,
+    /// Pinned addresses, consulted before the cache and the resolver.
+    overrides: HostOverrides,
+    /// `HTTPS` record lookups, with `--upstream-ech`.
+    ech: Option<EchLookup>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    negative_hits: AtomicU64,
+}
+
+impl DnsCache {
+    pub fn new(
+        ttl: Duration,
+        negative_ttl: Duration,
+        capacity: usize,
+        resolver: DnsResolver,
+    ) -> Self {
+        Self {
+            ttl,
+            negative_ttl,
+            capacity,
+            entries: RwLock::new(HashMap::new()),
+            negative: RwLock::new(HashMap::new()),
+            resolver,
+            overrides: HostOverrides::default(),
+            ech: None,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            negative_hits: AtomicU64::new(0),
+        }
+    }
+
+    /// Answer hosts matching `overrides` with their pinned addresses.
+    pub fn with_host_overrides(mut self, overrides: HostOverrides) -> Self {
+        self.overrides = overrides;
+        self
+    }
+
+    /// Look up ECH configs of HTTPS targets through `ech`.
+    pub fn with_ech(mut self, ech: EchLookup) -> Self {
+        self.ech = Some(ech);
+        self
+    }
+
+    pub fn ech(&self) -> Option<&EchLookup> {
+        self.ech.as_ref()
+    }
+
+    /// Cumulative (hits, misses, negative hits).
+    pub fn stats(&self) -> (u64, u64, u64) {
+        (
+            self.hits.load(Ordering::Relaxed),
+            self.misses.load(Ordering::Relaxed),
+            self.negative_hits.load(Ordering::Relaxed),
+        )
+    }
+
+    /// Hit/miss counters for the heartbeat payload.
+    pub fn stats_json(&self) -> serde_json::Value {
+        let (hits, misses, negative_hits) = self.stats();
+        let lookups = hits + misses + negative_hits;
+        let hit_rate = if lookups > 0 {
+            (hits + negative_hits) as f64 / lookups as f64
+        } else {
+            0.0
+        };
+        serde_json::json!({
+            "hits": hits,
+            "misses": misses,
+            "negative_hits": negative_hits,
+            "hit_rate": hit_rate,
+        })
+    }
+
+    /// Look up cached public addresses for a host (any port).
+    ///
+    /// Used by `SafeDnsResolver` which only knows the hostname — returns the
+    /// first unexpired entry whose key starts with `host:`.
+    pub async fn get_by_host(&self, host: &str) -> Option<Arc<Vec<SocketAddr>>> {
+        if self.capacity == 0 || self.ttl.is_zero() {
+            return None;
+        }
+        let prefix = format!("{}:", host.to_ascii_lowercase());
+        let now = Instant::now();
+        let entries = self.entries.read().await;
+        for (key, entry) in entries.iter() {
+            if key.starts_with(&prefix) && entry.expires_at > now {
+                return Some(Arc::clone(&entry.addrs));
+            }
+        }
+        None
+    }
+
+    /// Look up cached public addresses for a host + port.
+    pub async fn get(&self, host: &str, port: u16) -> Option<Arc<Vec<SocketAddr>>> {
+        if self.capacity == 0 || self.ttl.is_zero() {
+            return None;
+        }
+        let key = Self::key(host, port);
+        let now = Instant::now();
+
+        // Fast path: read lock for cache hit
+        {
+            let entries = self.entries.read().await;
+            match entries.get(&key) {
+                Some(entry) if entry.expires_at > now => {
+                    self.hits.fetch_add(1, Ordering::Relaxed);
+                    return Some(Arc::clone(&entry.addrs));
+                }
+                None => return None,
+                Some(_) => {} // expired, fall through to evict
+            }
+        }
+
+        // Slow path: write lock to remove expired entry
+        let mut entries = self.entries.write().await;
+        entries.remove(&key);
+        None
+    }
+
+    /// Whether `host` is cached as non-existent.
+    async fn is_negative(&self, host: &str) -> bool {
+        if self.capacity == 0 || self.negative_ttl.is_zero() {
+            return false;
+        }
+        let host = host.to_ascii_lowercase();
+        let now = Instant::now();
+        {
+            let negative = self.negative.read().await;
+            match negative.get(&host) {
+                Some(expires_at) if *expires_at > now => {
+                    self.negative_hits.fetch_add(1, Ordering::Relaxed);
+                    return true;
+                }
+                None => return false,
+                Some(_) => {}
+            }
+        }
+        self.negative.write().await.remove(&host);
+        false
+    }
+
+    /// Remember that `host` does not exist, for the zone's negative TTL
+    /// capped at the configured one.
+    async fn insert_negative(&self, host: &str, ttl: Option<Duration>) {
+        let ttl = ttl.map_or(self.negative_ttl, |t| t.min(self.negative_ttl));
+        if self.capacity == 0 || ttl.is_zero() {
+            return;
+        }
+        let now = Instant::now();
+        let mut negative = self.negative.write().await;
+        negative.retain(|_, expires_at| *expires_at > now);
+        if negative.len() >= self.capacity {
+            // Expiry order approximates insertion order closely enough here.
+            if let Some(key) = negative
+                .iter()
+                .min_by_key(|(_, expires_at)| **expires_at)
+                .map(|(key, _)| key.clone())
+            {
+                negative.remove(&key);
+            }
+        }
+        negative.insert(host.to_ascii_lowercase(), now + ttl);
+    }
+
+    /// Insert resolved public addresses into cache.  `ttl` is the record
+    /// TTL reported by the resolver (capped at the configured TTL); `None`
+    /// uses the configured TTL.
+    pub async fn insert(
+        &self,
+        host: &str,
+        port: u16,
+        addrs: Arc<Vec<SocketAddr>>,
+        ttl: Option<Duration>,
+    ) {
+        let ttl = ttl.map_or(self.ttl, |t| t.min(self.ttl));
+        if self.capacity == 0 || ttl.is_zero() || addrs.is_empty() {
+            return;
+        }
+        let key = Self::key(host, port);
+        let now = Instant::now();
+        let mut entries = self.entries.write().await;
+        entries.retain(|_, entry| entry.expires_at > now);
+        while entries.len() >= self.capacity {
+            let oldest_key = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.inserted_at)
+                .map(|(key, _)| key.clone());
+            if let Some(key) = oldest_key {
+                entries.remove(&key);
+            } else {
+                break;
+            }
+        }
+        entries.insert(
+            key,
+            DnsCacheEntry {
+                addrs,
+                expires_at: now + ttl,
+                inserted_at: now,
+            },
+        );
+    }
+
+    fn key(host: &str, port: u16) -> String {
+        format!("{}:{}", host.to_ascii_lowercase(), port)
+    }
+}
+
+/// Resolve a hostname to public (non-private) socket addresses.
+///
+/// Results are cached in `dns_cache`. Addresses rejected by `policy`
+/// (private/reserved ranges, the node's own addresses) are filtered out.
+/// Returns an error if no permitted addresses remain after filtering.
+pub async fn resolve_public_addrs(
+    host: &str,
+    port: u16,
+    policy: &AddressPolicy,
+    dns_cache: &DnsCache,
+) -> Result<Vec<SocketAddr>, FilterError> {
+    // Pinned addresses skip DNS but not the address policy
+    if let Some(ips) = dns_cache.overrides.get(host) {
+        let public: Vec<SocketAddr> = ips
+            .iter()
+            .filter(|ip| policy.check_ip(ip).is_ok())
+            .map(|ip| SocketAddr::new(*ip, port))
+            .collect();
+        if public.is_empty() {
+            return Err(FilterError::NoPublicAddrs(host.to_string()));
+        }
+        return Ok(public);
+    }
+
+    // Cache hit
+    if let Some(addrs) = dns_cache.get(host, port).await {
+        return Ok((*addrs).clone());
+    }
+    if dns_cache.is_negative(host).await {
+        return Err(FilterError::DnsResolutionFailed(host.to_string()));
+    }
+    dns_cache.misses.fetch_add(1, Ordering::Relaxed);
+
+    // Async DNS resolution
+    let resolved = match dns_cache.resolver.lookup(host, port).await {
+        Ok(resolved) => resolved,
+        Err(LookupError::NotFound { negative_ttl }) => {
+            dns_cache.insert_negative(host, negative_ttl).await;
+            return Err(FilterError::DnsResolutionFailed(host.to_string()));
+        }
+        Err(LookupError::Failed(e)) => {
+            tracing::debug!(host, error = %e, "DNS lookup failed");
+            return Err(FilterError::DnsResolutionFailed(host.to_string()));
+        }
+    };
+
+    if resolved.addrs.is_empty() {
+        return Err(FilterError::DnsResolutionFailed(host.to_string()));
+    }
+
+    // Filter out private/reserved and self addresses
+    let public: Vec<SocketAddr> = resolved
+        .addrs
+        .into_iter()
+        .filter(|addr| policy.check_ip(&addr.ip()).is_ok())
+        .collect();
+
+    if public.is_empty() {
+        return Err(FilterError::NoPublicAddrs(host.to_string()));
+    }
+
+    // Cache the validated public addresses
+    let arc_addrs = Arc::new(public);
+    dns_cache
+        .insert(host, port, Arc::clone(&arc_addrs), resolved.ttl)
+        .await;
+    Ok((*arc_addrs).clone())
+}
+
+/// Checks that need no DNS: the port whitelist, domain allow/deny rules and
+/// blocked host names, plus the address rules when `host` is a literal
+/// address (returned as `Some`).
+fn check_unresolved(
+    host: &str,
+    port: u16,
+    allowed_ports: &HashSet<u16>,
+    domain_rules: &DomainRules,
+    cidr_rules: &CidrRules,
+    policy: &AddressPolicy,
+) -> Result<Option<Vec<SocketAddr>>, FilterError> {
+    // Port whitelist check
+    if !allowed_ports.contains(&port) {
+        return Err(FilterError::PortNotAllowed(port));
+    }
+
+    // Domain allow/deny rules (checked before any DNS work)
+    domain_rules.check(host)?;
+    policy.check_host(host)?;
+
+    // Try parsing as IP directly (no DNS needed)
+    let literal = host.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(ip) = literal.parse::<IpAddr>() {
+        policy.check_ip(&ip)?;
+        cidr_rules.check(&ip)?;
+        return Ok(Some(vec![SocketAddr::new(ip, port)]));
+    }
+    Ok(None)
+}
+
+/// Validate that the target host:port is allowed.
+///
+/// Performs port whitelist check, domain allow/deny rules, address policy
+/// filtering and CIDR rules on the resolved IPs, and DNS resolution with
+/// caching. The resolved addresses are stored in the shared DnsCache so that
+/// the connector's resolver can reuse them, eliminating the TOCTTOU gap.
+pub async fn validate_target(
+    host: &str,
+    port: u16,
+    allowed_ports: &HashSet<u16>,
+    domain_rules: &DomainRules,
+    cidr_rules: &CidrRules,
+    policy: &AddressPolicy,
+    dns_cache: &DnsCache,
+) -> Result<Vec<SocketAddr>, FilterError> {
+    if let Some(addrs) =
+        check_unresolved(host, port, allowed_ports, domain_rules, cidr_rules, policy)?
+    {
+        return Ok(addrs);
+    }
+
+    // Resolve and validate DNS (populates cache for the connector's resolver)
+    let addrs = resolve_public_addrs(host, port, policy, dns_cache).await?;
+    cidr_rules.check_all(&addrs)?;
+    Ok(addrs)
+}
+
+/// Validate a target whose name the upstream proxy resolves (`socks5h://`
+/// or an HTTP proxy).  Names that only the proxy can resolve (`.onion`)
+/// must not be looked up locally, so only the port, domain and host-name
+/// checks apply; literal addresses are still checked in full.  The result
+/// is empty for names.
+pub fn validate_proxied_target(
+    host: &str,
+    port: u16,
+    allowed_ports: &HashSet<u16>,
+    domain_rules: &DomainRules,
+    cidr_rules: &CidrRules,
+    policy: &AddressPolicy,
+) -> Result<Vec<SocketAddr>, FilterError> {
+    check_unresolved(host, port, allowed_ports, domain_rules, cidr_rules, policy)
+        .map(Option::unwrap_or_default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ports() -> HashSet<u16> {
+        [80, 443, 8080, 8443].into_iter().collect()
+    }
+
+    fn cache() -> DnsCache {
+        DnsCache::new(
+            Duration::from_secs(60),
+            Duration::from_secs(30),
+            128,
+            DnsResolver::Getaddrinfo,
+        )
+    }
+
+    fn no_rules() -> DomainRules {
+        DomainRules::default()
+    }
+
+    fn no_cidrs() -> CidrRules {
+        CidrRules::default()
+    }
+
+    fn policy() -> AddressPolicy {
+        AddressPolicy::default()
+    }
+
+    #[test]
+    fn test_private_ipv4() {
+        assert!(is_private_ip(&IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))));
+        assert!(is_private_ip(&IpAddr::V4(Ipv4Addr::new(172, 16, 0, 1))));
+        assert!(is_private_ip(&IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1))));
+        assert!(is_private_ip(&IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))));
+        assert!(is_private_ip(&IpAddr::V4(Ipv4Addr::new(169, 254, 1, 1))));
+        assert!(is_private_ip(&IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0))));
+        // CGNAT
+        assert!(is_private_ip(&IpAddr::V4(Ipv4Addr::new(100, 64, 0, 1))));
+        assert!(is_private_ip(&IpAddr::V4(Ipv4Addr::new(
+            100, 127, 255, 254
+        ))));
+        assert!(!is_private_ip(&IpAddr::V4(Ipv4Addr::new(
+            100, 63, 255, 254
+        ))));
+        // Benchmark testing
+        assert!(is_private_ip(&IpAddr::V4(Ipv4Addr::new(198, 18, 0, 1))));
+        // Reserved
+        assert!(is_private_ip(&IpAddr::V4(Ipv4Addr::new(240, 0, 0, 1))));
+        // Public
+        assert!(!is_private_ip(&IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8))));
+        assert!(!is_private_ip(&IpAddr::V4(Ipv4Addr::new(203, 0, 113, 1))));
+    }
+
+    #[test]
+    fn test_private_ipv6() {
+        assert!(is_private_ip(&IpAddr::V6(Ipv6Addr::LOCALHOST)));
+        assert!(is_private_ip(&IpAddr::V6(Ipv6Addr::UNSPECIFIED)));
+        // fc00::1 (ULA)
+        assert!(is_private_ip(&IpAddr::V6(Ipv6Addr::new(
+            0xfc00, 0, 0, 0, 0, 0, 0, 1
+        ))));
+        // fe80::1 (link-local)
+        assert!(is_private_ip(&IpAddr::V6(Ipv6Addr::new(
+            0xfe80, 0, 0, 0, 0, 0, 0, 1
+        ))));
+    }
+
+    #[tokio::test]
+    async fn test_port_not_allowed() {
+        let cache = cache();
+        let result = validate_target(
+            "8.8.8.8",
+            22,
+            &ports(),
+            &no_rules(),
+            &no_cidrs(),
+            &policy(),
+            &cache,
+        )
+        .await;
+        assert!(matches!(result, Err(FilterError::PortNotAllowed(22))));
+    }
+
+    #[tokio::test]
+    async fn test_private_ip_blocked() {
+        let cache = cache();
+        let result = validate_target(
+            "127.0.0.1",
+            80,
+            &ports(),
+            &no_rules(),
+            &no_cidrs(),
+            &policy(),
+            &cache,
+        )
+        .await;
+        assert!(matches!(result, Err(FilterError::PrivateIp(_))));
+    }
+
+    #[tokio::test]
+    async fn test_public_ip_allowed() {
+        let cache = cache();
+        let result = validate_target(
+            "8.8.8.8",
+            443,
+            &ports(),
+            &no_rules(),
+            &no_cidrs(),
+            &policy(),
+            &cache,
+        )
+        .await;
+        assert!(result.is_ok());
+        let addrs = result.unwrap();
+        assert_eq!(addrs.len(), 1);
+        assert_eq!(addrs[0].ip(), IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8)));
+    }
+
+    #[tokio::test]
+    async fn test_self_addresses_blocked() {
+        let cache = cache();
+        let mut policy = AddressPolicy::new(false);
+        policy.block_ip(IpAddr::V4(Ipv4Addr::new(203, 0, 113, 7)));
+        policy.block_host("Aether.Example.com");
+
+        let result = validate_target(
+            "203.0.113.7",
+            443,
+            &ports(),
+            &no_rules(),
+            &no_cidrs(),
+            &policy,
+            &cache,
+        )
+        .await;
+        assert!(matches!(result, Err(FilterError::SelfTarget(_))));
+
+        let mapped = "::ffff:203.0.113.7".parse::<IpAddr>().unwrap();
+        assert!(matches!(
+            policy.check_ip(&mapped),
+            Err(FilterError::SelfTarget(_))
+        ));
+
+        let result = validate_target(
+            "aether.example.com",
+            443,
+            &ports(),
+            &no_rules(),
+            &no_cidrs(),
+            &policy,
+            &cache,
+        )
+        .await;
+        assert!(matches!(result, Err(FilterError::SelfTarget(_))));
+    }
+
+    #[tokio::test]
+    async fn test_control_plane_ips_blocked() {
+        let cache = cache();
+        let policy = AddressPolicy::new(false);
+        let aether = IpAddr::V4(Ipv4Addr::new(198, 51, 100, 20));
+        policy.set_control_plane_ips(
+            "aether.example.com",
+            [aether, IpAddr::V4(Ipv4Addr::LOCALHOST)],
+        );
+
+        let result = validate_target(
+            "198.51.100.20",
+            443,
+            &ports(),
+            &no_rules(),
+            &no_cidrs(),
+            &policy,
+            &cache,
+        )
+        .await;
+        assert!(matches!(result, Err(FilterError::SelfTarget(_))));
+
+        // A re-resolution replaces the previous addresses
+        policy.set_control_plane_ips(
+            "Aether.example.com.",
+            [IpAddr::V4(Ipv4Addr::new(198, 51, 100, 21))],
+        );
+        assert!(policy.check_ip(&aether).is_ok());
+        assert!(matches!(
+            policy.check_ip(&"198.51.100.21".parse().unwrap()),
+            Err(FilterError::SelfTarget(_))
+        ));
+    }
+
+    #[test]
+    fn test_private_control_plane_blocked_with_allow_private() {
+        let mut policy = AddressPolicy::new(true);
+        let aether = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 5));
+        policy.set_control_plane_ips("aether.lan", [aether]);
+        policy.block_host("10.0.0.6");
+
+        assert!(matches!(
+            policy.check_ip(&aether),
+            Err(FilterError::SelfTarget(_))
+        ));
+        assert!(matches!(
+            policy.check_ip(&"10.0.0.6".parse().unwrap()),
+            Err(FilterError::SelfTarget(_))
+        ));
+        // Other LAN addresses stay open
+        assert!(policy.check_ip(&"10.0.0.7".parse().unwrap()).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_allow_private_escape_hatch() {
+        let cache = cache();
+        let policy = AddressPolicy::new(true);
+        let result = validate_target(
+            "127.0.0.1",
+            8080,
+            &ports(),
+            &no_rules(),
+            &no_cidrs(),
+            &policy,
+            &cache,
+        )
+        .await;
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn test_domain_pattern_parse() {
+        assert_eq!(
+            DomainPattern::parse("API.OpenAI.com.").unwrap(),
+            DomainPattern::Exact("api.openai.com".into())
+        );
+        assert_eq!(
+            DomainPattern::parse("*.openai.com").unwrap(),
+            DomainPattern::Suffix(".openai.com".into())
+        );
+        assert!(DomainPattern::parse("").is_err());
+        assert!(DomainPattern::parse("*.").is_err());
+        assert!(DomainPattern::parse("api.*.com").is_err());
+    }
+
+    #[test]
+    fn test_domain_rules_allowlist() {
+        let rules = DomainRules::new(&["api.openai.com", "*.anthropic.com"], &[]).unwrap();
+        assert!(rules.check("api.openai.com").is_ok());
+        assert!(rules.check("API.OPENAI.COM").is_ok());
+        assert!(rules.check("api.anthropic.com").is_ok());
+        assert!(matches!(
+            rules.check("anthropic.com"),
+            Err(FilterError::DomainNotAllowed(_))
+        ));
+        assert!(matches!(
+            rules.check("evil-openai.com"),
+            Err(FilterError::DomainNotAllowed(_))
+        ));
+        assert!(matches!(
+            rules.check("8.8.8.8"),
+            Err(FilterError::DomainNotAllowed(_))
+        ));
+    }
+
+    #[test]
+    fn test_domain_rules_deny_wins() {
+        let rules = DomainRules::new(&["*.example.com"], &["bad.example.com"]).unwrap();
+        assert!(rules.check("good.example.com").is_ok());
+        assert!(matches!(
+            rules.check("bad.example.com"),
+            Err(FilterError::DomainBlocked(_))
+        ));
+
+        let deny_only = DomainRules::new(&[], &["*.tracker.net"]).unwrap();
+        assert!(deny_only.check("api.openai.com").is_ok());
+        assert!(matches!(
+            deny_only.check("a.tracker.net"),
+            Err(FilterError::DomainBlocked(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_domain_rules_checked_before_dns() {
+        let cache = cache();
+        let rules = DomainRules::new(&["api.openai.com"], &[]).unwrap();
+        let result = validate_target(
+            "example.invalid",
+            443,
+            &ports(),
+            &rules,
+            &no_cidrs(),
+            &policy(),
+            &cache,
+        )
+        .await;
+        assert!(matches!(result, Err(FilterError::DomainNotAllowed(_))));
+    }
+
+    #[test]
+    fn test_proxied_target_skips_dns() {
+        let mut policy = AddressPolicy::new(false);
+        policy.block_host("aether.example.com");
+        let check = |host: &str, port: u16| {
+            validate_proxied_target(host, port, &ports(), &no_rules(), &no_cidrs(), &policy)
+        };
+        assert_eq!(check("abc.onion", 443).unwrap(), vec![]);
+        assert!(matches!(
+            check("abc.onion", 22),
+            Err(FilterError::PortNotAllowed(22))
+        ));
+        assert!(matches!(
+            check("aether.example.com", 443),
+            Err(FilterError::SelfTarget(_))
+        ));
+        assert!(matches!(
+            check("127.0.0.1", 443),
+            Err(FilterError::PrivateIp(_))
+        ));
+    }
+
+    #[test]
+    fn test_cidr_rules_parse() {
+        let rules = CidrRules::new(&["104.18.0.0/16", "2606:4700::/32", "1.2.3.4"], &[]).unwrap();
+        let (allow, _) = rules.to_strings();
+        assert_eq!(allow, vec!["104.18.0.0/16", "2606:4700::/32", "1.2.3.4/32"]);
+
+        // Host bits are masked off
+        let rules = CidrRules::new(&["10.1.2.3/8"], &[]).unwrap();
+        assert_eq!(rules.to_strings().0, vec!["10.0.0.0/8"]);
+
+        assert!(CidrRules::new(&["1.2.3.0/33"], &[]).is_err());
+        assert!(CidrRules::new(&[], &["not-a-range"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_cidr_rules() {
+        let cache = cache();
+        let rules = CidrRules::new(&["8.8.0.0/16", "2001:4860::/32"], &["8.8.4.0/24"]).unwrap();
+
+        let ok = validate_target(
+            "8.8.8.8",
+            443,
+            &ports(),
+            &no_rules(),
+            &rules,
+            &policy(),
+            &cache,
+        )
+        .await;
+        assert!(ok.is_ok());
+        let blocked = validate_target(
+            "8.8.4.4",
+            443,
+            &ports(),
+            &no_rules(),
+            &rules,
+            &policy(),
+            &cache,
+        )
+        .await;
+        assert!(matches!(blocked, Err(FilterError::IpBlocked(_))));
+        let outside = validate_target(
+            "1.1.1.1",
+            443,
+            &ports(),
+            &no_rules(),
+            &rules,
+            &policy(),
+            &cache,
+        )
+        .await;
+        assert!(matches!(outside, Err(FilterError::IpNotAllowed(_))));
+
+        // IPv4-mapped IPv6 is matched against the IPv4 ranges
+        let mapped = "::ffff:8.8.4.4".parse::<IpAddr>().unwrap();
+        assert!(matches!(
+            rules.check(&mapped),
+            Err(FilterError::IpBlocked(_))
+        ));
+
+        // Any disallowed address rejects the whole target
+        let mixed = vec![
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8)), 443),
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::new(8, 8, 4, 4)), 443),
+        ];
+        assert!(rules.check_all(&mixed).is_err());
+    }
+
+    #[test]
+    fn test_client_rules_narrow_node_rules() {
+        let addr = [SocketAddr::new(IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8)), 443)];
+        assert!(ClientRules::default()
+            .check("api.example.com", 8080, &addr)
+            .is_ok());
+
+        let rules = ClientRules {
+            allowed_ports: Some([443].into_iter().collect()),
+            domain_rules: DomainRules::new(&["*.example.com"], &[]).unwrap(),
+            cidr_rules: CidrRules::new(&[], &["8.8.0.0/16"]).unwrap(),
+        };
+        assert!(matches!(
+            rules.check("api.example.com", 8080, &[]),
+            Err(FilterError::PortNotAllowed(8080))
+        ));
+        assert!(matches!(
+            rules.check("api.other.com", 443, &[]),
+            Err(FilterError::DomainNotAllowed(_))
+        ));
+        assert!(matches!(
+            rules.check("api.example.com", 443, &addr),
+            Err(FilterError::IpBlocked(_))
+        ));
+        assert!(rules.check("api.example.com", 443, &[]).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_cache_stores_multiple_addrs() {
+        let cache = cache();
+        let addrs = vec![
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)), 443),
+            SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 0, 0, 1)), 443),
+        ];
+        cache
+            .insert("example.com", 443, Arc::new(addrs.clone()), None)
+            .await;
+        let cached = cache.get("example.com", 443).await.unwrap();
+        assert_eq!(*cached, addrs);
+    }
+
+    #[tokio::test]
+    async fn test_cache_key_case_insensitive() {
+        let cache = cache();
+        let addrs = vec![SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)), 443)];
+        cache
+            .insert("Example.COM", 443, Arc::new(addrs.clone()), None)
+            .await;
+        let cached = cache.get("example.com", 443).await.unwrap();
+        assert_eq!(*cached, addrs);
+    }
+
+    #[tokio::test]
+    async fn test_cache_honors_record_ttl() {
+        let cache = cache();
+        let addrs = Arc::new(vec![SocketAddr::new(
+            IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)),
+            443,
+        )]);
+        // A zero record TTL must not be cached at all
+        cache
+            .insert("a.example", 443, Arc::clone(&addrs), Some(Duration::ZERO))
+            .await;
+        assert!(cache.get("a.example", 443).await.is_none());
+
+        cache
+            .insert(
+                "b.example",
+                443,
+                Arc::clone(&addrs),
+                Some(Duration::from_millis(20)),
+            )
+            .await;
+        assert!(cache.get("b.example", 443).await.is_some());
+        tokio::time::sleep(Duration::from_millis(40)).await;
+        assert!(cache.get("b.example", 443).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_negative_cache_and_stats() {
+        let cache = cache();
+        assert!(!cache.is_negative("gone.example").await);
+        cache.insert_negative("Gone.Example", None).await;
+        assert!(cache.is_negative("gone.example").await);
+
+        let err = resolve_public_addrs("gone.example", 443, &AddressPolicy::new(false), &cache)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, FilterError::DnsResolutionFailed(_)));
+
+        let stats = cache.stats_json();
+        assert_eq!(stats["negative_hits"], 2);
+        assert_eq!(stats["misses"], 0);
+
+        // An expired negative entry no longer short-circuits
+        cache
+            .insert_negative("short.example", Some(Duration::from_millis(10)))
+            .await;
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert!(!cache.is_negative("short.example").await);
+    }
+
+    /// A nameserver on a random loopback port: `live.example` has an A
+    /// record with a 1s TTL, `gone.example` does not exist, and the zone's
+    /// negative TTL is 1s.  Returns its address and the queries seen.
+    async fn mock_nameserver() -> (SocketAddr, Arc<std::sync::Mutex<Vec<String>>>) {
+        use hickory_resolver::proto::op::{Message, MessageType, ResponseCode};
+        use hickory_resolver::proto::rr::rdata::{A, SOA};
+        use hickory_resolver::proto::rr::{Name, RData, Record, RecordType};
+
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        let queries = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = Arc::clone(&queries);
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            while let Ok((len, client)) = socket.recv_from(&mut buf).await {
+                let Ok(request) = Message::from_vec(&buf[..len]) else {
+                    continue;
+                };
+                let Some(query) = request.queries().first().cloned() else {
+                    continue;
+                };
+                let name = query.name().to_ascii();
+                seen.lock()
+                    .unwrap()
+                    .push(format!("{} {}", name, query.query_type()));
+                let mut response = Message::new();
+                response
+                    .set_id(request.id())
+                    .set_message_type(MessageType::Response)
+                    .set_recursion_desired(true)
+                    .set_recursion_available(true)
+                    .add_query(query.clone());
+                if name == "live.example." && query.query_type() == RecordType::A {
+                    let a = RData::A(A::new(1, 1, 1, 1));
+                    response.add_answer(Record::from_rdata(query.name().clone(), 1, a));
+                } else {
+                    if name != "live.example." {
+                        response.set_response_code(ResponseCode::NXDomain);
+                    }
+                    let zone = Name::from_ascii("example.").unwrap();
+                    let soa = SOA::new(zone.clone(), zone.clone(), 1, 60, 60, 60, 1);
+                    response.add_name_server(Record::from_rdata(zone, 1, RData::SOA(soa)));
+                }
+                let _ = socket.send_to(&response.to_vec().unwrap(), client).await;
+            }
+        });
+        (addr, queries)
+    }
+
+    #[tokio::test]
+    async fn test_resolver_ttls_expire_cache_entries() {
+        let (server, queries) = mock_nameserver().await;
+        let settings =
+            crate::dns::DnsSettings::parse("plain", &[server.to_string()], None).unwrap();
+        let resolver = DnsResolver::build(&settings).await.unwrap();
+        let cache = DnsCache::new(
+            Duration::from_secs(60),
+            Duration::from_secs(30),
+            128,
+            resolver,
+        );
+        let policy = AddressPolicy::new(false);
+        let lookups = |name: &str| {
+            let query = format!("{name}. A");
+            queries
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|q| **q == query)
+                .count()
+        };
+
+        // The record's 1s TTL wins over the 60s cap
+        let addrs = resolve_public_addrs("live.example", 443, &policy, &cache)
+            .await
+            .unwrap();
+        assert_eq!(addrs, vec!["1.1.1.1:443".parse().unwrap()]);
+        assert!(resolve_public_addrs("live.example", 443, &policy, &cache)
+            .await
+            .is_ok());
+        assert_eq!(lookups("live.example"), 1);
+
+        // NXDOMAIN is cached for the zone's negative TTL
+        for _ in 0..2 {
+            let err = resolve_public_addrs("gone.example", 443, &policy, &cache)
+                .await
+                .unwrap_err();
+            assert!(matches!(err, FilterError::DnsResolutionFailed(_)));
+        }
+        assert_eq!(lookups("gone.example"), 1);
+        assert_eq!(cache.stats().2, 1);
+
+        tokio::time::sleep(Duration::from_millis(1200)).await;
+        assert!(cache.get("live.example", 443).await.is_none());
+        assert!(!cache.is_negative("gone.example").await);
+        assert!(resolve_public_addrs("live.example", 443, &policy, &cache)
+            .await
+            .is_ok());
+        assert!(resolve_public_addrs("gone.example", 443, &policy, &cache)
+            .await
+            .is_err());
+        assert_eq!(lookups("live.example"), 2);
+        assert_eq!(lookups("gone.example"), 2);
+    }
+
+    #[tokio::test]
+    async fn test_host_overrides_skip_dns() {
+        let overrides = HostOverrides::parse(&[
+            "pinned.invalid=1.2.3.4|10.0.0.1",
+            "internal.invalid=10.0.0.2",
+        ])
+        .unwrap();
+        let cache = cache().with_host_overrides(overrides);
+        let policy = AddressPolicy::new(false);
+
+        // .invalid never resolves; the pinned public address is used
+        let addrs = resolve_public_addrs("pinned.invalid", 443, &policy, &cache)
+            .await
+            .unwrap();
+        assert_eq!(addrs, vec!["1.2.3.4:443".parse().unwrap()]);
+        assert_eq!(cache.stats(), (0, 0, 0));
+
+        let err = resolve_public_addrs("internal.invalid", 443, &policy, &cache)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, FilterError::NoPublicAddrs(_)));
+    }
+}
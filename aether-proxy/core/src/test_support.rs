@@ -0,0 +1,738 @@
+//! In-process fakes for end-to-end tests (feature `test-support`).
+//!
+//! [`MockAether`] serves the control-plane API a node talks to (register,
+//! unregister, uploads, the token check used by `aether-proxy check`) and
+//! the WebSocket tunnel endpoint, on a random loopback port.  Every call is
+//! recorded; heartbeats are acknowledged, carrying the remote config set
+//! with [`MockAether::set_remote_config`], and uploads can be refused as
+//! an older Aether does ([`MockAether::reject_uploads`]).  Each tunnel the node opens is
+//! handed out as a [`MockTunnel`] that sends requests through the node the
+//! way Aether does, whole or with a body streamed in pieces.
+//!
+//! [`echo_upstream`] is an origin server that answers every request with
+//! its method, path and body; [`stalled_upstream`] accepts connections and
+//! never reads from them.
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures_util::{SinkExt, StreamExt};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tokio_tungstenite::tungstenite::{http, Message};
+
+use crate::config::{Config, ServerEntry};
+use crate::tunnel::protocol::{decompress_if_gzip, flags, Frame, MsgType};
+
+/// Node id the mock assigns on registration.
+pub const NODE_ID: &str = "mock-node-1";
+const TUNNEL_PATH: &str = "/api/internal/proxy-tunnel";
+/// How long [`MockTunnel::request`], [`MockUpload::error`] and
+/// [`MockAether::tunnel`] wait.
+const WAIT: Duration = Duration::from_secs(30);
+
+#[derive(Default)]
+struct Recorded {
+    registrations: Vec<serde_json::Value>,
+    unregistrations: Vec<String>,
+    heartbeats: Vec<serde_json::Value>,
+    /// `(config_version, remote_config)` sent with every heartbeat ACK.
+    remote_config: Option<(u64, serde_json::Value)>,
+    /// Paths of every upload call (usage, access log, destinations).
+    uploads: Vec<String>,
+    /// Answer uploads with 404, like an Aether that predates them.
+    uploads_unsupported: bool,
+}
+
+struct Shared {
+    token: String,
+    recorded: Mutex<Recorded>,
+    tunnels: mpsc::UnboundedSender<MockTunnel>,
+}
+
+/// A fake Aether control plane.
+pub struct MockAether {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    tunnels: tokio::sync::Mutex<mpsc::UnboundedReceiver<MockTunnel>>,
+    task: JoinHandle<()>,
+}
+
+impl MockAether {
+    /// Start serving on a random loopback port, accepting `token` as the
+    /// management token.  The mock listens on 127.0.0.2: the node blocks
+    /// every control-plane address, and the upstream fakes are on 127.0.0.1.
+    pub async fn start(token: &str) -> Self {
+        let listener = TcpListener::bind("127.0.0.2:0")
+            .await
+            .expect("bind mock Aether");
+        let addr = listener.local_addr().expect("mock Aether address");
+        let (tunnels_tx, tunnels_rx) = mpsc::unbounded_channel();
+        let shared = Arc::new(Shared {
+            token: token.to_string(),
+            recorded: Mutex::default(),
+            tunnels: tunnels_tx,
+        });
+        let accept_shared = Arc::clone(&shared);
+        let task = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve_connection(stream, Arc::clone(&accept_shared)));
+            }
+        });
+        Self {
+            addr,
+            shared,
+            tunnels: tokio::sync::Mutex::new(tunnels_rx),
+            task,
+        }
+    }
+
+    /// Base URL to configure as `--aether-url`.
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// A node config pointing at this mock, with everything that would
+    /// reach the network (IP and region detection) pinned, a single tunnel
+    /// connection, 1s heartbeats and logging off.  `extra` is appended to
+    /// the CLI args.
+    pub fn node_config(&self, extra: &[&str]) -> Config {
+        use clap::Parser;
+
+        let url = self.url();
+        let mut args = vec![
+            "aether-proxy",
+            "--aether-url",
+            &url,
+            "--management-token",
+            &self.shared.token,
+            "--public-ip",
+            "203.0.113.10",
+            "--node-region",
+            "test",
+            "--tunnel-connections",
+            "1",
+            "--heartbeat-interval",
+            "1",
+            "--log-level",
+            "off",
+        ];
+        args.extend_from_slice(extra);
+        Config::try_parse_from(args).expect("mock node config")
+    }
+
+    /// The server list for a config from [`Self::node_config`].
+    pub fn servers(config: &Config) -> Vec<ServerEntry> {
+        vec![ServerEntry {
+            aether_url: config.aether_url.clone(),
+            management_token: config.management_token.clone(),
+            node_name: None,
+        }]
+    }
+
+    /// Push `remote_config` with every heartbeat ACK from now on.
+    pub fn set_remote_config(&self, version: u64, remote_config: serde_json::Value) {
+        self.recorded().remote_config = Some((version, remote_config));
+    }
+
+    /// Bodies of all register calls so far.
+    pub fn registrations(&self) -> Vec<serde_json::Value> {
+        self.recorded().registrations.clone()
+    }
+
+    /// Node ids of all unregister calls so far.
+    pub fn unregistrations(&self) -> Vec<String> {
+        self.recorded().unregistrations.clone()
+    }
+
+    /// Answer upload calls with 404 from now on, as an older Aether does.
+    pub fn reject_uploads(&self) {
+        self.recorded().uploads_unsupported = true;
+    }
+
+    /// Paths of all upload calls so far, answered or not.
+    pub fn uploads(&self) -> Vec<String> {
+        self.recorded().uploads.clone()
+    }
+
+    /// Payloads of all heartbeats received so far.
+    pub fn heartbeats(&self) -> Vec<serde_json::Value> {
+        self.recorded().heartbeats.clone()
+    }
+
+    /// Wait for the node's next tunnel connection.
+    pub async fn tunnel(&self) -> MockTunnel {
+        let mut tunnels = self.tunnels.lock().await;
+        tokio::time::timeout(WAIT, tunnels.recv())
+            .await
+            .expect("timed out waiting for a tunnel")
+            .expect("mock Aether stopped")
+    }
+
+    /// Wait until at least `count` heartbeats have arrived.
+    pub async fn wait_for_heartbeats(&self, count: usize) {
+        tokio::time::timeout(WAIT, async {
+            while self.recorded().heartbeats.len() < count {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .expect("timed out waiting for heartbeats");
+    }
+
+    fn recorded(&self) -> std::sync::MutexGuard<'_, Recorded> {
+        self.shared.recorded.lock().unwrap()
+    }
+}
+
+impl Drop for MockAether {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Response to a request sent through a [`MockTunnel`].
+#[derive(Debug)]
+pub struct MockResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Bytes,
+}
+
+impl MockResponse {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+type StreamMap = Arc<Mutex<HashMap<u32, mpsc::UnboundedSender<Frame>>>>;
+
+/// One tunnel connection from the node, seen from Aether's side.
+pub struct MockTunnel {
+    /// Headers of the WebSocket upgrade request (lowercase names).
+    pub headers: HashMap<String, String>,
+    frames: mpsc::UnboundedSender<Frame>,
+    streams: StreamMap,
+    next_stream_id: u32,
+}
+
+impl MockTunnel {
+    /// Send a request through the node.  `Err` carries the reason of a
+    /// `STREAM_ERROR` (e.g. a blocked target).
+    pub async fn request(
+        &mut self,
+        method: &str,
+        url: &str,
+        headers: &[(&str, &str)],
+        body: &[u8],
+    ) -> Result<MockResponse, String> {
+        let (stream_id, mut rx) = self.open(method, url, headers)?;
+        let sent = self.frames.send(Frame::new(
+            stream_id,
+            MsgType::RequestBody,
+            flags::END_STREAM,
+            body.to_vec(),
+        ));
+        if sent.is_err() {
+            return Err("tunnel closed".to_string());
+        }
+
+        let result = tokio::time::timeout(WAIT, async {
+            let mut status = None;
+            let mut response_headers = Vec::new();
+            let mut response_body = Vec::new();
+            while let Some(frame) = rx.recv().await {
+                let payload = decompress_if_gzip(&frame).map_err(|e| e.to_string())?;
+                match frame.msg_type {
+                    MsgType::ResponseHeaders => {
+                        #[derive(serde::Deserialize)]
+                        struct Meta {
+                            status: u16,
+                            headers: Vec<(String, String)>,
+                        }
+                        let meta: Meta =
+                            serde_json::from_slice(&payload).map_err(|e| e.to_string())?;
+                        status = Some(meta.status);
+                        response_headers = meta.headers;
+                    }
+                    MsgType::ResponseBody => response_body.extend_from_slice(&payload),
+                    MsgType::StreamEnd => break,
+                    MsgType::StreamError => {
+                        return Err(String::from_utf8_lossy(&payload).into_owned())
+                    }
+                    _ => {}
+                }
+                if frame.is_end_stream() && frame.msg_type != MsgType::ResponseHeaders {
+                    break;
+                }
+            }
+            Ok(MockResponse {
+                status: status.ok_or("stream ended without response headers")?,
+                headers: response_headers,
+                body: Bytes::from(response_body),
+            })
+        })
+        .await
+        .unwrap_or_else(|_| Err("timed out waiting for the response".to_string()));
+        self.streams.lock().unwrap().remove(&stream_id);
+        result
+    }
+
+    /// Start a request whose body is then sent piece by piece through the
+    /// returned [`MockUpload`].
+    pub fn upload(
+        &mut self,
+        method: &str,
+        url: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<MockUpload, String> {
+        let (stream_id, replies) = self.open(method, url, headers)?;
+        Ok(MockUpload {
+            stream_id,
+            frames: self.frames.clone(),
+            replies,
+        })
+    }
+
+    /// Send the headers of a new stream; frames the node sends back on it
+    /// arrive on the returned receiver.
+    fn open(
+        &mut self,
+        method: &str,
+        url: &str,
+        headers: &[(&str, &str)],
+    ) -> Result<(u32, mpsc::UnboundedReceiver<Frame>), String> {
+        let stream_id = self.next_stream_id;
+        self.next_stream_id += 1;
+        let (tx, rx) = mpsc::unbounded_channel();
+        self.streams.lock().unwrap().insert(stream_id, tx);
+
+        let meta = serde_json::json!({
+            "method": method,
+            "url": url,
+            "headers": headers
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<HashMap<_, _>>(),
+            "timeout": WAIT.as_secs(),
+        });
+        self.frames
+            .send(Frame::new(
+                stream_id,
+                MsgType::RequestHeaders,
+                0,
+                serde_json::to_vec(&meta).unwrap(),
+            ))
+            .map_err(|_| "tunnel closed".to_string())?;
+        Ok((stream_id, rx))
+    }
+}
+
+/// A request started with [`MockTunnel::upload`] whose body is still open.
+pub struct MockUpload {
+    stream_id: u32,
+    frames: mpsc::UnboundedSender<Frame>,
+    replies: mpsc::UnboundedReceiver<Frame>,
+}
+
+impl MockUpload {
+    /// Send the next piece of the body without ending it.
+    pub fn send(&self, data: &[u8]) -> Result<(), String> {
+        self.frames
+            .send(Frame::new(
+                self.stream_id,
+                MsgType::RequestBody,
+                0,
+                data.to_vec(),
+            ))
+            .map_err(|_| "tunnel closed".to_string())
+    }
+
+    /// Wait for the node to fail the stream; the reason of its
+    /// `STREAM_ERROR`, or `None` when none arrives.
+    pub async fn error(&mut self) -> Option<String> {
+        tokio::time::timeout(WAIT, async {
+            while let Some(frame) = self.replies.recv().await {
+                if frame.msg_type == MsgType::StreamError {
+                    return Some(String::from_utf8_lossy(&frame.payload).into_owned());
+                }
+            }
+            None
+        })
+        .await
+        .ok()
+        .flatten()
+    }
+}
+
+async fn serve_connection(stream: TcpStream, shared: Arc<Shared>) {
+    if peek_path(&stream).await.as_deref() == Some(TUNNEL_PATH) {
+        serve_tunnel(stream, shared).await;
+    } else {
+        serve_api(stream, &shared).await;
+    }
+}
+
+/// Path of the request on `stream`, without consuming it.
+async fn peek_path(stream: &TcpStream) -> Option<String> {
+    let mut buf = [0u8; 512];
+    for _ in 0..200 {
+        let n = stream.peek(&mut buf).await.ok()?;
+        if n == 0 {
+            return None;
+        }
+        if let Some(end) = buf[..n].windows(2).position(|w| w == b"\r\n") {
+            let line = std::str::from_utf8(&buf[..end]).ok()?;
+            let target = line.split(' ').nth(1)?;
+            return Some(target.split('?').next()?.to_string());
+        }
+        if n == buf.len() {
+            return None;
+        }
+        tokio::time::sleep(Duration::from_millis(5)).await;
+    }
+    None
+}
+
+// The handshake callback's error type is tungstenite's
+#[allow(clippy::result_large_err)]
+async fn serve_tunnel(stream: TcpStream, shared: Arc<Shared>) {
+    let expected = format!("Bearer {}", shared.token);
+    let mut upgrade_headers = HashMap::new();
+    let accepted =
+        tokio_tungstenite::accept_hdr_async(stream, |request: &Request, response: Response| {
+            upgrade_headers = request
+                .headers()
+                .iter()
+                .map(|(k, v)| {
+                    let value = v.to_str().unwrap_or_default().to_string();
+                    (k.as_str().to_string(), value)
+                })
+                .collect();
+            if upgrade_headers.get("authorization") != Some(&expected) {
+                let rejection: ErrorResponse = http::Response::builder()
+                    .status(http::StatusCode::UNAUTHORIZED)
+                    .body(Some("invalid management token".to_string()))
+                    .unwrap();
+                return Err(rejection);
+            }
+            Ok(response)
+        })
+        .await;
+    let Ok(ws) = accepted else {
+        return;
+    };
+    let (mut sink, mut source) = ws.split();
+    let (frames_tx, mut frames_rx) = mpsc::unbounded_channel::<Frame>();
+    let streams = StreamMap::default();
+    let _ = shared.tunnels.send(MockTunnel {
+        headers: upgrade_headers,
+        frames: frames_tx.clone(),
+        streams: Arc::clone(&streams),
+        next_stream_id: 1,
+    });
+
+    let writer = tokio::spawn(async move {
+        while let Some(frame) = frames_rx.recv().await {
+            if sink
+                .send(Message::Binary(frame.encode().to_vec()))
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+    });
+    while let Some(Ok(message)) = source.next().await {
+        let Message::Binary(data) = message else {
+            continue;
+        };
+        let Ok(frame) = Frame::decode(Bytes::from(data)) else {
+            continue;
+        };
+        match frame.msg_type {
+            MsgType::HeartbeatData => {
+                let heartbeat: serde_json::Value =
+                    serde_json::from_slice(&frame.payload).unwrap_or_default();
+                let mut ack = serde_json::json!({ "heartbeat_id": heartbeat["heartbeat_id"] });
+                {
+                    let mut recorded = shared.recorded.lock().unwrap();
+                    if let Some((version, config)) = &recorded.remote_config {
+                        ack["config_version"] = (*version).into();
+                        ack["remote_config"] = config.clone();
+                    }
+                    recorded.heartbeats.push(heartbeat);
+                }
+                let _ = frames_tx.send(Frame::control(
+                    MsgType::HeartbeatAck,
+                    serde_json::to_vec(&ack).unwrap(),
+                ));
+            }
+            MsgType::Ping => {
+                let _ = frames_tx.send(Frame::control(MsgType::Pong, frame.payload));
+            }
+            _ => {
+                let stream = streams.lock().unwrap().get(&frame.stream_id).cloned();
+                if let Some(stream) = stream {
+                    let _ = stream.send(frame);
+                }
+            }
+        }
+    }
+    writer.abort();
+}
+
+async fn serve_api(mut stream: TcpStream, shared: &Shared) {
+    let Some(request) = read_request(&mut stream).await else {
+        return;
+    };
+    let authorized = request.headers.get("authorization").map(String::as_str)
+        == Some(&format!("Bearer {}", shared.token));
+    let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap_or_default();
+    let (status, reply) = if !authorized {
+        (
+            "401 Unauthorized",
+            serde_json::json!({ "detail": "invalid token" }),
+        )
+    } else {
+        let mut recorded = shared.recorded.lock().unwrap();
+        match (request.method.as_str(), request.path.as_str()) {
+            ("POST", "/api/admin/proxy-nodes/register") => {
+                recorded.registrations.push(body);
+                ("200 OK", serde_json::json!({ "node_id": NODE_ID }))
+            }
+            ("POST", "/api/admin/proxy-nodes/unregister") => {
+                let node_id = body["node_id"].as_str().unwrap_or_default();
+                recorded.unregistrations.push(node_id.to_string());
+                ("200 OK", serde_json::json!({}))
+            }
+            (
+                "POST",
+                path @ ("/api/admin/proxy-nodes/access-log"
+                | "/api/admin/proxy-nodes/usage"
+                | "/api/admin/proxy-nodes/destinations"),
+            ) => {
+                recorded.uploads.push(path.to_string());
+                if recorded.uploads_unsupported {
+                    (
+                        "404 Not Found",
+                        serde_json::json!({ "detail": "not found" }),
+                    )
+                } else {
+                    ("200 OK", serde_json::json!({}))
+                }
+            }
+            ("GET", "/api/admin/proxy-nodes") => ("200 OK", serde_json::json!({ "items": [] })),
+            _ => (
+                "404 Not Found",
+                serde_json::json!({ "detail": "not found" }),
+            ),
+        }
+    };
+    write_response(
+        &mut stream,
+        status,
+        "application/json",
+        reply.to_string().as_bytes(),
+    )
+    .await;
+}
+
+/// Start a SOCKS5 proxy (no authentication, IPv4 and domain targets) on a
+/// random loopback port; the counter is the number of tunnels it opened.
+pub async fn socks5_proxy() -> (SocketAddr, Arc<AtomicUsize>) {
+    socks5_proxy_with_hosts(&[]).await
+}
+
+/// Like [`socks5_proxy`], but the proxy resolves the names in `hosts`
+/// itself (as Tor does for `.onion`), so they need not resolve locally.
+pub async fn socks5_proxy_with_hosts(
+    hosts: &[(&str, SocketAddr)],
+) -> (SocketAddr, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind SOCKS5 proxy");
+    let addr = listener.local_addr().expect("SOCKS5 proxy address");
+    let hosts: Arc<HashMap<String, SocketAddr>> = Arc::new(
+        hosts
+            .iter()
+            .map(|(name, addr)| (name.to_string(), *addr))
+            .collect(),
+    );
+    let tunnels = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&tunnels);
+    tokio::spawn(async move {
+        while let Ok((mut client, _)) = listener.accept().await {
+            let counter = Arc::clone(&counter);
+            let hosts = Arc::clone(&hosts);
+            tokio::spawn(async move {
+                let Some(mut target) = socks5_accept(&mut client, &hosts).await else {
+                    return;
+                };
+                counter.fetch_add(1, Ordering::SeqCst);
+                let _ = tokio::io::copy_bidirectional(&mut client, &mut target).await;
+            });
+        }
+    });
+    (addr, tunnels)
+}
+
+async fn socks5_accept(
+    client: &mut TcpStream,
+    hosts: &HashMap<String, SocketAddr>,
+) -> Option<TcpStream> {
+    let mut head = [0u8; 2];
+    client.read_exact(&mut head).await.ok()?;
+    let mut methods = vec![0u8; head[1] as usize];
+    client.read_exact(&mut methods).await.ok()?;
+    client.write_all(&[5, 0]).await.ok()?;
+
+    let mut request = [0u8; 4];
+    client.read_exact(&mut request).await.ok()?;
+    let host = match request[3] {
+        1 => {
+            let mut ip = [0u8; 4];
+            client.read_exact(&mut ip).await.ok()?;
+            std::net::Ipv4Addr::from(ip).to_string()
+        }
+        3 => {
+            let mut len = [0u8; 1];
+            client.read_exact(&mut len).await.ok()?;
+            let mut name = vec![0u8; len[0] as usize];
+            client.read_exact(&mut name).await.ok()?;
+            String::from_utf8(name).ok()?
+        }
+        _ => return None,
+    };
+    let mut port = [0u8; 2];
+    client.read_exact(&mut port).await.ok()?;
+    let target = match hosts.get(&host) {
+        Some(addr) => TcpStream::connect(addr).await.ok()?,
+        None => TcpStream::connect((host.as_str(), u16::from_be_bytes(port)))
+            .await
+            .ok()?,
+    };
+    client
+        .write_all(&[5, 0, 0, 1, 0, 0, 0, 0, 0, 0])
+        .await
+        .ok()?;
+    Some(target)
+}
+
+/// Start an origin server on a random loopback port that answers every
+/// request with `200` and a body of `<method> <path>\n<request body>`.
+pub async fn echo_upstream() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind echo upstream");
+    let addr = listener.local_addr().expect("echo upstream address");
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let Some(request) = read_request(&mut stream).await else {
+                    return;
+                };
+                let mut body = format!("{} {}\n", request.method, request.path).into_bytes();
+                body.extend_from_slice(&request.body);
+                write_response(&mut stream, "200 OK", "text/plain", &body).await;
+            });
+        }
+    });
+    addr
+}
+
+/// Start an origin server on a random loopback port that accepts
+/// connections and never reads from them, like a target that stops taking
+/// an upload.
+pub async fn stalled_upstream() -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("bind stalled upstream");
+    let addr = listener.local_addr().expect("stalled upstream address");
+    tokio::spawn(async move {
+        let mut held = Vec::new();
+        while let Ok((stream, _)) = listener.accept().await {
+            held.push(stream);
+        }
+    });
+    addr
+}
+
+struct HttpRequest {
+    method: String,
+    path: String,
+    /// Lowercase names.
+    headers: HashMap<String, String>,
+    body: Vec<u8>,
+}
+
+/// Read one HTTP/1.1 request with a `Content-Length` body (or none).
+async fn read_request(stream: &mut TcpStream) -> Option<HttpRequest> {
+    let mut buf = Vec::new();
+    let head_end = loop {
+        if let Some(pos) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos;
+        }
+        if buf.len() > 64 * 1024 {
+            return None;
+        }
+        let mut chunk = [0u8; 4096];
+        let n = stream.read(&mut chunk).await.ok()?;
+        if n == 0 {
+            return None;
+        }
+        buf.extend_from_slice(&chunk[..n]);
+    };
+    let head = std::str::from_utf8(&buf[..head_end]).ok()?;
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next()?.split(' ');
+    let method = request_line.next()?.to_string();
+    let target = request_line.next()?;
+    let path = target.split('?').next()?.to_string();
+    let headers: HashMap<String, String> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(k, v)| (k.trim().to_ascii_lowercase(), v.trim().to_string()))
+        .collect();
+    let length: usize = headers
+        .get("content-length")
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(0);
+    let mut body = buf[head_end + 4..].to_vec();
+    while body.len() < length {
+        let mut chunk = [0u8; 4096];
+        let n = stream.read(&mut chunk).await.ok()?;
+        if n == 0 {
+            return None;
+        }
+        body.extend_from_slice(&chunk[..n]);
+    }
+    body.truncate(length);
+    Some(HttpRequest {
+        method,
+        path,
+        headers,
+        body,
+    })
+}
+
+async fn write_response(stream: &mut TcpStream, status: &str, content_type: &str, body: &[u8]) {
+    let head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    let _ = stream.write_all(head.as_bytes()).await;
+    let _ = stream.write_all(body).await;
+    let _ = stream.shutdown().await;
+}
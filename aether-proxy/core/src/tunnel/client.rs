@@ -1,17 +1,27 @@
 //! WebSocket tunnel client: connect, authenticate, and run the tunnel.
 
+use std::io;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use tokio::net::TcpStream;
 use tokio::sync::watch;
 use tokio_tungstenite::tungstenite::client::IntoClientRequest;
 use tokio_tungstenite::tungstenite::http;
 use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+use tokio_tungstenite::tungstenite::Error as WsError;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream};
+use tower_service::Service;
 use tracing::{debug, info, warn};
 
+use crate::registration::endpoint::Endpoint;
 use crate::state::{AppState, ServerContext};
+use crate::upstream_client;
+use crate::upstream_proxy::UpstreamProxy;
 
+use super::interference::{self, InterferenceMonitor, Signal};
 use super::{dispatcher, heartbeat, writer};
 
 /// Outcome of a tunnel session.
@@ -32,7 +42,8 @@ pub async fn connect_and_run(
     conn_idx: usize,
     shutdown: &mut watch::Receiver<bool>,
 ) -> Result<TunnelOutcome, anyhow::Error> {
-    let ws_url = build_tunnel_url(server);
+    let (endpoint_index, endpoint) = server.aether_client.endpoint();
+    let ws_url = build_tunnel_url(&endpoint.base_url);
     info!(url = %ws_url, conn = conn_idx, "connecting tunnel");
 
     // Build WebSocket request with auth headers
@@ -58,61 +69,28 @@ pub async fn connect_and_run(
     let max_streams = state.config.tunnel_max_streams.unwrap_or(128);
     headers.insert("X-Tunnel-Max-Streams", http::HeaderValue::from(max_streams));
 
-    // Parse host:port from URL
-    let uri: http::Uri = ws_url.parse()?;
-    let host = uri
-        .host()
-        .ok_or_else(|| anyhow::anyhow!("missing host in tunnel URL"))?;
-    let is_tls = uri.scheme_str() == Some("wss");
-    let port = uri.port_u16().unwrap_or(if is_tls { 443 } else { 80 });
+    let is_tls = ws_url.starts_with("wss://");
 
-    // TCP connect with timeout
-    let connect_timeout = Duration::from_secs(state.config.tunnel_connect_timeout_secs);
-    let tcp_stream = tokio::time::timeout(connect_timeout, TcpStream::connect((host, port)))
-        .await
-        .map_err(|_| {
-            anyhow::anyhow!(
-                "tunnel TCP connect timeout ({}s)",
-                connect_timeout.as_secs()
-            )
-        })??;
-
-    // Configure TCP parameters via socket2
-    configure_tcp_socket(&tcp_stream, state);
-
-    // WebSocket upgrade (with TLS if wss://)
-    let connector = if is_tls {
-        Some(tokio_tungstenite::Connector::Rustls(Arc::clone(
-            &state.tunnel_tls_config,
-        )))
-    } else {
-        None
-    };
-    // Match Python-side _MAX_FRAME_SIZE (64 MiB) to prevent tungstenite's
-    // default 16 MiB limit from rejecting large AI API payloads (multi-image
-    // base64 requests can exceed 16 MiB).
-    let ws_config = WebSocketConfig {
-        max_frame_size: Some(64 << 20),
-        max_message_size: Some(64 << 20),
-        ..Default::default()
-    };
-    let handshake_timeout = Duration::from_secs(state.config.tunnel_connect_timeout_secs);
-    let (ws_stream, _response) = tokio::time::timeout(
-        handshake_timeout,
-        tokio_tungstenite::client_async_tls_with_config(
-            request,
-            tcp_stream,
-            Some(ws_config),
-            connector,
-        ),
+    // A control-plane URL that cannot be connected to or upgraded is given
+    // up in favour of the next one (when several are configured).
+    let opened = open_websocket(
+        state,
+        endpoint,
+        server.aether_client.proxy(),
+        request,
+        is_tls,
+        &server.interference,
     )
-    .await
-    .map_err(|_| {
-        anyhow::anyhow!(
-            "tunnel WebSocket handshake timeout ({}s)",
-            handshake_timeout.as_secs()
-        )
-    })??;
+    .await;
+    let (ws_stream, response) = match opened {
+        Ok(opened) => opened,
+        Err(e) => {
+            server.aether_client.fail_over(endpoint_index);
+            return Err(e);
+        }
+    };
+    crate::clock::observe_date(response.headers().get("date").and_then(|v| v.to_str().ok()));
+    server.control_plane.on_contact();
     info!(
         conn = conn_idx,
         tcp_keepalive_secs = state.config.tunnel_tcp_keepalive_secs,
@@ -121,6 +99,8 @@ pub async fn connect_and_run(
         stale_timeout_secs = state.config.tunnel_stale_timeout_secs,
         "tunnel connected"
     );
+    let _connected = ConnectedGuard::new(&server.connected_tunnels);
+    let connected_at = Instant::now();
 
     // NOTE: reconnect_attempts reset is handled by the caller (mod.rs)
     // based on how long the connection stayed alive.
@@ -136,7 +116,7 @@ pub async fn connect_and_run(
     // resetting shared atomic metrics via swap(0))
     let hb_handle = if conn_idx == 0 {
         heartbeat::spawn(
-            Arc::clone(&state.config),
+            Arc::clone(state),
             Arc::clone(server),
             frame_tx.clone(),
             shutdown.clone(),
@@ -156,7 +136,13 @@ pub async fn connect_and_run(
         result = dispatcher::run(state_clone, server_clone, ws_read, frame_tx.clone(), hb_handle) => {
             match result {
                 Ok(()) => TunnelOutcome::Disconnected,
-                Err(e) => return Err(e),
+                Err(e) => {
+                    let reset = e.downcast_ref::<WsError>().is_some_and(interference::is_reset);
+                    if reset && connected_at.elapsed() < interference::EARLY_DROP_WITHIN {
+                        server.interference.record(Signal::EarlyDrop);
+                    }
+                    return Err(e);
+                }
             }
         }
         writer_result = &mut writer_handle => {
@@ -194,39 +180,159 @@ pub async fn connect_and_run(
     Ok(outcome)
 }
 
-/// Configure TCP keepalive and NODELAY on an established socket.
+/// Counts the tunnel as established for as long as it is alive.
+struct ConnectedGuard<'a>(&'a AtomicU64);
+
+impl<'a> ConnectedGuard<'a> {
+    fn new(count: &'a AtomicU64) -> Self {
+        count.fetch_add(1, Ordering::AcqRel);
+        Self(count)
+    }
+}
+
+impl Drop for ConnectedGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Configure TCP keepalive, NODELAY and buffer sizes on an established
+/// socket.
 fn configure_tcp_socket(stream: &TcpStream, state: &Arc<AppState>) {
     let sock_ref = socket2::SockRef::from(stream);
+    let config = &state.config;
 
-    if state.config.tunnel_tcp_keepalive_secs > 0 {
+    if config.tunnel_tcp_keepalive_secs > 0 {
+        let interval = config.tcp_keepalive_interval_secs.unwrap_or(5);
         let keepalive = socket2::TcpKeepalive::new()
-            .with_time(Duration::from_secs(state.config.tunnel_tcp_keepalive_secs))
-            .with_interval(Duration::from_secs(5));
+            .with_time(Duration::from_secs(config.tunnel_tcp_keepalive_secs))
+            .with_interval(Duration::from_secs(interval));
         #[cfg(not(target_os = "windows"))]
-        let keepalive = keepalive.with_retries(3);
+        let keepalive = keepalive.with_retries(config.tcp_keepalive_probes.unwrap_or(3));
         if let Err(e) = sock_ref.set_tcp_keepalive(&keepalive) {
             warn!(error = %e, "failed to set TCP keepalive on tunnel socket");
         }
     }
 
-    if state.config.tunnel_tcp_nodelay {
+    if config.tunnel_tcp_nodelay {
         if let Err(e) = sock_ref.set_nodelay(true) {
             warn!(error = %e, "failed to set TCP_NODELAY on tunnel socket");
         }
     }
+
+    if let Some(size) = config.tcp_recv_buffer {
+        if let Err(e) = sock_ref.set_recv_buffer_size(size) {
+            warn!(error = %e, "failed to set SO_RCVBUF on tunnel socket");
+        }
+    }
+    if let Some(size) = config.tcp_send_buffer {
+        if let Err(e) = sock_ref.set_send_buffer_size(size) {
+            warn!(error = %e, "failed to set SO_SNDBUF on tunnel socket");
+        }
+    }
 }
 
-/// Build rustls ClientConfig with system root certificates.
-pub fn build_tls_config() -> rustls::ClientConfig {
-    let root_store =
-        rustls::RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
-    rustls::ClientConfig::builder()
-        .with_root_certificates(root_store)
-        .with_no_client_auth()
+/// TCP connect (preferring the elected address of `endpoint`, or through
+/// `proxy`) and WebSocket upgrade, with TLS for `wss://`.  Handshakes that
+/// are reset or never finish on a connected socket are reported to
+/// `interference`.
+async fn open_websocket(
+    state: &Arc<AppState>,
+    endpoint: &Endpoint,
+    proxy: Option<&UpstreamProxy>,
+    request: http::Request<()>,
+    is_tls: bool,
+    interference: &InterferenceMonitor,
+) -> Result<
+    (
+        WebSocketStream<MaybeTlsStream<TcpStream>>,
+        http::Response<Option<Vec<u8>>>,
+    ),
+    anyhow::Error,
+> {
+    // TCP connect with timeout
+    let connect_timeout = Duration::from_secs(state.config.tunnel_connect_timeout_secs);
+    let connect = async {
+        let Some(proxy) = proxy else {
+            return endpoint.selector.connect().await;
+        };
+        let (host, port) = endpoint.selector.authority();
+        // Reached like the upstream proxies, with the egress binding and
+        // socket options applied.
+        let mut stream = upstream_client::proxy_connector(&state.config, &state.egress)
+            .call(proxy.uri())
+            .await
+            .map_err(io::Error::other)?
+            .into_inner();
+        let target = if proxy.resolves_locally() && host.parse::<IpAddr>().is_err() {
+            tokio::net::lookup_host((host, port))
+                .await?
+                .next()
+                .map(|addr| addr.ip().to_string())
+                .ok_or_else(|| io::Error::other(format!("{} resolved to no addresses", host)))?
+        } else {
+            host.to_string()
+        };
+        proxy.tunnel(&mut stream, &target, port).await?;
+        Ok(stream)
+    };
+    let tcp_stream = tokio::time::timeout(connect_timeout, connect)
+        .await
+        .map_err(|_| {
+            anyhow::anyhow!(
+                "tunnel TCP connect timeout ({}s)",
+                connect_timeout.as_secs()
+            )
+        })??;
+
+    // Configure TCP parameters via socket2
+    configure_tcp_socket(&tcp_stream, state);
+
+    // WebSocket upgrade (with TLS if wss://)
+    let connector = if is_tls {
+        Some(tokio_tungstenite::Connector::Rustls(Arc::clone(
+            &state.aether_tls_config,
+        )))
+    } else {
+        None
+    };
+    // Match Python-side _MAX_FRAME_SIZE (64 MiB) to prevent tungstenite's
+    // default 16 MiB limit from rejecting large AI API payloads (multi-image
+    // base64 requests can exceed 16 MiB).
+    let ws_config = WebSocketConfig {
+        max_frame_size: Some(64 << 20),
+        max_message_size: Some(64 << 20),
+        ..Default::default()
+    };
+    let handshake_timeout = Duration::from_secs(state.config.tunnel_connect_timeout_secs);
+    interference.record_attempt();
+    let opened = tokio::time::timeout(
+        handshake_timeout,
+        tokio_tungstenite::client_async_tls_with_config(
+            request,
+            tcp_stream,
+            Some(ws_config),
+            connector,
+        ),
+    )
+    .await
+    .map_err(|_| {
+        interference.record(Signal::HandshakeTimeout);
+        anyhow::anyhow!(
+            "tunnel WebSocket handshake timeout ({}s)",
+            handshake_timeout.as_secs()
+        )
+    })?
+    .inspect_err(|e| {
+        if interference::is_reset(e) {
+            interference.record(Signal::HandshakeReset);
+        }
+    })?;
+    Ok(opened)
 }
 
-fn build_tunnel_url(server: &ServerContext) -> String {
-    let base = server.aether_url.trim_end_matches('/');
+fn build_tunnel_url(base_url: &str) -> String {
+    let base = base_url.trim_end_matches('/');
     let ws_base = if base.starts_with("https://") {
         base.replacen("https://", "wss://", 1)
     } else if base.starts_with("http://") {
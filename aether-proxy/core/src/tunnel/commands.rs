@@ -0,0 +1,179 @@
+//! One-shot commands from the control plane.
+//!
+//! Heartbeat ACKs may carry a `commands` list next to `remote_config`:
+//!
+//! ```json
+//! {"commands": [{"id": "c-17", "action": "set_log_level", "level": "debug"}]}
+//! ```
+//!
+//! Commands arrive only over the tunnel, which is authenticated with the
+//! management token, and are refused (with a failed result) when
+//! `AETHER_PROXY_REMOTE_COMMANDS=false`.  Each one is executed once (the
+//! control plane re-sends a command until its result is acknowledged) and
+//! its outcome is reported in `command_results` of the following
+//! heartbeats.
+
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::runtime;
+use crate::state::{AppState, ServerContext};
+
+use super::live_view::MetadataSealer;
+
+/// Command IDs remembered to skip re-deliveries.
+const SEEN_CAPACITY: usize = 256;
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+enum Action {
+    /// Unregister and shut down gracefully, like SIGTERM.
+    Drain,
+    /// Same as `drain`; accepted for control planes that name it so.
+    Shutdown,
+    SetLogLevel {
+        level: String,
+    },
+    /// Terminate one in-flight stream by its admin/live-view ID.
+    TerminateStream {
+        stream: u64,
+    },
+    /// Seal live-view snapshots to a new public key (enabling the live
+    /// view if it was off), or disable it with `null`.
+    RotateLiveViewKey {
+        public_key: Option<String>,
+    },
+}
+
+/// Executes commands and queues their results for the next heartbeats.
+#[derive(Default)]
+pub struct CommandQueue {
+    seen: VecDeque<String>,
+    results: Vec<serde_json::Value>,
+}
+
+impl CommandQueue {
+    /// Run every command not executed before.
+    pub fn execute(
+        &mut self,
+        state: &AppState,
+        server: &ServerContext,
+        commands: Vec<serde_json::Value>,
+    ) {
+        for raw in commands {
+            let Some(id) = raw.get("id").and_then(|v| v.as_str()).map(str::to_string) else {
+                warn!(command = %raw, "ignoring remote command without id");
+                continue;
+            };
+            if self.seen.contains(&id) {
+                continue;
+            }
+            if self.seen.len() == SEEN_CAPACITY {
+                self.seen.pop_front();
+            }
+            self.seen.push_back(id.clone());
+
+            let outcome = if state.config.remote_commands {
+                serde_json::from_value::<Action>(raw)
+                    .map_err(|e| format!("unsupported command: {}", e))
+                    .and_then(|action| run(state, server, action))
+            } else {
+                Err("remote commands are disabled on this node".to_string())
+            };
+            match &outcome {
+                Ok(()) => info!(id = %id, "remote command executed"),
+                Err(e) => warn!(id = %id, error = %e, "remote command failed"),
+            }
+            self.results.push(match outcome {
+                Ok(()) => serde_json::json!({ "id": id, "ok": true }),
+                Err(e) => serde_json::json!({ "id": id, "ok": false, "error": e }),
+            });
+        }
+    }
+
+    /// Results not yet acknowledged, to include in the next heartbeat.
+    pub fn pending_results(&self) -> &[serde_json::Value] {
+        &self.results
+    }
+
+    /// The first `count` results reached the control plane.
+    pub fn acknowledge(&mut self, count: usize) {
+        self.results.drain(..count.min(self.results.len()));
+    }
+}
+
+fn run(state: &AppState, server: &ServerContext, action: Action) -> Result<(), String> {
+    match action {
+        Action::Drain | Action::Shutdown => {
+            state.lifecycle.request_drain();
+            Ok(())
+        }
+        Action::SetLogLevel { level } => runtime::set_log_level(&server.dynamic, &level),
+        Action::TerminateStream { stream } => {
+            if server.live_streams.kill(stream) {
+                Ok(())
+            } else {
+                Err(format!("no such stream {}", stream))
+            }
+        }
+        Action::RotateLiveViewKey { public_key } => {
+            let sealer = public_key
+                .as_deref()
+                .map(MetadataSealer::from_base64)
+                .transpose()?;
+            state.live_view_sealer.store(sealer.map(Arc::new));
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_actions_parse() {
+        let action: Action = serde_json::from_value(serde_json::json!({
+            "id": "c-1",
+            "action": "terminate_stream",
+            "stream": 7,
+        }))
+        .unwrap();
+        assert!(matches!(action, Action::TerminateStream { stream: 7 }));
+
+        let action: Action = serde_json::from_value(serde_json::json!({
+            "id": "c-2",
+            "action": "rotate_live_view_key",
+            "public_key": null,
+        }))
+        .unwrap();
+        assert!(matches!(
+            action,
+            Action::RotateLiveViewKey { public_key: None }
+        ));
+
+        assert!(serde_json::from_value::<Action>(serde_json::json!({
+            "id": "c-3",
+            "action": "format_disk",
+        }))
+        .is_err());
+    }
+
+    #[test]
+    fn test_acknowledge_drops_reported_results() {
+        let mut queue = CommandQueue {
+            seen: VecDeque::new(),
+            results: vec![
+                serde_json::json!({ "id": "a" }),
+                serde_json::json!({ "id": "b" }),
+            ],
+        };
+        queue.acknowledge(1);
+        assert_eq!(queue.pending_results(), [serde_json::json!({ "id": "b" })]);
+        queue.acknowledge(5);
+        assert!(queue.pending_results().is_empty());
+    }
+}
@@ -0,0 +1,408 @@
+//! Frame dispatcher: reads incoming WebSocket frames and routes them.
+
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures_util::StreamExt;
+use tokio::sync::mpsc;
+use tokio::sync::mpsc::error::TrySendError;
+use tokio::task::JoinHandle;
+use tokio_tungstenite::tungstenite::Message;
+use tracing::{debug, error, error_span, info, warn, Instrument};
+
+use crate::features::Feature;
+use crate::quota;
+use crate::request_id;
+use crate::state::{AppState, ServerContext};
+use crate::usage;
+
+use super::heartbeat::HeartbeatHandle;
+use super::interference::Signal;
+use super::protocol::{decompress_if_gzip, Frame, MsgType, RequestMeta};
+use super::stream_handler;
+use super::writer::FrameSender;
+
+/// Body frames buffered per stream while its handler is busy elsewhere;
+/// a stream that falls further behind is failed (see [`forward_body`]).
+const BODY_BACKLOG: usize = 64;
+
+/// Run the dispatcher loop, reading from the WebSocket stream.
+pub async fn run<S>(
+    state: Arc<AppState>,
+    server: Arc<ServerContext>,
+    mut ws_stream: S,
+    frame_tx: FrameSender,
+    heartbeat: HeartbeatHandle,
+) -> Result<(), anyhow::Error>
+where
+    S: StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>>
+        + Unpin
+        + Send
+        + 'static,
+{
+    // Active streams: stream_id -> body sender
+    let mut streams: HashMap<u32, mpsc::Sender<Frame>> = HashMap::new();
+    // Track spawned stream handlers so we can wait for them on shutdown
+    let mut handler_handles: Vec<JoinHandle<()>> = Vec::new();
+    let max_streams = state.config.tunnel_max_streams.unwrap_or(128) as usize;
+    let mut frames_since_cleanup: u32 = 0;
+    let stale_timeout = Duration::from_secs(state.config.tunnel_stale_timeout_secs);
+
+    // Track last time we received any data to detect stale connections
+    let mut last_data_at = tokio::time::Instant::now();
+
+    let read_err = loop {
+        let msg_result = tokio::select! {
+            msg = ws_stream.next() => {
+                match msg {
+                    Some(r) => r,
+                    None => break None,
+                }
+            }
+            _ = tokio::time::sleep_until(last_data_at + stale_timeout) => {
+                warn!(
+                    stale_secs = stale_timeout.as_secs(),
+                    "tunnel connection stale, no data received"
+                );
+                server.interference.record(Signal::Stall);
+                break None;
+            }
+        };
+
+        let msg = match msg_result {
+            Ok(m) => m,
+            Err(e) => {
+                error!(error = %e, "WebSocket read error");
+                break Some(e);
+            }
+        };
+
+        // Any successfully received message proves the connection is alive
+        last_data_at = tokio::time::Instant::now();
+
+        let data = match msg {
+            Message::Binary(data) => Bytes::from(data),
+            Message::Ping(_) => continue,
+            Message::Pong(_) => continue,
+            Message::Close(_) => {
+                info!("received WebSocket close");
+                break None;
+            }
+            _ => continue,
+        };
+
+        let frame = match Frame::decode(data) {
+            Ok(f) => f,
+            Err(e) => {
+                warn!(error = %e, "failed to decode frame");
+                continue;
+            }
+        };
+
+        match frame.msg_type {
+            MsgType::RequestHeaders => {
+                // Decompress if the frame is gzip-compressed, then parse metadata
+                let payload = match decompress_if_gzip(&frame) {
+                    Ok(p) => p,
+                    Err(e) => {
+                        warn!(stream_id = frame.stream_id, error = %e, "frame decompress failed");
+                        continue;
+                    }
+                };
+                let meta: RequestMeta = match serde_json::from_slice(&payload) {
+                    Ok(m) => m,
+                    Err(e) => {
+                        warn!(stream_id = frame.stream_id, error = %e, "invalid request metadata");
+                        reject_stream(
+                            &frame_tx,
+                            frame.stream_id,
+                            format!("invalid request metadata: {e}"),
+                        );
+                        continue;
+                    }
+                };
+
+                let request_id = request_id::of(&meta.headers, state.config.trust_request_id);
+                let class = state.load_shedder.classify(&meta);
+                if state.load_shedder.should_shed(class)
+                    && server.feature_enabled(Feature::LoadShedding)
+                {
+                    server.metrics.shed_requests.fetch_add(1, Ordering::Release);
+                    debug!(
+                        stream_id = frame.stream_id,
+                        request_id,
+                        class = class.as_str(),
+                        "stream shed under load"
+                    );
+                    let reason = format!("node overloaded, {} request shed", class.as_str());
+                    stream_handler::record_rejected(
+                        &state,
+                        &server,
+                        frame.stream_id,
+                        &request_id,
+                        &meta,
+                        None,
+                        &reason,
+                    );
+                    reject_stream(&frame_tx, frame.stream_id, reason);
+                    continue;
+                }
+
+                if streams.len() >= max_streams {
+                    warn!(
+                        stream_id = frame.stream_id,
+                        request_id, "max concurrent streams reached"
+                    );
+                    stream_handler::record_rejected(
+                        &state,
+                        &server,
+                        frame.stream_id,
+                        &request_id,
+                        &meta,
+                        None,
+                        "max concurrent streams reached",
+                    );
+                    reject_stream(&frame_tx, frame.stream_id, "max concurrent streams reached");
+                    continue;
+                }
+
+                let client = usage::client_of(&meta.headers);
+                let limit = state.config.max_streams_per_client;
+                let slot = match client.as_deref().filter(|_| limit > 0) {
+                    Some(client) => match server.client_streams.open(client, limit) {
+                        Some(slot) => Some(slot),
+                        None => {
+                            debug!(
+                                stream_id = frame.stream_id,
+                                request_id, client, "client stream limit reached"
+                            );
+                            stream_handler::record_rejected(
+                                &state,
+                                &server,
+                                frame.stream_id,
+                                &request_id,
+                                &meta,
+                                None,
+                                "client stream limit reached",
+                            );
+                            reject_stream(
+                                &frame_tx,
+                                frame.stream_id,
+                                "client stream limit reached",
+                            );
+                            continue;
+                        }
+                    },
+                    None => None,
+                };
+
+                if let Some(exceeded) = client.and_then(|client| {
+                    let rules = Arc::clone(&server.dynamic.load().client_quotas);
+                    server.quotas.admit(&rules, &client).err()
+                }) {
+                    debug!(
+                        stream_id = frame.stream_id,
+                        request_id,
+                        quota = exceeded.kind(),
+                        "client quota exceeded"
+                    );
+                    stream_handler::record_rejected(
+                        &state,
+                        &server,
+                        frame.stream_id,
+                        &request_id,
+                        &meta,
+                        Some(429),
+                        &exceeded.to_string(),
+                    );
+                    for reply in quota_exceeded_frames(frame.stream_id, &request_id, exceeded) {
+                        if frame_tx.try_send(reply).is_err() {
+                            warn!(
+                                stream_id = frame.stream_id,
+                                "writer channel full, quota response dropped"
+                            );
+                            break;
+                        }
+                    }
+                    continue;
+                }
+
+                // Create body channel and spawn handler
+                let (body_tx, body_rx) = mpsc::channel::<Frame>(BODY_BACKLOG);
+                streams.insert(frame.stream_id, body_tx);
+
+                let state_clone = Arc::clone(&state);
+                let server_clone = Arc::clone(&server);
+                let tx_clone = frame_tx.clone();
+                let sid = frame.stream_id;
+                // At error level so the ID is on every event of the stream
+                // whatever the log level.
+                let span = error_span!("stream", stream_id = sid, request_id = %request_id);
+                let handle = tokio::spawn(
+                    async move {
+                        // Counts against the client's limit until the stream ends
+                        let _slot = slot;
+                        stream_handler::handle_stream(
+                            state_clone,
+                            server_clone,
+                            sid,
+                            request_id,
+                            meta,
+                            body_rx,
+                            tx_clone,
+                        )
+                        .await;
+                    }
+                    .instrument(span),
+                );
+                handler_handles.push(handle);
+
+                debug!(stream_id = frame.stream_id, "new stream started");
+            }
+
+            MsgType::RequestBody => {
+                let last = frame.is_end_stream();
+                forward_body(&mut streams, frame, last, &frame_tx);
+            }
+
+            MsgType::StreamEnd => {
+                // Passed on so the handler can tell the end of a request
+                // body from a cancellation.
+                forward_body(&mut streams, frame, true, &frame_tx);
+            }
+
+            MsgType::StreamError => {
+                // A cancellation that doesn't fit still gets through as the
+                // closed channel.
+                if let Some(tx) = streams.remove(&frame.stream_id) {
+                    let _ = tx.try_send(frame);
+                }
+            }
+
+            MsgType::Ping => {
+                // Use try_send to avoid blocking the read loop when writer is congested
+                if frame_tx
+                    .try_send(Frame::control(MsgType::Pong, frame.payload))
+                    .is_err()
+                {
+                    warn!("writer channel full, Pong dropped");
+                }
+            }
+
+            MsgType::HeartbeatAck => {
+                heartbeat.on_ack(frame.payload).await;
+            }
+
+            MsgType::GoAway => {
+                info!("received GOAWAY");
+                break None;
+            }
+
+            _ => {
+                debug!(msg_type = ?frame.msg_type, "ignoring unexpected frame type");
+            }
+        }
+
+        // Periodically clean up finished handles to avoid unbounded growth.
+        // Trigger every 64 frames OR when the count exceeds max_streams.
+        frames_since_cleanup += 1;
+        if frames_since_cleanup >= 64 || handler_handles.len() > max_streams {
+            handler_handles.retain(|h| !h.is_finished());
+            frames_since_cleanup = 0;
+        }
+    };
+
+    // Drop body senders so stream handlers waiting on body_rx will unblock
+    streams.clear();
+
+    // Wait for active stream handlers to finish so their frame_tx clones
+    // are dropped before the writer closes the sink.
+    drain_handlers(handler_handles).await;
+
+    match read_err {
+        Some(e) => Err(e.into()),
+        None => Ok(()),
+    }
+}
+
+/// Wait for all active stream handlers to finish (with a timeout).
+async fn drain_handlers(handles: Vec<JoinHandle<()>>) {
+    if handles.is_empty() {
+        return;
+    }
+    let count = handles.len();
+    debug!(count, "waiting for active stream handlers to finish");
+    let _ = tokio::time::timeout(Duration::from_secs(30), async {
+        for h in handles {
+            let _ = h.await;
+        }
+    })
+    .await;
+}
+
+/// Hand a body frame (or the STREAM_END after it) to its stream's handler;
+/// `last` ends the stream's body.  Never waits: a handler that isn't
+/// reading its body (still connecting, or a target slow to take the
+/// upload) would otherwise hold up the read loop, and with it every other
+/// stream and the heartbeat ACKs on the tunnel.  A stream whose backlog is
+/// full is failed on its own instead.
+fn forward_body(
+    streams: &mut HashMap<u32, mpsc::Sender<Frame>>,
+    frame: Frame,
+    last: bool,
+    frame_tx: &FrameSender,
+) {
+    let stream_id = frame.stream_id;
+    let Some(tx) = streams.get(&stream_id) else {
+        return;
+    };
+    match tx.try_send(frame) {
+        Ok(()) if !last => {}
+        Ok(()) | Err(TrySendError::Closed(_)) => {
+            streams.remove(&stream_id);
+        }
+        Err(TrySendError::Full(_)) => {
+            // Dropping the sender cancels the handler's request body
+            streams.remove(&stream_id);
+            warn!(stream_id, "request body backlog full, failing stream");
+            reject_stream(frame_tx, stream_id, "request body not consumed in time");
+        }
+    }
+}
+
+/// Refuse a stream with a `StreamError` carrying `reason`.  Uses `try_send`
+/// so the read loop never blocks; the error is dropped when the writer is
+/// backed up.
+fn reject_stream(frame_tx: &FrameSender, stream_id: u32, reason: impl Into<Bytes>) {
+    let frame = Frame::new(stream_id, MsgType::StreamError, 0, reason);
+    if frame_tx.try_send(frame).is_err() {
+        warn!(stream_id, "writer channel full, StreamError dropped");
+    }
+}
+
+/// A complete `429 Too Many Requests` response for a client over quota.
+fn quota_exceeded_frames(
+    stream_id: u32,
+    request_id: &str,
+    exceeded: quota::Exceeded,
+) -> [Frame; 3] {
+    stream_handler::local_response_frames(
+        stream_id,
+        429,
+        vec![
+            (request_id::HEADER.to_string(), request_id.to_string()),
+            (
+                "x-aether-quota-exceeded".to_string(),
+                exceeded.kind().to_string(),
+            ),
+            (
+                "retry-after".to_string(),
+                quota::secs_until_reset().to_string(),
+            ),
+        ],
+        &exceeded.to_string(),
+    )
+}
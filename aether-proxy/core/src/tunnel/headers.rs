@@ -0,0 +1,237 @@
+//! Hop-by-hop header handling (RFC 7230 §6.1) and `Via` (§5.7.1).
+//!
+//! Hop-by-hop headers describe a single connection, so they are dropped in
+//! both directions: the fixed set below plus every header named in the
+//! message's own `Connection` header.  Each forwarded message gets this
+//! node appended to `Via`, unless the configured [`Anonymity`] level says
+//! the node should not reveal itself.
+//!
+//! Nodes that hide themselves pass on the hop count only to targets known
+//! to be other relay nodes, in a [`HopKey`]-signed header whose name looks
+//! random to anyone without the key.
+
+use std::collections::HashSet;
+
+use ring::hmac;
+
+/// Headers that only apply to one connection.
+pub const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Pseudonym this node uses in `Via`, so the node name is not disclosed.
+pub const VIA_PSEUDONYM: &str = "aether-proxy";
+
+/// Headers that carry the address of the original client.
+pub const CLIENT_IDENTIFYING: &[&str] = &[
+    "client-ip",
+    "forwarded",
+    "true-client-ip",
+    "x-client-ip",
+    "x-forwarded-for",
+    "x-originating-ip",
+    "x-real-ip",
+];
+
+/// Headers that reveal the request passed through a proxy.
+pub const PROXY_REVEALING: &[&str] = &[
+    "via",
+    "x-forwarded-host",
+    "x-forwarded-port",
+    "x-forwarded-proto",
+];
+
+/// How much of the request's proxy trail reaches the origin.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Anonymity {
+    /// Forward client headers untouched and announce this node in `Via`.
+    Transparent,
+    /// Announce this node in `Via` but strip client addresses.
+    Anonymous,
+    /// Strip client addresses and every sign of proxying, this node's
+    /// `Via` included.  The hop count is only sent signed ([`HopKey`]),
+    /// and only to relay nodes.
+    Elite,
+}
+
+impl Anonymity {
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "" | "transparent" => Ok(Self::Transparent),
+            "anonymous" => Ok(Self::Anonymous),
+            "elite" => Ok(Self::Elite),
+            other => Err(format!(
+                "unknown level {:?} (use transparent, anonymous or elite)",
+                other
+            )),
+        }
+    }
+
+    /// Whether this node announces itself: `Via` in both directions and
+    /// the hop count sent to the target under its descriptive name.
+    pub fn reveals_node(self) -> bool {
+        self != Self::Elite
+    }
+
+    /// Whether the client-supplied header `name` (lower-case) is dropped.
+    pub fn strips(self, name: &str) -> bool {
+        match self {
+            Self::Transparent => false,
+            Self::Anonymous => CLIENT_IDENTIFYING.contains(&name),
+            Self::Elite => CLIENT_IDENTIFYING.contains(&name) || PROXY_REVEALING.contains(&name),
+        }
+    }
+}
+
+/// Lower-cased names listed in any `Connection` header among `headers`.
+pub fn connection_listed<'a>(
+    headers: impl IntoIterator<Item = (&'a str, &'a str)>,
+) -> HashSet<String> {
+    headers
+        .into_iter()
+        .filter(|(name, _)| name.eq_ignore_ascii_case("connection"))
+        .flat_map(|(_, value)| value.split(','))
+        .map(|token| token.trim().to_ascii_lowercase())
+        .filter(|token| !token.is_empty())
+        .collect()
+}
+
+/// Whether `name` (lower-case) must not be forwarded.
+pub fn is_hop_by_hop(name: &str, listed: &HashSet<String>) -> bool {
+    HOP_BY_HOP.contains(&name) || listed.contains(name)
+}
+
+/// `Via` value with this node appended to any existing entries.
+pub fn via(existing: Option<&str>) -> String {
+    let own = format!("1.1 {VIA_PSEUDONYM}");
+    match existing.map(str::trim).filter(|v| !v.is_empty()) {
+        Some(existing) => format!("{existing}, {own}"),
+        None => own,
+    }
+}
+
+/// Signs the hop count sent by nodes that hide themselves.
+///
+/// The key is derived from the management token, which nodes registered
+/// with the same Aether share, so those nodes can read each other's count.
+/// The value is the count in clear text followed by its MAC; values that
+/// do not verify are ignored, so a client cannot fake a count.
+pub struct HopKey {
+    key: hmac::Key,
+    header: String,
+}
+
+impl HopKey {
+    pub fn new(management_token: &str) -> Self {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, management_token.as_bytes());
+        let header = format!(
+            "x-{}",
+            hex::encode(&hmac::sign(&key, b"hop-header").as_ref()[..5])
+        );
+        Self { key, header }
+    }
+
+    /// Lower-case name of the signed hop header.
+    pub fn header(&self) -> &str {
+        &self.header
+    }
+
+    /// Header value carrying `hops`: `<hops>.<hex MAC>`.
+    pub fn encode(&self, hops: u8) -> String {
+        format!(
+            "{hops}.{}",
+            hex::encode(hmac::sign(&self.key, &[b'h', hops]).as_ref())
+        )
+    }
+
+    /// Hop count in `value`, if it was signed with this key.
+    pub fn decode(&self, value: &str) -> Option<u8> {
+        let (hops, tag) = value.trim().split_once('.')?;
+        if hops.is_empty() || !hops.bytes().all(|b| b.is_ascii_digit()) {
+            return None;
+        }
+        let hops: u8 = hops.parse().ok()?;
+        let tag = hex::decode(tag).ok()?;
+        hmac::verify(&self.key, &[b'h', hops], &tag).ok()?;
+        Some(hops)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connection_listed_headers_are_hop_by_hop() {
+        let headers = [
+            ("Connection", "keep-alive, X-Session-Hint"),
+            ("connection", "close"),
+            ("X-Session-Hint", "1"),
+            ("Accept", "*/*"),
+        ];
+        let listed = connection_listed(headers);
+        assert!(is_hop_by_hop("x-session-hint", &listed));
+        assert!(is_hop_by_hop("close", &listed));
+        assert!(is_hop_by_hop("transfer-encoding", &listed));
+        assert!(!is_hop_by_hop("accept", &listed));
+    }
+
+    #[test]
+    fn test_via_appends() {
+        assert_eq!(via(None), "1.1 aether-proxy");
+        assert_eq!(via(Some("1.0 fred")), "1.0 fred, 1.1 aether-proxy");
+        assert_eq!(via(Some(" ")), "1.1 aether-proxy");
+    }
+
+    #[test]
+    fn test_hop_key_round_trip() {
+        let key = HopKey::new("ae_token");
+        assert!(key.header().starts_with("x-"));
+        assert_eq!(key.header().len(), 12);
+        assert_eq!(key.decode(&key.encode(2)), Some(2));
+        assert_ne!(key.encode(2), key.encode(3));
+
+        // The count is readable but changing it breaks the MAC
+        let signed = key.encode(2);
+        assert!(signed.starts_with("2."));
+        assert_eq!(key.decode(&signed.replacen('2', "1", 1)), None);
+        assert_eq!(key.decode(&signed.replacen('2', "+2", 1)), None);
+        assert_eq!(key.decode(&signed[..signed.len() - 2]), None);
+
+        // Other deployments and made-up values don't verify
+        let other = HopKey::new("ae_other");
+        assert_ne!(other.header(), key.header());
+        assert_eq!(other.decode(&key.encode(2)), None);
+        assert_eq!(key.decode("abc"), None);
+        assert_eq!(key.decode("2"), None);
+    }
+
+    #[test]
+    fn test_anonymity_levels() {
+        assert_eq!(Anonymity::parse("").unwrap(), Anonymity::Transparent);
+        assert_eq!(Anonymity::parse(" Elite ").unwrap(), Anonymity::Elite);
+        assert!(Anonymity::parse("high").is_err());
+
+        let transparent = Anonymity::Transparent;
+        assert!(transparent.reveals_node());
+        assert!(!transparent.strips("x-forwarded-for"));
+
+        let anonymous = Anonymity::Anonymous;
+        assert!(anonymous.reveals_node());
+        assert!(anonymous.strips("x-forwarded-for"));
+        assert!(anonymous.strips("forwarded"));
+        assert!(!anonymous.strips("x-forwarded-proto"));
+
+        let elite = Anonymity::Elite;
+        assert!(!elite.reveals_node());
+        assert!(elite.strips("x-real-ip"));
+        assert!(elite.strips("x-forwarded-proto"));
+        assert!(!elite.strips("accept"));
+    }
+}
@@ -0,0 +1,111 @@
+//! Control-plane reachability of one server.
+//!
+//! Contact is a heartbeat ACK, a completed tunnel handshake or a
+//! successful registration.  A server that has not been heard from for
+//! [`DEGRADED_AFTER_INTERVALS`] heartbeat intervals (at least
+//! [`MIN_DEGRADED_AFTER`]) is degraded: reported in the admin status and
+//! metrics, and re-registered before the next tunnel connect, since Aether
+//! may have dropped the node while it was unreachable.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tracing::{info, warn};
+
+/// Missed heartbeat intervals before the control plane counts as lost.
+pub const DEGRADED_AFTER_INTERVALS: u32 = 3;
+/// Floor for the silence threshold, for very short heartbeat intervals.
+const MIN_DEGRADED_AFTER: Duration = Duration::from_secs(30);
+
+pub struct ControlPlaneHealth {
+    last_contact: Mutex<Instant>,
+    degraded: AtomicBool,
+    /// Set when degraded; cleared once the node has registered again.
+    needs_register: AtomicBool,
+}
+
+impl Default for ControlPlaneHealth {
+    fn default() -> Self {
+        // Contexts are created right after a successful registration.
+        Self {
+            last_contact: Mutex::new(Instant::now()),
+            degraded: AtomicBool::new(false),
+            needs_register: AtomicBool::new(false),
+        }
+    }
+}
+
+impl ControlPlaneHealth {
+    /// The control plane answered.
+    pub fn on_contact(&self) {
+        *self.last_contact.lock().unwrap() = Instant::now();
+        if self.degraded.swap(false, Ordering::Relaxed) {
+            info!("control plane reachable again");
+        }
+    }
+
+    /// Time since the control plane was last heard from.
+    pub fn silence(&self) -> Duration {
+        self.last_contact.lock().unwrap().elapsed()
+    }
+
+    /// Degraded as of the last [`Self::check`].
+    pub fn is_degraded(&self) -> bool {
+        self.degraded.load(Ordering::Relaxed)
+    }
+
+    /// Re-evaluate against the current heartbeat interval; true if degraded.
+    pub fn check(&self, heartbeat_interval: Duration) -> bool {
+        let silence = self.silence();
+        let threshold = (heartbeat_interval * DEGRADED_AFTER_INTERVALS).max(MIN_DEGRADED_AFTER);
+        let degraded = silence > threshold;
+        if degraded && !self.degraded.swap(true, Ordering::Relaxed) {
+            warn!(
+                silent_secs = silence.as_secs(),
+                "control plane unreachable, node degraded"
+            );
+            self.needs_register.store(true, Ordering::Relaxed);
+        }
+        degraded
+    }
+
+    /// Whether a re-registration is due; the caller reports the outcome
+    /// through [`Self::registered`] or by calling this again later.
+    pub fn take_register(&self) -> bool {
+        self.needs_register.swap(false, Ordering::Relaxed)
+    }
+
+    /// Outcome of a re-registration started after [`Self::take_register`].
+    pub fn registered(&self, ok: bool) {
+        if ok {
+            self.on_contact();
+        } else {
+            self.needs_register.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_degrades_after_silence_and_recovers() {
+        let health = ControlPlaneHealth::default();
+        assert!(!health.check(Duration::from_secs(30)));
+        assert!(!health.take_register());
+
+        *health.last_contact.lock().unwrap() -= Duration::from_secs(91);
+        assert!(health.check(Duration::from_secs(30)));
+        // The floor applies to short intervals
+        assert!(health.check(Duration::from_secs(1)));
+
+        assert!(health.take_register());
+        health.registered(false);
+        assert!(health.take_register());
+        health.registered(true);
+        assert!(!health.take_register());
+        assert!(!health.check(Duration::from_secs(30)));
+    }
+}
@@ -10,22 +10,27 @@ use bytes::Bytes;
 use tokio::sync::watch;
 use tracing::{debug, info, warn};
 
-use crate::config::Config;
+use crate::error_report::{self, Event, Kind};
+use crate::features::Feature;
 use crate::registration::client::RemoteConfig;
+use crate::remote_upgrade;
 use crate::runtime;
-use crate::state::ServerContext;
+use crate::state::{AppState, MetricsTotals, ServerContext};
 
+use super::commands::CommandQueue;
+use super::live_view;
 use super::protocol::{Frame, MsgType};
 use super::writer::FrameSender;
 
 const CURRENT_VERSION: &str = env!("CARGO_PKG_VERSION");
 static UPGRADE_IN_PROGRESS: AtomicBool = AtomicBool::new(false);
-static NON_ROOT_UPGRADE_WARNED: AtomicBool = AtomicBool::new(false);
+static NO_UPGRADER_WARNED: AtomicBool = AtomicBool::new(false);
 
 enum AckDecision {
     Accept {
         heartbeat_id: Option<u64>,
         upgrade_to: Option<String>,
+        commands: Vec<serde_json::Value>,
     },
     Ignore,
 }
@@ -51,18 +56,9 @@ pub fn spawn_noop() -> HeartbeatHandle {
     HeartbeatHandle { ack_tx }
 }
 
-#[derive(Debug, Clone, Copy, Default)]
-struct HeartbeatSnapshot {
-    requests: u64,
-    latency_ns: u64,
-    failed: u64,
-    dns_failures: u64,
-    stream_errors: u64,
-}
-
 /// Spawn the heartbeat task. Returns a handle for forwarding ACKs.
 pub fn spawn(
-    _config: Arc<Config>,
+    state: Arc<AppState>,
     server: Arc<ServerContext>,
     frame_tx: FrameSender,
     mut shutdown: watch::Receiver<bool>,
@@ -73,10 +69,14 @@ pub fn spawn(
         // Read initial interval from dynamic config (may be updated by remote config).
         let initial_interval = Duration::from_secs(server.dynamic.load().heartbeat_interval);
         let mut current_interval = initial_interval;
-        // At most one in-flight heartbeat snapshot is tracked at a time.
-        // Snapshot is only cleared after receiving an ACK, which avoids losing
-        // interval counters when ACK/frame delivery is temporarily unstable.
-        let mut pending: Option<(u64, HeartbeatSnapshot)> = None;
+        // At most one in-flight heartbeat is tracked at a time, together with
+        // the cumulative totals it reported.  The acknowledged baseline only
+        // advances on ACK, so counters are never lost when ACK/frame delivery
+        // is temporarily unstable.
+        // The pending heartbeat also remembers how many command results it
+        // carried, so exactly those are dropped once it is acknowledged.
+        let mut pending: Option<(u64, MetricsTotals, usize)> = None;
+        let mut commands = CommandQueue::default();
         let mut next_heartbeat_id: u64 = 1;
         let heartbeat_session_id = format!(
             "{}-{}",
@@ -87,36 +87,52 @@ pub fn spawn(
                 .as_nanos()
         );
 
-        // Skip first immediate tick by sleeping first.
-        tokio::time::sleep(current_interval).await;
+        // Heartbeats are jittered so a fleet doesn't beat in lockstep, and
+        // back off while they go unacknowledged.
+        let salt = super::mix_u64(
+            u64::from(std::process::id()) << 32
+                ^ SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .subsec_nanos() as u64,
+        );
+        let mut misses: u32 = 0;
+        let mut next_at = tokio::time::Instant::now() + heartbeat_delay(current_interval, 0, salt);
 
         loop {
             tokio::select! {
-                _ = tokio::time::sleep(current_interval) => {
-                    let (heartbeat_id, snapshot) = if let Some((id, snap)) = pending {
-                        (id, snap)
+                _ = tokio::time::sleep_until(next_at) => {
+                    if pending.is_some() {
+                        misses = misses.saturating_add(1);
                     } else {
-                        let snap = collect_snapshot(&server);
+                        misses = 0;
+                    }
+                    server.control_plane.check(current_interval);
+                    let results = commands.pending_results();
+                    let (heartbeat_id, totals) = if let Some((id, totals, _)) = pending {
+                        (id, totals)
+                    } else {
+                        let totals = server.metrics.totals();
                         let id = next_heartbeat_id;
                         next_heartbeat_id = next_heartbeat_id.wrapping_add(1);
                         if next_heartbeat_id == 0 {
                             next_heartbeat_id = 1;
                         }
-                        pending = Some((id, snap));
-                        (id, snap)
+                        (id, totals)
                     };
+                    pending = Some((heartbeat_id, totals, results.len()));
+                    let acked = *server.metrics.heartbeat_acked.lock().unwrap();
 
                     let payload = build_heartbeat_payload(
+                        &state,
                         &server,
                         &heartbeat_session_id,
                         heartbeat_id,
-                        snapshot
+                        totals.since(acked),
+                        results,
                     );
                     let frame = Frame::control(MsgType::HeartbeatData, payload);
                     if frame_tx.send(frame).await.is_err() {
-                        if let Some((_, snap)) = pending.take() {
-                            restore_snapshot(&server, snap);
-                        }
                         break; // Writer closed
                     }
                     debug!("sent heartbeat data");
@@ -134,26 +150,40 @@ pub fn spawn(
                         );
                         current_interval = new_interval;
                     }
+                    if misses > 0 {
+                        debug!(misses, "heartbeat unacknowledged, backing off");
+                    }
+                    if misses == error_report::HEARTBEAT_MISSES_REPORTED {
+                        let node_id = server.node_id.read().unwrap().clone();
+                        error_report::report(
+                            Event::new(
+                                Kind::HeartbeatFailures,
+                                format!("{} consecutive heartbeats unacknowledged", misses),
+                            )
+                            .server(&server.server_label, Some(&node_id)),
+                        );
+                    }
+                    next_at = tokio::time::Instant::now()
+                        + heartbeat_delay(current_interval, misses, super::mix_u64(salt ^ heartbeat_id));
                 }
                 Some(ack_payload) = ack_rx.recv() => {
                     match handle_ack(&server, &ack_payload) {
                         AckDecision::Accept {
                             heartbeat_id: ack_id,
                             upgrade_to,
+                            commands: received,
                         } => {
-                            if let Some((pending_id, _)) = pending {
-                                match ack_id {
-                                    Some(id) if id == pending_id => {
-                                        pending = None;
-                                    }
-                                    None => {
-                                        // Backward-compatible with servers that don't echo
-                                        // heartbeat_id in ACK payload yet.
-                                        pending = None;
-                                    }
-                                    _ => {}
+                            server.control_plane.on_contact();
+                            if let Some((pending_id, totals, reported)) = pending {
+                                // A missing ACK id is accepted for servers that
+                                // don't echo heartbeat_id in the payload yet.
+                                if ack_id.is_none_or(|id| id == pending_id) {
+                                    *server.metrics.heartbeat_acked.lock().unwrap() = totals;
+                                    commands.acknowledge(reported);
+                                    pending = None;
                                 }
                             }
+                            commands.execute(&state, &server, received);
                             maybe_trigger_upgrade(upgrade_to);
                         }
                         AckDecision::Ignore => {}
@@ -161,9 +191,6 @@ pub fn spawn(
                 }
                 _ = shutdown.changed() => {
                     debug!("heartbeat task shutting down");
-                    if let Some((_, snap)) = pending.take() {
-                        restore_snapshot(&server, snap);
-                    }
                     break;
                 }
             }
@@ -173,54 +200,28 @@ pub fn spawn(
     HeartbeatHandle { ack_tx }
 }
 
-fn collect_snapshot(server: &ServerContext) -> HeartbeatSnapshot {
-    HeartbeatSnapshot {
-        requests: server.metrics.total_requests.swap(0, Ordering::AcqRel),
-        latency_ns: server.metrics.total_latency_ns.swap(0, Ordering::AcqRel),
-        failed: server.metrics.failed_requests.swap(0, Ordering::AcqRel),
-        dns_failures: server.metrics.dns_failures.swap(0, Ordering::AcqRel),
-        stream_errors: server.metrics.stream_errors.swap(0, Ordering::AcqRel),
-    }
-}
+/// Longest delay between heartbeats while backing off (unless the
+/// configured interval is longer).
+const MAX_HEARTBEAT_BACKOFF: Duration = Duration::from_secs(300);
 
-fn restore_snapshot(server: &ServerContext, snap: HeartbeatSnapshot) {
-    if snap.requests > 0 {
-        server
-            .metrics
-            .total_requests
-            .fetch_add(snap.requests, Ordering::Release);
-    }
-    if snap.latency_ns > 0 {
-        server
-            .metrics
-            .total_latency_ns
-            .fetch_add(snap.latency_ns, Ordering::Release);
-    }
-    if snap.failed > 0 {
-        server
-            .metrics
-            .failed_requests
-            .fetch_add(snap.failed, Ordering::Release);
-    }
-    if snap.dns_failures > 0 {
-        server
-            .metrics
-            .dns_failures
-            .fetch_add(snap.dns_failures, Ordering::Release);
-    }
-    if snap.stream_errors > 0 {
-        server
-            .metrics
-            .stream_errors
-            .fetch_add(snap.stream_errors, Ordering::Release);
-    }
+/// Delay before the next heartbeat: `interval`, doubled for each
+/// consecutive unacknowledged heartbeat up to [`MAX_HEARTBEAT_BACKOFF`],
+/// with ±10% jitter drawn from `random`.
+fn heartbeat_delay(interval: Duration, misses: u32, random: u64) -> Duration {
+    let backoff = interval
+        .saturating_mul(1 << misses.min(4))
+        .min(MAX_HEARTBEAT_BACKOFF.max(interval));
+    let span_ms = backoff.as_millis() as u64 / 5;
+    backoff - backoff / 10 + Duration::from_millis(random % (span_ms + 1))
 }
 
 fn build_heartbeat_payload(
+    state: &AppState,
     server: &ServerContext,
     heartbeat_session_id: &str,
     heartbeat_id: u64,
-    snapshot: HeartbeatSnapshot,
+    snapshot: MetricsTotals,
+    command_results: &[serde_json::Value],
 ) -> Bytes {
     let node_id = server.node_id.read().unwrap().clone();
 
@@ -230,7 +231,7 @@ fn build_heartbeat_payload(
         None
     };
 
-    let payload = serde_json::json!({
+    let mut payload = serde_json::json!({
         "node_id": node_id,
         "heartbeat_session_id": heartbeat_session_id,
         "heartbeat_id": heartbeat_id,
@@ -240,19 +241,48 @@ fn build_heartbeat_payload(
         "failed_requests": snapshot.failed,
         "dns_failures": snapshot.dns_failures,
         "stream_errors": snapshot.stream_errors,
+        "upstream_resets": snapshot.upstream_resets,
+        "shed_requests": snapshot.shed_requests,
+        "stalled_streams": snapshot.stalled_streams,
         "proxy_metadata": {
             "version": CURRENT_VERSION,
+            "features": feature_states(server),
+            "lifecycle": state.lifecycle.status_json(),
+            "dns_cache": state.dns_cache.stats_json(),
+            "response_cache": state
+                .response_cache
+                .as_ref()
+                .map(|cache| cache.stats_json()),
+            "warm_pool": state.warm_pool.as_ref().map(|pool| pool.stats_json()),
+            "latency_probes": state.prober.as_ref().map(|prober| prober.stats_json()),
+            "interference": server.interference.status_json(),
         },
     });
+    if let Some(sealer) = &*state.live_view_sealer.load() {
+        payload["proxy_metadata"]["live_view"] =
+            live_view::sealed_snapshot(sealer, &server.live_streams);
+    }
+    if !command_results.is_empty() {
+        payload["command_results"] = command_results.into();
+    }
 
     Bytes::from(serde_json::to_vec(&payload).unwrap_or_default())
 }
 
+/// Effective on/off state of every known feature flag on this node.
+fn feature_states(server: &ServerContext) -> serde_json::Map<String, serde_json::Value> {
+    Feature::ALL
+        .iter()
+        .map(|&f| (f.as_str().to_string(), server.feature_enabled(f).into()))
+        .collect()
+}
+
 fn handle_ack(server: &ServerContext, payload: &[u8]) -> AckDecision {
     if payload.is_empty() {
         return AckDecision::Accept {
             heartbeat_id: None,
             upgrade_to: None,
+            commands: Vec::new(),
         };
     }
 
@@ -266,6 +296,8 @@ fn handle_ack(server: &ServerContext, payload: &[u8]) -> AckDecision {
         heartbeat_id: Option<u64>,
         #[serde(default)]
         upgrade_to: Option<String>,
+        #[serde(default)]
+        commands: Vec<serde_json::Value>,
     }
 
     match serde_json::from_slice::<AckPayload>(payload) {
@@ -276,6 +308,7 @@ fn handle_ack(server: &ServerContext, payload: &[u8]) -> AckDecision {
             AckDecision::Accept {
                 heartbeat_id: ack.heartbeat_id,
                 upgrade_to: ack.upgrade_to.and_then(normalize_upgrade_target),
+                commands: ack.commands,
             }
         }
         Err(e) => {
@@ -301,29 +334,30 @@ fn maybe_trigger_upgrade(version: Option<String>) {
     let Some(target_version) = version else {
         return;
     };
-    if !crate::setup::service::is_root() {
-        if NON_ROOT_UPGRADE_WARNED
+    if UPGRADE_IN_PROGRESS
+        .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+        .is_err()
+    {
+        debug!(target_version = %target_version, "upgrade already in progress, ignoring");
+        return;
+    }
+    let Some(upgrade) = remote_upgrade::start(target_version.clone()) else {
+        UPGRADE_IN_PROGRESS.store(false, Ordering::Release);
+        if NO_UPGRADER_WARNED
             .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
             .is_ok()
         {
             warn!(
                 target_version = %target_version,
-                "remote upgrade skipped: root privileges are required"
+                "remote upgrade skipped: this process does not apply upgrades"
             );
         }
         return;
-    }
-    if UPGRADE_IN_PROGRESS
-        .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
-        .is_err()
-    {
-        debug!(target_version = %target_version, "upgrade already in progress, ignoring");
-        return;
-    }
+    };
 
     tokio::spawn(async move {
         info!(target_version = %target_version, "received remote upgrade instruction");
-        match crate::setup::upgrade::perform_upgrade(&target_version).await {
+        match upgrade.await {
             Ok(()) => {
                 info!(target_version = %target_version, "remote upgrade finished");
             }
@@ -338,3 +372,21 @@ fn maybe_trigger_upgrade(version: Option<String>) {
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_heartbeat_delay_jitter_and_backoff() {
+        let interval = Duration::from_secs(30);
+        assert_eq!(heartbeat_delay(interval, 0, 0), Duration::from_secs(27));
+        assert_eq!(heartbeat_delay(interval, 0, 6_000), Duration::from_secs(33));
+        assert_eq!(heartbeat_delay(interval, 2, 0), Duration::from_secs(108));
+        // Capped, however many heartbeats were missed
+        assert_eq!(heartbeat_delay(interval, 9, 0), Duration::from_secs(270));
+        // An interval above the cap is never shortened
+        let long = Duration::from_secs(600);
+        assert_eq!(heartbeat_delay(long, 3, 0), Duration::from_secs(540));
+    }
+}
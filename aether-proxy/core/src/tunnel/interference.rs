@@ -0,0 +1,204 @@
+//! Signs of active interference on the tunnel path to one server.
+//!
+//! The node only ever talks to Aether over its tunnel connections, so
+//! those are what a censor on the path breaks.  Patterns typical of
+//! blocking rather than ordinary network trouble are counted:
+//!
+//! - the TCP connect succeeds but the connection is reset or closed during
+//!   the TLS/WebSocket handshake, i.e. right after the ClientHello
+//! - the TCP connect succeeds but the handshake never completes
+//! - an established tunnel is reset within [`EARLY_DROP_WITHIN`]
+//! - an established tunnel suddenly receives nothing, not even pings, for
+//!   `--tunnel-stale-timeout-secs`
+//!
+//! Interference is suspected when, over the last [`WINDOW`], at least
+//! [`MIN_SIGNALS`] such events occurred and they make up at least half of
+//! the handshake attempts.  The flag and counters go into heartbeats (sent
+//! as soon as a tunnel gets through again) so the control plane can move
+//! users off a burned node.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tokio_tungstenite::tungstenite::error::ProtocolError;
+use tokio_tungstenite::tungstenite::Error as WsError;
+use tracing::{info, warn};
+
+/// Span over which events are weighed.
+pub const WINDOW: Duration = Duration::from_secs(600);
+/// Events within [`WINDOW`] before interference is suspected.
+pub const MIN_SIGNALS: usize = 3;
+/// A tunnel reset sooner than this after connecting is an early drop.
+pub const EARLY_DROP_WITHIN: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signal {
+    HandshakeReset,
+    HandshakeTimeout,
+    EarlyDrop,
+    Stall,
+}
+
+#[derive(Default)]
+struct Recent {
+    attempts: VecDeque<Instant>,
+    signals: VecDeque<Instant>,
+}
+
+impl Recent {
+    fn prune(&mut self, now: Instant) {
+        for events in [&mut self.attempts, &mut self.signals] {
+            while events
+                .front()
+                .is_some_and(|at| now.duration_since(*at) > WINDOW)
+            {
+                events.pop_front();
+            }
+        }
+    }
+
+    fn suspected(&self) -> bool {
+        self.signals.len() >= MIN_SIGNALS && self.signals.len() * 2 >= self.attempts.len()
+    }
+}
+
+#[derive(Default)]
+pub struct InterferenceMonitor {
+    handshake_resets: AtomicU64,
+    handshake_timeouts: AtomicU64,
+    early_drops: AtomicU64,
+    stalls: AtomicU64,
+    recent: Mutex<Recent>,
+    suspected: AtomicBool,
+}
+
+impl InterferenceMonitor {
+    /// A TLS/WebSocket handshake started on a connected socket.
+    pub fn record_attempt(&self) {
+        self.record_attempt_at(Instant::now());
+    }
+
+    pub fn record(&self, signal: Signal) {
+        self.record_at(signal, Instant::now());
+    }
+
+    fn record_attempt_at(&self, now: Instant) {
+        let mut recent = self.recent.lock().unwrap();
+        recent.attempts.push_back(now);
+        self.update(&mut recent, now);
+    }
+
+    fn record_at(&self, signal: Signal, now: Instant) {
+        let counter = match signal {
+            Signal::HandshakeReset => &self.handshake_resets,
+            Signal::HandshakeTimeout => &self.handshake_timeouts,
+            Signal::EarlyDrop => &self.early_drops,
+            Signal::Stall => &self.stalls,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        let mut recent = self.recent.lock().unwrap();
+        recent.signals.push_back(now);
+        self.update(&mut recent, now);
+    }
+
+    fn update(&self, recent: &mut Recent, now: Instant) {
+        recent.prune(now);
+        let suspected = recent.suspected();
+        if self.suspected.swap(suspected, Ordering::Relaxed) != suspected {
+            if suspected {
+                warn!(
+                    signals = recent.signals.len(),
+                    attempts = recent.attempts.len(),
+                    "tunnel interference suspected"
+                );
+            } else {
+                info!("tunnel interference no longer suspected");
+            }
+        }
+    }
+
+    /// Whether interference is suspected as of now.
+    pub fn is_suspected(&self) -> bool {
+        let mut recent = self.recent.lock().unwrap();
+        self.update(&mut recent, Instant::now());
+        self.suspected.load(Ordering::Relaxed)
+    }
+
+    pub fn status_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "suspected": self.is_suspected(),
+            "handshake_resets": self.handshake_resets.load(Ordering::Relaxed),
+            "handshake_timeouts": self.handshake_timeouts.load(Ordering::Relaxed),
+            "early_drops": self.early_drops.load(Ordering::Relaxed),
+            "stalls": self.stalls.load(Ordering::Relaxed),
+        })
+    }
+}
+
+/// Whether a WebSocket error means the peer (or something on the path)
+/// tore the connection down rather than closing it.
+pub fn is_reset(err: &WsError) -> bool {
+    match err {
+        WsError::Io(e) => matches!(
+            e.kind(),
+            std::io::ErrorKind::ConnectionReset
+                | std::io::ErrorKind::ConnectionAborted
+                | std::io::ErrorKind::UnexpectedEof
+                | std::io::ErrorKind::BrokenPipe
+        ),
+        WsError::Protocol(ProtocolError::ResetWithoutClosingHandshake) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_suspected_by_share_of_attempts() {
+        let monitor = InterferenceMonitor::default();
+        let start = Instant::now();
+        for i in 0..6 {
+            monitor.record_attempt_at(start + Duration::from_secs(i));
+        }
+        monitor.record_at(Signal::HandshakeReset, start);
+        monitor.record_at(Signal::EarlyDrop, start);
+        assert!(!monitor.suspected.load(Ordering::Relaxed));
+        monitor.record_at(Signal::Stall, start + Duration::from_secs(10));
+        assert!(monitor.suspected.load(Ordering::Relaxed));
+
+        // Healthy handshakes dilute the signals...
+        monitor.record_attempt_at(start + Duration::from_secs(20));
+        assert!(!monitor.suspected.load(Ordering::Relaxed));
+        monitor.record_at(Signal::HandshakeTimeout, start + Duration::from_secs(30));
+        assert!(monitor.suspected.load(Ordering::Relaxed));
+        // ...and old ones age out
+        monitor.record_attempt_at(start + WINDOW + Duration::from_secs(15));
+        assert!(!monitor.suspected.load(Ordering::Relaxed));
+
+        let status = monitor.status_json();
+        assert_eq!(status["handshake_resets"], 1);
+        assert_eq!(status["handshake_timeouts"], 1);
+        assert_eq!(status["early_drops"], 1);
+        assert_eq!(status["stalls"], 1);
+    }
+
+    #[test]
+    fn test_is_reset() {
+        use std::io::{Error, ErrorKind};
+        assert!(is_reset(&WsError::Io(Error::from(
+            ErrorKind::ConnectionReset
+        ))));
+        assert!(is_reset(&WsError::Io(Error::from(
+            ErrorKind::UnexpectedEof
+        ))));
+        assert!(is_reset(&WsError::Protocol(
+            ProtocolError::ResetWithoutClosingHandshake
+        )));
+        assert!(!is_reset(&WsError::Io(Error::from(ErrorKind::TimedOut))));
+        assert!(!is_reset(&WsError::ConnectionClosed));
+    }
+}
@@ -0,0 +1,342 @@
+//! Encrypted per-stream metadata for the control plane's live view.
+//!
+//! When `--live-view-public-key` is set, every heartbeat carries a snapshot
+//! of the node's in-flight streams (method, target host/port, request class,
+//! age — never headers or payloads).  The snapshot is sealed to the control
+//! plane's X25519 public key, so TLS-terminating intermediaries in front of
+//! Aether only ever see ciphertext.
+//!
+//! The same tracking backs the admin API's connection list, which can also
+//! terminate a stream by ID.
+//!
+//! Sealing scheme (`x25519-hkdf-sha256-chacha20poly1305`):
+//! a fresh ephemeral X25519 key per snapshot, HKDF-SHA256 over the shared
+//! secret with `ephemeral_pub || recipient_pub` as salt, and
+//! ChaCha20-Poly1305 with an all-zero nonce (safe because every key is used
+//! exactly once).  Output is `base64(ephemeral_pub || ciphertext || tag)`.
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use base64::Engine;
+use ring::aead::{self, Aad, LessSafeKey, Nonce, UnboundKey, CHACHA20_POLY1305};
+use ring::agreement::{self, EphemeralPrivateKey, UnparsedPublicKey, X25519};
+use ring::hkdf::{Salt, HKDF_SHA256};
+use ring::rand::SystemRandom;
+use tokio::sync::Notify;
+
+pub const ALGORITHM: &str = "x25519-hkdf-sha256-chacha20poly1305";
+const HKDF_INFO: &[u8] = b"aether-proxy live-view v1";
+const KEY_LEN: usize = 32;
+
+/// Upper bound on streams included in one snapshot, keeping heartbeat
+/// frames small on busy nodes.
+const MAX_SNAPSHOT_STREAMS: usize = 512;
+
+/// Seals snapshots to the control plane's public key.
+pub struct MetadataSealer {
+    recipient: [u8; KEY_LEN],
+    rng: SystemRandom,
+}
+
+impl MetadataSealer {
+    /// Parse a base64-encoded 32-byte X25519 public key.
+    pub fn from_base64(raw: &str) -> Result<Self, String> {
+        let bytes = base64::engine::general_purpose::STANDARD
+            .decode(raw.trim())
+            .map_err(|e| format!("invalid base64: {}", e))?;
+        let recipient: [u8; KEY_LEN] = bytes
+            .try_into()
+            .map_err(|_| format!("expected a {}-byte X25519 public key", KEY_LEN))?;
+        Ok(Self {
+            recipient,
+            rng: SystemRandom::new(),
+        })
+    }
+
+    /// Encrypt `plaintext`; returns the base64 envelope.
+    pub fn seal(&self, plaintext: &[u8]) -> Result<String, String> {
+        let ephemeral = EphemeralPrivateKey::generate(&X25519, &self.rng)
+            .map_err(|_| "failed to generate ephemeral key".to_string())?;
+        let ephemeral_pub = ephemeral
+            .compute_public_key()
+            .map_err(|_| "failed to derive ephemeral public key".to_string())?;
+
+        let key = agreement::agree_ephemeral(
+            ephemeral,
+            &UnparsedPublicKey::new(&X25519, self.recipient),
+            |shared| derive_key(shared, ephemeral_pub.as_ref(), &self.recipient),
+        )
+        .map_err(|_| "key agreement failed".to_string())??;
+
+        let mut out = ephemeral_pub.as_ref().to_vec();
+        let mut buf = plaintext.to_vec();
+        key.seal_in_place_append_tag(
+            Nonce::assume_unique_for_key([0; aead::NONCE_LEN]),
+            Aad::empty(),
+            &mut buf,
+        )
+        .map_err(|_| "encryption failed".to_string())?;
+        out.extend_from_slice(&buf);
+        Ok(base64::engine::general_purpose::STANDARD.encode(out))
+    }
+}
+
+fn derive_key(
+    shared: &[u8],
+    ephemeral_pub: &[u8],
+    recipient: &[u8],
+) -> Result<LessSafeKey, String> {
+    let salt = Salt::new(HKDF_SHA256, &[ephemeral_pub, recipient].concat());
+    let info = [HKDF_INFO];
+    let prk = salt.extract(shared);
+    let okm = prk
+        .expand(&info, &CHACHA20_POLY1305)
+        .map_err(|_| "key derivation failed".to_string())?;
+    Ok(LessSafeKey::new(UnboundKey::from(okm)))
+}
+
+/// Stream IDs are unique across all servers so the admin API can address
+/// a stream by ID alone.
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Bytes moved on one stream: `received` from the client (request body),
+/// `sent` back to it (response body).
+#[derive(Debug, Default)]
+pub struct Transfer {
+    pub received: AtomicU64,
+    pub sent: AtomicU64,
+}
+
+struct StreamInfo {
+    method: String,
+    host: String,
+    port: u16,
+    class: &'static str,
+    /// Client identity, for the admin API only: it never leaves the node in
+    /// the heartbeat snapshot.
+    client: Option<String>,
+    started_at: Instant,
+    transfer: Arc<Transfer>,
+    kill: Arc<Notify>,
+}
+
+/// In-flight streams of one server connection.
+#[derive(Default)]
+pub struct LiveStreams {
+    streams: Mutex<HashMap<u64, StreamInfo>>,
+}
+
+impl LiveStreams {
+    /// Record a stream until the returned guard is dropped.
+    pub fn track(
+        self: &Arc<Self>,
+        method: &str,
+        host: &str,
+        port: u16,
+        class: &'static str,
+        client: Option<String>,
+    ) -> StreamGuard {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let transfer = Arc::new(Transfer::default());
+        let kill = Arc::new(Notify::new());
+        self.streams.lock().unwrap().insert(
+            id,
+            StreamInfo {
+                method: method.to_string(),
+                host: host.to_string(),
+                port,
+                class,
+                client,
+                started_at: Instant::now(),
+                transfer: Arc::clone(&transfer),
+                kill: Arc::clone(&kill),
+            },
+        );
+        StreamGuard {
+            streams: Arc::clone(self),
+            id,
+            transfer,
+            kill,
+        }
+    }
+
+    /// Plaintext snapshot, oldest streams first.
+    pub fn snapshot_json(&self) -> serde_json::Value {
+        let streams = self.streams.lock().unwrap();
+        let mut entries: Vec<&StreamInfo> = streams.values().collect();
+        entries.sort_by_key(|s| s.started_at);
+        let now = Instant::now();
+        let list: Vec<serde_json::Value> = entries
+            .iter()
+            .take(MAX_SNAPSHOT_STREAMS)
+            .map(|s| {
+                serde_json::json!({
+                    "method": s.method,
+                    "host": s.host,
+                    "port": s.port,
+                    "class": s.class,
+                    "age_ms": now.duration_since(s.started_at).as_millis() as u64,
+                })
+            })
+            .collect();
+        serde_json::json!({
+            "total": streams.len(),
+            "streams": list,
+        })
+    }
+
+    /// Every stream with its ID and byte counts, oldest first.
+    pub fn list_json(&self) -> Vec<serde_json::Value> {
+        let streams = self.streams.lock().unwrap();
+        let mut entries: Vec<(&u64, &StreamInfo)> = streams.iter().collect();
+        entries.sort_by_key(|(_, s)| s.started_at);
+        let now = Instant::now();
+        entries
+            .into_iter()
+            .map(|(id, s)| {
+                serde_json::json!({
+                    "id": id,
+                    "method": s.method,
+                    "host": s.host,
+                    "port": s.port,
+                    "class": s.class,
+                    "client": s.client,
+                    "bytes_received": s.transfer.received.load(Ordering::Relaxed),
+                    "bytes_sent": s.transfer.sent.load(Ordering::Relaxed),
+                    "age_ms": now.duration_since(s.started_at).as_millis() as u64,
+                })
+            })
+            .collect()
+    }
+
+    /// Ask the stream `id` to terminate.  Returns whether it was found.
+    pub fn kill(&self, id: u64) -> bool {
+        match self.streams.lock().unwrap().get(&id) {
+            Some(stream) => {
+                stream.kill.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Removes its stream from [`LiveStreams`] on drop.
+pub struct StreamGuard {
+    streams: Arc<LiveStreams>,
+    id: u64,
+    transfer: Arc<Transfer>,
+    kill: Arc<Notify>,
+}
+
+impl StreamGuard {
+    pub fn transfer(&self) -> Arc<Transfer> {
+        Arc::clone(&self.transfer)
+    }
+
+    /// Resolves once an operator terminates the stream.
+    pub async fn killed(&self) {
+        self.kill.notified().await
+    }
+}
+
+impl Drop for StreamGuard {
+    fn drop(&mut self) {
+        self.streams.streams.lock().unwrap().remove(&self.id);
+    }
+}
+
+/// Sealed live-view object for the heartbeat payload.
+pub fn sealed_snapshot(sealer: &MetadataSealer, streams: &LiveStreams) -> serde_json::Value {
+    let plaintext = serde_json::to_vec(&streams.snapshot_json()).unwrap_or_default();
+    match sealer.seal(&plaintext) {
+        Ok(sealed) => serde_json::json!({ "alg": ALGORITHM, "sealed": sealed }),
+        Err(e) => {
+            tracing::warn!(error = %e, "failed to seal live view snapshot");
+            serde_json::Value::Null
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Control-plane side of the scheme, for round-trip checks.
+    fn open(recipient: EphemeralPrivateKey, recipient_pub: &[u8], sealed: &str) -> Vec<u8> {
+        let raw = base64::engine::general_purpose::STANDARD
+            .decode(sealed)
+            .unwrap();
+        let (ephemeral_pub, ciphertext) = raw.split_at(KEY_LEN);
+        let key = agreement::agree_ephemeral(
+            recipient,
+            &UnparsedPublicKey::new(&X25519, ephemeral_pub),
+            |shared| derive_key(shared, ephemeral_pub, recipient_pub),
+        )
+        .unwrap()
+        .unwrap();
+        let mut buf = ciphertext.to_vec();
+        let plain = key
+            .open_in_place(
+                Nonce::assume_unique_for_key([0; aead::NONCE_LEN]),
+                Aad::empty(),
+                &mut buf,
+            )
+            .unwrap();
+        plain.to_vec()
+    }
+
+    #[test]
+    fn test_seal_round_trip() {
+        let rng = SystemRandom::new();
+        let recipient = EphemeralPrivateKey::generate(&X25519, &rng).unwrap();
+        let recipient_pub = recipient.compute_public_key().unwrap();
+        let encoded = base64::engine::general_purpose::STANDARD.encode(recipient_pub.as_ref());
+        let sealer = MetadataSealer::from_base64(&encoded).unwrap();
+
+        let sealed = sealer.seal(b"hello").unwrap();
+        assert!(!sealed.contains("hello"));
+        // Fresh ephemeral key per message
+        assert_ne!(sealed, sealer.seal(b"hello").unwrap());
+        assert_eq!(open(recipient, recipient_pub.as_ref(), &sealed), b"hello");
+
+        assert!(MetadataSealer::from_base64("AAAA").is_err());
+        assert!(MetadataSealer::from_base64("not base64!").is_err());
+    }
+
+    #[test]
+    fn test_guard_untracks_stream() {
+        let live = Arc::new(LiveStreams::default());
+        let guard = live.track("POST", "api.example.com", 443, "interactive", None);
+        let _other = live.track("GET", "cdn.example.com", 443, "bulk", None);
+        assert_eq!(live.snapshot_json()["total"], 2);
+        assert_eq!(
+            live.snapshot_json()["streams"][0]["host"],
+            "api.example.com"
+        );
+        drop(guard);
+        let snap = live.snapshot_json();
+        assert_eq!(snap["total"], 1);
+        assert_eq!(snap["streams"][0]["method"], "GET");
+    }
+
+    #[tokio::test]
+    async fn test_kill_notifies_stream() {
+        let live = Arc::new(LiveStreams::default());
+        let guard = live.track("GET", "api.example.com", 443, "bulk", Some("team-a".into()));
+        guard.transfer().sent.fetch_add(5, Ordering::Relaxed);
+        let list = live.list_json();
+        assert_eq!(list[0]["bytes_sent"], 5);
+        assert_eq!(list[0]["client"], "team-a");
+        let id = list[0]["id"].as_u64().unwrap();
+
+        assert!(!live.kill(id + 1000));
+        assert!(live.kill(id));
+        tokio::time::timeout(std::time::Duration::from_secs(1), guard.killed())
+            .await
+            .unwrap();
+    }
+}
@@ -1,15 +1,21 @@
 pub mod client;
+pub mod commands;
 pub mod dispatcher;
+pub mod headers;
+pub mod health;
 pub mod heartbeat;
+pub mod interference;
+pub mod live_view;
 pub mod protocol;
 pub mod stream_handler;
+pub mod upgrade;
 pub mod writer;
 
 use std::sync::Arc;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 use tokio::sync::watch;
-use tracing::{error, info};
+use tracing::{error, info, warn};
 
 use crate::state::{AppState, ServerContext};
 
@@ -88,6 +94,18 @@ pub async fn run(
             consecutive_failures = consecutive_failures.saturating_add(1);
         }
 
+        // Aether may have dropped the node while it was unreachable; the
+        // first connection re-registers before the pool reconnects.
+        let heartbeat_interval = Duration::from_secs(server.dynamic.load().heartbeat_interval);
+        server.control_plane.check(heartbeat_interval);
+        if conn_idx == 0 && server.control_plane.take_register() {
+            let result = crate::registration::reregister(state, server).await;
+            if let Err(e) = &result {
+                warn!(server = %server.server_label, error = %e, "re-registration failed, will retry");
+            }
+            server.control_plane.registered(result.is_ok());
+        }
+
         let reconnect_delay = compute_reconnect_delay(
             state.config.tunnel_reconnect_base_ms,
             state.config.tunnel_reconnect_max_ms,
@@ -0,0 +1,1571 @@
+//! Per-stream request handler.
+//!
+//! Receives request frames, executes the upstream HTTP request,
+//! and sends response frames back through the writer channel.
+//!
+//! The tunnel protocol has no interim-response or trailer frames yet, so
+//! `Expect: 100-continue` is not answered here (the body is already on its
+//! way from Aether) and response trailers are dropped.
+
+use std::collections::HashMap;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use futures_util::StreamExt;
+use http_body_util::{BodyExt, StreamBody};
+use hyper_util::rt::TokioIo;
+use tokio::sync::mpsc;
+use tracing::{debug, warn};
+
+use crate::access_log;
+use crate::circuit_breaker::{Admission, CircuitBreaker};
+use crate::clock;
+use crate::features::Feature;
+use crate::header_rules::HeaderRules;
+use crate::lifecycle::Phase;
+use crate::load_shed;
+use crate::request_id;
+use crate::response_cache::{self, Lookup, ResponseCache};
+use crate::state::{AppState, ServerContext};
+use crate::target_filter;
+use crate::upstream_client::{self, BoxError, UpstreamRequestBody};
+use crate::upstream_proxy::ProxyRoutes;
+use crate::usage;
+
+use super::headers::{self, Anonymity, HopKey};
+use super::live_view::{StreamGuard, Transfer};
+use super::protocol::{
+    compress_payload, decompress_if_gzip, flags, Frame, MsgType, RequestMeta, ResponseMeta,
+};
+use super::upgrade;
+use super::writer::FrameSender;
+
+/// Maximum response body chunk size per frame (32 KB).
+pub(super) const MAX_CHUNK_SIZE: usize = 32 * 1024;
+/// Request bytes held in memory before the rest of the body is streamed.
+const MAX_BUFFERED_BODY: usize = 1024 * 1024;
+
+/// Timeout for sending a single frame to the writer channel.
+/// If the writer is congested (TCP backpressure), we abandon the stream
+/// rather than blocking indefinitely and exhausting the stream pool.
+const FRAME_SEND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Minimum allowed upstream request timeout (seconds).
+const MIN_TIMEOUT_SECS: u64 = 5;
+/// Maximum allowed upstream request timeout (seconds).
+const MAX_TIMEOUT_SECS: u64 = 300;
+
+/// Headers that must not be forwarded to upstream besides the hop-by-hop
+/// ones (see [`headers`]).
+///
+/// `host` and `content-length` are managed by the HTTP client (reqwest/hyper):
+/// - `host` → translated to `:authority` pseudo-header in HTTP/2; forwarding
+///   the original `host` alongside `:authority` triggers PROTOCOL_ERROR on
+///   strict H2 implementations (e.g. Google APIs).
+/// - `content-length` → recalculated by hyper from the actual body; a stale
+///   value from the tunnel (body may have been re-compressed) causes H2
+///   PROTOCOL_ERROR when it mismatches the real frame length.
+///
+/// `via` is rebuilt with this node appended.
+const BLOCKED_HEADERS: &[&str] = &[
+    "content-length",
+    "host",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "via",
+];
+/// Hop counter added to every upstream request.  A request arriving with a
+/// count at or above `max_hops` has already passed through that many
+/// aether-proxy nodes and is rejected to break routing loops.
+const HOP_HEADER: &str = "x-aether-proxy-hops";
+
+/// Handle a single stream: receive body, execute upstream, send response.
+pub async fn handle_stream(
+    state: Arc<AppState>,
+    server: Arc<ServerContext>,
+    stream_id: u32,
+    request_id: String,
+    meta: RequestMeta,
+    mut body_rx: mpsc::Receiver<Frame>,
+    frame_tx: FrameSender,
+) {
+    server.active_connections.fetch_add(1, Ordering::Release);
+    let started = Instant::now();
+    let (host, port) = meta_target(&meta);
+    let method = meta.method.clone();
+    let client = usage::client_of(&meta.headers);
+
+    // Tracked for the live view and the admin API's connection list, where
+    // an operator can also terminate the stream.
+    let live = (state.live_view_sealer.load().is_some() || state.config.admin_listen.is_some())
+        .then(|| {
+            let class = state.load_shedder.classify(&meta);
+            server
+                .live_streams
+                .track(&meta.method, &host, port, class.as_str(), client.clone())
+        });
+    let mut report = StreamReport {
+        transfer: live.as_ref().map(StreamGuard::transfer).unwrap_or_default(),
+        request_id,
+        ..Default::default()
+    };
+    let inner = handle_stream_inner(
+        &state,
+        &server,
+        stream_id,
+        meta,
+        &mut body_rx,
+        &frame_tx,
+        &mut report,
+    );
+    let finished = match &live {
+        Some(guard) => tokio::select! {
+            elapsed = inner => Some(elapsed),
+            _ = guard.killed() => None,
+        },
+        None => Some(inner.await),
+    };
+    let connect_elapsed = match finished {
+        Some(elapsed) => elapsed,
+        None => {
+            warn!(stream_id, "stream terminated by operator");
+            report
+                .fail(&frame_tx, stream_id, "stream terminated by node operator")
+                .await;
+            None
+        }
+    };
+
+    server.active_connections.fetch_sub(1, Ordering::Release);
+    if let Some(d) = connect_elapsed {
+        server.metrics.record_request(d);
+    }
+    let outcome = report.outcome();
+    let bytes_received = report.transfer.received.load(Ordering::Relaxed);
+    let bytes_sent = report.transfer.sent.load(Ordering::Relaxed);
+    if report.close_reason.is_some() {
+        server.metrics.record_upgraded(bytes_received, bytes_sent);
+    }
+    if let Some(client) = client.as_deref() {
+        server.quotas.add_bytes(client, bytes_received + bytes_sent);
+        server.usage.record(
+            client,
+            outcome != access_log::Outcome::Completed,
+            bytes_received,
+            bytes_sent,
+        );
+    }
+    if state.config.destination_report_interval_secs > 0 {
+        server.destinations.record(
+            &state.privacy.host(&host),
+            outcome != access_log::Outcome::Completed,
+            bytes_received,
+            bytes_sent,
+        );
+    }
+    if state.access_log.enabled() {
+        state.access_log.record(access_log::Record {
+            ts: clock::now_ms(),
+            server: server.server_label.clone(),
+            stream_id,
+            request_id: report.request_id,
+            client: state.privacy.client(client),
+            method,
+            host: state.privacy.host(&host),
+            port,
+            status: report.status,
+            outcome,
+            reason: report
+                .error
+                .map(|reason| state.privacy.scrub(reason, &host)),
+            close_reason: report.close_reason,
+            bytes_received,
+            bytes_sent,
+            duration_ms: started.elapsed().as_millis() as u64,
+        });
+    }
+}
+
+/// Account and access-log a stream the dispatcher refused before handling
+/// it.
+pub(super) fn record_rejected(
+    state: &AppState,
+    server: &ServerContext,
+    stream_id: u32,
+    request_id: &str,
+    meta: &RequestMeta,
+    status: Option<u16>,
+    reason: &str,
+) {
+    let client = usage::client_of(&meta.headers);
+    if let Some(client) = client.as_deref() {
+        server.usage.record(client, true, 0, 0);
+    }
+    let (host, port) = meta_target(meta);
+    if state.config.destination_report_interval_secs > 0 {
+        server
+            .destinations
+            .record(&state.privacy.host(&host), true, 0, 0);
+    }
+    if !state.access_log.enabled() {
+        return;
+    }
+    state.access_log.record(access_log::Record {
+        ts: clock::now_ms(),
+        server: server.server_label.clone(),
+        stream_id,
+        request_id: request_id.to_string(),
+        client: state.privacy.client(client),
+        method: meta.method.clone(),
+        host: state.privacy.host(&host),
+        port,
+        status,
+        outcome: access_log::Outcome::Rejected,
+        reason: Some(state.privacy.scrub(reason.to_string(), &host)),
+        close_reason: None,
+        bytes_received: 0,
+        bytes_sent: 0,
+        duration_ms: 0,
+    });
+}
+
+/// What happened on a stream, for the access log.
+#[derive(Default)]
+struct StreamReport {
+    /// Shared with the live view's entry when the stream is tracked.
+    transfer: Arc<Transfer>,
+    /// Returned with every response and logged with the stream.
+    request_id: String,
+    /// Upstream status, once response headers were relayed.
+    status: Option<u16>,
+    /// Error reported to the client in a STREAM_ERROR frame.
+    error: Option<String>,
+    /// The client or the tunnel went away before the stream finished.
+    cancelled: bool,
+    /// How an upgraded stream ended.
+    close_reason: Option<access_log::CloseReason>,
+}
+
+impl StreamReport {
+    /// Report `msg` to the client and remember it as the stream's error.
+    async fn fail(&mut self, tx: &FrameSender, stream_id: u32, msg: &str) {
+        self.error = Some(msg.to_string());
+        send_error(tx, stream_id, msg).await;
+    }
+
+    /// Answer the stream with a `status` response of the node's own,
+    /// carrying `msg`, and remember `msg` as the stream's error.
+    async fn reject(&mut self, tx: &FrameSender, stream_id: u32, status: u16, msg: &str) {
+        self.reject_with(tx, stream_id, status, Vec::new(), msg)
+            .await;
+    }
+
+    /// [`Self::reject`] with extra response headers.
+    async fn reject_with(
+        &mut self,
+        tx: &FrameSender,
+        stream_id: u32,
+        status: u16,
+        mut headers: Vec<(String, String)>,
+        msg: &str,
+    ) {
+        headers.push((request_id::HEADER.to_string(), self.request_id.clone()));
+        self.status = Some(status);
+        self.error = Some(msg.to_string());
+        for frame in local_response_frames(stream_id, status, headers, msg) {
+            if !send_frame(tx, frame).await {
+                break;
+            }
+        }
+    }
+
+    /// Answer the stream with a response from the cache; `how` goes in the
+    /// cache status header.
+    async fn respond_cached(
+        &mut self,
+        tx: &FrameSender,
+        stream_id: u32,
+        entry: &response_cache::Entry,
+        how: &str,
+    ) {
+        let mut headers = entry.response_headers(how);
+        headers.push((request_id::HEADER.to_string(), self.request_id.clone()));
+        self.status = Some(entry.status);
+        let meta = ResponseMeta {
+            status: entry.status,
+            headers,
+        };
+        let (payload, flags) =
+            compress_payload(serde_json::to_vec(&meta).unwrap_or_default().into());
+        let mut frames = vec![Frame::new(
+            stream_id,
+            MsgType::ResponseHeaders,
+            flags,
+            payload,
+        )];
+        let mut offset = 0;
+        while offset < entry.body.len() {
+            let end = (offset + MAX_CHUNK_SIZE).min(entry.body.len());
+            let (payload, flags) = compress_payload(entry.body.slice(offset..end));
+            frames.push(Frame::new(stream_id, MsgType::ResponseBody, flags, payload));
+            offset = end;
+        }
+        frames.push(Frame::new(
+            stream_id,
+            MsgType::StreamEnd,
+            flags::END_STREAM,
+            Bytes::new(),
+        ));
+        for frame in frames {
+            let len = if frame.msg_type == MsgType::ResponseBody {
+                frame.payload.len()
+            } else {
+                0
+            };
+            if !send_frame(tx, frame).await {
+                self.cancelled = true;
+                return;
+            }
+            self.transfer.sent.fetch_add(len as u64, Ordering::Relaxed);
+        }
+    }
+
+    fn outcome(&self) -> access_log::Outcome {
+        if self.error.is_some() {
+            access_log::Outcome::Failed
+        } else if self.cancelled || self.status.is_none() {
+            access_log::Outcome::Cancelled
+        } else {
+            access_log::Outcome::Completed
+        }
+    }
+}
+
+/// What one request found out about its target, for the circuit breaker.
+/// Recorded once the target was tried; a half-open trial the request
+/// ends without using is handed back on drop.
+struct CircuitOutcome<'a> {
+    breaker: &'a CircuitBreaker,
+    target: &'a str,
+    trial: bool,
+}
+
+impl CircuitOutcome<'_> {
+    /// Record whether the target could be reached.  Returns true when this
+    /// opened the circuit.
+    fn record(&mut self, ok: bool) -> bool {
+        self.trial = false;
+        self.breaker.record(self.target, ok)
+    }
+}
+
+impl Drop for CircuitOutcome<'_> {
+    fn drop(&mut self) {
+        if self.trial {
+            self.breaker.release(self.target);
+        }
+    }
+}
+
+/// Send a frame to the writer with a timeout. Returns false if send failed.
+pub(super) async fn send_frame(tx: &FrameSender, frame: Frame) -> bool {
+    match tokio::time::timeout(FRAME_SEND_TIMEOUT, tx.send(frame)).await {
+        Ok(Ok(())) => true,
+        Ok(Err(_)) => {
+            // Channel closed (writer exited)
+            false
+        }
+        Err(_) => {
+            // Timeout — writer is congested
+            warn!("frame send timeout (writer congested), abandoning stream");
+            false
+        }
+    }
+}
+
+/// Returns the connection-establishment duration (DNS + TCP/TLS + TTFB) if the
+/// upstream request succeeded, or `None` if the request never reached the
+/// response-headers stage.
+async fn handle_stream_inner(
+    state: &AppState,
+    server: &ServerContext,
+    stream_id: u32,
+    mut meta: RequestMeta,
+    body_rx: &mut mpsc::Receiver<Frame>,
+    frame_tx: &FrameSender,
+    report: &mut StreamReport,
+) -> Option<Duration> {
+    // Collect request body.  Upgrade requests go out right away: their
+    // "body" is the upgraded byte stream, relayed after the 101 response.
+    let upgrade = upgrade::requested_protocol(&meta).is_some();
+    // Bodies larger than MAX_BUFFERED_BODY are streamed upstream (chunked)
+    // from that point on instead of being held in memory.
+    let mut body_parts: Vec<Bytes> = Vec::new();
+    let mut buffered_len = 0;
+    let mut body_done = upgrade;
+    let max_request_body = body_limit(state.config.max_request_body);
+    if let Some(limit) = max_request_body {
+        if declared_length(&meta.headers).is_some_and(|len| len > limit) {
+            report
+                .reject(frame_tx, stream_id, 413, &request_too_large(limit))
+                .await;
+            return None;
+        }
+    }
+
+    // Drain body frames
+    while !body_done {
+        match body_rx.recv().await {
+            Some(frame) => {
+                if frame.msg_type == MsgType::RequestBody {
+                    let payload = match decompress_if_gzip(&frame) {
+                        Ok(d) => d,
+                        Err(e) => {
+                            report
+                                .fail(frame_tx, stream_id, &format!("gzip decompress failed: {e}"))
+                                .await;
+                            return None;
+                        }
+                    };
+                    if !payload.is_empty() {
+                        report
+                            .transfer
+                            .received
+                            .fetch_add(payload.len() as u64, Ordering::Relaxed);
+                        buffered_len += payload.len();
+                        body_parts.push(payload);
+                    }
+                    if max_request_body.is_some_and(|limit| buffered_len as u64 > limit) {
+                        let limit = state.config.max_request_body;
+                        report
+                            .reject(frame_tx, stream_id, 413, &request_too_large(limit))
+                            .await;
+                        return None;
+                    }
+                    if frame.is_end_stream() {
+                        body_done = true;
+                    } else if buffered_len >= MAX_BUFFERED_BODY {
+                        break;
+                    }
+                } else if frame.msg_type == MsgType::StreamEnd
+                    || frame.msg_type == MsgType::StreamError
+                {
+                    body_done = true;
+                    if frame.msg_type == MsgType::StreamError {
+                        report.cancelled = true;
+                        return None; // Client cancelled
+                    }
+                }
+            }
+            None => {
+                report.cancelled = true;
+                return None; // Channel closed
+            }
+        }
+    }
+
+    let body: Bytes = if body_parts.is_empty() {
+        Bytes::new()
+    } else if body_parts.len() == 1 {
+        body_parts.into_iter().next().unwrap()
+    } else {
+        let total: usize = body_parts.iter().map(|b| b.len()).sum();
+        let mut combined = Vec::with_capacity(total);
+        for part in &body_parts {
+            combined.extend_from_slice(part);
+        }
+        Bytes::from(combined)
+    };
+    let mut streamed_body = (!body_done).then(|| {
+        let rest = std::mem::replace(body_rx, mpsc::channel(1).1);
+        stream_request_body(
+            body.clone(),
+            rest,
+            Arc::clone(&report.transfer),
+            max_request_body,
+        )
+    });
+
+    // Validate target
+    let target_url = match url::Url::parse(&meta.url) {
+        Ok(u) => u,
+        Err(e) => {
+            report
+                .fail(frame_tx, stream_id, &format!("invalid URL: {e}"))
+                .await;
+            return None;
+        }
+    };
+
+    // Only allow http/https schemes (block file://, data://, etc.)
+    match target_url.scheme() {
+        "http" | "https" => {}
+        other => {
+            report
+                .fail(
+                    frame_tx,
+                    stream_id,
+                    &format!("unsupported URL scheme: {other}"),
+                )
+                .await;
+            return None;
+        }
+    }
+
+    let hops = incoming_hops(&meta, &server.hop_key);
+    if hops >= state.config.max_hops {
+        warn!(stream_id, hops, url = %meta.url, "proxy loop suspected, rejecting");
+        report
+            .fail(
+                frame_tx,
+                stream_id,
+                &format!("proxy loop detected ({hops} hops)"),
+            )
+            .await;
+        return None;
+    }
+
+    let Some((host, port)) = target_host_port(&target_url) else {
+        report
+            .fail(frame_tx, stream_id, "missing host in URL")
+            .await;
+        return None;
+    };
+
+    if state
+        .upstream_proxy
+        .as_deref()
+        .is_some_and(|routes| routes.rejects(&host))
+    {
+        let msg = format!("target blocked: {host} rejected by upstream proxy rule");
+        state.activity.record_error(&server.server_label, &msg);
+        report.fail(frame_tx, stream_id, &msg).await;
+        return None;
+    }
+
+    // DNS + target validation (populates dns_cache for SafeDnsResolver)
+    let connect_start = Instant::now();
+    {
+        let dynamic = server.dynamic.load_full();
+        let client_rules =
+            usage::client_of(&meta.headers).and_then(|client| dynamic.client_rules.get(&client));
+        // Names the upstream proxy resolves are not looked up locally
+        let checked = if state
+            .upstream_proxy
+            .as_deref()
+            .is_some_and(|routes| routes.resolves_remotely(&host))
+        {
+            target_filter::validate_proxied_target(
+                &host,
+                port,
+                &dynamic.allowed_ports,
+                &dynamic.domain_rules,
+                &dynamic.cidr_rules,
+                &state.address_policy,
+            )
+        } else {
+            target_filter::validate_target(
+                &host,
+                port,
+                &dynamic.allowed_ports,
+                &dynamic.domain_rules,
+                &dynamic.cidr_rules,
+                &state.address_policy,
+                &state.dns_cache,
+            )
+            .await
+        };
+        if let Err(e) = checked.and_then(|addrs| match client_rules {
+            Some(rules) => rules.check(&host, port, &addrs),
+            None => Ok(()),
+        }) {
+            server.metrics.dns_failures.fetch_add(1, Ordering::Release);
+            let msg = format!("target blocked: {e}");
+            state.activity.record_error(&server.server_label, &msg);
+            report.fail(frame_tx, stream_id, &msg).await;
+            return None;
+        }
+    }
+    let dns_ms = connect_start.elapsed().as_millis() as u64;
+
+    // Targets that keep failing are refused without trying them again.
+    // Checked only now so a request refused above doesn't use up the
+    // half-open trial.
+    let circuit_target = format!("{host}:{port}");
+    let mut circuit = None;
+    if server.feature_enabled(Feature::CircuitBreaker) {
+        match state.circuit_breaker.check(&circuit_target) {
+            Ok(admission) => {
+                circuit = Some(CircuitOutcome {
+                    breaker: &state.circuit_breaker,
+                    target: &circuit_target,
+                    trial: admission == Admission::Trial,
+                });
+            }
+            Err(retry_after) => {
+                let retry_after = retry_after.as_secs().max(1);
+                report
+                    .reject_with(
+                        frame_tx,
+                        stream_id,
+                        502,
+                        vec![("retry-after".to_string(), retry_after.to_string())],
+                        &format!("target {circuit_target} unreachable, circuit open"),
+                    )
+                    .await;
+                return None;
+            }
+        }
+    }
+    let mut record_outcome = |ok: bool| {
+        if circuit.as_mut().is_some_and(|c| c.record(ok)) {
+            let msg = format!("{circuit_target} keeps failing, circuit opened");
+            warn!(stream_id, "{msg}");
+            state.activity.record_error(&server.server_label, &msg);
+        }
+    };
+
+    // Execute upstream request
+    let client = if upgrade {
+        &state.upstream_upgrade_client
+    } else {
+        &state.upstream_client
+    };
+    let timeout = Duration::from_secs(meta.timeout.clamp(MIN_TIMEOUT_SECS, MAX_TIMEOUT_SECS));
+
+    let header_rules = server.dynamic.load().header_rules.clone();
+    let first_body = streamed_body
+        .take()
+        .unwrap_or_else(|| upstream_client::full_body(body.clone()));
+    let mut request = match build_upstream_request(
+        &meta,
+        first_body,
+        outgoing_hops(state, &host, hops),
+        &server.hop_key,
+        state.upstream_proxy.as_deref(),
+        state.anonymity,
+        &header_rules,
+    ) {
+        Ok(request) => request,
+        Err(e) => {
+            report
+                .fail(
+                    frame_tx,
+                    stream_id,
+                    &format!("invalid upstream request: {e}"),
+                )
+                .await;
+            return None;
+        }
+    };
+
+    // Answer from the response cache, or revalidate a stale entry.  Keys
+    // and variants are taken from the headers the target would receive.
+    let cache_slot = state
+        .response_cache
+        .as_ref()
+        .filter(|_| !upgrade && body_done && body.is_empty())
+        .and_then(|cache| {
+            let sent = response_cache::request_headers(request.headers());
+            let key = ResponseCache::key(&meta.method, &meta.url, &sent)?;
+            Some((cache, key, sent))
+        });
+    let mut revalidating = None;
+    if let Some((cache, key, sent)) = &cache_slot {
+        match cache.lookup(key, sent) {
+            Lookup::Fresh(entry) => {
+                report
+                    .respond_cached(frame_tx, stream_id, &entry, "hit")
+                    .await;
+                return None;
+            }
+            Lookup::Stale(entry) => {
+                for (name, value) in entry.validators() {
+                    if let (Ok(name), Ok(value)) = (
+                        hyper::header::HeaderName::from_bytes(name.as_bytes()),
+                        hyper::header::HeaderValue::from_str(&value),
+                    ) {
+                        request.headers_mut().insert(name, value);
+                    }
+                }
+                // Kept for a retry, which rebuilds the request
+                meta.headers.extend(entry.validators());
+                revalidating = Some(entry);
+            }
+            Lookup::Miss => {}
+        }
+    }
+
+    let mut connection_capture = spawn_connection_capture(&mut request);
+
+    let upstream_start = Instant::now();
+    let mut result = tokio::time::timeout(timeout, client.request(request)).await;
+
+    // An immediate RST while connecting is the classic active-blocking
+    // signature: record it, and retry once via the alternate egress if set.
+    if matches!(&result, Ok(Err(e)) if upstream_client::is_connection_reset(e)) {
+        server
+            .metrics
+            .upstream_resets
+            .fetch_add(1, Ordering::Release);
+        let alt_client = state
+            .upstream_alt_client
+            .as_ref()
+            // A streamed body has been consumed and cannot be replayed.
+            .filter(|_| !upgrade && body_done && server.feature_enabled(Feature::EgressRetry));
+        if let Some(alt_client) = alt_client {
+            warn!(
+                stream_id,
+                host = %host,
+                port,
+                "upstream connection reset, retrying via alternate egress"
+            );
+            if let Ok(mut retry_request) = build_upstream_request(
+                &meta,
+                upstream_client::full_body(body.clone()),
+                outgoing_hops(state, &host, hops),
+                &server.hop_key,
+                state.upstream_proxy.as_deref(),
+                state.anonymity,
+                &header_rules,
+            ) {
+                connection_capture.abort();
+                connection_capture = spawn_connection_capture(&mut retry_request);
+                let remaining = timeout.saturating_sub(upstream_start.elapsed());
+                result = tokio::time::timeout(remaining, alt_client.request(retry_request)).await;
+            }
+        } else {
+            warn!(
+                stream_id,
+                host = %host,
+                port,
+                "upstream connection reset during connect"
+            );
+        }
+    }
+
+    let response = match result {
+        Ok(Ok(response)) => {
+            record_outcome(!state.circuit_breaker.is_failure(response.status()));
+            response
+        }
+        // The streamed body went over the limit; nothing was answered yet.
+        Ok(Err(_))
+            if max_request_body
+                .is_some_and(|limit| report.transfer.received.load(Ordering::Relaxed) > limit) =>
+        {
+            connection_capture.abort();
+            let limit = state.config.max_request_body;
+            report
+                .reject(frame_tx, stream_id, 413, &request_too_large(limit))
+                .await;
+            return None;
+        }
+        Ok(Err(e)) => {
+            connection_capture.abort();
+            server
+                .metrics
+                .failed_requests
+                .fetch_add(1, Ordering::Release);
+            if e.is_connect() {
+                record_outcome(false);
+            }
+            let msg = if e.is_connect() {
+                format!("upstream connect error: {e}")
+            } else {
+                format!("upstream error: {e}")
+            };
+            state
+                .activity
+                .record_error(&server.server_label, &format!("{host}:{port} {msg}"));
+            report.fail(frame_tx, stream_id, &msg).await;
+            return None;
+        }
+        Err(_) => {
+            connection_capture.abort();
+            server
+                .metrics
+                .failed_requests
+                .fetch_add(1, Ordering::Release);
+            // A slow answer (a long time to first byte is normal for some
+            // APIs) says nothing about whether the target is reachable.
+            state.activity.record_error(
+                &server.server_label,
+                &format!("{host}:{port} upstream timeout"),
+            );
+            report.fail(frame_tx, stream_id, "upstream timeout").await;
+            return None;
+        }
+    };
+
+    // Capture connection-establishment duration (DNS + TCP/TLS + TTFB)
+    // before proceeding to stream the response body.
+    let connect_elapsed = connect_start.elapsed();
+
+    if let (Some((cache, key, _)), Some(stale)) = (&cache_slot, &revalidating) {
+        if response.status() == hyper::StatusCode::NOT_MODIFIED {
+            let not_modified: Vec<(String, String)> = response
+                .headers()
+                .iter()
+                .filter(|(k, _)| !headers::is_hop_by_hop(k.as_str(), &Default::default()))
+                .filter_map(|(k, v)| Some((k.to_string(), v.to_str().ok()?.to_string())))
+                .collect();
+            let entry = cache.revalidated(key, stale, &not_modified);
+            report
+                .respond_cached(frame_tx, stream_id, &entry, "revalidated")
+                .await;
+            return Some(connect_elapsed);
+        }
+    }
+
+    let max_response_body = body_limit(state.config.max_response_body);
+    if let Some(limit) = max_response_body {
+        let declared = response
+            .headers()
+            .get(hyper::header::CONTENT_LENGTH)
+            .and_then(|v| v.to_str().ok()?.trim().parse::<u64>().ok());
+        if declared.is_some_and(|len| len > limit) {
+            let msg = response_too_large(limit);
+            warn!(stream_id, host = %host, port, "{msg}");
+            report.reject(frame_tx, stream_id, 502, &msg).await;
+            return Some(connect_elapsed);
+        }
+    }
+
+    // Send RESPONSE_HEADERS
+    let status = response.status().as_u16();
+    report.status = Some(status);
+    let ttfb_ms = upstream_start.elapsed().as_millis() as u64;
+    // Short timeout: on connection reuse hyper may never fire the connect
+    // callback, so avoid blocking indefinitely.
+    let connection_acquire_ms =
+        match tokio::time::timeout(Duration::from_millis(100), connection_capture).await {
+            Ok(Ok(ms)) => ms,
+            Ok(Err(_)) => None, // JoinError (task panicked / cancelled)
+            Err(_) => None,     // timeout -- task is detached but lightweight
+        };
+    let request_timing =
+        upstream_client::resolve_request_timing(&response, connection_acquire_ms, ttfb_ms);
+    let mut resp_headers: Vec<(String, String)> = Vec::with_capacity(response.headers().len() + 3);
+    let listed = headers::connection_listed(
+        response
+            .headers()
+            .iter()
+            .filter_map(|(k, v)| Some((k.as_str(), v.to_str().ok()?))),
+    );
+    // A switched connection keeps the headers that describe the switch.
+    let switching = upgrade && status == 101;
+    for (k, v) in response.headers() {
+        let name = k.as_str();
+        if name == "via"
+            || name == request_id::HEADER
+            || (headers::is_hop_by_hop(name, &listed)
+                && !(switching && (name == "connection" || name == "upgrade")))
+        {
+            continue;
+        }
+        if let Ok(vs) = v.to_str() {
+            resp_headers.push((name.to_string(), vs.to_string()));
+        }
+    }
+    let upstream_via = response
+        .headers()
+        .get_all(hyper::header::VIA)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .collect::<Vec<_>>()
+        .join(", ");
+    if state.anonymity.reveals_node() {
+        resp_headers.push(("via".to_string(), headers::via(Some(upstream_via.as_str()))));
+    } else if !upstream_via.is_empty() {
+        resp_headers.push(("via".to_string(), upstream_via));
+    }
+    let timing = serde_json::json!({
+        "dns_ms": dns_ms,
+        "connection_acquire_ms": request_timing.connection_acquire_ms,
+        "connection_reused": request_timing.connection_reused,
+        "connect_ms": request_timing.connect_ms,
+        "tls_ms": request_timing.tls_ms,
+        "ttfb_ms": ttfb_ms,
+        "upstream_ms": ttfb_ms,
+        "response_wait_ms": request_timing.response_wait_ms,
+        "upstream_processing_ms": request_timing.response_wait_ms,
+        "timing_source": "instrumented_connector",
+        "total_ms": connect_elapsed.as_millis() as u64,
+        "body_size": report.transfer.received.load(Ordering::Relaxed),
+        "mode": "tunnel",
+    });
+    resp_headers.push(("x-proxy-timing".to_string(), timing.to_string()));
+    resp_headers.push((request_id::HEADER.to_string(), report.request_id.clone()));
+    // The body is collected for the cache while it is relayed
+    let mut capture = None;
+    if let Some((cache, ..)) = &cache_slot {
+        resp_headers.push((
+            response_cache::STATUS_HEADER.to_string(),
+            "miss".to_string(),
+        ));
+        if ResponseCache::storable(status, &resp_headers) {
+            capture = Some((resp_headers.clone(), Vec::new(), cache.max_entry()));
+        }
+    }
+    let resp_meta = ResponseMeta {
+        status,
+        headers: resp_headers,
+    };
+    let meta_json: Bytes = serde_json::to_vec(&resp_meta).unwrap_or_default().into();
+    let (meta_payload, meta_flags) = compress_payload(meta_json);
+    if !send_frame(
+        frame_tx,
+        Frame::new(
+            stream_id,
+            MsgType::ResponseHeaders,
+            meta_flags,
+            meta_payload,
+        ),
+    )
+    .await
+    {
+        report.cancelled = true;
+        return Some(connect_elapsed);
+    }
+
+    let stall_timeout = (state.config.upstream_stall_timeout_secs > 0)
+        .then(|| Duration::from_secs(state.config.upstream_stall_timeout_secs));
+    if upgrade && status == 101 {
+        let result = match hyper::upgrade::on(response).await {
+            Ok(upgraded) => {
+                upgrade::relay(
+                    TokioIo::new(upgraded),
+                    stream_id,
+                    body_rx,
+                    frame_tx,
+                    &report.transfer,
+                    stall_timeout,
+                )
+                .await
+            }
+            Err(e) => Err(std::io::Error::other(e)),
+        };
+        report.close_reason = Some(match &result {
+            // The dispatcher dropped the stream: client gone, or the node
+            // is shutting down
+            Ok(upgrade::Closed::Abandoned) if state.lifecycle.phase() >= Phase::Draining => {
+                access_log::CloseReason::Shutdown
+            }
+            Ok(upgrade::Closed::Client | upgrade::Closed::Abandoned) => {
+                access_log::CloseReason::ClientClosed
+            }
+            Ok(upgrade::Closed::Target) => access_log::CloseReason::TargetClosed,
+            Err(_) => access_log::CloseReason::Error,
+        });
+        if let Err(e) = result {
+            server.metrics.stream_errors.fetch_add(1, Ordering::Release);
+            let msg = format!("upgraded connection error: {e}");
+            debug!(stream_id, "{msg}");
+            report.fail(frame_tx, stream_id, &msg).await;
+            return Some(connect_elapsed);
+        }
+        let _ = send_frame(
+            frame_tx,
+            Frame::new(
+                stream_id,
+                MsgType::StreamEnd,
+                flags::END_STREAM,
+                Bytes::new(),
+            ),
+        )
+        .await;
+        debug!(stream_id, "upgraded stream completed");
+        return Some(connect_elapsed);
+    }
+
+    // Stream response body — relay upstream bytes through the tunnel.
+    // Apply tunnel-level frame compression for chunks that benefit from it
+    // (e.g. uncompressed SSE text). Already-compressed data (gzip/br from
+    // upstream Content-Encoding) won't shrink further and will be sent as-is
+    // thanks to the size check in compress_payload().
+    let mut stream = response.into_body().into_data_stream();
+    let mut relayed: u64 = 0;
+    loop {
+        let next = match stall_timeout {
+            Some(limit) => match tokio::time::timeout(limit, stream.next()).await {
+                Ok(next) => next,
+                Err(_) => {
+                    server
+                        .metrics
+                        .stalled_streams
+                        .fetch_add(1, Ordering::Release);
+                    let msg = format!("upstream stalled: no data for {}s", limit.as_secs());
+                    warn!(stream_id, host = %host, port, "{msg}");
+                    state
+                        .activity
+                        .record_error(&server.server_label, &format!("{host}:{port} {msg}"));
+                    report.fail(frame_tx, stream_id, &msg).await;
+                    return Some(connect_elapsed);
+                }
+            },
+            None => stream.next().await,
+        };
+        let Some(chunk_result) = next else {
+            break;
+        };
+        match chunk_result {
+            Ok(chunk) => {
+                relayed += chunk.len() as u64;
+                capture = capture.filter(|(_, buf, max)| buf.len() + chunk.len() <= *max);
+                if let Some((_, buf, _)) = &mut capture {
+                    buf.extend_from_slice(&chunk);
+                }
+                if let Some(limit) = max_response_body.filter(|&limit| relayed > limit) {
+                    let msg = response_too_large(limit);
+                    warn!(stream_id, host = %host, port, "{msg}");
+                    report.fail(frame_tx, stream_id, &msg).await;
+                    return Some(connect_elapsed);
+                }
+                report
+                    .transfer
+                    .sent
+                    .fetch_add(chunk.len() as u64, Ordering::Relaxed);
+                if chunk.len() <= MAX_CHUNK_SIZE {
+                    let (payload, extra_flags) = compress_payload(chunk);
+                    if !send_frame(
+                        frame_tx,
+                        Frame::new(stream_id, MsgType::ResponseBody, extra_flags, payload),
+                    )
+                    .await
+                    {
+                        report.cancelled = true;
+                        return Some(connect_elapsed);
+                    }
+                } else {
+                    // Split oversized chunks, compress each slice
+                    let mut offset = 0;
+                    while offset < chunk.len() {
+                        let end = (offset + MAX_CHUNK_SIZE).min(chunk.len());
+                        let slice = chunk.slice(offset..end);
+                        let (payload, extra_flags) = compress_payload(slice);
+                        if !send_frame(
+                            frame_tx,
+                            Frame::new(stream_id, MsgType::ResponseBody, extra_flags, payload),
+                        )
+                        .await
+                        {
+                            report.cancelled = true;
+                            return Some(connect_elapsed);
+                        }
+                        offset = end;
+                    }
+                }
+            }
+            Err(e) => {
+                server.metrics.stream_errors.fetch_add(1, Ordering::Release);
+                warn!(stream_id, error = %e, "upstream body read error");
+                let msg = format!("body read error: {e}");
+                state
+                    .activity
+                    .record_error(&server.server_label, &format!("{host}:{port} {msg}"));
+                report.fail(frame_tx, stream_id, &msg).await;
+                return Some(connect_elapsed);
+            }
+        }
+    }
+
+    if let (Some((cache, key, sent)), Some((headers, body, _))) = (&cache_slot, capture) {
+        cache.store(key, sent, status, &headers, body.into());
+    }
+
+    // Send STREAM_END
+    let _ = send_frame(
+        frame_tx,
+        Frame::new(
+            stream_id,
+            MsgType::StreamEnd,
+            flags::END_STREAM,
+            Bytes::new(),
+        ),
+    )
+    .await;
+
+    debug!(stream_id, status, "stream completed");
+    Some(connect_elapsed)
+}
+
+/// Target host and port of `url`.  `host_str()` keeps the brackets around
+/// IPv6 literals; the parsed host makes `[2001:db8::1]` a plain address for
+/// validation and logging.
+fn target_host_port(url: &url::Url) -> Option<(String, u16)> {
+    let host = match url.host()? {
+        url::Host::Domain(d) => d.to_string(),
+        url::Host::Ipv4(ip) => ip.to_string(),
+        url::Host::Ipv6(ip) => ip.to_string(),
+    };
+    Some((host, url.port_or_known_default().unwrap_or(443)))
+}
+
+/// Target host and port of the request, empty if its URL does not parse.
+fn meta_target(meta: &RequestMeta) -> (String, u16) {
+    url::Url::parse(&meta.url)
+        .ok()
+        .and_then(|url| target_host_port(&url))
+        .unwrap_or_default()
+}
+
+/// A configured body size limit; 0 means unlimited.
+fn body_limit(bytes: u64) -> Option<u64> {
+    (bytes > 0).then_some(bytes)
+}
+
+/// The request's `content-length`, when it sent a valid one.
+fn declared_length(headers: &HashMap<String, String>) -> Option<u64> {
+    headers
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, v)| v.trim().parse().ok())
+}
+
+fn request_too_large(limit: u64) -> String {
+    format!("request body too large (limit {limit} bytes)")
+}
+
+fn response_too_large(limit: u64) -> String {
+    format!("response body too large (limit {limit} bytes)")
+}
+
+/// Number of aether-proxy hops the request has already taken, from
+/// [`HOP_HEADER`] or the hop header signed with `hop_key`.  A
+/// [`HOP_HEADER`] that is not a number in range counts as the maximum, so
+/// it cannot be used to get around `max_hops`; a signed header that does
+/// not verify was not sent by a node and is ignored.
+fn incoming_hops(meta: &RequestMeta, hop_key: &HopKey) -> u8 {
+    meta.headers
+        .iter()
+        .filter_map(|(k, v)| {
+            if k.eq_ignore_ascii_case(HOP_HEADER) {
+                Some(v.trim().parse().unwrap_or(u8::MAX))
+            } else if k.eq_ignore_ascii_case(hop_key.header()) {
+                hop_key.decode(v)
+            } else {
+                None
+            }
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// Hop count to send on to `host`, if any.  A node that hides itself only
+/// tells targets configured as relay nodes, since a constant signed header
+/// on every request would single the node out.
+fn outgoing_hops(state: &AppState, host: &str, hops: u8) -> Option<u8> {
+    (state.anonymity.reveals_node() || state.relay_hosts.iter().any(|p| p.matches_host(host)))
+        .then_some(hops + 1)
+}
+
+/// Build the upstream request from tunnel metadata, dropping blocked headers
+/// and those `anonymity` hides, applying `rules` and stamping the outgoing
+/// hop count, if given (signed with `hop_key` when the node hides itself).
+fn build_upstream_request(
+    meta: &RequestMeta,
+    body: UpstreamRequestBody,
+    hops: Option<u8>,
+    hop_key: &HopKey,
+    proxy: Option<&ProxyRoutes>,
+    anonymity: Anonymity,
+    rules: &HeaderRules,
+) -> Result<hyper::Request<UpstreamRequestBody>, hyper::http::Error> {
+    let method: hyper::Method = meta.method.parse().unwrap_or(hyper::Method::GET);
+    let mut request = hyper::Request::builder()
+        .method(method)
+        .uri(meta.url.as_str())
+        .body(body)?;
+
+    let listed =
+        headers::connection_listed(meta.headers.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+    let client_via = meta
+        .headers
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case("via"))
+        .map(|(_, v)| v.as_str());
+
+    let target_host = request.uri().host().unwrap_or_default().to_string();
+    let headers = request.headers_mut();
+    for (k, v) in &meta.headers {
+        let k_lower = k.to_ascii_lowercase();
+        if BLOCKED_HEADERS.contains(&k_lower.as_str())
+            || headers::is_hop_by_hop(&k_lower, &listed)
+            || anonymity.strips(&k_lower)
+            || k_lower == HOP_HEADER
+            || k_lower == hop_key.header()
+            || k_lower == load_shed::CLASS_HEADER
+            || k_lower == usage::CLIENT_HEADER
+            || k_lower == request_id::HEADER
+        {
+            continue;
+        }
+        if let (Ok(name), Ok(value)) = (
+            hyper::header::HeaderName::from_bytes(k.as_bytes()),
+            hyper::header::HeaderValue::from_str(v),
+        ) {
+            headers.insert(name, value);
+        }
+    }
+    rules.apply(&target_host, headers);
+    if anonymity.reveals_node() {
+        if let Some(hops) = hops {
+            headers.insert(
+                HOP_HEADER,
+                hyper::header::HeaderValue::from(u16::from(hops)),
+            );
+        }
+        if let Ok(via) = hyper::header::HeaderValue::from_str(&headers::via(client_via)) {
+            headers.insert(hyper::header::VIA, via);
+        }
+    } else if let Some((Ok(name), Ok(value))) = hops.map(|hops| {
+        (
+            hyper::header::HeaderName::from_bytes(hop_key.header().as_bytes()),
+            hyper::header::HeaderValue::from_str(&hop_key.encode(hops)),
+        )
+    }) {
+        headers.insert(name, value);
+    }
+    // Connection-level headers are dropped above; an upgrade is the one
+    // hop-by-hop negotiation forwarded as such.
+    if let Some(protocol) = upgrade::requested_protocol(meta) {
+        if let Ok(value) = hyper::header::HeaderValue::from_str(protocol) {
+            headers.insert(hyper::header::UPGRADE, value);
+            headers.insert(
+                hyper::header::CONNECTION,
+                hyper::header::HeaderValue::from_static("upgrade"),
+            );
+        }
+    }
+    let uri = request.uri();
+    if let Some(auth) = uri
+        .host()
+        .and_then(|host| proxy?.select(host)?.single())
+        .and_then(|p| p.authorization_for(uri))
+    {
+        let auth = auth.clone();
+        request
+            .headers_mut()
+            .insert(hyper::header::PROXY_AUTHORIZATION, auth);
+    }
+    Ok(request)
+}
+
+/// Watch for the request's connection metadata and report how long it took
+/// to acquire a connection (pooled or fresh).
+fn spawn_connection_capture(
+    request: &mut hyper::Request<UpstreamRequestBody>,
+) -> tokio::task::JoinHandle<Option<u64>> {
+    let mut captured_connection = upstream_client::capture_connection(request);
+    let connection_start = Instant::now();
+    tokio::spawn(async move {
+        let connected = captured_connection.wait_for_connection_metadata().await;
+        connected
+            .as_ref()
+            .map(|_| connection_start.elapsed().as_millis() as u64)
+    })
+}
+
+/// Request body that starts with the already received `prefix` and goes on
+/// with REQUEST_BODY frames as they arrive, until END_STREAM or a
+/// STREAM_END frame.  A STREAM_ERROR, or a stream that closes without
+/// either end marker, was cancelled: that fails the upstream request
+/// rather than sending it truncated, as does going over `limit` bytes in
+/// total.
+fn stream_request_body(
+    prefix: Bytes,
+    rx: mpsc::Receiver<Frame>,
+    transfer: Arc<Transfer>,
+    limit: Option<u64>,
+) -> UpstreamRequestBody {
+    let start = (rx, false, prefix.len() as u64);
+    let rest = futures_util::stream::unfold(start, move |(mut rx, ended, total)| {
+        let transfer = Arc::clone(&transfer);
+        async move {
+            if ended {
+                return None;
+            }
+            loop {
+                let Some(frame) = rx.recv().await else {
+                    let err: BoxError = "request body cancelled".into();
+                    return Some((Err(err), (rx, true, total)));
+                };
+                match frame.msg_type {
+                    MsgType::RequestBody => {}
+                    MsgType::StreamEnd => return None,
+                    MsgType::StreamError => {
+                        let err: BoxError = "request body cancelled".into();
+                        return Some((Err(err), (rx, true, total)));
+                    }
+                    _ => continue,
+                }
+                let end = frame.is_end_stream();
+                return match decompress_if_gzip(&frame) {
+                    Ok(data) => {
+                        transfer
+                            .received
+                            .fetch_add(data.len() as u64, Ordering::Relaxed);
+                        let total = total + data.len() as u64;
+                        if let Some(limit) = limit.filter(|&limit| total > limit) {
+                            let err: BoxError = request_too_large(limit).into();
+                            return Some((Err(err), (rx, true, total)));
+                        }
+                        Some((Ok(hyper::body::Frame::data(data)), (rx, end, total)))
+                    }
+                    Err(e) => Some((Err(e.into()), (rx, true, total))),
+                };
+            }
+        }
+    });
+    let prefix = futures_util::stream::once(async move { Ok(hyper::body::Frame::data(prefix)) });
+    StreamBody::new(prefix.chain(rest)).boxed_unsync()
+}
+
+/// A complete plain-text response generated by the node itself.
+pub(super) fn local_response_frames(
+    stream_id: u32,
+    status: u16,
+    mut headers: Vec<(String, String)>,
+    body: &str,
+) -> [Frame; 3] {
+    headers.insert(
+        0,
+        (
+            "content-type".to_string(),
+            "text/plain; charset=utf-8".to_string(),
+        ),
+    );
+    let meta = ResponseMeta { status, headers };
+    let (meta_payload, meta_flags) =
+        compress_payload(serde_json::to_vec(&meta).unwrap_or_default().into());
+    [
+        Frame::new(
+            stream_id,
+            MsgType::ResponseHeaders,
+            meta_flags,
+            meta_payload,
+        ),
+        Frame::new(
+            stream_id,
+            MsgType::ResponseBody,
+            0,
+            Bytes::from(body.to_string()),
+        ),
+        Frame::new(
+            stream_id,
+            MsgType::StreamEnd,
+            flags::END_STREAM,
+            Bytes::new(),
+        ),
+    ]
+}
+
+async fn send_error(tx: &FrameSender, stream_id: u32, msg: &str) {
+    // Error frames use best-effort delivery — don't block if writer is congested
+    let _ = send_frame(
+        tx,
+        Frame::new(
+            stream_id,
+            MsgType::StreamError,
+            0,
+            Bytes::from(msg.to_string()),
+        ),
+    )
+    .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meta(headers: &[(&str, &str)]) -> RequestMeta {
+        RequestMeta {
+            method: "GET".into(),
+            url: "https://api.example.com/v1/models".into(),
+            headers: headers
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            timeout: 60,
+        }
+    }
+
+    #[test]
+    fn test_unparsable_hop_count_is_rejected() {
+        let key = HopKey::new("ae_token");
+        assert_eq!(incoming_hops(&meta(&[]), &key), 0);
+        assert_eq!(
+            incoming_hops(&meta(&[("X-Aether-Proxy-Hops", " 2 ")]), &key),
+            2
+        );
+        // At or above any max_hops, so the request is refused
+        for bad in ["999", "-1", "abc", ""] {
+            assert_eq!(
+                incoming_hops(&meta(&[(HOP_HEADER, bad)]), &key),
+                u8::MAX,
+                "{bad:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_hop_count_survives_elite_mode() {
+        let key = HopKey::new("ae_token");
+        let build = |meta: &RequestMeta, anonymity| {
+            let hops = incoming_hops(meta, &key) + 1;
+            let body = upstream_client::full_body(Bytes::new());
+            build_upstream_request(
+                meta,
+                body,
+                Some(hops),
+                &key,
+                None,
+                anonymity,
+                &HeaderRules::default(),
+            )
+            .unwrap()
+        };
+        let request = build(&meta(&[(HOP_HEADER, "1")]), Anonymity::Elite);
+        assert!(request.headers().get(HOP_HEADER).is_none());
+        assert!(request.headers().get(hyper::header::VIA).is_none());
+        let signed = request.headers()[key.header()].to_str().unwrap();
+        assert_eq!(key.decode(signed), Some(2));
+
+        // The next node counts the signed header, whatever its own level
+        let next = meta(&[(key.header(), signed)]);
+        assert_eq!(incoming_hops(&next, &key), 2);
+        let request = build(&next, Anonymity::Transparent);
+        assert_eq!(request.headers()[HOP_HEADER], "3");
+        assert!(request.headers().get(key.header()).is_none());
+    }
+
+    #[test]
+    fn test_elite_mode_sends_no_hop_count_to_plain_targets() {
+        let key = HopKey::new("ae_token");
+        let signed = key.encode(1);
+        let meta = meta(&[(HOP_HEADER, "1"), (key.header(), &signed)]);
+        let request = build_upstream_request(
+            &meta,
+            upstream_client::full_body(Bytes::new()),
+            None,
+            &key,
+            None,
+            Anonymity::Elite,
+            &HeaderRules::default(),
+        )
+        .unwrap();
+        assert!(request.headers().get(HOP_HEADER).is_none());
+        assert!(request.headers().get(key.header()).is_none());
+    }
+
+    #[test]
+    fn test_client_hop_markers_are_ignored() {
+        let key = HopKey::new("ae_token");
+        // Unsigned values under the signed name, or any other header,
+        // neither count nor trip the loop check
+        assert_eq!(incoming_hops(&meta(&[(key.header(), "999")]), &key), 0);
+        assert_eq!(incoming_hops(&meta(&[("x-h7k", "abc")]), &key), 0);
+        let other = HopKey::new("ae_other");
+        let foreign = other.encode(9);
+        assert_eq!(incoming_hops(&meta(&[(key.header(), &foreign)]), &key), 0);
+    }
+
+    #[tokio::test]
+    async fn test_streamed_request_body() {
+        let (tx, rx) = mpsc::channel(4);
+        let transfer = Arc::new(Transfer::default());
+        transfer.received.store(2, Ordering::Relaxed);
+        let body = stream_request_body(Bytes::from_static(b"ab"), rx, Arc::clone(&transfer), None);
+        tx.send(Frame::new(1, MsgType::RequestBody, 0, &b"cd"[..]))
+            .await
+            .unwrap();
+        tx.send(Frame::new(
+            1,
+            MsgType::RequestBody,
+            flags::END_STREAM,
+            &b"ef"[..],
+        ))
+        .await
+        .unwrap();
+        let collected = body.collect().await.unwrap().to_bytes();
+        assert_eq!(collected, &b"abcdef"[..]);
+        assert_eq!(transfer.received.load(Ordering::Relaxed), 6);
+
+        // A separate STREAM_END frame ends the body normally
+        let (tx, rx) = mpsc::channel(4);
+        let body = stream_request_body(Bytes::from_static(b"ab"), rx, Arc::default(), None);
+        tx.send(Frame::new(1, MsgType::RequestBody, 0, &b"cd"[..]))
+            .await
+            .unwrap();
+        tx.send(Frame::new(1, MsgType::StreamEnd, 0, Bytes::new()))
+            .await
+            .unwrap();
+        drop(tx);
+        let collected = body.collect().await.unwrap().to_bytes();
+        assert_eq!(collected, &b"abcd"[..]);
+
+        // STREAM_ERROR cancels it
+        let (tx, rx) = mpsc::channel(4);
+        let body = stream_request_body(Bytes::new(), rx, Arc::default(), None);
+        tx.send(Frame::new(1, MsgType::StreamError, 0, Bytes::new()))
+            .await
+            .unwrap();
+        assert!(body.collect().await.is_err());
+
+        // Closed without END_STREAM: the upstream request must fail
+        let (tx, rx) = mpsc::channel(4);
+        let body = stream_request_body(Bytes::new(), rx, Arc::default(), None);
+        tx.send(Frame::new(1, MsgType::RequestBody, 0, &b"partial"[..]))
+            .await
+            .unwrap();
+        drop(tx);
+        assert!(body.collect().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_streamed_request_body_limit() {
+        let (tx, rx) = mpsc::channel(4);
+        let transfer = Arc::new(Transfer::default());
+        let body = stream_request_body(
+            Bytes::from_static(b"abcd"),
+            rx,
+            Arc::clone(&transfer),
+            Some(6),
+        );
+        tx.send(Frame::new(1, MsgType::RequestBody, 0, &b"ef"[..]))
+            .await
+            .unwrap();
+        tx.send(Frame::new(
+            1,
+            MsgType::RequestBody,
+            flags::END_STREAM,
+            &b"g"[..],
+        ))
+        .await
+        .unwrap();
+        let err = body.collect().await.unwrap_err();
+        assert!(err.to_string().contains("too large"));
+        assert_eq!(transfer.received.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn test_local_response_frames() {
+        let frames = local_response_frames(7, 413, Vec::new(), "too large");
+        assert_eq!(frames[0].msg_type, MsgType::ResponseHeaders);
+        let meta: serde_json::Value =
+            serde_json::from_slice(&decompress_if_gzip(&frames[0]).unwrap()).unwrap();
+        assert_eq!(meta["status"], 413);
+        assert_eq!(&frames[1].payload[..], b"too large");
+        assert!(frames[2].is_end_stream());
+        assert_eq!(
+            declared_length(&HashMap::from([(
+                "Content-Length".to_string(),
+                " 12 ".to_string()
+            )])),
+            Some(12)
+        );
+    }
+}
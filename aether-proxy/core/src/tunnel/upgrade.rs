@@ -0,0 +1,208 @@
+//! Relaying upgraded connections (`101 Switching Protocols`, e.g. WebSocket).
+//!
+//! An upgrade request is sent upstream as soon as its headers arrive
+//! instead of waiting for the request body.  Once the origin switches
+//! protocols, the client's bytes keep arriving as REQUEST_BODY frames and
+//! the origin's bytes are returned as RESPONSE_BODY frames until either
+//! side closes, the same way a CONNECT tunnel would carry them.  An origin
+//! that stops taking the client's bytes for the stall timeout is treated
+//! as having closed, so its stream's frames don't pile up in the
+//! dispatcher.
+
+use std::io;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use bytes::Bytes;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc;
+use tracing::debug;
+
+use super::live_view::Transfer;
+use super::protocol::{compress_payload, decompress_if_gzip, Frame, MsgType, RequestMeta};
+use super::stream_handler::{send_frame, MAX_CHUNK_SIZE};
+use super::writer::FrameSender;
+
+/// The protocol named in `Upgrade` when `Connection` carries the `upgrade`
+/// token, i.e. the client asked to switch protocols.
+pub fn requested_protocol(meta: &RequestMeta) -> Option<&str> {
+    let header = |name: &str| {
+        meta.headers
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    };
+    let connection = header("connection")?;
+    if !connection
+        .split(',')
+        .any(|token| token.trim().eq_ignore_ascii_case("upgrade"))
+    {
+        return None;
+    }
+    header("upgrade")
+        .map(str::trim)
+        .filter(|proto| !proto.is_empty())
+}
+
+/// The side that ended a relayed connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Closed {
+    /// The client sent its end of stream.
+    Client,
+    /// The stream's body channel closed without an end of stream.
+    Abandoned,
+    /// The origin closed first, or stopped reading.
+    Target,
+}
+
+/// Shuttle bytes between the tunnel stream and the upgraded connection.
+/// Returns once the origin closes or takes no bytes for `stall_timeout`,
+/// or the client closes and the origin has finished sending.
+pub async fn relay<T>(
+    upgraded: T,
+    stream_id: u32,
+    body_rx: &mut mpsc::Receiver<Frame>,
+    frame_tx: &FrameSender,
+    transfer: &Transfer,
+    stall_timeout: Option<Duration>,
+) -> io::Result<Closed>
+where
+    T: AsyncRead + AsyncWrite,
+{
+    let (mut origin_rd, mut origin_wr) = tokio::io::split(upgraded);
+
+    let upload = async {
+        let mut closed = Closed::Abandoned;
+        while let Some(frame) = body_rx.recv().await {
+            match frame.msg_type {
+                MsgType::RequestBody => {
+                    let data = decompress_if_gzip(&frame)?;
+                    let write = origin_wr.write_all(&data);
+                    match stall_timeout {
+                        Some(limit) => match tokio::time::timeout(limit, write).await {
+                            Ok(written) => written?,
+                            Err(_) => {
+                                debug!(stream_id, "origin stopped reading, closing");
+                                return Ok(Closed::Target);
+                            }
+                        },
+                        None => write.await?,
+                    }
+                    transfer
+                        .received
+                        .fetch_add(data.len() as u64, Ordering::Relaxed);
+                    if frame.is_end_stream() {
+                        closed = Closed::Client;
+                        break;
+                    }
+                }
+                MsgType::StreamEnd => {
+                    closed = Closed::Client;
+                    break;
+                }
+                MsgType::StreamError => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::ConnectionAborted,
+                        "client cancelled the stream",
+                    ))
+                }
+                _ => {}
+            }
+        }
+        origin_wr.shutdown().await.map(|()| closed)
+    };
+
+    let download = async {
+        let mut buf = vec![0u8; MAX_CHUNK_SIZE];
+        loop {
+            let n = origin_rd.read(&mut buf).await?;
+            if n == 0 {
+                return Ok(());
+            }
+            transfer.sent.fetch_add(n as u64, Ordering::Relaxed);
+            let (payload, flags) = compress_payload(Bytes::copy_from_slice(&buf[..n]));
+            if !send_frame(
+                frame_tx,
+                Frame::new(stream_id, MsgType::ResponseBody, flags, payload),
+            )
+            .await
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::BrokenPipe,
+                    "tunnel writer closed",
+                ));
+            }
+        }
+    };
+
+    tokio::pin!(upload, download);
+    tokio::select! {
+        result = &mut download => result.map(|()| Closed::Target),
+        result = &mut upload => match result? {
+            Closed::Target => Ok(Closed::Target),
+            closed => download.await.map(|()| closed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meta(headers: &[(&str, &str)]) -> RequestMeta {
+        RequestMeta {
+            method: "GET".into(),
+            url: "https://example.com/ws".into(),
+            headers: headers
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+            timeout: 60,
+        }
+    }
+
+    #[test]
+    fn test_requested_protocol() {
+        assert_eq!(
+            requested_protocol(&meta(&[
+                ("Connection", "keep-alive, Upgrade"),
+                ("Upgrade", "websocket"),
+            ])),
+            Some("websocket")
+        );
+        assert_eq!(requested_protocol(&meta(&[("Upgrade", "websocket")])), None);
+        assert_eq!(
+            requested_protocol(&meta(&[("Connection", "upgrade")])),
+            None
+        );
+        assert_eq!(requested_protocol(&meta(&[("Connection", "close")])), None);
+    }
+
+    #[tokio::test]
+    async fn test_origin_that_stops_reading_is_closed() {
+        // The test keeps the origin's end open and never reads from it
+        let (relayed, _origin) = tokio::io::duplex(64);
+        let (body_tx, mut body_rx) = mpsc::channel(8);
+        let (frame_tx, _frame_rx) = mpsc::channel(8);
+        body_tx
+            .send(Frame::new(1, MsgType::RequestBody, 0, vec![0u8; 1024]))
+            .await
+            .unwrap();
+        let transfer = Transfer::default();
+        let closed = tokio::time::timeout(
+            Duration::from_secs(5),
+            relay(
+                relayed,
+                1,
+                &mut body_rx,
+                &frame_tx,
+                &transfer,
+                Some(Duration::from_millis(50)),
+            ),
+        )
+        .await
+        .expect("relay gives up on the stalled origin")
+        .unwrap();
+        assert_eq!(closed, Closed::Target);
+    }
+}
@@ -0,0 +1,1025 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use bytes::Bytes;
+use http_body_util::combinators::UnsyncBoxBody;
+use http_body_util::{BodyExt, Full};
+use hyper::rt;
+use hyper::Response;
+use hyper::Uri;
+pub use hyper_util::client::legacy::connect::capture_connection;
+use hyper_util::client::legacy::connect::dns::Name;
+use hyper_util::client::legacy::connect::{Connected, Connection, HttpConnector};
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::{TokioExecutor, TokioIo, TokioTimer};
+use rustls::pki_types::ServerName;
+use rustls::ClientConfig;
+use tokio::net::TcpStream;
+use tokio_rustls::TlsConnector;
+use tower_service::Service;
+use tracing::debug;
+
+use crate::config::Config;
+use crate::target_filter::{self, AddressPolicy, DnsCache, DomainPattern};
+use crate::upstream_proxy::{ProxyPool, ProxyRoutes, UpstreamProxy};
+use crate::upstream_tls::UpstreamTls;
+use crate::warm_pool::WarmPool;
+
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+type PlainStream = TokioIo<TcpStream>;
+type Connecting = Pin<Box<dyn Future<Output = Result<PlainStream, BoxError>> + Send>>;
+type TlsStream = TokioIo<tokio_rustls::client::TlsStream<TcpStream>>;
+
+/// Either a fully buffered body or one streamed from the tunnel.
+pub type UpstreamRequestBody = UnsyncBoxBody<Bytes, BoxError>;
+pub type UpstreamClient = Client<InstrumentedConnector, UpstreamRequestBody>;
+
+/// Request body that is already fully received.
+pub fn full_body(data: Bytes) -> UpstreamRequestBody {
+    Full::new(data).map_err(Into::into).boxed_unsync()
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ConnectTiming {
+    pub connect_ms: u64,
+    pub tls_ms: u64,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct RequestTiming {
+    pub connection_acquire_ms: u64,
+    pub connect_ms: u64,
+    pub tls_ms: u64,
+    pub response_wait_ms: u64,
+    pub connection_reused: bool,
+}
+
+#[derive(Clone)]
+pub struct ValidatedResolver {
+    dns_cache: Arc<DnsCache>,
+    address_policy: Arc<AddressPolicy>,
+    /// Addresses handed to the connector, which tries them in order.
+    max_addrs: usize,
+}
+
+impl ValidatedResolver {
+    pub fn new(
+        dns_cache: Arc<DnsCache>,
+        address_policy: Arc<AddressPolicy>,
+        max_addrs: usize,
+    ) -> Self {
+        Self {
+            dns_cache,
+            address_policy,
+            max_addrs,
+        }
+    }
+}
+
+pub struct ValidatedAddrs {
+    inner: std::vec::IntoIter<SocketAddr>,
+}
+
+impl Iterator for ValidatedAddrs {
+    type Item = SocketAddr;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next()
+    }
+}
+
+impl Service<Name> for ValidatedResolver {
+    type Response = ValidatedAddrs;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        let dns_cache = Arc::clone(&self.dns_cache);
+        let address_policy = Arc::clone(&self.address_policy);
+        let host = name.as_str().to_string();
+        let max_addrs = self.max_addrs;
+        Box::pin(async move {
+            if let Some(addrs) = dns_cache.get_by_host(&host).await {
+                return Ok(ValidatedAddrs {
+                    inner: connect_order((*addrs).clone(), max_addrs).into_iter(),
+                });
+            }
+
+            let resolved = target_filter::resolve_public_addrs(
+                &host,
+                0,
+                address_policy.as_ref(),
+                dns_cache.as_ref(),
+            )
+            .await
+            .map_err(|err| io::Error::other(err.to_string()))?;
+            Ok(ValidatedAddrs {
+                inner: connect_order(resolved, max_addrs).into_iter(),
+            })
+        })
+    }
+}
+
+/// The first `max` addresses to try, in connection order.  A refused or
+/// timed-out address makes the connector move on to the next one.
+fn connect_order(addrs: Vec<SocketAddr>, max: usize) -> Vec<SocketAddr> {
+    let mut ordered = interleave_families(addrs);
+    ordered.truncate(max.max(1));
+    ordered
+}
+
+/// Order addresses for connection racing per RFC 8305 §4: alternate
+/// families starting with IPv6, preserving resolver order within each.
+/// The connector tries the first family and starts the other after the
+/// Happy Eyeballs delay.
+fn interleave_families(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let (v6, v4): (Vec<_>, Vec<_>) = addrs.into_iter().partition(SocketAddr::is_ipv6);
+    let mut v6 = v6.into_iter();
+    let mut v4 = v4.into_iter();
+    let mut out = Vec::with_capacity(v6.len() + v4.len());
+    loop {
+        match (v6.next(), v4.next()) {
+            (None, None) => break,
+            (a, b) => out.extend(a.into_iter().chain(b)),
+        }
+    }
+    out
+}
+
+#[derive(Clone)]
+pub struct InstrumentedConnector {
+    http: HttpConnector<ValidatedResolver>,
+    tls_config: Arc<ClientConfig>,
+    /// Proxy routing table and the connector used to reach the proxies.
+    /// Proxy hosts are resolved normally: they are operator-configured,
+    /// not targets.
+    proxy: Option<(Arc<ProxyRoutes>, HttpConnector)>,
+    /// Validates targets that a `socks5://` proxy should connect to by IP.
+    resolver: ValidatedResolver,
+    /// Plain-HTTP hosts spoken to with HTTP/2 prior knowledge (h2c).
+    h2c_hosts: Arc<Vec<DomainPattern>>,
+    /// Bound on the proxy negotiation and on the TLS handshake.
+    handshake_timeout: Duration,
+    /// Client configs for targets advertising ECH, with `--upstream-ech`.
+    ech: Option<Arc<EchConfigs>>,
+    /// Ready connections to hot targets.
+    warm: Option<Arc<WarmPool>>,
+}
+
+/// ECH client configs, one per ECH config list seen in DNS.
+struct EchConfigs {
+    tls: UpstreamTls,
+    alpn: Vec<Vec<u8>>,
+    built: Mutex<HashMap<Arc<[u8]>, Arc<ClientConfig>>>,
+}
+
+impl EchConfigs {
+    /// Bound on distinct config lists kept; they rotate with the DNS.
+    const CAPACITY: usize = 256;
+
+    /// The client config for `list`; `None` when no config in it is usable,
+    /// in which case the target gets a normal handshake.
+    fn get(&self, host: &str, list: &Arc<[u8]>) -> Option<Arc<ClientConfig>> {
+        let mut built = self.built.lock().unwrap();
+        if let Some(config) = built.get(list) {
+            return Some(Arc::clone(config));
+        }
+        let config = match self.tls.ech_client_config(list, self.alpn.clone()) {
+            Ok(config) => Arc::new(config),
+            Err(e) => {
+                tracing::debug!(host, error = %e, "unusable ECH config, not using ECH");
+                return None;
+            }
+        };
+        if built.len() >= Self::CAPACITY {
+            built.clear();
+        }
+        built.insert(Arc::clone(list), Arc::clone(&config));
+        Some(config)
+    }
+}
+
+/// Whether a TLS handshake failed because the server refused our ECH config.
+fn ech_rejected(err: &io::Error) -> bool {
+    err.get_ref()
+        .and_then(|inner| inner.downcast_ref::<rustls::Error>())
+        .is_some_and(|e| {
+            matches!(
+                e,
+                rustls::Error::PeerIncompatible(
+                    rustls::PeerIncompatible::ServerRejectedEncryptedClientHello(_)
+                )
+            )
+        })
+}
+
+impl InstrumentedConnector {
+    /// Target host for a SOCKS5 `CONNECT`: resolved and validated here
+    /// unless the proxy does the resolution itself.
+    async fn socks_target(
+        mut resolver: ValidatedResolver,
+        proxy: &UpstreamProxy,
+        host: &str,
+    ) -> io::Result<String> {
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if !proxy.resolves_locally() || host.parse::<IpAddr>().is_ok() {
+            return Ok(host.to_string());
+        }
+        let name = Name::from_str(host).map_err(io::Error::other)?;
+        let mut addrs = resolver.call(name).await?;
+        addrs
+            .next()
+            .map(|addr| addr.ip().to_string())
+            .ok_or_else(|| io::Error::other(format!("no usable address for {host}")))
+    }
+}
+
+impl Service<Uri> for InstrumentedConnector {
+    type Response = TimedConn;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        if let Some((_, http)) = &mut self.proxy {
+            if let Poll::Ready(Err(err)) = http.poll_ready(cx) {
+                return Poll::Ready(Err(err.into()));
+            }
+        }
+        self.http.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, dst: Uri) -> Self::Future {
+        if let Some(conn) = self.warm.as_ref().and_then(|pool| pool.take(&dst)) {
+            return Box::pin(async move { Ok(conn) });
+        }
+        let scheme = dst.scheme_str().map(|value| value.to_ascii_lowercase());
+        let tls_config = Arc::clone(&self.tls_config);
+        let resolver = self.resolver.clone();
+        let h2c = dst
+            .host()
+            .is_some_and(|host| self.h2c_hosts.iter().any(|p| p.matches_host(host)));
+        let route = self.proxy.as_ref().and_then(|(routes, http)| {
+            let pool = Arc::clone(routes.select(dst.host()?)?);
+            Some((pool, http.clone()))
+        });
+        let direct: Option<Connecting> = route.is_none().then(|| {
+            let connecting = self.http.call(dst.clone());
+            Box::pin(async move { connecting.await.map_err(Into::into) }) as Connecting
+        });
+        let connect_start = std::time::Instant::now();
+        let handshake_timeout = self.handshake_timeout;
+        let ech = self.ech.clone();
+        let dns_cache = Arc::clone(&self.resolver.dns_cache);
+
+        Box::pin(async move {
+            let default_port = match scheme.as_deref() {
+                Some("http") => 80,
+                Some("https") => 443,
+                Some(other) => {
+                    return Err(io::Error::other(format!("unsupported scheme {other}")).into())
+                }
+                None => return Err(io::Error::other("missing scheme").into()),
+            };
+            let server_name = if default_port == 443 {
+                Some(resolve_server_name(&dst)?)
+            } else {
+                None
+            };
+
+            // The target's ECH config is looked up while connecting
+            let ech_host = match (&ech, &server_name) {
+                (Some(_), Some(ServerName::DnsName(name))) => Some(name.as_ref().to_string()),
+                _ => None,
+            };
+            let ech_list = async {
+                match (&ech_host, dns_cache.ech()) {
+                    (Some(host), Some(lookup)) => lookup.config_list(host).await,
+                    _ => None,
+                }
+            };
+            let connect = async {
+                Ok::<_, BoxError>(match (direct, route) {
+                    (Some(direct), _) => (direct.await?, None),
+                    (None, Some((pool, http))) => {
+                        let mut order = pool.failover_order().into_iter();
+                        let (tcp, proxy) =
+                            connect_pool(&pool, &mut order, http.clone(), None).await?;
+                        let route = PoolAttempt {
+                            pool,
+                            order,
+                            http,
+                            proxy,
+                        };
+                        (tcp, Some(route))
+                    }
+                    (None, None) => unreachable!("either a direct or a proxied route is chosen"),
+                })
+            };
+            let (connected, ech_list) = tokio::join!(connect, ech_list);
+            let (mut tcp, mut route) = connected?;
+            let ech_config = match (&ech, &ech_host, ech_list) {
+                (Some(ech), Some(host), Some(list)) => ech.get(host, &list),
+                _ => None,
+            };
+
+            // A member that fails the tunnel negotiation or drops the TLS
+            // handshake is marked down like one that refuses the TCP
+            // connection, and the next member is tried.
+            loop {
+                // Errors carry whether the proxy member is to blame.
+                let attempt: Result<TimedConn, (BoxError, bool)> = async {
+                    // Requests on a connection to a known HTTP proxy are
+                    // written in absolute-form; everything else is tunneled
+                    // to the target.
+                    let mut proxied = false;
+                    if let Some(route) = &route {
+                        if server_name.is_none() && route.pool.forwards_plain_http() {
+                            proxied = true;
+                        } else {
+                            let host = dst
+                                .host()
+                                .ok_or_else(|| (io::Error::other("missing host").into(), false))?;
+                            let port = dst.port_u16().unwrap_or(default_port);
+                            let target = Self::socks_target(resolver.clone(), &route.proxy, host)
+                                .await
+                                .map_err(|e| (e.into(), false))?;
+                            let tunnel = route.proxy.tunnel(tcp.inner_mut(), &target, port);
+                            handshake(handshake_timeout, "upstream proxy handshake", tunnel)
+                                .await
+                                .map_err(|e| (e.into(), true))?;
+                        }
+                    }
+                    let connect_ms = connect_start.elapsed().as_millis() as u64;
+
+                    let Some(server_name) = server_name.clone() else {
+                        let mut conn = TimedConn::new(
+                            MaybeHttpsStream::Http(tcp),
+                            ConnectTiming {
+                                connect_ms,
+                                tls_ms: 0,
+                            },
+                        );
+                        conn.proxied = proxied;
+                        // An HTTP proxy relays absolute-form HTTP/1.1 only.
+                        conn.h2c = h2c && !proxied;
+                        return Ok(conn);
+                    };
+
+                    let tls_start = std::time::Instant::now();
+                    let config = ech_config
+                        .clone()
+                        .unwrap_or_else(|| Arc::clone(&tls_config));
+                    let tls = TlsConnector::from(config).connect(server_name, tcp.into_inner());
+                    let tls_stream = match handshake(handshake_timeout, "TLS handshake", tls).await
+                    {
+                        Err(err) if ech_config.is_some() && ech_rejected(&err) => {
+                            // Stale config: the next connection queries DNS again
+                            let host = ech_host.clone().unwrap_or_default();
+                            if let Some(lookup) = dns_cache.ech() {
+                                lookup.forget(&host);
+                            }
+                            let err = io::Error::other(format!(
+                                "{host} rejected Encrypted Client Hello: {err}"
+                            ));
+                            return Err((err.into(), false));
+                        }
+                        // Through a proxy, a handshake that is cut off or
+                        // stalls is the proxy's doing; certificate and
+                        // protocol errors come from the target.
+                        Err(err) => {
+                            let member =
+                                route.is_some() && err.kind() != io::ErrorKind::InvalidData;
+                            return Err((err.into(), member));
+                        }
+                        Ok(stream) => stream,
+                    };
+                    let tls_ms = tls_start.elapsed().as_millis() as u64;
+
+                    Ok(TimedConn::new(
+                        MaybeHttpsStream::Https(Box::new(TokioIo::new(tls_stream))),
+                        ConnectTiming { connect_ms, tls_ms },
+                    ))
+                }
+                .await;
+
+                let Some(route) = route.as_mut() else {
+                    return attempt.map_err(|(err, _)| err);
+                };
+                match attempt {
+                    Ok(conn) => {
+                        route.pool.mark(&route.proxy, true);
+                        return Ok(conn);
+                    }
+                    Err((err, false)) => return Err(err),
+                    Err((err, true)) => {
+                        debug!(
+                            proxy = %route.proxy.display(),
+                            error = %err,
+                            "upstream proxy failed the handshake, trying the next member"
+                        );
+                        route.pool.mark(&route.proxy, false);
+                        let next = connect_pool(
+                            &route.pool,
+                            &mut route.order,
+                            route.http.clone(),
+                            Some(err),
+                        );
+                        (tcp, route.proxy) = next.await?;
+                    }
+                }
+            }
+        })
+    }
+}
+
+/// A proxied connection in progress: the pool, the members not yet tried
+/// and the member currently connected.
+struct PoolAttempt {
+    pool: Arc<ProxyPool>,
+    order: std::vec::IntoIter<Arc<UpstreamProxy>>,
+    http: HttpConnector,
+    proxy: Arc<UpstreamProxy>,
+}
+
+/// Await a handshake `step`, failing it after `timeout`.
+async fn handshake<T>(
+    timeout: Duration,
+    step: &str,
+    fut: impl Future<Output = io::Result<T>>,
+) -> io::Result<T> {
+    tokio::time::timeout(timeout, fut).await.map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("{step} timed out after {}s", timeout.as_secs()),
+        )
+    })?
+}
+
+/// Connect to the next reachable member of `pool` in `order`, marking the
+/// ones that refuse down.  Members are marked up once the whole connection
+/// is established.  `last_err` is returned if no member is left.
+async fn connect_pool(
+    pool: &ProxyPool,
+    order: &mut std::vec::IntoIter<Arc<UpstreamProxy>>,
+    mut http: HttpConnector,
+    mut last_err: Option<BoxError>,
+) -> Result<(PlainStream, Arc<UpstreamProxy>), BoxError> {
+    for proxy in order {
+        match http.call(proxy.uri()).await {
+            Ok(tcp) => return Ok((tcp, proxy)),
+            Err(err) => {
+                pool.mark(&proxy, false);
+                last_err = Some(err.into());
+            }
+        }
+    }
+    Err(last_err.unwrap_or_else(|| "no upstream proxy configured".into()))
+}
+
+/// Local source binding for outbound upstream connections.
+///
+/// Each address only applies to targets of its own family; a target whose
+/// family has no address uses the kernel's default source.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct EgressBinding {
+    pub ipv4: Option<Ipv4Addr>,
+    pub ipv6: Option<Ipv6Addr>,
+    /// Interface name for SO_BINDTODEVICE (Linux only).
+    pub interface: Option<String>,
+}
+
+impl EgressBinding {
+    /// Split `ips` by family; at most one address per family.
+    pub fn from_ips(ips: &[IpAddr]) -> Result<Self, String> {
+        let mut binding = Self::default();
+        for ip in ips {
+            match *ip {
+                IpAddr::V4(v4) if binding.ipv4.replace(v4).is_some() => {
+                    return Err("at most one IPv4 address may be given".into());
+                }
+                IpAddr::V6(v6) if binding.ipv6.replace(v6).is_some() => {
+                    return Err("at most one IPv6 address may be given".into());
+                }
+                _ => {}
+            }
+        }
+        Ok(binding)
+    }
+
+    fn apply<R>(&self, http: &mut HttpConnector<R>) {
+        match (self.ipv4, self.ipv6) {
+            (Some(v4), Some(v6)) => http.set_local_addresses(v4, v6),
+            (v4, v6) => http.set_local_address(v4.map(IpAddr::V4).or(v6.map(IpAddr::V6))),
+        }
+        #[cfg(target_os = "linux")]
+        if let Some(interface) = &self.interface {
+            http.set_interface(interface.as_str());
+        }
+    }
+}
+
+/// Build the upstream client with the given source binding (the primary
+/// `--egress-bind-ip` profile, or the alternate egress used for retries),
+/// optionally routed through `--upstream-proxy` / `--upstream-proxy-rule`.
+/// `http1_only` disables HTTP/2 (ALPN and h2c), as needed for `Upgrade`
+/// requests.  New connections to hot targets come from `warm_pool` when it
+/// has one ready.
+pub fn build_upstream_client(
+    config: &Config,
+    dns_cache: Arc<DnsCache>,
+    address_policy: Arc<AddressPolicy>,
+    egress: &EgressBinding,
+    upstream_proxy: Option<Arc<ProxyRoutes>>,
+    http1_only: bool,
+    warm_pool: Option<Arc<WarmPool>>,
+) -> UpstreamClient {
+    let mut connector = build_connector(
+        config,
+        dns_cache,
+        address_policy,
+        egress,
+        upstream_proxy,
+        http1_only,
+    );
+    connector.warm = warm_pool;
+
+    let mut builder = Client::builder(TokioExecutor::new());
+    builder.pool_max_idle_per_host(config.upstream_pool_max_idle_per_host);
+    builder.pool_idle_timeout(Duration::from_secs(config.upstream_pool_idle_timeout_secs));
+    builder.pool_timer(TokioTimer::new());
+    builder.build(connector)
+}
+
+/// The connector behind [`build_upstream_client`], without a warm pool.
+pub fn build_connector(
+    config: &Config,
+    dns_cache: Arc<DnsCache>,
+    address_policy: Arc<AddressPolicy>,
+    egress: &EgressBinding,
+    upstream_proxy: Option<Arc<ProxyRoutes>>,
+    http1_only: bool,
+) -> InstrumentedConnector {
+    let resolver =
+        ValidatedResolver::new(dns_cache, address_policy, config.upstream_connect_attempts);
+    let mut http = HttpConnector::new_with_resolver(resolver.clone());
+    http.enforce_http(false);
+    egress.apply(&mut http);
+    http.set_connect_timeout(Some(Duration::from_secs(
+        config.upstream_connect_timeout_secs,
+    )));
+    tune_sockets(config, &mut http);
+    http.set_happy_eyeballs_timeout(
+        (config.upstream_happy_eyeballs_delay_ms > 0)
+            .then(|| Duration::from_millis(config.upstream_happy_eyeballs_delay_ms)),
+    );
+
+    let proxy = upstream_proxy.map(|routes| (routes, proxy_connector(config, egress)));
+
+    // Validated at startup.
+    let tls = config.upstream_tls().unwrap_or_default();
+    let alpn = if http1_only {
+        vec![b"http/1.1".to_vec()]
+    } else {
+        vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+    };
+    InstrumentedConnector {
+        http,
+        tls_config: Arc::new(tls.client_config(alpn.clone())),
+        proxy,
+        resolver,
+        h2c_hosts: Arc::new(if http1_only {
+            Vec::new()
+        } else {
+            config.upstream_h2c_hosts().unwrap_or_default()
+        }),
+        handshake_timeout: Duration::from_secs(config.upstream_handshake_timeout_secs),
+        ech: config.upstream_ech.then(|| {
+            Arc::new(EchConfigs {
+                tls,
+                alpn,
+                built: Mutex::default(),
+            })
+        }),
+        warm: None,
+    }
+}
+
+/// TCP connector for reaching upstream proxies (also used by their health
+/// checks).
+pub fn proxy_connector(config: &Config, egress: &EgressBinding) -> HttpConnector {
+    let mut http = HttpConnector::new();
+    http.set_connect_timeout(Some(Duration::from_secs(
+        config.upstream_connect_timeout_secs,
+    )));
+    tune_sockets(config, &mut http);
+    egress.apply(&mut http);
+    http
+}
+
+/// Upstream socket options: nodelay, keepalive and buffer sizes.
+fn tune_sockets<R>(config: &Config, http: &mut HttpConnector<R>) {
+    http.set_nodelay(config.upstream_tcp_nodelay);
+    if config.upstream_tcp_keepalive_secs > 0 {
+        http.set_keepalive(Some(Duration::from_secs(
+            config.upstream_tcp_keepalive_secs,
+        )));
+        http.set_keepalive_interval(config.tcp_keepalive_interval_secs.map(Duration::from_secs));
+        http.set_keepalive_retries(config.tcp_keepalive_probes);
+    } else {
+        http.set_keepalive(None);
+    }
+    http.set_recv_buffer_size(config.tcp_recv_buffer);
+    http.set_send_buffer_size(config.tcp_send_buffer);
+}
+
+pub fn resolve_request_timing<B>(
+    response: &Response<B>,
+    connection_acquire_ms: Option<u64>,
+    ttfb_ms: u64,
+) -> RequestTiming {
+    let raw = response
+        .extensions()
+        .get::<ConnectTiming>()
+        .copied()
+        .unwrap_or_default();
+
+    let raw_connection_ms = raw.connect_ms.saturating_add(raw.tls_ms);
+    let measured_acquire_ms = connection_acquire_ms.unwrap_or(raw_connection_ms.min(ttfb_ms));
+    let likely_reused = measured_acquire_ms <= 5 && raw_connection_ms > 0;
+    let connector_matches_request = raw_connection_ms <= measured_acquire_ms.saturating_add(25);
+
+    let (connect_ms, tls_ms) = if likely_reused || !connector_matches_request {
+        (0, 0)
+    } else {
+        (raw.connect_ms, raw.tls_ms)
+    };
+
+    RequestTiming {
+        connection_acquire_ms: measured_acquire_ms,
+        connect_ms,
+        tls_ms,
+        response_wait_ms: ttfb_ms.saturating_sub(measured_acquire_ms),
+        connection_reused: likely_reused,
+    }
+}
+
+/// Whether an upstream request failed because the connection was reset
+/// while it was being established (TCP or TLS handshake).
+///
+/// An immediate RST is the classic signature of active blocking on the path
+/// to the destination.
+pub fn is_connection_reset(err: &hyper_util::client::legacy::Error) -> bool {
+    err.is_connect() && error_chain_has_reset(err)
+}
+
+fn error_chain_has_reset(err: &(dyn std::error::Error + 'static)) -> bool {
+    let mut current = Some(err);
+    while let Some(e) = current {
+        if let Some(io_err) = e.downcast_ref::<io::Error>() {
+            if io_error_is_reset(io_err) {
+                return true;
+            }
+        }
+        current = e.source();
+    }
+    false
+}
+
+fn io_error_is_reset(err: &io::Error) -> bool {
+    if err.kind() == io::ErrorKind::ConnectionReset {
+        return true;
+    }
+    // `io::Error::other(inner)` hides `inner` from `source()`; look inside.
+    err.get_ref()
+        .and_then(|inner| inner.downcast_ref::<io::Error>())
+        .is_some_and(io_error_is_reset)
+}
+
+fn resolve_server_name(uri: &Uri) -> Result<ServerName<'static>, BoxError> {
+    let host = uri.host().ok_or_else(|| io::Error::other("missing host"))?;
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    if let Ok(ip) = host.parse::<IpAddr>() {
+        return Ok(ServerName::from(ip));
+    }
+
+    Ok(ServerName::try_from(host.to_string())?)
+}
+
+pub struct TimedConn {
+    inner: MaybeHttpsStream,
+    timing: ConnectTiming,
+    /// Plain-HTTP connection to a chained HTTP proxy.
+    proxied: bool,
+    /// Plain-HTTP connection that speaks HTTP/2 from the start.
+    h2c: bool,
+}
+
+impl TimedConn {
+    fn new(inner: MaybeHttpsStream, timing: ConnectTiming) -> Self {
+        Self {
+            inner,
+            timing,
+            proxied: false,
+            h2c: false,
+        }
+    }
+
+    pub fn timing(&self) -> ConnectTiming {
+        self.timing
+    }
+
+    /// Handed out from the warm pool: the request waited for no connect.
+    pub(crate) fn prewarmed(mut self) -> Self {
+        self.timing = ConnectTiming::default();
+        self
+    }
+}
+
+impl Connection for TimedConn {
+    fn connected(&self) -> Connected {
+        let connected = self.inner.connected().proxy(self.proxied);
+        if self.h2c {
+            connected.negotiated_h2().extra(self.timing)
+        } else {
+            connected.extra(self.timing)
+        }
+    }
+}
+
+impl rt::Read for TimedConn {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: rt::ReadBufCursor<'_>,
+    ) -> Poll<Result<(), io::Error>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl rt::Write for TimedConn {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<(), io::Error>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[std::io::IoSlice<'_>],
+    ) -> Poll<Result<usize, io::Error>> {
+        Pin::new(&mut self.inner).poll_write_vectored(cx, bufs)
+    }
+}
+
+pub enum MaybeHttpsStream {
+    Http(PlainStream),
+    Https(Box<TlsStream>),
+}
+
+impl Connection for MaybeHttpsStream {
+    fn connected(&self) -> Connected {
+        match self {
+            Self::Http(stream) => stream.connected(),
+            Self::Https(stream) => {
+                let (tcp, tls) = stream.inner().get_ref();
+                if tls.alpn_protocol() == Some(b"h2") {
+                    tcp.connected().negotiated_h2()
+                } else {
+                    tcp.connected()
+                }
+            }
+        }
+    }
+}
+
+impl rt::Read for MaybeHttpsStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: rt::ReadBufCursor<'_>,
+    ) -> Poll<Result<(), io::Error>> {
+        match Pin::get_mut(self) {
+            Self::Http(stream) => Pin::new(stream).poll_read(cx, buf),
+            Self::Https(stream) => Pin::new(stream.as_mut()).poll_read(cx, buf),
+        }
+    }
+}
+
+impl rt::Write for MaybeHttpsStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        match Pin::get_mut(self) {
+            Self::Http(stream) => Pin::new(stream).poll_write(cx, buf),
+            Self::Https(stream) => Pin::new(stream.as_mut()).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        match Pin::get_mut(self) {
+            Self::Http(stream) => Pin::new(stream).poll_flush(cx),
+            Self::Https(stream) => Pin::new(stream.as_mut()).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        match Pin::get_mut(self) {
+            Self::Http(stream) => Pin::new(stream).poll_shutdown(cx),
+            Self::Https(stream) => Pin::new(stream.as_mut()).poll_shutdown(cx),
+        }
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        match self {
+            Self::Http(stream) => stream.is_write_vectored(),
+            Self::Https(stream) => stream.is_write_vectored(),
+        }
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[std::io::IoSlice<'_>],
+    ) -> Poll<Result<usize, io::Error>> {
+        match Pin::get_mut(self) {
+            Self::Http(stream) => Pin::new(stream).poll_write_vectored(cx, bufs),
+            Self::Https(stream) => Pin::new(stream.as_mut()).poll_write_vectored(cx, bufs),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::Response;
+
+    #[test]
+    fn egress_binding_splits_families() {
+        let v4: IpAddr = "203.0.113.7".parse().unwrap();
+        let v6: IpAddr = "2001:db8::7".parse().unwrap();
+        let binding = EgressBinding::from_ips(&[v6, v4]).unwrap();
+        assert_eq!(binding.ipv4, Some("203.0.113.7".parse().unwrap()));
+        assert_eq!(binding.ipv6, Some("2001:db8::7".parse().unwrap()));
+
+        assert!(EgressBinding::from_ips(&[v4, "198.51.100.1".parse().unwrap()]).is_err());
+        assert_eq!(
+            EgressBinding::from_ips(&[]).unwrap(),
+            EgressBinding::default()
+        );
+    }
+
+    #[test]
+    fn interleave_families_starts_with_ipv6() {
+        let addrs: Vec<SocketAddr> = [
+            "1.1.1.1:443",
+            "1.0.0.1:443",
+            "8.8.8.8:443",
+            "[2606:4700::1111]:443",
+            "[2606:4700::1001]:443",
+        ]
+        .iter()
+        .map(|s| s.parse().unwrap())
+        .collect();
+        let ordered: Vec<String> = interleave_families(addrs)
+            .iter()
+            .map(|a| a.to_string())
+            .collect();
+        assert_eq!(
+            ordered,
+            [
+                "[2606:4700::1111]:443",
+                "1.1.1.1:443",
+                "[2606:4700::1001]:443",
+                "1.0.0.1:443",
+                "8.8.8.8:443",
+            ]
+        );
+        let first: Vec<SocketAddr> = ["1.1.1.1:443", "1.0.0.1:443", "[2606:4700::1111]:443"]
+            .iter()
+            .map(|s| s.parse().unwrap())
+            .collect();
+        assert_eq!(
+            connect_order(first.clone(), 2),
+            interleave_families(first)[..2]
+        );
+    }
+
+    #[tokio::test]
+    async fn h2c_connection_is_reported_as_h2() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let tcp = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let mut conn = TimedConn::new(
+            MaybeHttpsStream::Http(TokioIo::new(tcp)),
+            ConnectTiming::default(),
+        );
+        assert!(!conn.connected().is_negotiated_h2());
+        conn.h2c = true;
+        assert!(conn.connected().is_negotiated_h2());
+    }
+
+    #[tokio::test]
+    async fn stalled_handshake_times_out() {
+        let pending = std::future::pending::<io::Result<()>>();
+        let err = handshake(Duration::from_millis(10), "TLS handshake", pending)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(err.to_string().starts_with("TLS handshake timed out"));
+    }
+
+    #[test]
+    fn reset_detected_through_wrapped_io_errors() {
+        let reset = io::Error::from(io::ErrorKind::ConnectionReset);
+        let wrapped = io::Error::other(io::Error::other(reset));
+        assert!(error_chain_has_reset(&wrapped));
+
+        let refused = io::Error::other(io::Error::from(io::ErrorKind::ConnectionRefused));
+        assert!(!error_chain_has_reset(&refused));
+    }
+
+    #[test]
+    fn fresh_connection_uses_connector_breakdown() {
+        let mut response = Response::new(());
+        response.extensions_mut().insert(ConnectTiming {
+            connect_ms: 80,
+            tls_ms: 40,
+        });
+
+        let timing = resolve_request_timing(&response, Some(125), 600);
+
+        assert_eq!(timing.connection_acquire_ms, 125);
+        assert_eq!(timing.connect_ms, 80);
+        assert_eq!(timing.tls_ms, 40);
+        assert_eq!(timing.response_wait_ms, 475);
+        assert!(!timing.connection_reused);
+    }
+
+    #[test]
+    fn reused_connection_zeroes_stale_connect_timings() {
+        let mut response = Response::new(());
+        response.extensions_mut().insert(ConnectTiming {
+            connect_ms: 70,
+            tls_ms: 30,
+        });
+
+        let timing = resolve_request_timing(&response, Some(0), 310);
+
+        assert_eq!(timing.connection_acquire_ms, 0);
+        assert_eq!(timing.connect_ms, 0);
+        assert_eq!(timing.tls_ms, 0);
+        assert_eq!(timing.response_wait_ms, 310);
+        assert!(timing.connection_reused);
+    }
+
+    #[test]
+    fn falls_back_to_connector_timings_when_capture_missing() {
+        let mut response = Response::new(());
+        response.extensions_mut().insert(ConnectTiming {
+            connect_ms: 55,
+            tls_ms: 25,
+        });
+
+        let timing = resolve_request_timing(&response, None, 400);
+
+        assert_eq!(timing.connection_acquire_ms, 80);
+        assert_eq!(timing.connect_ms, 55);
+        assert_eq!(timing.tls_ms, 25);
+        assert_eq!(timing.response_wait_ms, 320);
+        assert!(!timing.connection_reused);
+    }
+}
@@ -0,0 +1,262 @@
+//! Weighted groups of interchangeable upstream proxies.
+//!
+//! A pool is written as a list of proxy URLs with an optional
+//! `#weight=N` suffix (default 1).  Connections are spread over healthy
+//! members by weight; a member that refuses a connection, fails the
+//! tunnel negotiation or cuts off the TLS handshake is marked down and the
+//! next one is tried, so one dead second hop does not take the node
+//! offline.  Down members are re-probed by [`spawn_health_checks`] and,
+//! if every member is down, still tried as a last resort.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use hyper_util::client::legacy::connect::HttpConnector;
+use tokio::sync::watch;
+use tower_service::Service;
+use tracing::{info, warn};
+
+use super::{ProxyRoutes, UpstreamProxy};
+
+#[derive(Debug)]
+struct Member {
+    proxy: Arc<UpstreamProxy>,
+    weight: u64,
+    healthy: AtomicBool,
+}
+
+#[derive(Debug)]
+pub struct ProxyPool {
+    members: Vec<Member>,
+    cursor: AtomicU64,
+}
+
+impl ProxyPool {
+    /// Parse members separated by `separator`.
+    pub fn parse(raw: &str, separator: char) -> Result<Self, String> {
+        let mut members = Vec::new();
+        for entry in raw
+            .split(separator)
+            .map(str::trim)
+            .filter(|e| !e.is_empty())
+        {
+            let (url, weight) = match entry.rsplit_once("#weight=") {
+                Some((url, weight)) => {
+                    let weight = weight
+                        .parse::<u64>()
+                        .ok()
+                        .filter(|w| (1..=1000).contains(w))
+                        .ok_or_else(|| format!("{:?}: weight must be 1-1000", entry))?;
+                    (url, weight)
+                }
+                None => (entry, 1),
+            };
+            members.push(Member {
+                proxy: Arc::new(UpstreamProxy::parse(url)?),
+                weight,
+                healthy: AtomicBool::new(true),
+            });
+        }
+        if members.is_empty() {
+            return Err("empty proxy list".to_string());
+        }
+        Ok(Self {
+            members,
+            cursor: AtomicU64::new(0),
+        })
+    }
+
+    /// The only member, for single-proxy pools.
+    pub fn single(&self) -> Option<&Arc<UpstreamProxy>> {
+        match self.members.as_slice() {
+            [member] => Some(&member.proxy),
+            _ => None,
+        }
+    }
+
+    /// Plain-HTTP requests can only be forwarded in absolute-form when the
+    /// proxy (and thus its `Proxy-Authorization`) is known up front; in a
+    /// multi-member pool they are tunneled like HTTPS instead.
+    pub fn forwards_plain_http(&self) -> bool {
+        self.single()
+            .is_some_and(|proxy| proxy.forwards_plain_http())
+    }
+
+    /// Whether every member resolves target names itself, so they must not
+    /// be looked up locally.
+    pub fn resolves_remotely(&self) -> bool {
+        self.proxies().all(|proxy| !proxy.resolves_locally())
+    }
+
+    /// Members in the order to try them: a weighted pick among healthy
+    /// members, the remaining healthy members, then the unhealthy ones.
+    pub fn failover_order(&self) -> Vec<Arc<UpstreamProxy>> {
+        let healthy: Vec<&Member> = self.members.iter().filter(|m| m.is_healthy()).collect();
+        let healthy_weight: u64 = healthy.iter().map(|m| m.weight).sum();
+        let mut order = Vec::with_capacity(self.members.len());
+        if healthy_weight > 0 {
+            let mut ticket = self.cursor.fetch_add(1, Ordering::Relaxed) % healthy_weight;
+            let first = healthy
+                .iter()
+                .position(|m| match ticket.checked_sub(m.weight) {
+                    Some(rest) => {
+                        ticket = rest;
+                        false
+                    }
+                    None => true,
+                })
+                .unwrap_or(0);
+            order.extend(
+                healthy[first..]
+                    .iter()
+                    .chain(&healthy[..first])
+                    .map(|m| Arc::clone(&m.proxy)),
+            );
+        }
+        order.extend(
+            self.members
+                .iter()
+                .filter(|m| !m.is_healthy())
+                .map(|m| Arc::clone(&m.proxy)),
+        );
+        order
+    }
+
+    /// Record the outcome of connecting to `proxy`, logging transitions.
+    pub fn mark(&self, proxy: &Arc<UpstreamProxy>, healthy: bool) {
+        let Some(member) = self.members.iter().find(|m| Arc::ptr_eq(&m.proxy, proxy)) else {
+            return;
+        };
+        if member.healthy.swap(healthy, Ordering::AcqRel) != healthy {
+            if healthy {
+                info!(proxy = %proxy.display(), "upstream proxy is back up");
+            } else {
+                warn!(proxy = %proxy.display(), "upstream proxy is down, failing over");
+            }
+        }
+    }
+
+    /// `url[#weight=N]` list for startup logs.
+    pub fn display(&self) -> String {
+        let members: Vec<String> = self
+            .members
+            .iter()
+            .map(|m| match m.weight {
+                1 => m.proxy.display(),
+                weight => format!("{}#weight={}", m.proxy.display(), weight),
+            })
+            .collect();
+        members.join("|")
+    }
+
+    pub fn status_json(&self) -> Vec<serde_json::Value> {
+        self.members
+            .iter()
+            .map(|m| {
+                serde_json::json!({
+                    "proxy": m.proxy.display(),
+                    "weight": m.weight,
+                    "healthy": m.is_healthy(),
+                })
+            })
+            .collect()
+    }
+
+    fn proxies(&self) -> impl Iterator<Item = &Arc<UpstreamProxy>> {
+        self.members.iter().map(|m| &m.proxy)
+    }
+}
+
+impl Member {
+    fn is_healthy(&self) -> bool {
+        self.healthy.load(Ordering::Acquire)
+    }
+}
+
+/// Probe every proxy every `interval` with a connect and, for SOCKS5, a
+/// method negotiation, using the same connector (timeouts, egress binding)
+/// as real requests.  The negotiation is bounded by `handshake_timeout`.
+pub fn spawn_health_checks(
+    routes: Arc<ProxyRoutes>,
+    connector: HttpConnector,
+    interval: Duration,
+    handshake_timeout: Duration,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                _ = shutdown.changed() => return,
+            }
+            let mut probes = Vec::new();
+            for pool in routes.pools() {
+                for proxy in pool.proxies() {
+                    let connector = connector.clone();
+                    probes.push(async move {
+                        let healthy = probe(proxy, connector, handshake_timeout).await;
+                        pool.mark(proxy, healthy);
+                    });
+                }
+            }
+            futures_util::future::join_all(probes).await;
+        }
+    });
+}
+
+async fn probe(proxy: &UpstreamProxy, mut connector: HttpConnector, timeout: Duration) -> bool {
+    let Ok(mut stream) = connector.call(proxy.uri()).await else {
+        return false;
+    };
+    matches!(
+        tokio::time::timeout(timeout, proxy.probe(stream.inner_mut())).await,
+        Ok(Ok(()))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(order: &[Arc<UpstreamProxy>]) -> Vec<String> {
+        order.iter().map(|p| p.display()).collect()
+    }
+
+    #[test]
+    fn test_parse_pool() {
+        let pool = ProxyPool::parse("http://a:1#weight=3, socks5://b:2", ',').unwrap();
+        assert_eq!(pool.display(), "http://a:1#weight=3|socks5://b:2");
+        assert!(pool.single().is_none());
+        assert!(!pool.forwards_plain_http());
+        assert!(ProxyPool::parse("http://a:1", '|')
+            .unwrap()
+            .forwards_plain_http());
+
+        assert!(ProxyPool::parse("", ',').is_err());
+        assert!(ProxyPool::parse("http://a:1#weight=0", ',').is_err());
+        assert!(ProxyPool::parse("http://a:1#weight=x", ',').is_err());
+    }
+
+    #[test]
+    fn test_weighted_selection_and_failover() {
+        let pool = ProxyPool::parse("http://a:1#weight=3|http://b:2", '|').unwrap();
+        let firsts: Vec<String> = (0..8).map(|_| pool.failover_order()[0].display()).collect();
+        assert_eq!(firsts.iter().filter(|p| p.contains("a:1")).count(), 6);
+        assert_eq!(firsts.iter().filter(|p| p.contains("b:2")).count(), 2);
+
+        let a = Arc::clone(&pool.members[0].proxy);
+        pool.mark(&a, false);
+        for _ in 0..4 {
+            assert_eq!(names(&pool.failover_order()), ["http://b:2", "http://a:1"]);
+        }
+
+        // With every member down, all are still tried
+        pool.mark(&Arc::clone(&pool.members[1].proxy), false);
+        assert_eq!(pool.failover_order().len(), 2);
+        pool.mark(&a, true);
+        assert_eq!(names(&pool.failover_order())[0], "http://a:1");
+    }
+}
@@ -0,0 +1,228 @@
+//! SOCKS5 client handshake (RFC 1928, username/password per RFC 1929).
+
+use std::io;
+use std::net::IpAddr;
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const VERSION: u8 = 0x05;
+const METHOD_NONE: u8 = 0x00;
+const METHOD_USER_PASS: u8 = 0x02;
+const METHOD_UNACCEPTABLE: u8 = 0xff;
+const CMD_CONNECT: u8 = 0x01;
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+/// Ask the SOCKS5 server on `stream` to connect to `host:port`.  `host` is
+/// sent as an address when it is an IP literal, otherwise as a domain name
+/// for the proxy to resolve.
+pub async fn handshake<S>(
+    stream: &mut S,
+    host: &str,
+    port: u16,
+    credentials: Option<(&str, &str)>,
+) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    greet(stream, credentials).await?;
+    stream.write_all(&connect_request(host, port)?).await?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[1] != 0 {
+        return Err(io::Error::other(format!(
+            "SOCKS5 CONNECT to {host}:{port} failed: {}",
+            reply_message(reply[1])
+        )));
+    }
+    // Skip the bound address, which is of no use to us.
+    let addr_len = match reply[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len).await?;
+            usize::from(len[0])
+        }
+        other => {
+            return Err(io::Error::other(format!(
+                "SOCKS5 reply has unknown address type {other:#04x}"
+            )))
+        }
+    };
+    let mut bound = vec![0u8; addr_len + 2];
+    stream.read_exact(&mut bound).await?;
+    Ok(())
+}
+
+/// Negotiate the authentication method (and authenticate) without asking
+/// for a connection, as a health probe of the server.
+pub async fn greet<S>(stream: &mut S, credentials: Option<(&str, &str)>) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let method = if credentials.is_some() {
+        METHOD_USER_PASS
+    } else {
+        METHOD_NONE
+    };
+    stream.write_all(&[VERSION, 1, method]).await?;
+    let mut choice = [0u8; 2];
+    stream.read_exact(&mut choice).await?;
+    if choice[0] != VERSION {
+        return Err(io::Error::other("upstream proxy is not a SOCKS5 server"));
+    }
+    match (choice[1], credentials) {
+        (METHOD_NONE, _) => {}
+        (METHOD_USER_PASS, Some((user, pass))) => authenticate(stream, user, pass).await?,
+        (METHOD_UNACCEPTABLE, _) => {
+            return Err(io::Error::other(
+                "SOCKS5 proxy rejected the offered authentication method",
+            ))
+        }
+        (other, _) => {
+            return Err(io::Error::other(format!(
+                "SOCKS5 proxy chose unsupported method {other:#04x}"
+            )))
+        }
+    }
+    Ok(())
+}
+
+async fn authenticate<S>(stream: &mut S, user: &str, pass: &str) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (user, pass) = (user.as_bytes(), pass.as_bytes());
+    let mut msg = Vec::with_capacity(3 + user.len() + pass.len());
+    msg.push(0x01);
+    msg.push(field_len(user, "username")?);
+    msg.extend_from_slice(user);
+    msg.push(field_len(pass, "password")?);
+    msg.extend_from_slice(pass);
+    stream.write_all(&msg).await?;
+
+    let mut status = [0u8; 2];
+    stream.read_exact(&mut status).await?;
+    if status[1] != 0 {
+        return Err(io::Error::other("SOCKS5 proxy rejected the credentials"));
+    }
+    Ok(())
+}
+
+fn connect_request(host: &str, port: u16) -> io::Result<Vec<u8>> {
+    let mut req = vec![VERSION, CMD_CONNECT, 0x00];
+    match host.trim_matches(['[', ']']).parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => {
+            req.push(ATYP_IPV4);
+            req.extend_from_slice(&ip.octets());
+        }
+        Ok(IpAddr::V6(ip)) => {
+            req.push(ATYP_IPV6);
+            req.extend_from_slice(&ip.octets());
+        }
+        Err(_) => {
+            req.push(ATYP_DOMAIN);
+            req.push(field_len(host.as_bytes(), "host name")?);
+            req.extend_from_slice(host.as_bytes());
+        }
+    }
+    req.extend_from_slice(&port.to_be_bytes());
+    Ok(req)
+}
+
+fn field_len(field: &[u8], what: &str) -> io::Result<u8> {
+    u8::try_from(field.len())
+        .ok()
+        .filter(|len| *len > 0)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("SOCKS5 {what} must be 1-255 bytes"),
+            )
+        })
+}
+
+fn reply_message(code: u8) -> &'static str {
+    match code {
+        0x01 => "general failure",
+        0x02 => "connection not allowed by ruleset",
+        0x03 => "network unreachable",
+        0x04 => "host unreachable",
+        0x05 => "connection refused",
+        0x06 => "TTL expired",
+        0x07 => "command not supported",
+        0x08 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_connect_request_encoding() {
+        assert_eq!(
+            connect_request("192.0.2.1", 443).unwrap(),
+            [5, 1, 0, 1, 192, 0, 2, 1, 0x01, 0xbb]
+        );
+        let v6 = connect_request("[2001:db8::1]", 80).unwrap();
+        assert_eq!(&v6[..4], &[5, 1, 0, 4]);
+        assert_eq!(v6.len(), 4 + 16 + 2);
+        let mut expected = vec![5, 1, 0, 3, 11];
+        expected.extend_from_slice(b"example.com");
+        expected.extend_from_slice(&[0x00, 0x50]);
+        assert_eq!(connect_request("example.com", 80).unwrap(), expected);
+        assert!(connect_request(&"a".repeat(256), 80).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_handshake_with_credentials() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        let peer = tokio::spawn(async move {
+            let mut greeting = [0u8; 3];
+            server.read_exact(&mut greeting).await.unwrap();
+            assert_eq!(greeting, [5, 1, METHOD_USER_PASS]);
+            server.write_all(&[5, METHOD_USER_PASS]).await.unwrap();
+
+            let mut auth = [0u8; 1 + 1 + 4 + 1 + 6];
+            server.read_exact(&mut auth).await.unwrap();
+            assert_eq!(&auth[2..6], b"user");
+            server.write_all(&[1, 0]).await.unwrap();
+
+            let mut req = [0u8; 5 + 11 + 2];
+            server.read_exact(&mut req).await.unwrap();
+            assert_eq!(&req[5..16], b"example.com");
+            server
+                .write_all(&[5, 0, 0, 1, 10, 0, 0, 1, 0x1f, 0x90])
+                .await
+                .unwrap();
+        });
+        handshake(&mut client, "example.com", 443, Some(("user", "secret")))
+            .await
+            .unwrap();
+        peer.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_handshake_reports_refusal() {
+        let (mut client, mut server) = tokio::io::duplex(1024);
+        tokio::spawn(async move {
+            let mut buf = [0u8; 64];
+            let _ = server.read(&mut buf).await;
+            server.write_all(&[5, METHOD_NONE]).await.unwrap();
+            let _ = server.read(&mut buf).await;
+            server
+                .write_all(&[5, 0x05, 0, 1, 0, 0, 0, 0, 0, 0])
+                .await
+                .unwrap();
+        });
+        let err = handshake(&mut client, "192.0.2.1", 443, None)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("connection refused"));
+    }
+}
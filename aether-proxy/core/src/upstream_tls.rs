@@ -0,0 +1,295 @@
+//! TLS verification towards target origins.
+//!
+//! HTTPS targets are verified against the bundled web PKI roots.  Three
+//! options relax or tighten that per destination, so that an origin behind
+//! a corporate TLS-inspecting proxy or a private CA can be reached on
+//! purpose instead of failing with an unknown-issuer error:
+//!
+//! - `--upstream-ca-bundle` trusts the CAs of a PEM bundle in addition to
+//!   the web PKI roots
+//! - `--upstream-cert-pin api.example.com=sha256/<base64>|<base64>`
+//!   requires one certificate of the presented chain to carry a pinned
+//!   public key (base64 SHA-256 of the SPKI, as for `--aether-cert-pin`)
+//! - `--upstream-tls-insecure internal.example.com` skips chain and name
+//!   validation for matching hosts (`*` for every target).  A pin for the
+//!   same host is still enforced, which is how a self-signed origin is
+//!   trusted without trusting everything it presents
+//!
+//! Domains use the `--allowed-domains` syntax (`*.example.com` matches
+//! subdomains); the first pin rule matching a host applies.
+//!
+//! With `--upstream-ech`, targets whose DNS `HTTPS` record advertises an
+//! Encrypted Client Hello config get a TLS 1.3 ClientHello with the real
+//! server name encrypted, leaving only the provider's public name visible
+//! on the wire.  The same verification applies.
+
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::{EchConfig, EchMode, WebPkiServerVerifier};
+use rustls::crypto::aws_lc_rs::hpke;
+use rustls::crypto::CryptoProvider;
+use rustls::pki_types::{CertificateDer, EchConfigListBytes, ServerName, UnixTime};
+use rustls::{
+    ClientConfig, ConfigBuilder, DigitallySignedStruct, RootCertStore, SignatureScheme,
+    WantsVerifier,
+};
+
+use crate::registration::tls::{parse_pin, read_certs, spki_sha256};
+use crate::target_filter::DomainPattern;
+
+/// `None` matches every host.
+type HostMatch = Option<DomainPattern>;
+
+fn parse_host(raw: &str) -> Result<HostMatch, String> {
+    match raw.trim() {
+        "*" => Ok(None),
+        p => DomainPattern::parse(p).map(Some),
+    }
+}
+
+fn host_matches(pattern: &HostMatch, host: &str) -> bool {
+    pattern.as_ref().is_none_or(|p| p.matches_host(host))
+}
+
+/// Parsed origin TLS options.
+#[derive(Debug, Clone, Default)]
+pub struct UpstreamTls {
+    /// Extra trusted CAs.
+    ca_bundle: Vec<CertificateDer<'static>>,
+    insecure: Vec<HostMatch>,
+    pins: Vec<(HostMatch, Vec<[u8; 32]>)>,
+}
+
+impl UpstreamTls {
+    pub fn new(
+        ca_bundle: Option<&str>,
+        insecure: &[String],
+        pins: &[String],
+    ) -> Result<Self, String> {
+        let ca_bundle = ca_bundle.map(read_certs).transpose()?.unwrap_or_default();
+        let insecure = insecure
+            .iter()
+            .map(|raw| raw.trim())
+            .filter(|raw| !raw.is_empty())
+            .map(|raw| parse_host(raw).map_err(|e| format!("insecure host {:?}: {}", raw, e)))
+            .collect::<Result<_, _>>()?;
+        let pins = pins
+            .iter()
+            .map(|raw| raw.trim())
+            .filter(|raw| !raw.is_empty())
+            .map(|raw| {
+                let invalid = |why: String| format!("pin {:?}: {}", raw, why);
+                let (host, list) = raw
+                    .split_once('=')
+                    .ok_or_else(|| invalid("must be `domain=pin[|pin...]`".to_string()))?;
+                let host = parse_host(host).map_err(invalid)?;
+                let list = list
+                    .split('|')
+                    .map(parse_pin)
+                    .collect::<Result<_, _>>()
+                    .map_err(invalid)?;
+                Ok((host, list))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self {
+            ca_bundle,
+            insecure,
+            pins,
+        })
+    }
+
+    /// Client config for origins, offering `alpn`.
+    pub fn client_config(&self, alpn: Vec<Vec<u8>>) -> ClientConfig {
+        self.finish(ClientConfig::builder(), alpn)
+    }
+
+    /// Client config for origins advertising `ech_config_list` in DNS,
+    /// encrypting the ClientHello (TLS 1.3 only).  Fails when no config in
+    /// the list is usable.
+    pub fn ech_client_config(
+        &self,
+        ech_config_list: &[u8],
+        alpn: Vec<Vec<u8>>,
+    ) -> Result<ClientConfig, rustls::Error> {
+        let ech = EchConfig::new(
+            EchConfigListBytes::from(ech_config_list),
+            hpke::ALL_SUPPORTED_SUITES,
+        )?;
+        let provider = CryptoProvider::get_default()
+            .cloned()
+            .unwrap_or_else(|| Arc::new(rustls::crypto::ring::default_provider()));
+        let builder =
+            ClientConfig::builder_with_provider(provider).with_ech(EchMode::Enable(ech))?;
+        Ok(self.finish(builder, alpn))
+    }
+
+    fn finish(
+        &self,
+        builder: ConfigBuilder<ClientConfig, WantsVerifier>,
+        alpn: Vec<Vec<u8>>,
+    ) -> ClientConfig {
+        let mut roots = RootCertStore::from_iter(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        // Validated by `new`
+        let _ = roots.add_parsable_certificates(self.ca_bundle.iter().cloned());
+        let builder = if self.insecure.is_empty() && self.pins.is_empty() {
+            builder.with_root_certificates(roots)
+        } else {
+            let inner = WebPkiServerVerifier::builder(Arc::new(roots))
+                .build()
+                .expect("web PKI roots are never empty");
+            builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(OriginVerifier {
+                    inner,
+                    hosts: Self {
+                        ca_bundle: Vec::new(),
+                        insecure: self.insecure.clone(),
+                        pins: self.pins.clone(),
+                    },
+                }))
+        };
+        let mut config = builder.with_no_client_auth();
+        config.alpn_protocols = alpn;
+        config
+    }
+
+    fn is_insecure(&self, host: &str) -> bool {
+        self.insecure.iter().any(|p| host_matches(p, host))
+    }
+
+    fn pins_for(&self, host: &str) -> Option<&[[u8; 32]]> {
+        self.pins
+            .iter()
+            .find(|(p, _)| host_matches(p, host))
+            .map(|(_, pins)| pins.as_slice())
+    }
+}
+
+/// Web PKI verification (plus the extra CAs), skipped for insecure hosts,
+/// followed by the host's pins if any.
+#[derive(Debug)]
+struct OriginVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    /// The insecure hosts and pins.
+    hosts: UpstreamTls,
+}
+
+impl ServerCertVerifier for OriginVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let host = match server_name {
+            ServerName::DnsName(name) => name.as_ref().to_string(),
+            ServerName::IpAddress(ip) => IpAddr::from(*ip).to_string(),
+            _ => String::new(),
+        };
+        if !self.hosts.is_insecure(&host) {
+            self.inner.verify_server_cert(
+                end_entity,
+                intermediates,
+                server_name,
+                ocsp_response,
+                now,
+            )?;
+        }
+        if let Some(pins) = self.hosts.pins_for(&host) {
+            let pinned = std::iter::once(end_entity)
+                .chain(intermediates)
+                .filter_map(spki_sha256)
+                .any(|hash| pins.contains(&hash));
+            if !pinned {
+                return Err(rustls::Error::General(format!(
+                    "certificate of {} matches no --upstream-cert-pin",
+                    host
+                )));
+            }
+        }
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PIN: &str = "sha256/47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=";
+
+    fn strings(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_host_options() {
+        let tls = UpstreamTls::new(
+            None,
+            &strings(&["*.corp.example", "10.0.0.5"]),
+            &[
+                format!(
+                    "api.example.com={}|2jmj7l5rSw0yVb/vlWAYkK/YBwkAAAAAAAAAAAAAAAA=",
+                    PIN
+                ),
+                format!("*={}", PIN),
+            ],
+        )
+        .unwrap();
+        assert!(tls.is_insecure("git.corp.example"));
+        assert!(tls.is_insecure("10.0.0.5"));
+        assert!(!tls.is_insecure("corp.example"));
+        assert_eq!(tls.pins_for("API.example.com.").unwrap().len(), 2);
+        assert_eq!(tls.pins_for("example.org").unwrap().len(), 1);
+
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let config = tls.client_config(vec![b"h2".to_vec()]);
+        assert_eq!(config.alpn_protocols, [b"h2".to_vec()]);
+        let _ = UpstreamTls::default().client_config(Vec::new());
+        assert!(tls.ech_client_config(b"garbage", Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_invalid_options() {
+        let none = &[];
+        assert!(UpstreamTls::new(Some("/nonexistent/ca.pem"), none, none).is_err());
+        assert!(UpstreamTls::new(None, &strings(&["api.*.com"]), none).is_err());
+        for bad in [
+            "api.example.com",
+            "api.example.com=not-a-pin",
+            "api.example.com=",
+            "api.*.com=sha256/47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=",
+        ] {
+            assert!(
+                UpstreamTls::new(None, none, &strings(&[bad])).is_err(),
+                "{bad:?}"
+            );
+        }
+        assert!(UpstreamTls::new(None, &strings(&["", " "]), &strings(&[""])).is_ok());
+    }
+}
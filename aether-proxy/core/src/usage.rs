@@ -0,0 +1,231 @@
+//! Per-client usage accounting.
+//!
+//! Aether tags each stream with the client it serves in the
+//! `x-aether-client` request header (stripped before the request goes
+//! upstream).  Each server connection rolls up requests and bytes per
+//! client, and every `--usage-report-interval` seconds the rollup is
+//! uploaded to that server's `/api/admin/proxy-nodes/usage` endpoint, for
+//! per-tenant billing and quotas.  Rollups that cannot be delivered are
+//! kept (up to [`MAX_PENDING_ROLLUPS`] per server) and sent, oldest first,
+//! once the control plane is reachable again.
+//!
+//! The endpoint needs an Aether release that accepts usage uploads; the
+//! reporter is off by default, and a server that answers 404 is not sent
+//! rollups again until the node restarts.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::sync::watch;
+use tracing::{debug, warn};
+
+use crate::clock::now_ms;
+use crate::registration::client;
+use crate::state::ServerContext;
+
+/// Request header naming the client a stream is served for.
+pub const CLIENT_HEADER: &str = "x-aether-client";
+/// Longest client identity kept; longer ones are truncated.
+const MAX_CLIENT_LEN: usize = 128;
+/// Distinct clients per rollup; further clients are counted under
+/// [`OVERFLOW_CLIENT`].
+const MAX_CLIENTS: usize = 10_000;
+const OVERFLOW_CLIENT: &str = "_other";
+/// Undelivered rollups kept per server; the oldest are dropped beyond this.
+const MAX_PENDING_ROLLUPS: usize = 360;
+
+/// Client identity of a stream, from [`CLIENT_HEADER`].
+pub fn client_of(headers: &HashMap<String, String>) -> Option<String> {
+    let (_, value) = headers
+        .iter()
+        .find(|(k, _)| k.eq_ignore_ascii_case(CLIENT_HEADER))?;
+    let value = value.trim();
+    if value.is_empty() {
+        return None;
+    }
+    let mut end = value.len().min(MAX_CLIENT_LEN);
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    Some(value[..end].to_string())
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct ClientUsage {
+    pub requests: u64,
+    /// Requests that were rejected or failed.
+    pub failed: u64,
+    pub bytes_received: u64,
+    pub bytes_sent: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Rollup {
+    /// Unix milliseconds, start inclusive.
+    pub period_start: u64,
+    pub period_end: u64,
+    pub clients: HashMap<String, ClientUsage>,
+}
+
+struct Period {
+    started: u64,
+    clients: HashMap<String, ClientUsage>,
+}
+
+/// Usage of one server connection since the last rollup.
+pub struct UsageMeter {
+    current: Mutex<Period>,
+}
+
+impl Default for UsageMeter {
+    fn default() -> Self {
+        Self {
+            current: Mutex::new(Period {
+                started: now_ms(),
+                clients: HashMap::new(),
+            }),
+        }
+    }
+}
+
+impl UsageMeter {
+    /// Account one finished (or refused) stream.
+    pub fn record(&self, client: &str, failed: bool, bytes_received: u64, bytes_sent: u64) {
+        let mut current = self.current.lock().unwrap();
+        let key = if current.clients.len() < MAX_CLIENTS || current.clients.contains_key(client) {
+            client
+        } else {
+            OVERFLOW_CLIENT
+        };
+        let usage = current.clients.entry(key.to_string()).or_default();
+        usage.requests += 1;
+        usage.failed += u64::from(failed);
+        usage.bytes_received += bytes_received;
+        usage.bytes_sent += bytes_sent;
+    }
+
+    /// Close the current period; `None` if nothing was used in it.
+    pub fn take(&self) -> Option<Rollup> {
+        let now = now_ms();
+        let mut current = self.current.lock().unwrap();
+        let period = std::mem::replace(
+            &mut *current,
+            Period {
+                started: now,
+                clients: HashMap::new(),
+            },
+        );
+        (!period.clients.is_empty()).then_some(Rollup {
+            period_start: period.started,
+            period_end: now,
+            clients: period.clients,
+        })
+    }
+}
+
+/// Upload rollups every `interval` until shutdown, then once more.
+pub fn spawn_reporter(
+    servers: Arc<tokio::sync::Mutex<Vec<Arc<ServerContext>>>>,
+    interval: Duration,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    tokio::spawn(async move {
+        let mut pending: HashMap<String, VecDeque<Rollup>> = HashMap::new();
+        // Servers without the usage endpoint
+        let mut unsupported: HashSet<String> = HashSet::new();
+        let mut ticker = tokio::time::interval(interval);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        ticker.tick().await;
+        loop {
+            let stopping = tokio::select! {
+                _ = ticker.tick() => false,
+                _ = shutdown.changed() => true,
+            };
+            let servers = servers.lock().await.clone();
+            for server in &servers {
+                let rollup = server.usage.take();
+                if unsupported.contains(&server.server_label) {
+                    continue;
+                }
+                let queue = pending.entry(server.server_label.clone()).or_default();
+                if let Some(rollup) = rollup {
+                    if queue.len() == MAX_PENDING_ROLLUPS {
+                        queue.pop_front();
+                        warn!(server = %server.server_label, "usage backlog full, dropping oldest rollup");
+                    }
+                    queue.push_back(rollup);
+                }
+                if !upload(server, queue).await {
+                    pending.remove(&server.server_label);
+                    unsupported.insert(server.server_label.clone());
+                }
+            }
+            if stopping {
+                return;
+            }
+        }
+    });
+}
+
+/// Send the queued rollups; false when the server has no usage endpoint.
+async fn upload(server: &ServerContext, queue: &mut VecDeque<Rollup>) -> bool {
+    if queue.is_empty() {
+        return true;
+    }
+    let node_id = server.node_id.read().unwrap().clone();
+    let (front, back) = queue.as_slices();
+    let rollups: Vec<&Rollup> = front.iter().chain(back).collect();
+    match server.aether_client.upload_usage(&node_id, &rollups).await {
+        Ok(()) => queue.clear(),
+        Err(e) if client::is_unsupported(&e) => {
+            warn!(server = %server.server_label, error = %e, "usage reporting disabled");
+            return false;
+        }
+        Err(e) => debug!(
+            server = %server.server_label,
+            pending = queue.len(),
+            error = %e,
+            "usage upload failed, keeping rollups"
+        ),
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_client_of() {
+        let mut headers = HashMap::new();
+        assert_eq!(client_of(&headers), None);
+        headers.insert("X-Aether-Client".to_string(), " tenant-1 ".to_string());
+        assert_eq!(client_of(&headers).as_deref(), Some("tenant-1"));
+        headers.insert("X-Aether-Client".to_string(), "é".repeat(100));
+        assert_eq!(client_of(&headers).unwrap().len(), MAX_CLIENT_LEN);
+    }
+
+    #[test]
+    fn test_rollup_resets_period() {
+        let meter = UsageMeter::default();
+        assert!(meter.take().is_none());
+        meter.record("a", false, 10, 100);
+        meter.record("a", true, 5, 0);
+        meter.record("b", false, 0, 7);
+        let rollup = meter.take().unwrap();
+        assert_eq!(
+            rollup.clients["a"],
+            ClientUsage {
+                requests: 2,
+                failed: 1,
+                bytes_received: 15,
+                bytes_sent: 100,
+            }
+        );
+        assert_eq!(rollup.clients["b"].bytes_sent, 7);
+        assert!(rollup.period_end >= rollup.period_start);
+        assert!(meter.take().is_none());
+    }
+}
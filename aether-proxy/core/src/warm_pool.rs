@@ -0,0 +1,278 @@
+//! Pre-established connections to hot targets.
+//!
+//! Targets named with `--hot-target` (or the control plane's
+//! `remote_config.hot_targets`, which replaces the local list) get up to
+//! `--hot-target-connections` idle connections kept ready: resolved,
+//! connected and, for HTTPS, past the TLS handshake.  When the upstream
+//! client needs a new connection to such a target it takes one of these
+//! instead of dialing, so the first request pays about one round trip.
+//!
+//! Entries look like `api.openai.com:443`, `api.openai.com` (port 443) or
+//! `http://internal.example:8080`; without a scheme, port 80 means HTTP and
+//! anything else HTTPS.  Connections go through the same resolver, address
+//! checks, egress binding and upstream proxy routes as any other.  An idle
+//! connection is replaced after `--hot-target-max-idle-secs`, before
+//! origins time out connections that never sent a request.
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use hyper::Uri;
+use tokio::sync::watch;
+use tower_service::Service;
+
+use crate::config::Config;
+use crate::state::ServerContext;
+use crate::upstream_client::{InstrumentedConnector, TimedConn};
+
+/// How often the pools are topped up.
+const REFILL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A destination to keep connections to.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct HotTarget {
+    https: bool,
+    host: String,
+    port: u16,
+}
+
+impl HotTarget {
+    pub fn parse(raw: &str) -> Result<Self, String> {
+        let raw = raw.trim();
+        let (scheme, rest) = match raw.split_once("://") {
+            Some((scheme, rest)) => (Some(scheme.to_ascii_lowercase()), rest),
+            None => (None, raw),
+        };
+        let uri: Uri = format!("http://{}", rest.trim_end_matches('/'))
+            .parse()
+            .map_err(|_| format!("invalid hot target {:?}", raw))?;
+        let host = uri
+            .host()
+            .filter(|host| !host.is_empty() && uri.path() == "/")
+            .ok_or_else(|| format!("invalid hot target {:?}: expected host[:port]", raw))?;
+        let port = uri.port_u16();
+        let https = match scheme.as_deref() {
+            Some("https") => true,
+            Some("http") => false,
+            Some(other) => {
+                return Err(format!(
+                    "invalid hot target {:?}: unsupported scheme {}",
+                    raw, other
+                ))
+            }
+            None => port != Some(80),
+        };
+        Ok(Self {
+            https,
+            host: host.trim_end_matches('.').to_ascii_lowercase(),
+            port: port.unwrap_or(if https { 443 } else { 80 }),
+        })
+    }
+
+    pub(crate) fn uri(&self) -> Uri {
+        // Parsed from a valid authority
+        self.to_string().parse().expect("hot target URI")
+    }
+}
+
+impl fmt::Display for HotTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let scheme = if self.https { "https" } else { "http" };
+        write!(f, "{}://{}:{}", scheme, self.host, self.port)
+    }
+}
+
+/// Parse a list of hot targets, skipping blank entries.
+pub fn parse_targets<S: AsRef<str>>(raw: &[S]) -> Result<Vec<HotTarget>, String> {
+    raw.iter()
+        .map(|t| t.as_ref().trim())
+        .filter(|t| !t.is_empty())
+        .map(HotTarget::parse)
+        .collect()
+}
+
+/// Pool key of a connection request (`scheme://host:port`).
+fn key_of(dst: &Uri) -> Option<String> {
+    let https = match dst.scheme_str()? {
+        s if s.eq_ignore_ascii_case("https") => true,
+        s if s.eq_ignore_ascii_case("http") => false,
+        _ => return None,
+    };
+    let target = HotTarget {
+        https,
+        host: dst.host()?.trim_end_matches('.').to_ascii_lowercase(),
+        port: dst.port_u16().unwrap_or(if https { 443 } else { 80 }),
+    };
+    Some(target.to_string())
+}
+
+/// Idle connections per hot target.
+pub struct WarmPool {
+    per_target: usize,
+    max_idle: Duration,
+    /// Keyed by [`HotTarget`] display; only hot targets have an entry.
+    idle: Mutex<HashMap<String, VecDeque<(TimedConn, Instant)>>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl WarmPool {
+    /// `None` when `--hot-target-connections` is 0.
+    pub fn from_config(config: &Config) -> Option<Self> {
+        (config.hot_target_connections > 0).then(|| Self {
+            per_target: config.hot_target_connections,
+            max_idle: Duration::from_secs(config.hot_target_max_idle_secs.max(1)),
+            idle: Mutex::new(HashMap::new()),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        })
+    }
+
+    /// A ready connection for `dst`, if it is a hot target with one idle.
+    pub fn take(&self, dst: &Uri) -> Option<TimedConn> {
+        let key = key_of(dst)?;
+        let mut idle = self.idle.lock().unwrap();
+        let conns = idle.get_mut(&key)?;
+        while let Some((conn, since)) = conns.pop_front() {
+            if since.elapsed() < self.max_idle {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return Some(conn.prewarmed());
+            }
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        None
+    }
+
+    /// Drop pools of targets no longer hot and expired connections; the
+    /// number of connections each target is short of.
+    fn prune(&self, targets: &[HotTarget]) -> Vec<(HotTarget, usize)> {
+        let wanted: HashSet<String> = targets.iter().map(|t| t.to_string()).collect();
+        let mut idle = self.idle.lock().unwrap();
+        idle.retain(|key, _| wanted.contains(key));
+        targets
+            .iter()
+            .filter_map(|target| {
+                let conns = idle.entry(target.to_string()).or_default();
+                conns.retain(|(_, since)| since.elapsed() < self.max_idle);
+                let missing = self.per_target.saturating_sub(conns.len());
+                (missing > 0).then(|| (target.clone(), missing))
+            })
+            .collect()
+    }
+
+    fn put(&self, target: &HotTarget, conn: TimedConn) {
+        let mut idle = self.idle.lock().unwrap();
+        // The target may have been dropped while dialing
+        if let Some(conns) = idle.get_mut(&target.to_string()) {
+            if conns.len() < self.per_target {
+                conns.push_back((conn, Instant::now()));
+            }
+        }
+    }
+
+    pub fn stats_json(&self) -> serde_json::Value {
+        let idle = self.idle.lock().unwrap();
+        serde_json::json!({
+            "targets": idle.len(),
+            "idle": idle.values().map(VecDeque::len).sum::<usize>(),
+            "hits": self.hits.load(Ordering::Relaxed),
+            "misses": self.misses.load(Ordering::Relaxed),
+        })
+    }
+}
+
+/// Keep `pool` topped up for the hot targets of all servers, dialing with
+/// `connector`.
+pub fn spawn_refill(
+    pool: Arc<WarmPool>,
+    connector: InstrumentedConnector,
+    servers: Arc<tokio::sync::Mutex<Vec<Arc<ServerContext>>>>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(REFILL_INTERVAL);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {}
+                _ = shutdown.changed() => return,
+            }
+            let mut targets: Vec<HotTarget> = Vec::new();
+            for server in servers.lock().await.iter() {
+                for target in server.dynamic.load().hot_targets.iter() {
+                    if !targets.contains(target) {
+                        targets.push(target.clone());
+                    }
+                }
+            }
+            let mut dials = Vec::new();
+            for (target, missing) in pool.prune(&targets) {
+                for _ in 0..missing {
+                    let mut connector = connector.clone();
+                    let pool = Arc::clone(&pool);
+                    let target = target.clone();
+                    dials.push(async move {
+                        match connector.call(target.uri()).await {
+                            Ok(conn) => pool.put(&target, conn),
+                            Err(e) => {
+                                tracing::debug!(target = %target, error = %e, "hot target connect failed")
+                            }
+                        }
+                    });
+                }
+            }
+            futures_util::future::join_all(dials).await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_targets() {
+        let targets = parse_targets(&[
+            "api.openai.com:443",
+            "API.Anthropic.com.",
+            "internal.example:80",
+            "http://internal.example:8080/",
+            "https://10.0.0.5:80",
+            "[2001:db8::1]:8443",
+            " ",
+        ])
+        .unwrap();
+        let shown: Vec<String> = targets.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            shown,
+            [
+                "https://api.openai.com:443",
+                "https://api.anthropic.com:443",
+                "http://internal.example:80",
+                "http://internal.example:8080",
+                "https://10.0.0.5:80",
+                "https://[2001:db8::1]:8443",
+            ]
+        );
+        for bad in ["ftp://a.example", "a.example/path", ":443", "a b"] {
+            assert!(HotTarget::parse(bad).is_err(), "{bad:?}");
+        }
+    }
+
+    #[test]
+    fn test_request_key() {
+        let key = |uri: &str| key_of(&uri.parse().unwrap());
+        assert_eq!(
+            key("https://API.openai.com/v1/models").as_deref(),
+            Some("https://api.openai.com:443")
+        );
+        assert_eq!(
+            key("http://internal.example:8080").as_deref(),
+            Some("http://internal.example:8080")
+        );
+        assert_eq!(key("/relative"), None);
+    }
+}
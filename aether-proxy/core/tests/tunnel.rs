@@ -0,0 +1,501 @@
+//! End-to-end: a node registers with a mock Aether, relays requests over
+//! the tunnel and unregisters on shutdown.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use aether_proxy_core::app;
+use aether_proxy_core::test_support::{
+    echo_upstream, socks5_proxy, socks5_proxy_with_hosts, stalled_upstream, MockAether, NODE_ID,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use tokio::task::JoinHandle;
+
+const TOKEN: &str = "ae_test_token";
+
+/// Start a node with `extra` args; the returned sender shuts it down.
+fn start_node(
+    mock: &MockAether,
+    extra: &[&str],
+) -> (oneshot::Sender<()>, JoinHandle<anyhow::Result<()>>) {
+    let config = mock.node_config(extra);
+    let servers = MockAether::servers(&config);
+    let (stop_tx, stop_rx) = oneshot::channel::<()>();
+    let node = tokio::spawn(app::run_until(config, servers, async {
+        let _ = stop_rx.await;
+    }));
+    (stop_tx, node)
+}
+
+#[tokio::test]
+async fn relays_requests_and_unregisters_on_shutdown() {
+    let mock = MockAether::start(TOKEN).await;
+    let upstream = echo_upstream().await;
+    let port = upstream.port().to_string();
+    let (stop, node) = start_node(
+        &mock,
+        &["--allow-private-targets", "--allowed-ports", &port],
+    );
+
+    let mut tunnel = mock.tunnel().await;
+    assert_eq!(
+        tunnel.headers.get("x-node-id").map(String::as_str),
+        Some(NODE_ID)
+    );
+    let registrations = mock.registrations();
+    assert_eq!(registrations.len(), 1);
+    assert_eq!(registrations[0]["ip"], "203.0.113.10");
+    assert_eq!(registrations[0]["tunnel_mode"], true);
+
+    let response = tunnel
+        .request(
+            "POST",
+            &format!("http://{}/v1/echo", upstream),
+            &[("content-type", "text/plain")],
+            b"hello",
+        )
+        .await
+        .expect("relayed response");
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("content-type"), Some("text/plain"));
+    assert_eq!(&response.body[..], b"POST /v1/echo\nhello");
+    let request_id = response.header("x-aether-request-id").expect("request id");
+    assert_eq!(request_id.len(), 32);
+
+    mock.wait_for_heartbeats(1).await;
+    assert_eq!(mock.heartbeats()[0]["node_id"], NODE_ID);
+
+    stop.send(()).unwrap();
+    tokio::time::timeout(Duration::from_secs(30), node)
+        .await
+        .expect("node stops")
+        .unwrap()
+        .unwrap();
+    assert_eq!(mock.unregistrations(), [NODE_ID]);
+}
+
+#[tokio::test]
+async fn blocks_private_and_disallowed_targets() {
+    let mock = MockAether::start(TOKEN).await;
+    let upstream = echo_upstream().await;
+    let port = upstream.port().to_string();
+    let (_stop, _node) = start_node(&mock, &["--allowed-ports", &port]);
+    let mut tunnel = mock.tunnel().await;
+
+    // Loopback is private and not allowed by default
+    let err = tunnel
+        .request("GET", &format!("http://{}/", upstream), &[], b"")
+        .await
+        .unwrap_err();
+    assert!(err.contains("target blocked"), "{err}");
+
+    // Ports outside --allowed-ports are refused before any lookup
+    let err = tunnel
+        .request("GET", "http://example.com:9/", &[], b"")
+        .await
+        .unwrap_err();
+    assert!(err.contains("target blocked"), "{err}");
+}
+
+#[tokio::test]
+async fn a_stalled_upload_does_not_hold_up_the_tunnel() {
+    let mock = MockAether::start(TOKEN).await;
+    let upstream = echo_upstream().await;
+    let stalled = stalled_upstream().await;
+    let ports = format!("{},{}", upstream.port(), stalled.port());
+    let (_stop, _node) = start_node(
+        &mock,
+        &["--allow-private-targets", "--allowed-ports", &ports],
+    );
+    let mut tunnel = mock.tunnel().await;
+
+    // Far more than the target's socket buffers and the stream's backlog
+    // can take
+    let mut upload = tunnel
+        .upload("POST", &format!("http://{stalled}/upload"), &[])
+        .unwrap();
+    let chunk = vec![b'x'; 32 * 1024];
+    for _ in 0..1024 {
+        upload.send(&chunk).unwrap();
+    }
+
+    let response = tunnel
+        .request("GET", &format!("http://{upstream}/after"), &[], b"")
+        .await
+        .expect("the second stream completes");
+    assert_eq!(&response.body[..], b"GET /after\n");
+    let err = upload.error().await.expect("the stalled upload is failed");
+    assert!(err.contains("request body"), "{err}");
+}
+
+#[tokio::test]
+async fn leaves_socks5h_names_to_the_proxy() {
+    let mock = MockAether::start(TOKEN).await;
+    let upstream = echo_upstream().await;
+    let (proxy, tunnels) = socks5_proxy_with_hosts(&[("hidden.onion", upstream)]).await;
+    let rule = format!("*.onion=socks5h://{}", proxy);
+    let (_stop, _node) = start_node(
+        &mock,
+        &["--allowed-ports", "80", "--upstream-proxy-rule", &rule],
+    );
+    let mut tunnel = mock.tunnel().await;
+
+    // The name doesn't resolve locally, only the proxy knows it
+    let response = tunnel
+        .request("GET", "http://hidden.onion/", &[], b"")
+        .await
+        .expect("relayed response");
+    assert_eq!(&response.body[..], b"GET /\n");
+    assert_eq!(tunnels.load(Ordering::SeqCst), 1);
+
+    // Port rules still apply to proxied names
+    let err = tunnel
+        .request("GET", "http://hidden.onion:8080/", &[], b"")
+        .await
+        .unwrap_err();
+    assert!(err.contains("target blocked"), "{err}");
+}
+
+/// A SOCKS5 server that accepts the greeting and then hangs up on every
+/// CONNECT; the counter is the number of connections it took.
+async fn broken_socks5_proxy() -> (std::net::SocketAddr, Arc<AtomicUsize>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let accepted = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&accepted);
+    tokio::spawn(async move {
+        while let Ok((mut client, _)) = listener.accept().await {
+            counter.fetch_add(1, Ordering::SeqCst);
+            let mut head = [0u8; 3];
+            if client.read_exact(&mut head).await.is_ok() {
+                let _ = client.write_all(&[5, 0]).await;
+            }
+        }
+    });
+    (addr, accepted)
+}
+
+#[tokio::test]
+async fn fails_over_when_a_proxy_breaks_the_handshake() {
+    let mock = MockAether::start(TOKEN).await;
+    let upstream = echo_upstream().await;
+    let (broken, attempts) = broken_socks5_proxy().await;
+    let (proxy, tunnels) = socks5_proxy_with_hosts(&[("hidden.onion", upstream)]).await;
+    // The broken member is picked first
+    let rule = format!("*.onion=socks5h://{broken}#weight=1000|socks5h://{proxy}");
+    let (_stop, _node) = start_node(
+        &mock,
+        &[
+            "--allowed-ports",
+            "80",
+            "--upstream-proxy-rule",
+            &rule,
+            "--upstream-proxy-health-interval-secs",
+            "0",
+        ],
+    );
+    let mut tunnel = mock.tunnel().await;
+
+    for _ in 0..2 {
+        let response = tunnel
+            .request("GET", "http://hidden.onion/", &[], b"")
+            .await
+            .expect("relayed response");
+        assert_eq!(&response.body[..], b"GET /\n");
+    }
+    // Marked down after its failed handshake, so only tried once
+    assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    assert_eq!(tunnels.load(Ordering::SeqCst), 2);
+}
+
+#[tokio::test]
+async fn keeps_a_trusted_request_id() {
+    let mock = MockAether::start(TOKEN).await;
+    let upstream = echo_upstream().await;
+    let port = upstream.port().to_string();
+    let (_stop, _node) = start_node(
+        &mock,
+        &[
+            "--allow-private-targets",
+            "--allowed-ports",
+            &port,
+            "--trust-request-id",
+        ],
+    );
+    let mut tunnel = mock.tunnel().await;
+    let response = tunnel
+        .request(
+            "GET",
+            &format!("http://{}/", upstream),
+            &[("x-aether-request-id", "aether-42")],
+            b"",
+        )
+        .await
+        .expect("relayed response");
+    assert_eq!(response.header("x-aether-request-id"), Some("aether-42"));
+}
+
+#[tokio::test]
+async fn remote_config_changes_take_effect() {
+    let mock = MockAether::start(TOKEN).await;
+    let upstream = echo_upstream().await;
+    let port = upstream.port().to_string();
+    let (_stop, _node) = start_node(
+        &mock,
+        &["--allow-private-targets", "--allowed-ports", &port],
+    );
+    let mut tunnel = mock.tunnel().await;
+    let url = format!("http://{}/", upstream);
+    assert!(tunnel.request("GET", &url, &[], b"").await.is_ok());
+
+    // Aether narrows the allowed ports with the next heartbeat ACK
+    mock.set_remote_config(1, serde_json::json!({ "allowed_ports": [443] }));
+    let seen = mock.heartbeats().len();
+    mock.wait_for_heartbeats(seen + 2).await;
+    let err = tunnel.request("GET", &url, &[], b"").await.unwrap_err();
+    assert!(err.contains("target blocked"), "{err}");
+}
+
+#[tokio::test]
+async fn a_locally_refused_request_leaves_the_circuit_trial() {
+    let mock = MockAether::start(TOKEN).await;
+    // Nothing listens here: every request is a connect failure
+    let dead = TcpListener::bind("127.0.0.1:0")
+        .await
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let port = dead.port().to_string();
+    let (_stop, _node) = start_node(
+        &mock,
+        &[
+            "--allow-private-targets",
+            "--allowed-ports",
+            &port,
+            "--feature",
+            "circuit_breaker=on",
+            "--circuit-breaker-failures",
+            "1",
+            "--circuit-breaker-open-secs",
+            "5",
+        ],
+    );
+    let mut tunnel = mock.tunnel().await;
+    let url = format!("http://{dead}/");
+    let err = tunnel.request("GET", &url, &[], b"").await.unwrap_err();
+    assert!(err.contains("connect error"), "{err}");
+    let response = tunnel.request("GET", &url, &[], b"").await.unwrap();
+    assert_eq!(response.status, 502);
+
+    // Once the circuit is half-open, the request that would be the trial
+    // is refused before it reaches the target
+    mock.set_remote_config(1, serde_json::json!({ "allowed_ports": [443] }));
+    tokio::time::sleep(Duration::from_secs(5)).await;
+    let err = tunnel.request("GET", &url, &[], b"").await.unwrap_err();
+    assert!(err.contains("target blocked"), "{err}");
+
+    // The next request still gets to try the target
+    mock.set_remote_config(2, serde_json::json!({ "allowed_ports": [dead.port()] }));
+    let seen = mock.heartbeats().len();
+    mock.wait_for_heartbeats(seen + 2).await;
+    let err = tunnel.request("GET", &url, &[], b"").await.unwrap_err();
+    assert!(err.contains("connect error"), "{err}");
+}
+
+#[tokio::test]
+async fn serves_hot_targets_from_warm_connections() {
+    let mock = MockAether::start(TOKEN).await;
+    let upstream = echo_upstream().await;
+    let port = upstream.port().to_string();
+    let hot_target = format!("http://{}", upstream);
+    let (_stop, _node) = start_node(
+        &mock,
+        &[
+            "--allow-private-targets",
+            "--allowed-ports",
+            &port,
+            "--hot-target",
+            &hot_target,
+        ],
+    );
+    let mut tunnel = mock.tunnel().await;
+    let warm_pool = |key: &str| {
+        let heartbeats = mock.heartbeats();
+        heartbeats.last().map_or(0, |hb| {
+            hb["proxy_metadata"]["warm_pool"][key].as_u64().unwrap_or(0)
+        })
+    };
+
+    let mut seen = mock.heartbeats().len();
+    while warm_pool("idle") == 0 {
+        seen += 1;
+        assert!(seen < 15, "no warm connection was established");
+        mock.wait_for_heartbeats(seen).await;
+    }
+    let response = tunnel
+        .request("GET", &format!("{}/warm", hot_target), &[], b"")
+        .await
+        .expect("relayed response");
+    assert_eq!(&response.body[..], b"GET /warm\n");
+    mock.wait_for_heartbeats(seen + 2).await;
+    assert!(warm_pool("hits") >= 1);
+}
+
+#[tokio::test]
+async fn stops_usage_uploads_to_a_server_without_the_endpoint() {
+    let mock = MockAether::start(TOKEN).await;
+    mock.reject_uploads();
+    let upstream = echo_upstream().await;
+    let port = upstream.port().to_string();
+    let (_stop, _node) = start_node(
+        &mock,
+        &[
+            "--allow-private-targets",
+            "--allowed-ports",
+            &port,
+            "--usage-report-interval-secs",
+            "1",
+        ],
+    );
+    let mut tunnel = mock.tunnel().await;
+    let url = format!("http://{}/", upstream);
+    let client = [("x-aether-client", "tenant-1")];
+    let usage_uploads = || {
+        mock.uploads()
+            .iter()
+            .filter(|path| path.ends_with("/usage"))
+            .count()
+    };
+
+    tunnel.request("GET", &url, &client, b"").await.unwrap();
+    let mut seen = mock.heartbeats().len();
+    while usage_uploads() == 0 {
+        seen += 1;
+        assert!(seen < 15, "usage was never uploaded");
+        mock.wait_for_heartbeats(seen).await;
+    }
+
+    // The 404 disables reporting instead of retrying every interval
+    tunnel.request("GET", &url, &client, b"").await.unwrap();
+    mock.wait_for_heartbeats(seen + 3).await;
+    assert_eq!(usage_uploads(), 1);
+}
+
+#[tokio::test]
+async fn stops_access_log_uploads_to_a_server_without_the_endpoint() {
+    let mock = MockAether::start(TOKEN).await;
+    mock.reject_uploads();
+    let upstream = echo_upstream().await;
+    let port = upstream.port().to_string();
+    let (_stop, _node) = start_node(
+        &mock,
+        &[
+            "--allow-private-targets",
+            "--allowed-ports",
+            &port,
+            "--access-log",
+            "aether",
+        ],
+    );
+    let mut tunnel = mock.tunnel().await;
+    let url = format!("http://{}/", upstream);
+    let log_uploads = || {
+        mock.uploads()
+            .iter()
+            .filter(|path| path.ends_with("/access-log"))
+            .count()
+    };
+
+    tunnel.request("GET", &url, &[], b"").await.unwrap();
+    let mut seen = mock.heartbeats().len();
+    while log_uploads() == 0 {
+        seen += 1;
+        assert!(seen < 30, "the access log was never uploaded");
+        mock.wait_for_heartbeats(seen).await;
+    }
+
+    // Later records are not buffered or retried
+    tunnel.request("GET", &url, &[], b"").await.unwrap();
+    mock.wait_for_heartbeats(seen + 12).await;
+    assert_eq!(log_uploads(), 1);
+}
+
+#[tokio::test]
+async fn stops_destination_reports_to_a_server_without_the_endpoint() {
+    let mock = MockAether::start(TOKEN).await;
+    mock.reject_uploads();
+    let upstream = echo_upstream().await;
+    let port = upstream.port().to_string();
+    let (_stop, _node) = start_node(
+        &mock,
+        &[
+            "--allow-private-targets",
+            "--allowed-ports",
+            &port,
+            "--destination-report-interval-secs",
+            "1",
+        ],
+    );
+    let mut tunnel = mock.tunnel().await;
+    let url = format!("http://{}/", upstream);
+    let reports = || {
+        mock.uploads()
+            .iter()
+            .filter(|path| path.ends_with("/destinations"))
+            .count()
+    };
+
+    tunnel.request("GET", &url, &[], b"").await.unwrap();
+    let mut seen = mock.heartbeats().len();
+    while reports() == 0 {
+        seen += 1;
+        assert!(seen < 15, "no destinations report was sent");
+        mock.wait_for_heartbeats(seen).await;
+    }
+
+    tunnel.request("GET", &url, &[], b"").await.unwrap();
+    mock.wait_for_heartbeats(seen + 3).await;
+    assert_eq!(reports(), 1);
+}
+
+#[tokio::test]
+async fn reaches_aether_through_a_proxy() {
+    let mock = MockAether::start(TOKEN).await;
+    let (proxy, tunnels) = socks5_proxy().await;
+    let proxy_url = format!("socks5h://{}", proxy);
+    let (stop, node) = start_node(&mock, &["--aether-proxy-url", &proxy_url]);
+
+    let _tunnel = mock.tunnel().await;
+    assert_eq!(mock.registrations().len(), 1);
+    // One for the registration request, one for the tunnel
+    assert!(tunnels.load(Ordering::SeqCst) >= 2);
+    mock.wait_for_heartbeats(1).await;
+
+    stop.send(()).unwrap();
+    tokio::time::timeout(Duration::from_secs(30), node)
+        .await
+        .expect("node stops")
+        .unwrap()
+        .unwrap();
+    assert_eq!(mock.unregistrations(), [NODE_ID]);
+}
+
+#[tokio::test]
+async fn rejects_a_wrong_management_token() {
+    let mock = MockAether::start(TOKEN).await;
+    let mut config = mock.node_config(&[]);
+    config.management_token = "wrong".to_string();
+    let servers = MockAether::servers(&config);
+    let result = tokio::time::timeout(
+        Duration::from_secs(30),
+        app::run_until(config, servers, std::future::pending()),
+    )
+    .await
+    .expect("startup gives up");
+    let err = result.unwrap_err().to_string();
+    assert!(err.contains("no servers registered"), "{err}");
+    assert!(mock.registrations().is_empty());
+}
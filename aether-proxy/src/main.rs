@@ -1,20 +1,11 @@
-mod app;
-mod config;
-mod hardware;
-mod net;
-mod registration;
-mod runtime;
 mod setup;
-mod state;
-mod target_filter;
-mod tunnel;
-mod upstream_client;
 
 use std::path::PathBuf;
 
-use clap::{CommandFactory, FromArgMatches, Parser};
+use clap::{Args, CommandFactory, FromArgMatches, Parser};
 
-use config::Config;
+use aether_proxy_core::config::{self, Config};
+use aether_proxy_core::{app, features, handoff, remote_upgrade};
 
 /// Default config file name.
 const DEFAULT_CONFIG: &str = "aether-proxy.toml";
@@ -25,6 +16,11 @@ const DEFAULT_CONFIG: &str = "aether-proxy.toml";
 /// flags so that e.g. `aether-proxy setup` doesn't demand `--aether-url`.
 fn build_command() -> clap::Command {
     Config::command()
+        .subcommand(
+            Config::augment_args(clap::Command::new("run"))
+                .about("Run the proxy (the default when no subcommand is given)")
+                .long_about(None),
+        )
         .subcommand(
             clap::Command::new("setup")
                 .about("Interactive setup wizard (TUI)")
@@ -45,23 +41,91 @@ fn build_command() -> clap::Command {
                 .about("Self-upgrade from GitHub releases")
                 .arg(clap::Arg::new("version").help("Target version (e.g. 0.2.0)")),
         )
+        .subcommand(
+            clap::Command::new("support-bundle")
+                .about("Write a redacted diagnostics tarball for bug reports")
+                .arg(
+                    clap::Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .help("Output path (default: aether-proxy-support-<timestamp>.tar.gz)"),
+                )
+                .arg(
+                    clap::Arg::new("minutes")
+                        .long("minutes")
+                        .help("How many minutes of logs and stats to include")
+                        .value_parser(clap::value_parser!(u64).range(1..))
+                        .default_value("30"),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("check")
+                .about("Verify config, control-plane access, DNS and outbound reachability")
+                .arg(
+                    clap::Arg::new("target")
+                        .long("target")
+                        .help("URL fetched for the outbound check")
+                        .default_value(setup::check::DEFAULT_TARGET),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("healthcheck")
+                .about("Probe the local admin endpoint; exit 0 when healthy")
+                .arg(
+                    clap::Arg::new("ready")
+                        .long("ready")
+                        .help("Probe /readyz (serving with a connected tunnel) instead of /healthz")
+                        .action(clap::ArgAction::SetTrue),
+                )
+                .arg(
+                    clap::Arg::new("admin_listen")
+                        .long("admin-listen")
+                        .help(
+                            "Admin address (default: AETHER_PROXY_ADMIN_LISTEN or the config file)",
+                        )
+                        .value_parser(clap::value_parser!(std::net::SocketAddr)),
+                )
+                .arg(
+                    clap::Arg::new("timeout")
+                        .long("timeout")
+                        .help("Probe timeout in seconds")
+                        .value_parser(clap::value_parser!(u64).range(1..))
+                        .default_value("5"),
+                ),
+        )
+        .subcommand(
+            clap::Command::new("version")
+                .about("Print version and build information")
+                .arg(
+                    clap::Arg::new("json")
+                        .long("json")
+                        .help("Print as JSON")
+                        .action(clap::ArgAction::SetTrue),
+                ),
+        )
         .subcommand_negates_reqs(true)
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
+fn main() -> anyhow::Result<()> {
     rustls::crypto::ring::default_provider()
         .install_default()
         .map_err(|_| anyhow::anyhow!("Failed to install rustls CryptoProvider"))?;
 
+    handoff::record_launch();
+
     // Load config file as env-var defaults (before clap parsing)
     let config_file_path =
         std::env::var("AETHER_PROXY_CONFIG").unwrap_or_else(|_| DEFAULT_CONFIG.to_string());
     let config_path = std::path::Path::new(&config_file_path);
     if config_path.exists() {
         // Migrate legacy 0.1.x config to 0.2.0 format if needed
-        if let Err(e) = config::ConfigFile::migrate_legacy(config_path) {
-            eprintln!("  WARNING: config migration failed: {}", e);
+        match config::ConfigFile::migrate_legacy(config_path) {
+            Ok(Some(backup_path)) => {
+                eprintln!("  Config migrated from 0.1.x to 0.2.0 format.");
+                eprintln!("  Backup saved: {}", backup_path.display());
+            }
+            Ok(None) => {}
+            Err(e) => eprintln!("  WARNING: config migration failed: {}", e),
         }
         if let Ok(file_cfg) = config::ConfigFile::load(config_path) {
             file_cfg.inject_env();
@@ -69,8 +133,58 @@ async fn main() -> anyhow::Result<()> {
     }
 
     // Parse CLI (subcommands + config args in one pass)
-    match build_command().try_get_matches() {
+    let matches = build_command().try_get_matches();
+    let runtime = build_runtime(matches.as_ref().ok())?;
+    runtime.block_on(dispatch(matches, config_file_path))
+}
+
+/// Build the Tokio runtime sized by `--runtime-flavor`, `--worker-threads`
+/// and `--max-blocking-threads`; defaults when the CLI did not parse (the
+/// setup wizard takes over then).
+fn build_runtime(matches: Option<&clap::ArgMatches>) -> anyhow::Result<tokio::runtime::Runtime> {
+    let matches = matches.map(|m| match m.subcommand() {
+        Some(("run", sub_m)) => sub_m,
+        _ => m,
+    });
+    let flavor = matches
+        .and_then(|m| m.get_one::<String>("runtime_flavor"))
+        .map(String::as_str)
+        .unwrap_or("multi-thread");
+    let worker_threads = matches.and_then(|m| m.get_one::<usize>("worker_threads").copied());
+    let max_blocking = matches.and_then(|m| m.get_one::<usize>("max_blocking_threads").copied());
+
+    if worker_threads == Some(0) || max_blocking == Some(0) {
+        anyhow::bail!("worker_threads and max_blocking_threads must be >= 1");
+    }
+
+    let mut builder = match flavor {
+        "multi-thread" => {
+            let mut builder = tokio::runtime::Builder::new_multi_thread();
+            if let Some(n) = worker_threads {
+                builder.worker_threads(n);
+            }
+            builder
+        }
+        "current-thread" => tokio::runtime::Builder::new_current_thread(),
+        other => anyhow::bail!(
+            "invalid runtime_flavor {:?} (use multi-thread or current-thread)",
+            other
+        ),
+    };
+    if let Some(n) = max_blocking {
+        builder.max_blocking_threads(n);
+    }
+    Ok(builder.enable_all().build()?)
+}
+
+async fn dispatch(
+    matches: Result<clap::ArgMatches, clap::Error>,
+    config_file_path: String,
+) -> anyhow::Result<()> {
+    let config_path = std::path::Path::new(&config_file_path);
+    match matches {
         Ok(matches) => match matches.subcommand() {
+            Some(("run", sub_m)) => run_proxy(Config::from_arg_matches(sub_m)?).await,
             Some(("setup", sub_m)) => {
                 let path = sub_m
                     .get_one::<String>("config_path")
@@ -88,6 +202,36 @@ async fn main() -> anyhow::Result<()> {
                 let version = sub_m.get_one::<String>("version").cloned();
                 setup::upgrade::cmd_upgrade(version).await
             }
+            Some(("support-bundle", sub_m)) => {
+                let output = sub_m.get_one::<String>("output").map(PathBuf::from);
+                let minutes = sub_m.get_one::<u64>("minutes").copied().unwrap_or(30);
+                setup::bundle::cmd_support_bundle(config_path, output, minutes).await
+            }
+            Some(("check", sub_m)) => {
+                let target = sub_m
+                    .get_one::<String>("target")
+                    .map(String::as_str)
+                    .unwrap_or(setup::check::DEFAULT_TARGET);
+                let config = Config::from_arg_matches(&matches);
+                let servers = config.as_ref().map(resolve_servers).unwrap_or_default();
+                setup::check::cmd_check(config, servers, target).await
+            }
+            Some(("healthcheck", sub_m)) => {
+                let admin_listen = sub_m
+                    .get_one::<std::net::SocketAddr>("admin_listen")
+                    .copied();
+                let timeout = sub_m.get_one::<u64>("timeout").copied().unwrap_or(5);
+                setup::healthcheck::cmd_healthcheck(
+                    admin_listen,
+                    sub_m.get_flag("ready"),
+                    std::time::Duration::from_secs(timeout),
+                )
+                .await
+            }
+            Some(("version", sub_m)) => {
+                print_version(sub_m.get_flag("json"));
+                Ok(())
+            }
             Some(_) => unreachable!(),
             None => {
                 // No subcommand — run the proxy with parsed config.
@@ -96,7 +240,9 @@ async fn main() -> anyhow::Result<()> {
             }
         },
         Err(e) => {
-            if e.kind() == clap::error::ErrorKind::MissingRequiredArgument {
+            // A dry run reports missing config instead of starting the wizard
+            let dry_run = std::env::args().any(|a| a == "--dry-run" || a == "--validate");
+            if e.kind() == clap::error::ErrorKind::MissingRequiredArgument && !dry_run {
                 eprintln!("Missing required config, launching setup wizard...\n");
                 handle_setup_result(setup::run(PathBuf::from(&config_file_path))?).await
             } else {
@@ -106,11 +252,38 @@ async fn main() -> anyhow::Result<()> {
     }
 }
 
+/// `aether-proxy version [--json]`.
+fn print_version(json: bool) {
+    let version = env!("CARGO_PKG_VERSION");
+    if json {
+        let info = serde_json::json!({
+            "name": env!("CARGO_PKG_NAME"),
+            "version": version,
+            "os": std::env::consts::OS,
+            "arch": std::env::consts::ARCH,
+            "feature_flags": features::Feature::ALL
+                .iter()
+                .map(|f| f.as_str())
+                .collect::<Vec<_>>(),
+        });
+        println!("{}", info);
+    } else {
+        println!(
+            "{} {} ({}/{})",
+            env!("CARGO_PKG_NAME"),
+            version,
+            std::env::consts::OS,
+            std::env::consts::ARCH
+        );
+    }
+}
+
 /// Decide what to do after the setup wizard completes.
 async fn handle_setup_result(outcome: setup::SetupOutcome) -> anyhow::Result<()> {
     match outcome {
         setup::SetupOutcome::ServiceInstalled => Ok(()),
         setup::SetupOutcome::ReadyToRun(config_path) => {
+            handoff::record_setup_config(&config_path);
             // Reload config from the file that setup just wrote, overriding
             // any stale env vars from a previous config.
             match config::ConfigFile::load(&config_path) {
@@ -132,6 +305,11 @@ async fn handle_setup_result(outcome: setup::SetupOutcome) -> anyhow::Result<()>
 
 /// Start the proxy server, checking for systemd conflicts first.
 async fn run_proxy(config: Config) -> anyhow::Result<()> {
+    if config.dry_run {
+        let servers = resolve_servers(&config);
+        return setup::check::cmd_dry_run(config, servers).await;
+    }
+
     // Warn if systemd service is already running (would cause port conflict).
     // Skip this check when we ARE the systemd service (INVOCATION_ID is set by systemd).
     if std::env::var_os("INVOCATION_ID").is_none() && setup::service::is_service_active() {
@@ -141,10 +319,24 @@ async fn run_proxy(config: Config) -> anyhow::Result<()> {
         std::process::exit(1);
     }
 
-    // Resolve server list: prefer [[servers]] from TOML, fall back to CLI/env single server.
+    // Remote upgrades replace the binary and restart the unit, which only
+    // root can do; other nodes log the instruction and carry on.
+    if setup::service::is_root() {
+        remote_upgrade::set_handler(|version| {
+            Box::pin(async move { setup::upgrade::perform_upgrade(&version).await })
+        });
+    }
+
+    let servers = resolve_servers(&config);
+    app::run(config, servers).await
+}
+
+/// Resolve the server list: prefer [[servers]] from TOML, fall back to the
+/// CLI/env single server.
+fn resolve_servers(config: &Config) -> Vec<config::ServerEntry> {
     let config_path =
         std::env::var("AETHER_PROXY_CONFIG").unwrap_or_else(|_| DEFAULT_CONFIG.to_string());
-    let servers = if std::path::Path::new(&config_path).exists() {
+    if std::path::Path::new(&config_path).exists() {
         config::ConfigFile::load(std::path::Path::new(&config_path))
             .ok()
             .map(|f| f.effective_servers())
@@ -162,7 +354,5 @@ async fn run_proxy(config: Config) -> anyhow::Result<()> {
             management_token: config.management_token.clone(),
             node_name: None,
         }]
-    };
-
-    app::run(config, servers).await
+    }
 }
@@ -0,0 +1,268 @@
+//! `aether-proxy support-bundle`: collect diagnostics for a bug report.
+//!
+//! Produces a gzipped tarball with the redacted config file, an environment
+//! report, recent service logs, the last minutes of admin-API stats and
+//! version info.  Everything that looks like a credential (token/secret/
+//! password keys, `user:pass@` in URLs) is replaced before it is written.
+
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use clap::Parser;
+
+use aether_proxy_core::config::Config;
+use aether_proxy_core::redact::{redact_json, redact_table, redact_userinfo, redact_value};
+
+/// One file inside the bundle.
+struct Entry {
+    name: &'static str,
+    contents: String,
+}
+
+/// `aether-proxy support-bundle` -- write the bundle and print its path.
+pub async fn cmd_support_bundle(
+    config_path: &Path,
+    output: Option<PathBuf>,
+    minutes: u64,
+) -> anyhow::Result<()> {
+    let now = unix_now();
+    let output =
+        output.unwrap_or_else(|| PathBuf::from(format!("aether-proxy-support-{}.tar.gz", now)));
+
+    // The config file was already injected into the environment by main, so
+    // parsing from env alone yields the effective config.
+    let config = Config::try_parse_from(["aether-proxy"]);
+
+    let entries = vec![
+        Entry {
+            name: "version.txt",
+            contents: version_report(),
+        },
+        Entry {
+            name: "config.toml",
+            contents: config_report(config_path),
+        },
+        Entry {
+            name: "environment.txt",
+            contents: environment_report(&config),
+        },
+        Entry {
+            name: "events.log",
+            contents: events_report(minutes),
+        },
+        Entry {
+            name: "stats.json",
+            contents: match &config {
+                Ok(config) => stats_report(config, now, minutes).await,
+                Err(_) => "config did not parse; stats unavailable\n".to_string(),
+            },
+        },
+    ];
+
+    write_tarball(&output, &entries, now)?;
+    eprintln!("  Support bundle written to {}", output.display());
+    eprintln!("  Review it before attaching to an issue; credentials have been redacted.");
+    Ok(())
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn write_tarball(path: &Path, entries: &[Entry], mtime: u64) -> anyhow::Result<()> {
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    let file = std::fs::File::create(path)?;
+    let mut archive = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+    for entry in entries {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(entry.contents.len() as u64);
+        header.set_mode(0o600);
+        header.set_mtime(mtime);
+        header.set_cksum();
+        archive.append_data(
+            &mut header,
+            format!("aether-proxy-support/{}", entry.name),
+            entry.contents.as_bytes(),
+        )?;
+    }
+    archive.into_inner()?.finish()?.flush()?;
+    Ok(())
+}
+
+// ── Reports ──────────────────────────────────────────────────────────────────
+
+fn version_report() -> String {
+    format!(
+        "aether-proxy {}\nos: {}\narch: {}\nbuild: {}\n",
+        env!("CARGO_PKG_VERSION"),
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        if cfg!(debug_assertions) {
+            "debug"
+        } else {
+            "release"
+        },
+    )
+}
+
+fn config_report(path: &Path) -> String {
+    let raw = match std::fs::read_to_string(path) {
+        Ok(raw) => raw,
+        Err(e) => return format!("# {} not readable: {}\n", path.display(), e),
+    };
+    match raw.parse::<toml::Table>() {
+        Ok(mut table) => {
+            redact_table(&mut table);
+            format!(
+                "# {} (redacted)\n{}",
+                path.display(),
+                toml::to_string_pretty(&table).unwrap_or_default()
+            )
+        }
+        Err(e) => format!("# {} does not parse as TOML: {}\n", path.display(), e),
+    }
+}
+
+fn environment_report(config: &Result<Config, clap::Error>) -> String {
+    let mut out = String::new();
+    let hardware = aether_proxy_core::hardware::collect();
+    out.push_str(&format!(
+        "os_info: {}\ncpu_cores: {}\ntotal_memory_mb: {}\nfd_limit: {}\n",
+        hardware.os_info, hardware.cpu_cores, hardware.total_memory_mb, hardware.fd_limit
+    ));
+    out.push_str(&format!(
+        "systemd_service_active: {}\n",
+        super::service::is_service_active()
+    ));
+    out.push_str(&format!(
+        "config_valid: {}\n",
+        match config {
+            Ok(config) => match config.validate() {
+                Ok(()) => "yes".to_string(),
+                Err(e) => format!("no ({})", e),
+            },
+            Err(e) => format!("no ({})", e.kind()),
+        }
+    ));
+
+    out.push_str("\n# Effective AETHER_PROXY_* settings (config file + environment)\n");
+    let mut vars: Vec<(String, String)> = std::env::vars()
+        .filter(|(key, _)| key.starts_with("AETHER_PROXY_"))
+        .collect();
+    vars.sort();
+    for (key, value) in vars {
+        out.push_str(&format!("{}={}\n", key, redact_value(&key, &value)));
+    }
+    out
+}
+
+fn events_report(minutes: u64) -> String {
+    let since = format!("-{}min", minutes);
+    let output = Command::new("journalctl")
+        .args(["-u", "aether-proxy", "--since", &since, "--no-pager", "-o"])
+        .arg("short-iso")
+        .output();
+    match output {
+        Ok(output) if output.status.success() => {
+            let logs = String::from_utf8_lossy(&output.stdout);
+            logs.lines()
+                .map(redact_userinfo)
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+        Ok(output) => format!(
+            "journalctl failed: {}\n",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+        Err(e) => format!("journalctl unavailable ({}); attach your own logs\n", e),
+    }
+}
+
+/// Fetch `/api/status` from the running node's admin port and keep the
+/// last `minutes` of history.
+async fn stats_report(config: &Config, now: u64, minutes: u64) -> String {
+    let (Some(mut addr), Some(token)) = (config.admin_listen, config.admin_token.as_deref()) else {
+        return "admin port not configured (set --admin-listen/--admin-token); stats unavailable\n"
+            .to_string();
+    };
+    if addr.ip().is_unspecified() {
+        addr.set_ip(match addr {
+            std::net::SocketAddr::V4(_) => std::net::Ipv4Addr::LOCALHOST.into(),
+            std::net::SocketAddr::V6(_) => std::net::Ipv6Addr::LOCALHOST.into(),
+        });
+    }
+    let result = async {
+        let client = reqwest::Client::builder()
+            .timeout(Duration::from_secs(5))
+            .build()?;
+        client
+            .get(format!("http://{}/api/status", addr))
+            .bearer_auth(token)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<serde_json::Value>()
+            .await
+    }
+    .await;
+    match result {
+        Ok(mut status) => {
+            let cutoff = now.saturating_sub(minutes * 60);
+            if let Some(history) = status
+                .get_mut("history")
+                .and_then(serde_json::Value::as_array_mut)
+            {
+                history.retain(|s| s["at"].as_u64().is_some_and(|at| at >= cutoff));
+            }
+            redact_json(&mut status);
+            serde_json::to_string_pretty(&status).unwrap_or_default()
+        }
+        Err(e) => format!("admin API at {} unreachable: {}\n", addr, e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tarball_contains_entries() {
+        let dir = std::env::temp_dir().join(format!("aether-bundle-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("bundle.tar.gz");
+        let entries = [
+            Entry {
+                name: "version.txt",
+                contents: version_report(),
+            },
+            Entry {
+                name: "events.log",
+                contents: "line\n".into(),
+            },
+        ];
+        write_tarball(&path, &entries, 0).unwrap();
+
+        let file = std::fs::File::open(&path).unwrap();
+        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(file));
+        let names: Vec<String> = archive
+            .entries()
+            .unwrap()
+            .map(|e| e.unwrap().path().unwrap().display().to_string())
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "aether-proxy-support/version.txt",
+                "aether-proxy-support/events.log"
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
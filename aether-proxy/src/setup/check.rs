@@ -0,0 +1,399 @@
+//! `aether-proxy check`: verify the setup without serving.
+//!
+//! Every check runs even after an earlier one failed (except that nothing
+//! runs without a valid config).  A JSON report goes to stdout and a short
+//! summary to stderr; the exit status is non-zero when any check failed,
+//! so provisioning scripts can gate on it.
+
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+use serde::Serialize;
+
+use aether_proxy_core::config::{Config, ServerEntry};
+use aether_proxy_core::dns::{DnsResolver, LookupError};
+use aether_proxy_core::net;
+use aether_proxy_core::registration::client::AetherClient;
+use aether_proxy_core::registration::endpoint::split_urls;
+use aether_proxy_core::runtime::DynamicConfig;
+use aether_proxy_core::target_filter::{self, DnsCache};
+use aether_proxy_core::upstream_client;
+
+/// Default target for the outbound connectivity check.
+pub const DEFAULT_TARGET: &str = "https://www.cloudflare.com/cdn-cgi/trace";
+
+#[derive(Debug, Serialize)]
+struct Check {
+    name: String,
+    ok: bool,
+    detail: String,
+    duration_ms: u64,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct Report {
+    ok: bool,
+    checks: Vec<Check>,
+}
+
+impl Report {
+    /// Run one check, timing it.
+    async fn run<F>(&mut self, name: impl Into<String>, check: F)
+    where
+        F: Future<Output = Result<String, String>>,
+    {
+        let started = Instant::now();
+        let result = check.await;
+        self.push(name, result, started.elapsed());
+    }
+
+    fn push(&mut self, name: impl Into<String>, result: Result<String, String>, took: Duration) {
+        let (ok, detail) = match result {
+            Ok(detail) => (true, detail),
+            Err(detail) => (false, detail),
+        };
+        self.checks.push(Check {
+            name: name.into(),
+            ok,
+            detail,
+            duration_ms: took.as_millis() as u64,
+        });
+    }
+}
+
+/// `aether-proxy check` -- run all checks and print the report.
+pub async fn cmd_check(
+    config: Result<Config, clap::Error>,
+    servers: Vec<ServerEntry>,
+    target: &str,
+) -> anyhow::Result<()> {
+    print_report(run_checks(config, servers, target).await)
+}
+
+/// `aether-proxy run --dry-run` -- the offline subset of the checks: config,
+/// server URLs and listeners.  Nothing is sent to the control plane.
+pub async fn cmd_dry_run(config: Config, servers: Vec<ServerEntry>) -> anyhow::Result<()> {
+    let mut report = Report::default();
+    let Some(config) = check_config(&mut report, Ok(config)) else {
+        return print_report(finish(report));
+    };
+    check_servers(&mut report, &servers);
+    check_listeners(&mut report, &config).await;
+    print_report(finish(report))
+}
+
+fn print_report(report: Report) -> anyhow::Result<()> {
+    for check in &report.checks {
+        eprintln!(
+            "  [{}] {}: {}",
+            if check.ok { " ok " } else { "FAIL" },
+            check.name,
+            check.detail
+        );
+    }
+    println!("{}", serde_json::to_string_pretty(&report)?);
+
+    let failed = report.checks.iter().filter(|c| !c.ok).count();
+    if failed > 0 {
+        anyhow::bail!("{} of {} checks failed", failed, report.checks.len());
+    }
+    Ok(())
+}
+
+fn check_config(report: &mut Report, config: Result<Config, clap::Error>) -> Option<Config> {
+    let config = config
+        .map_err(|e| e.to_string())
+        .and_then(|c| c.validate().map(|()| c).map_err(|e| format!("{:#}", e)));
+    match config {
+        Ok(config) => {
+            report.push("config", Ok("valid".to_string()), Duration::ZERO);
+            Some(config)
+        }
+        Err(e) => {
+            let e = e.trim().trim_start_matches("error: ").to_string();
+            report.push("config", Err(e), Duration::ZERO);
+            None
+        }
+    }
+}
+
+/// Every server needs a token and at least one well-formed URL; a bare
+/// host is taken as https, as the tunnel and registration clients do.
+fn check_servers(report: &mut Report, servers: &[ServerEntry]) {
+    for (i, entry) in servers.iter().enumerate() {
+        let urls = split_urls(&entry.aether_url);
+        let mut problems = Vec::new();
+        if urls.is_empty() {
+            problems.push("aether_url is empty".to_string());
+        }
+        for raw in &urls {
+            let parsed = if raw.contains("://") {
+                url::Url::parse(raw)
+            } else {
+                url::Url::parse(&format!("https://{}", raw))
+            };
+            match parsed {
+                Ok(url) if !matches!(url.scheme(), "http" | "https") => {
+                    problems.push(format!("{}: scheme must be http or https", raw))
+                }
+                Ok(url) if !url.has_host() => problems.push(format!("{}: no host", raw)),
+                Ok(_) => {}
+                Err(e) => problems.push(format!("{}: {}", raw, e)),
+            }
+        }
+        if entry.management_token.trim().is_empty() {
+            problems.push("management_token is empty".to_string());
+        }
+        let result = if problems.is_empty() {
+            Ok(urls.join(", "))
+        } else {
+            Err(problems.join("; "))
+        };
+        report.push(format!("server[{}]", i), result, Duration::ZERO);
+    }
+}
+
+/// Ports this node would bind.
+async fn check_listeners(report: &mut Report, config: &Config) {
+    let mut listeners = Vec::new();
+    if let Some(addr) = config.admin_listen {
+        listeners.push(("admin_listen", addr));
+    }
+    if config.metrics_backend.eq_ignore_ascii_case("prometheus") {
+        listeners.push(("metrics_listen", config.metrics_listen));
+    }
+    for (name, addr) in listeners {
+        report
+            .run(format!("{} {}", name, addr), async {
+                tokio::net::TcpListener::bind(addr)
+                    .await
+                    .map(|_| "bindable".to_string())
+                    .map_err(|e| format!("{} (is the service already running?)", e))
+            })
+            .await;
+    }
+}
+
+async fn run_checks(
+    config: Result<Config, clap::Error>,
+    servers: Vec<ServerEntry>,
+    target: &str,
+) -> Report {
+    let mut report = Report::default();
+    let Some(config) = check_config(&mut report, config) else {
+        return finish(report);
+    };
+    check_servers(&mut report, &servers);
+
+    // Control plane: TLS setup, then reachability and token per URL
+    match config.aether_tls() {
+        Ok(tls) => {
+            for (i, entry) in servers.iter().enumerate() {
+                let started = Instant::now();
+                let client =
+                    AetherClient::new(&config, &tls, &entry.aether_url, &entry.management_token);
+                for (url, result) in client.verify_access().await {
+                    report.push(
+                        format!("control_plane[{}] {}", i, url),
+                        result
+                            .map(|()| "reachable, token accepted".to_string())
+                            .map_err(|e| e.to_string()),
+                        started.elapsed(),
+                    );
+                }
+            }
+        }
+        Err(e) => report.push("control_plane_tls", Err(e.to_string()), Duration::ZERO),
+    }
+
+    let mut public_addrs = net::PublicAddrs {
+        primary: "0.0.0.0".to_string(),
+        ipv6: None,
+    };
+    report
+        .run("public_ip", async {
+            let sources = config.ip_sources().map_err(|e| e.to_string())?;
+            public_addrs =
+                net::detect_public_addrs(config.public_ip.as_deref(), config.public_ipv6, &sources)
+                    .await;
+            if public_addrs.primary == "0.0.0.0" {
+                return Err("no source reported a public address; set --public-ip".to_string());
+            }
+            let source = if config.public_ip.is_some() {
+                "configured"
+            } else {
+                "detected"
+            };
+            match public_addrs.ipv6 {
+                Some(v6) => Ok(format!(
+                    "{}, ipv6 {} ({})",
+                    public_addrs.primary, v6, source
+                )),
+                None => Ok(format!("{} ({})", public_addrs.primary, source)),
+            }
+        })
+        .await;
+
+    let target_url = match url::Url::parse(target) {
+        Ok(url) if url.host_str().is_some() => url,
+        _ => {
+            report.push(
+                "dns",
+                Err(format!("invalid target {:?}", target)),
+                Duration::ZERO,
+            );
+            return finish(report);
+        }
+    };
+    let host = target_url.host_str().unwrap_or_default().to_string();
+    let port = target_url.port_or_known_default().unwrap_or(443);
+
+    let resolver = match config.dns_settings() {
+        Ok(settings) => DnsResolver::build(&settings).await,
+        Err(e) => Err(e.to_string()),
+    };
+    let resolver = match resolver {
+        Ok(resolver) => resolver,
+        Err(e) => {
+            report.push("dns", Err(e), Duration::ZERO);
+            return finish(report);
+        }
+    };
+    report
+        .run(format!("dns {}", host), async {
+            let overrides = config.host_overrides().map_err(|e| e.to_string())?;
+            if let Some(ips) = overrides.get(&host) {
+                let ips: Vec<String> = ips.iter().map(|ip| ip.to_string()).collect();
+                return Ok(format!("{} (host override)", ips.join(", ")));
+            }
+            let resolved = resolver.lookup(&host, port).await.map_err(|e| match e {
+                LookupError::NotFound { .. } => "no address records".to_string(),
+                LookupError::Failed(e) => e.to_string(),
+            })?;
+            let addrs: Vec<String> = resolved.addrs.iter().map(|a| a.ip().to_string()).collect();
+            Ok(addrs.join(", "))
+        })
+        .await;
+
+    report
+        .run(format!("outbound {}", target), async {
+            outbound_check(&config, &servers, &public_addrs, resolver, &target_url).await
+        })
+        .await;
+
+    check_listeners(&mut report, &config).await;
+    finish(report)
+}
+
+fn finish(mut report: Report) -> Report {
+    report.ok = report.checks.iter().all(|c| c.ok);
+    report
+}
+
+/// Fetch `target` the way a tunnel stream would: target rules, address
+/// policy, egress binding and upstream proxy all apply.
+async fn outbound_check(
+    config: &Config,
+    servers: &[ServerEntry],
+    public_addrs: &net::PublicAddrs,
+    resolver: DnsResolver,
+    target: &url::Url,
+) -> Result<String, String> {
+    let host = target.host_str().unwrap_or_default();
+    let port = target.port_or_known_default().unwrap_or(443);
+    let overrides = config.host_overrides().map_err(|e| e.to_string())?;
+    let dns_cache = Arc::new(
+        DnsCache::new(
+            Duration::from_secs(config.dns_cache_ttl_secs),
+            Duration::from_secs(config.dns_negative_ttl_secs),
+            config.dns_cache_capacity,
+            resolver,
+        )
+        .with_host_overrides(overrides),
+    );
+    let policy = Arc::new(aether_proxy_core::app::build_address_policy(
+        config,
+        servers,
+        public_addrs,
+    ));
+    aether_proxy_core::app::resolve_control_plane(&policy, servers).await;
+    let rules = DynamicConfig::from_config(config);
+    target_filter::validate_target(
+        host,
+        port,
+        &rules.allowed_ports,
+        &rules.domain_rules,
+        &rules.cidr_rules,
+        &policy,
+        &dns_cache,
+    )
+    .await
+    .map_err(|e| format!("target blocked: {}", e))?;
+
+    let egress = config.egress_binding().map_err(|e| e.to_string())?;
+    let upstream_proxy = config
+        .upstream_proxy()
+        .map_err(|e| e.to_string())?
+        .map(Arc::new);
+    if upstream_proxy
+        .as_deref()
+        .is_some_and(|routes| routes.rejects(host))
+    {
+        return Err(format!("{} rejected by upstream proxy rule", host));
+    }
+    let client = upstream_client::build_upstream_client(
+        config,
+        dns_cache,
+        policy,
+        &egress,
+        upstream_proxy,
+        false,
+        None,
+    );
+    let request = hyper::Request::get(target.as_str())
+        .body(upstream_client::full_body(Bytes::new()))
+        .map_err(|e| e.to_string())?;
+    let timeout = Duration::from_secs(
+        config.upstream_connect_timeout_secs + config.upstream_handshake_timeout_secs,
+    );
+    let response = tokio::time::timeout(timeout, client.request(request))
+        .await
+        .map_err(|_| format!("timed out after {}s", timeout.as_secs()))?
+        .map_err(|e| e.to_string())?;
+    Ok(format!("HTTP {}", response.status().as_u16()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn server(url: &str, token: &str) -> ServerEntry {
+        ServerEntry {
+            aether_url: url.to_string(),
+            management_token: token.to_string(),
+            node_name: None,
+        }
+    }
+
+    #[test]
+    fn test_check_servers() {
+        let mut report = Report::default();
+        check_servers(
+            &mut report,
+            &[
+                server("https://a.example.com, b.example.com:8443", "t"),
+                server("ftp://a.example.com", "t"),
+                server(" , ", ""),
+            ],
+        );
+        let ok: Vec<bool> = report.checks.iter().map(|c| c.ok).collect();
+        assert_eq!(ok, [true, false, false]);
+        assert!(report.checks[1].detail.contains("scheme"));
+        assert!(report.checks[2].detail.contains("aether_url is empty"));
+        assert!(report.checks[2]
+            .detail
+            .contains("management_token is empty"));
+    }
+}
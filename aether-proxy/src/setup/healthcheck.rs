@@ -0,0 +1,62 @@
+//! `aether-proxy healthcheck`: probe the local admin endpoint.
+//!
+//! Meant for Docker `HEALTHCHECK` and Kubernetes exec probes, so images
+//! need no curl.  Requires `--admin-listen`; the probe endpoints
+//! (`/healthz`, `/readyz`) need no admin token.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+
+/// `aether-proxy healthcheck` -- exit 0 when the probe answers 200.
+pub async fn cmd_healthcheck(
+    admin_listen: Option<SocketAddr>,
+    ready: bool,
+    timeout: Duration,
+) -> anyhow::Result<()> {
+    let admin_listen = match admin_listen {
+        Some(addr) => addr,
+        None => std::env::var("AETHER_PROXY_ADMIN_LISTEN")
+            .map_err(|_| anyhow::anyhow!("admin_listen is not configured"))?
+            .parse()
+            .map_err(|e| anyhow::anyhow!("invalid AETHER_PROXY_ADMIN_LISTEN: {}", e))?,
+    };
+    let path = if ready { "/readyz" } else { "/healthz" };
+    let url = format!("http://{}{}", probe_addr(admin_listen), path);
+
+    let client = reqwest::Client::builder()
+        .timeout(timeout)
+        .no_proxy()
+        .build()?;
+    let response = client.get(&url).send().await?;
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    if !status.is_success() {
+        anyhow::bail!("{} returned {}: {}", url, status, body.trim());
+    }
+    println!("{}", body.trim());
+    Ok(())
+}
+
+/// The address to connect to for a listen address: a wildcard listener is
+/// reached over loopback.
+fn probe_addr(listen: SocketAddr) -> SocketAddr {
+    let ip = match listen.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+    SocketAddr::new(ip, listen.port())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_probe_addr() {
+        let addr = |s: &str| s.parse::<SocketAddr>().unwrap();
+        assert_eq!(probe_addr(addr("0.0.0.0:9090")), addr("127.0.0.1:9090"));
+        assert_eq!(probe_addr(addr("[::]:9090")), addr("[::1]:9090"));
+        assert_eq!(probe_addr(addr("10.0.0.5:9090")), addr("10.0.0.5:9090"));
+    }
+}
@@ -1,3 +1,6 @@
+pub(crate) mod bundle;
+pub(crate) mod check;
+pub(crate) mod healthcheck;
 pub(crate) mod service;
 mod tui;
 pub(crate) mod upgrade;
@@ -60,11 +60,14 @@ pub fn install_service(config_path: &Path) -> anyhow::Result<()> {
          After=network.target\n\
          \n\
          [Service]\n\
-         Type=simple\n\
+         Type=notify\n\
+         NotifyAccess=all\n\
          WorkingDirectory={working_dir}\n\
          Environment=AETHER_PROXY_CONFIG={config_str}\n\
          ExecStart={exe_str}\n\
+         ExecReload=/bin/kill -USR2 $MAINPID\n\
          Restart=on-failure\n\
+         WatchdogSec=60\n\
          RestartSec=5\n\
          LimitNOFILE=65535\n\
          UMask=0077\n\
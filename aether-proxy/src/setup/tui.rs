@@ -20,7 +20,7 @@ use ratatui::widgets::{Block, Borders, Paragraph};
 use ratatui::Frame;
 use ratatui::Terminal;
 
-use crate::config::{ConfigFile, ServerEntry};
+use aether_proxy_core::config::{ConfigFile, ServerEntry};
 
 /// Outcome of the setup wizard, returned to the caller.
 pub enum SetupOutcome {
@@ -352,6 +352,7 @@ impl App {
         }
     }
 
+    #[allow(clippy::collapsible_match)]
     fn handle_normal(&mut self, key: KeyEvent) -> bool {
         // -- Quit handling (with unsaved-changes confirmation) -----------------
         let is_quit_key = matches!(key.code, KeyCode::Char('q') | KeyCode::Esc);
@@ -496,6 +497,7 @@ impl App {
         false
     }
 
+    #[allow(clippy::collapsible_match)]
     fn handle_edit(&mut self, key: KeyEvent) {
         match key.code {
             KeyCode::Esc => {
@@ -1,381 +0,0 @@
-use std::collections::{HashMap, HashSet};
-use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
-use std::sync::Arc;
-use std::time::{Duration, Instant};
-
-use tokio::sync::RwLock;
-
-/// Check if an IP address belongs to a private/reserved network.
-pub fn is_private_ip(ip: &IpAddr) -> bool {
-    match ip {
-        IpAddr::V4(v4) => is_private_ipv4(v4),
-        IpAddr::V6(v6) => is_private_ipv6(v6),
-    }
-}
-
-fn is_private_ipv4(ip: &Ipv4Addr) -> bool {
-    let octets = ip.octets();
-    // 10.0.0.0/8
-    if octets[0] == 10 {
-        return true;
-    }
-    // 172.16.0.0/12
-    if octets[0] == 172 && (16..=31).contains(&octets[1]) {
-        return true;
-    }
-    // 192.168.0.0/16
-    if octets[0] == 192 && octets[1] == 168 {
-        return true;
-    }
-    // 127.0.0.0/8
-    if octets[0] == 127 {
-        return true;
-    }
-    // 169.254.0.0/16 (link-local)
-    if octets[0] == 169 && octets[1] == 254 {
-        return true;
-    }
-    // 0.0.0.0/8
-    if octets[0] == 0 {
-        return true;
-    }
-    // 100.64.0.0/10 (CGNAT / shared address space)
-    if octets[0] == 100 && (64..=127).contains(&octets[1]) {
-        return true;
-    }
-    // 192.0.0.0/24 (IETF protocol assignments)
-    if octets[0] == 192 && octets[1] == 0 && octets[2] == 0 {
-        return true;
-    }
-    // 198.18.0.0/15 (benchmark testing)
-    if octets[0] == 198 && (18..=19).contains(&octets[1]) {
-        return true;
-    }
-    // 240.0.0.0/4 (reserved for future use)
-    if octets[0] >= 240 {
-        return true;
-    }
-    false
-}
-
-fn is_private_ipv6(ip: &Ipv6Addr) -> bool {
-    // ::1 loopback
-    if ip.is_loopback() {
-        return true;
-    }
-    // :: unspecified
-    if ip.is_unspecified() {
-        return true;
-    }
-    let segments = ip.segments();
-    // fc00::/7 (ULA) - first byte is 0xfc or 0xfd
-    if segments[0] & 0xfe00 == 0xfc00 {
-        return true;
-    }
-    // fe80::/10 (link-local)
-    if segments[0] & 0xffc0 == 0xfe80 {
-        return true;
-    }
-    // IPv4-mapped IPv6 (::ffff:x.x.x.x) - check the embedded IPv4
-    if let Some(v4) = ip.to_ipv4_mapped() {
-        return is_private_ipv4(&v4);
-    }
-    false
-}
-
-#[derive(Debug)]
-pub enum FilterError {
-    PrivateIp(IpAddr),
-    PortNotAllowed(u16),
-    DnsResolutionFailed(String),
-    NoPublicAddrs(String),
-}
-
-impl std::fmt::Display for FilterError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            Self::PrivateIp(ip) => write!(f, "target IP {} is in private/reserved range", ip),
-            Self::PortNotAllowed(port) => write!(f, "port {} not in allowed list", port),
-            Self::DnsResolutionFailed(host) => write!(f, "DNS resolution failed for {}", host),
-            Self::NoPublicAddrs(host) => {
-                write!(
-                    f,
-                    "all resolved addresses for {} are private/reserved",
-                    host
-                )
-            }
-        }
-    }
-}
-
-struct DnsCacheEntry {
-    addrs: Arc<Vec<SocketAddr>>,
-    expires_at: Instant,
-    inserted_at: Instant,
-}
-
-/// Lightweight DNS cache with TTL + capacity bounds.
-/// Stores all public resolved addresses per host (used by SafeDnsResolver
-/// to ensure reqwest connects to the same validated addresses).
-pub struct DnsCache {
-    ttl: Duration,
-    capacity: usize,
-    entries: RwLock<HashMap<String, DnsCacheEntry>>,
-}
-
-impl DnsCache {
-    pub fn new(ttl: Duration, capacity: usize) -> Self {
-        Self {
-            ttl,
-            capacity,
-            entries: RwLock::new(HashMap::new()),
-        }
-    }
-
-    /// Look up cached public addresses for a host (any port).
-    ///
-    /// Used by `SafeDnsResolver` which only knows the hostname — returns the
-    /// first unexpired entry whose key starts with `host:`.
-    pub async fn get_by_host(&self, host: &str) -> Option<Arc<Vec<SocketAddr>>> {
-        if self.capacity == 0 || self.ttl.is_zero() {
-            return None;
-        }
-        let prefix = format!("{}:", host.to_ascii_lowercase());
-        let now = Instant::now();
-        let entries = self.entries.read().await;
-        for (key, entry) in entries.iter() {
-            if key.starts_with(&prefix) && entry.expires_at > now {
-                return Some(Arc::clone(&entry.addrs));
-            }
-        }
-        None
-    }
-
-    /// Look up cached public addresses for a host + port.
-    pub async fn get(&self, host: &str, port: u16) -> Option<Arc<Vec<SocketAddr>>> {
-        if self.capacity == 0 || self.ttl.is_zero() {
-            return None;
-        }
-        let key = Self::key(host, port);
-        let now = Instant::now();
-
-        // Fast path: read lock for cache hit
-        {
-            let entries = self.entries.read().await;
-            match entries.get(&key) {
-                Some(entry) if entry.expires_at > now => return Some(Arc::clone(&entry.addrs)),
-                None => return None,
-                Some(_) => {} // expired, fall through to evict
-            }
-        }
-
-        // Slow path: write lock to remove expired entry
-        let mut entries = self.entries.write().await;
-        entries.remove(&key);
-        None
-    }
-
-    /// Insert resolved public addresses into cache.
-    pub async fn insert(&self, host: &str, port: u16, addrs: Arc<Vec<SocketAddr>>) {
-        if self.capacity == 0 || self.ttl.is_zero() || addrs.is_empty() {
-            return;
-        }
-        let key = Self::key(host, port);
-        let now = Instant::now();
-        let mut entries = self.entries.write().await;
-        entries.retain(|_, entry| entry.expires_at > now);
-        while entries.len() >= self.capacity {
-            let oldest_key = entries
-                .iter()
-                .min_by_key(|(_, entry)| entry.inserted_at)
-                .map(|(key, _)| key.clone());
-            if let Some(key) = oldest_key {
-                entries.remove(&key);
-            } else {
-                break;
-            }
-        }
-        entries.insert(
-            key,
-            DnsCacheEntry {
-                addrs,
-                expires_at: now + self.ttl,
-                inserted_at: now,
-            },
-        );
-    }
-
-    fn key(host: &str, port: u16) -> String {
-        format!("{}:{}", host.to_ascii_lowercase(), port)
-    }
-}
-
-/// Resolve a hostname to public (non-private) socket addresses.
-///
-/// Results are cached in `dns_cache`. Private/reserved IPs are filtered out.
-/// Returns an error if no public addresses remain after filtering.
-pub async fn resolve_public_addrs(
-    host: &str,
-    port: u16,
-    dns_cache: &DnsCache,
-) -> Result<Vec<SocketAddr>, FilterError> {
-    // Cache hit
-    if let Some(addrs) = dns_cache.get(host, port).await {
-        return Ok((*addrs).clone());
-    }
-
-    // Async DNS resolution
-    let addr_str = format!("{}:{}", host, port);
-    let resolved: Vec<SocketAddr> = tokio::net::lookup_host(&addr_str)
-        .await
-        .map_err(|_| FilterError::DnsResolutionFailed(host.to_string()))?
-        .collect();
-
-    if resolved.is_empty() {
-        return Err(FilterError::DnsResolutionFailed(host.to_string()));
-    }
-
-    // Filter out private/reserved addresses
-    let public: Vec<SocketAddr> = resolved
-        .into_iter()
-        .filter(|addr| !is_private_ip(&addr.ip()))
-        .collect();
-
-    if public.is_empty() {
-        return Err(FilterError::NoPublicAddrs(host.to_string()));
-    }
-
-    // Cache the validated public addresses
-    let arc_addrs = Arc::new(public);
-    dns_cache.insert(host, port, Arc::clone(&arc_addrs)).await;
-    Ok((*arc_addrs).clone())
-}
-
-/// Validate that the target host:port is allowed.
-///
-/// Performs port whitelist check, private IP filtering, and DNS resolution
-/// with caching. The resolved addresses are stored in the shared DnsCache
-/// so that the SafeDnsResolver can reuse them, eliminating the TOCTTOU gap.
-pub async fn validate_target(
-    host: &str,
-    port: u16,
-    allowed_ports: &HashSet<u16>,
-    dns_cache: &DnsCache,
-) -> Result<Vec<SocketAddr>, FilterError> {
-    // Port whitelist check
-    if !allowed_ports.contains(&port) {
-        return Err(FilterError::PortNotAllowed(port));
-    }
-
-    // Try parsing as IP directly (no DNS needed)
-    if let Ok(ip) = host.parse::<IpAddr>() {
-        if is_private_ip(&ip) {
-            return Err(FilterError::PrivateIp(ip));
-        }
-        return Ok(vec![SocketAddr::new(ip, port)]);
-    }
-
-    // Resolve and validate DNS (populates cache for SafeDnsResolver)
-    resolve_public_addrs(host, port, dns_cache).await
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    fn ports() -> HashSet<u16> {
-        [80, 443, 8080, 8443].into_iter().collect()
-    }
-
-    fn cache() -> DnsCache {
-        DnsCache::new(Duration::from_secs(60), 128)
-    }
-
-    #[test]
-    fn test_private_ipv4() {
-        assert!(is_private_ip(&IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))));
-        assert!(is_private_ip(&IpAddr::V4(Ipv4Addr::new(172, 16, 0, 1))));
-        assert!(is_private_ip(&IpAddr::V4(Ipv4Addr::new(192, 168, 1, 1))));
-        assert!(is_private_ip(&IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1))));
-        assert!(is_private_ip(&IpAddr::V4(Ipv4Addr::new(169, 254, 1, 1))));
-        assert!(is_private_ip(&IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0))));
-        // CGNAT
-        assert!(is_private_ip(&IpAddr::V4(Ipv4Addr::new(100, 64, 0, 1))));
-        assert!(is_private_ip(&IpAddr::V4(Ipv4Addr::new(
-            100, 127, 255, 254
-        ))));
-        assert!(!is_private_ip(&IpAddr::V4(Ipv4Addr::new(
-            100, 63, 255, 254
-        ))));
-        // Benchmark testing
-        assert!(is_private_ip(&IpAddr::V4(Ipv4Addr::new(198, 18, 0, 1))));
-        // Reserved
-        assert!(is_private_ip(&IpAddr::V4(Ipv4Addr::new(240, 0, 0, 1))));
-        // Public
-        assert!(!is_private_ip(&IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8))));
-        assert!(!is_private_ip(&IpAddr::V4(Ipv4Addr::new(203, 0, 113, 1))));
-    }
-
-    #[test]
-    fn test_private_ipv6() {
-        assert!(is_private_ip(&IpAddr::V6(Ipv6Addr::LOCALHOST)));
-        assert!(is_private_ip(&IpAddr::V6(Ipv6Addr::UNSPECIFIED)));
-        // fc00::1 (ULA)
-        assert!(is_private_ip(&IpAddr::V6(Ipv6Addr::new(
-            0xfc00, 0, 0, 0, 0, 0, 0, 1
-        ))));
-        // fe80::1 (link-local)
-        assert!(is_private_ip(&IpAddr::V6(Ipv6Addr::new(
-            0xfe80, 0, 0, 0, 0, 0, 0, 1
-        ))));
-    }
-
-    #[tokio::test]
-    async fn test_port_not_allowed() {
-        let cache = cache();
-        let result = validate_target("8.8.8.8", 22, &ports(), &cache).await;
-        assert!(matches!(result, Err(FilterError::PortNotAllowed(22))));
-    }
-
-    #[tokio::test]
-    async fn test_private_ip_blocked() {
-        let cache = cache();
-        let result = validate_target("127.0.0.1", 80, &ports(), &cache).await;
-        assert!(matches!(result, Err(FilterError::PrivateIp(_))));
-    }
-
-    #[tokio::test]
-    async fn test_public_ip_allowed() {
-        let cache = cache();
-        let result = validate_target("8.8.8.8", 443, &ports(), &cache).await;
-        assert!(result.is_ok());
-        let addrs = result.unwrap();
-        assert_eq!(addrs.len(), 1);
-        assert_eq!(addrs[0].ip(), IpAddr::V4(Ipv4Addr::new(8, 8, 8, 8)));
-    }
-
-    #[tokio::test]
-    async fn test_cache_stores_multiple_addrs() {
-        let cache = cache();
-        let addrs = vec![
-            SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)), 443),
-            SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 0, 0, 1)), 443),
-        ];
-        cache
-            .insert("example.com", 443, Arc::new(addrs.clone()))
-            .await;
-        let cached = cache.get("example.com", 443).await.unwrap();
-        assert_eq!(*cached, addrs);
-    }
-
-    #[tokio::test]
-    async fn test_cache_key_case_insensitive() {
-        let cache = cache();
-        let addrs = vec![SocketAddr::new(IpAddr::V4(Ipv4Addr::new(1, 1, 1, 1)), 443)];
-        cache
-            .insert("Example.COM", 443, Arc::new(addrs.clone()))
-            .await;
-        let cached = cache.get("example.com", 443).await.unwrap();
-        assert_eq!(*cached, addrs);
-    }
-}